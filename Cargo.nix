@@ -4793,12 +4793,19 @@ rec {
             name = "krb5-sys";
             packageId = "krb5-sys";
           }
+          {
+            name = "libc";
+            packageId = "libc";
+            optional = true;
+          }
           {
             name = "snafu";
             packageId = "snafu 0.8.5";
           }
         ];
-
+        features = {
+          "testing-tools" = [ "dep:libc" ];
+        };
       };
       "krb5-sys" = rec {
         crateName = "krb5-sys";
@@ -9229,6 +9236,10 @@ rec {
             name = "native-tls";
             packageId = "native-tls";
           }
+          {
+            name = "openssl";
+            packageId = "openssl";
+          }
           {
             name = "rand";
             packageId = "rand 0.9.0";
@@ -9269,7 +9280,20 @@ rec {
             packageId = "tracing-subscriber";
           }
         ];
-
+        devDependencies = [
+          {
+            name = "http";
+            packageId = "http";
+          }
+          {
+            name = "tower";
+            packageId = "tower 0.5.2";
+            features = [ "util" ];
+          }
+        ];
+        features = {
+          "failpoints" = [ "stackable-secret-operator-crd-utils/failpoints" ];
+        };
       };
       "stackable-operator" = rec {
         crateName = "stackable-operator";
@@ -9509,6 +9533,20 @@ rec {
             name = "h2";
             packageId = "h2";
           }
+          {
+            name = "http-body-util";
+            packageId = "http-body-util";
+          }
+          {
+            name = "hyper";
+            packageId = "hyper";
+            features = [ "server" "http1" ];
+          }
+          {
+            name = "hyper-util";
+            packageId = "hyper-util";
+            features = [ "tokio" ];
+          }
           {
             name = "libc";
             packageId = "libc";
@@ -9601,6 +9639,10 @@ rec {
             name = "tonic";
             packageId = "tonic";
           }
+          {
+            name = "tonic-health";
+            packageId = "tonic-health";
+          }
           {
             name = "tonic-reflection";
             packageId = "tonic-reflection";
@@ -9631,12 +9673,33 @@ rec {
           }
         ];
         devDependencies = [
+          {
+            name = "http";
+            packageId = "http";
+          }
           {
             name = "serde_yaml";
             packageId = "serde_yaml";
           }
+          {
+            name = "stackable-secret-operator-crd-utils";
+            packageId = "stackable-secret-operator-crd-utils";
+            features = [ "testing" ];
+          }
+          {
+            name = "tokio";
+            packageId = "tokio";
+            features = [ "test-util" ];
+          }
+          {
+            name = "tower";
+            packageId = "tower 0.5.2";
+            features = [ "util" ];
+          }
         ];
-
+        features = {
+          "failpoints" = [ "stackable-secret-operator-crd-utils/failpoints" ];
+        };
       };
       "stackable-secret-operator-crd-utils" = rec {
         crateName = "stackable-secret-operator-crd-utils";
@@ -9648,18 +9711,66 @@ rec {
           "Stackable GmbH <info@stackable.tech>"
         ];
         dependencies = [
+          {
+            name = "http";
+            packageId = "http";
+            optional = true;
+          }
           {
             name = "serde";
             packageId = "serde";
             features = [ "derive" ];
           }
+          {
+            name = "serde_json";
+            packageId = "serde_json";
+          }
+          {
+            name = "snafu";
+            packageId = "snafu 0.8.5";
+          }
           {
             name = "stackable-operator";
             packageId = "stackable-operator";
             features = [ "time" ];
           }
+          {
+            name = "tokio";
+            packageId = "tokio";
+            optional = true;
+            features = [ "full" ];
+          }
+          {
+            name = "tower";
+            packageId = "tower 0.5.2";
+            optional = true;
+            features = [ "util" ];
+          }
+          {
+            name = "tracing";
+            packageId = "tracing";
+            optional = true;
+          }
         ];
-
+        devDependencies = [
+          {
+            name = "http";
+            packageId = "http";
+          }
+          {
+            name = "tokio";
+            packageId = "tokio";
+          }
+          {
+            name = "tower";
+            packageId = "tower 0.5.2";
+            features = [ "util" ];
+          }
+        ];
+        features = {
+          "failpoints" = [ "dep:tokio" "dep:tracing" ];
+          "testing" = [ "dep:http" "dep:tower" ];
+        };
       };
       "stackable-shared" = rec {
         crateName = "stackable-shared";
@@ -10789,6 +10900,60 @@ rec {
         };
         resolvedDefaultFeatures = [ "default" "prost" "prost-build" "transport" ];
       };
+      "tonic-health" = rec {
+        crateName = "tonic-health";
+        version = "0.12.3";
+        edition = "2021";
+        sha256 = "1ch97bilfc8djdzhxaq1v4kvbj9kk51daqhic1f0y4hjp3fk9bqy";
+        libName = "tonic_health";
+        authors = [
+          "James Nugent <james@jen20.com>"
+        ];
+        dependencies = [
+          {
+            name = "async-stream";
+            packageId = "async-stream";
+          }
+          {
+            name = "prost";
+            packageId = "prost";
+          }
+          {
+            name = "tokio";
+            packageId = "tokio";
+            features = [ "sync" ];
+          }
+          {
+            name = "tokio-stream";
+            packageId = "tokio-stream";
+          }
+          {
+            name = "tonic";
+            packageId = "tonic";
+            usesDefaultFeatures = false;
+            features = [ "codegen" "prost" ];
+          }
+        ];
+        devDependencies = [
+          {
+            name = "prost-types";
+            packageId = "prost-types";
+          }
+          {
+            name = "tokio";
+            packageId = "tokio";
+            features = [ "rt-multi-thread" "macros" ];
+          }
+          {
+            name = "tokio-stream";
+            packageId = "tokio-stream";
+          }
+        ];
+        features = {
+          "default" = [ "transport" ];
+        };
+        resolvedDefaultFeatures = [ "default" "transport" ];
+      };
       "tonic-reflection" = rec {
         crateName = "tonic-reflection";
         version = "0.12.3";
@@ -0,0 +1,270 @@
+//! Named points at which faults can be injected, to test how secret-operator recovers from them.
+//!
+//! Failpoints only exist if secret-operator is built with the `failpoints` feature, and are compiled out entirely
+//! otherwise. They are configured by setting [`FAILPOINTS_ENV`] to a `;`-separated list of `<name>=<action>`, where
+//! `<action>` is one of:
+//!
+//! - `return`: fail with an [`InjectedFault`]
+//! - `sleep(<ms>)`: wait for `<ms>` milliseconds, then continue
+//! - `panic`: panic
+//! - `off`: continue
+//!
+//! Prefixing an action with `<n>*` (such as `1*return`) only applies it to the next `<n>` times that the failpoint is
+//! reached, after which it is turned off again.
+//!
+//! Each binary configures the failpoints once at startup (see [`configure_from_env`]), and lists the failpoints that
+//! it provides in its own `failpoint` module.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    num::ParseIntError,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use snafu::{OptionExt as _, ResultExt as _, Snafu};
+
+/// The environment variable that configures the failpoints, see the [module documentation](self).
+///
+/// The keytab provisioner inherits the operator's environment, so the same variable configures both.
+pub const FAILPOINTS_ENV: &str = "SECRET_OPERATOR_FAILPOINTS";
+
+#[derive(Debug, Snafu)]
+#[snafu(display("fault injected at failpoint {name:?}"))]
+pub struct InjectedFault {
+    name: String,
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum ParseError {
+    #[snafu(display("failpoint {entry:?} must have the form <name>=<action>"))]
+    MissingAction { entry: String },
+
+    #[snafu(display("failpoint {name:?} has an invalid count"))]
+    InvalidCount { source: ParseIntError, name: String },
+
+    #[snafu(display("failpoint {name:?} has an invalid sleep duration"))]
+    InvalidSleep { source: ParseIntError, name: String },
+
+    #[snafu(display("failpoint {name:?} has an unknown action {action:?}"))]
+    UnknownAction { name: String, action: String },
+}
+
+/// What happens when a failpoint is reached, see [`Action::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Return,
+    Sleep(Duration),
+    Panic,
+    Off,
+}
+
+impl Action {
+    /// Takes the action for the failpoint `name`, failing if it is [`Action::Return`].
+    pub async fn run(self, name: &str) -> Result<(), InjectedFault> {
+        match self {
+            Action::Return => {
+                tracing::warn!(failpoint = name, "injecting fault");
+                InjectedFaultSnafu { name }.fail()
+            }
+            Action::Sleep(duration) => {
+                tracing::warn!(failpoint = name, ?duration, "delaying at failpoint");
+                tokio::time::sleep(duration).await;
+                Ok(())
+            }
+            Action::Panic => panic!("failpoint {name:?} was configured to panic"),
+            Action::Off => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failpoint {
+    action: Action,
+    /// How many more times `action` applies, or `None` if it applies until the failpoint is reconfigured.
+    remaining: Option<u32>,
+}
+
+/// A set of configured failpoints, by name.
+#[derive(Debug, Default)]
+pub struct Failpoints(HashMap<String, Failpoint>);
+
+impl Failpoints {
+    /// Parses `spec`, see the [module documentation](self) for the format.
+    pub fn parse(spec: &str) -> Result<Self, ParseError> {
+        use parse_error::*;
+        spec.split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (name, action) = entry
+                    .split_once('=')
+                    .context(MissingActionSnafu { entry })?;
+                let (name, action) = (name.trim(), action.trim());
+                let (remaining, action) = match action.split_once('*') {
+                    Some((count, action)) => (
+                        Some(count.trim().parse().context(InvalidCountSnafu { name })?),
+                        action.trim(),
+                    ),
+                    None => (None, action),
+                };
+                let action = match action {
+                    "return" => Action::Return,
+                    "panic" => Action::Panic,
+                    "off" => Action::Off,
+                    _ => match action
+                        .strip_prefix("sleep(")
+                        .and_then(|ms| ms.strip_suffix(')'))
+                    {
+                        Some(ms) => Action::Sleep(Duration::from_millis(
+                            ms.trim().parse().context(InvalidSleepSnafu { name })?,
+                        )),
+                        None => return UnknownActionSnafu { name, action }.fail(),
+                    },
+                };
+                Ok((name.to_string(), Failpoint { action, remaining }))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Logs every configured failpoint, so that injected faults are never mistaken for real ones.
+    pub fn log_configured(&self) {
+        for (name, failpoint) in &self.0 {
+            tracing::warn!(failpoint = name, action = ?failpoint.action, remaining = failpoint.remaining, "failpoint configured");
+        }
+    }
+
+    /// Reaches the failpoint `name`, returning the action to take (see [`Action::run`]).
+    pub fn reach(&mut self, name: &str) -> Action {
+        let Some(failpoint) = self.0.get_mut(name) else {
+            return Action::Off;
+        };
+        match &mut failpoint.remaining {
+            Some(0) => Action::Off,
+            Some(remaining) => {
+                *remaining -= 1;
+                failpoint.action
+            }
+            None => failpoint.action,
+        }
+    }
+}
+
+static FAILPOINTS: Mutex<Option<Failpoints>> = Mutex::new(None);
+
+thread_local! {
+    static THREAD_FAILPOINTS: RefCell<Option<Failpoints>> = const { RefCell::new(None) };
+}
+
+/// Reaches the failpoint `name` of the current thread if it has any (see [`configure_for_current_thread`]), or of
+/// the process otherwise.
+fn reach(name: &str) -> Action {
+    THREAD_FAILPOINTS
+        .with_borrow_mut(|failpoints| failpoints.as_mut().map(|failpoints| failpoints.reach(name)))
+        .unwrap_or_else(|| {
+            FAILPOINTS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get_or_insert_default()
+                .reach(name)
+        })
+}
+
+/// Replaces all process-wide failpoints with the ones described by `spec`.
+pub fn configure(spec: &str) -> Result<(), ParseError> {
+    let failpoints = Failpoints::parse(spec)?;
+    failpoints.log_configured();
+    *FAILPOINTS.lock().unwrap_or_else(PoisonError::into_inner) = Some(failpoints);
+    Ok(())
+}
+
+/// Replaces the failpoints of the current thread with the ones described by `spec`, which then take precedence over
+/// the process-wide ones.
+///
+/// Tests run in parallel, so they should use this rather than [`configure`] to avoid affecting each other.
+pub fn configure_for_current_thread(spec: &str) -> Result<(), ParseError> {
+    let failpoints = Failpoints::parse(spec)?;
+    THREAD_FAILPOINTS.set(Some(failpoints));
+    Ok(())
+}
+
+/// Configures the process-wide failpoints from [`FAILPOINTS_ENV`], if it is set.
+pub fn configure_from_env() -> Result<(), ParseError> {
+    match std::env::var(FAILPOINTS_ENV) {
+        Ok(spec) => configure(&spec),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Reaches the failpoint `name`, failing if it is configured to `return`.
+pub async fn eval(name: &str) -> Result<(), InjectedFault> {
+    reach(name).run(name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{configure, configure_for_current_thread, eval};
+
+    #[tokio::test]
+    async fn counted_failpoint_should_turn_itself_off() {
+        configure_for_current_thread("test.a = 2*return; test.b=return").unwrap();
+        assert!(eval("test.a").await.is_err());
+        assert!(eval("test.a").await.is_err());
+        // Retries succeed once the fault has cleared
+        assert!(eval("test.a").await.is_ok());
+        assert!(eval("test.b").await.is_err());
+        assert!(eval("test.b").await.is_err());
+        assert!(eval("test.unconfigured").await.is_ok());
+
+        configure_for_current_thread("").unwrap();
+        assert!(eval("test.b").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn sleep_should_delay_without_failing() {
+        configure_for_current_thread("test.slow=sleep(50)").unwrap();
+        let start = Instant::now();
+        eval("test.slow").await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn invalid_failpoints_should_be_rejected() {
+        for spec in [
+            "test.a",
+            "test.a=explode",
+            "test.a=x*return",
+            "test.a=sleep(soon)",
+        ] {
+            assert!(configure_for_current_thread(spec).is_err(), "{spec}");
+        }
+        // The previous configuration is kept
+        configure_for_current_thread("test.a=return").unwrap();
+        assert!(configure_for_current_thread("test.a=off;test.b").is_err());
+        assert!(eval("test.a").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn thread_failpoints_should_shadow_process_wide_ones() {
+        configure("test.process_wide=return").unwrap();
+        assert!(eval("test.process_wide").await.is_err());
+
+        configure_for_current_thread("test.thread=return").unwrap();
+        assert!(eval("test.process_wide").await.is_ok());
+        assert!(eval("test.thread").await.is_err());
+        // Other threads still see the process-wide failpoints
+        let other_thread = std::thread::spawn(|| {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(async { (eval("test.process_wide").await, eval("test.thread").await) })
+        });
+        let (process_wide, thread) = other_thread.join().unwrap();
+        assert!(process_wide.is_err());
+        assert!(thread.is_ok());
+    }
+}
@@ -9,6 +9,14 @@ use stackable_operator::{
     schemars::{self, JsonSchema},
 };
 
+#[cfg(feature = "failpoints")]
+pub mod failpoint;
+pub mod ownership;
+pub mod rejection;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod volume;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ConfigMapReference {
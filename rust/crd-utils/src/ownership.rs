@@ -0,0 +1,358 @@
+//! Naming conventions for objects that are written by secret-operator components, and [`OwnedResourceWriter`], which
+//! applies them.
+
+use std::{collections::BTreeMap, fmt::Debug};
+
+use serde::{Serialize, de::DeserializeOwned};
+use snafu::{ResultExt, Snafu, ensure};
+use stackable_operator::{
+    k8s_openapi::{NamespaceResourceScope, apimachinery::pkg::apis::meta::v1::OwnerReference},
+    kube::{
+        self, Resource, ResourceExt,
+        api::{DeleteParams, DynamicObject, Patch, PatchParams, Preconditions},
+        runtime::reflector::ObjectRef,
+    },
+};
+
+/// The name that secret-operator identifies itself as to Kubernetes.
+pub const OPERATOR_NAME: &str = "secrets.stackable.tech";
+
+pub const LABEL_MANAGED_BY: &str = "app.kubernetes.io/managed-by";
+pub const LABEL_INSTANCE: &str = "app.kubernetes.io/instance";
+
+/// The field manager used by the component `scope` for server-side apply and patches.
+///
+/// This matches the naming used by [`stackable_operator::client::Client::apply_patch`], so that
+/// components that use a raw [`kube::Api`] still appear consistently in `managedFields`.
+pub fn field_manager(scope: &str) -> String {
+    format!("{OPERATOR_NAME}/{scope}")
+}
+
+/// The value of [`LABEL_MANAGED_BY`] for objects created by the component `scope`.
+fn managed_by(scope: &str) -> String {
+    format!("{OPERATOR_NAME}_{scope}")
+}
+
+/// The labels that should be set on every object created by the component `scope` on behalf of `instance`.
+///
+/// `instance` is the name of the object that the created object belongs to, such as the SecretClass that it was
+/// provisioned for.
+pub fn standard_labels(scope: &str, instance: &str) -> BTreeMap<String, String> {
+    [
+        (LABEL_MANAGED_BY.to_string(), managed_by(scope)),
+        (LABEL_INSTANCE.to_string(), instance.to_string()),
+    ]
+    .into()
+}
+
+/// A reference to the owner `name` (with the UID `uid`), so that the owned object is garbage collected along with it.
+pub fn owner_reference<K: Resource<DynamicType = ()>>(name: &str, uid: &str) -> OwnerReference {
+    OwnerReference {
+        api_version: K::api_version(&()).into_owned(),
+        kind: K::kind(&()).into_owned(),
+        name: name.to_string(),
+        uid: uid.to_string(),
+        ..Default::default()
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum DeleteError {
+    #[snafu(display(
+        "refusing to delete {object}, which is managed by {managed_by:?} rather than {expected:?}"
+    ))]
+    NotOwned {
+        object: ObjectRef<DynamicObject>,
+        managed_by: Option<String>,
+        expected: String,
+    },
+
+    #[snafu(display("failed to delete {object}"))]
+    Delete {
+        source: kube::Error,
+        object: ObjectRef<DynamicObject>,
+    },
+}
+
+/// Writes objects on behalf of the component `scope`, following the conventions above.
+///
+/// Objects that are created by the component carry the [`standard_labels`], and all writes are attributed to its
+/// [`field_manager`].
+#[derive(Clone)]
+pub struct OwnedResourceWriter {
+    client: kube::Client,
+    scope: &'static str,
+    dry_run: bool,
+}
+
+impl OwnedResourceWriter {
+    pub fn new(client: kube::Client, scope: &'static str) -> Self {
+        Self {
+            client,
+            scope,
+            dry_run: false,
+        }
+    }
+
+    /// Only validates writes with the API server, without persisting them.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    fn api<K>(&self, namespace: Option<&str>) -> kube::Api<K>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>,
+    {
+        kube::Api::namespaced(
+            self.client.clone(),
+            namespace.unwrap_or(self.client.default_namespace()),
+        )
+    }
+
+    fn patch_params(&self) -> PatchParams {
+        PatchParams {
+            field_manager: Some(field_manager(self.scope)),
+            dry_run: self.dry_run,
+            ..Default::default()
+        }
+    }
+
+    /// Creates or updates `object` with server-side apply, as created on behalf of `instance` (see
+    /// [`standard_labels`]).
+    ///
+    /// `owner` (see [`owner_reference`]) replaces any owner that was applied previously.
+    pub async fn apply<K>(
+        &self,
+        mut object: K,
+        instance: &str,
+        owner: Option<OwnerReference>,
+    ) -> kube::Result<K>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+            + Clone
+            + Debug
+            + Serialize
+            + DeserializeOwned,
+    {
+        object
+            .labels_mut()
+            .extend(standard_labels(self.scope, instance));
+        if let Some(owner) = owner {
+            object.meta_mut().owner_references = Some(vec![owner]);
+        }
+        // Applied objects are always owned by us, so conflicts with other field managers are resolved in our favour
+        self.api::<K>(object.meta().namespace.as_deref())
+            .patch(
+                &object.name_any(),
+                &PatchParams {
+                    force: true,
+                    ..self.patch_params()
+                },
+                &Patch::Apply(&object),
+            )
+            .await
+    }
+
+    /// Merge-patches the object `name`, which doesn't need to have been created by this component.
+    ///
+    /// Unlike [`Self::apply`], this doesn't label the object, since that would claim it for this component.
+    pub async fn merge_patch<K>(
+        &self,
+        namespace: &str,
+        name: &str,
+        patch: &serde_json::Value,
+    ) -> kube::Result<K>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+            + Clone
+            + Debug
+            + DeserializeOwned,
+    {
+        self.api::<K>(Some(namespace))
+            .patch(name, &self.patch_params(), &Patch::Merge(patch))
+            .await
+    }
+
+    /// Deletes `object`, as long as it was created by this component and hasn't been modified since it was loaded.
+    ///
+    /// Otherwise the API server rejects the deletion with `409 Conflict`. Deleting an object that no longer exists is
+    /// not an error.
+    pub async fn delete<K>(&self, object: &K) -> Result<(), DeleteError>
+    where
+        K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+            + Clone
+            + Debug
+            + DeserializeOwned,
+    {
+        use delete_error::*;
+        let object_ref = || ObjectRef::from_obj(object).erase();
+        let object_managed_by = object.labels().get(LABEL_MANAGED_BY);
+        let expected = managed_by(self.scope);
+        ensure!(
+            object_managed_by == Some(&expected),
+            NotOwnedSnafu {
+                object: object_ref(),
+                managed_by: object_managed_by.cloned(),
+                expected,
+            }
+        );
+        let params = DeleteParams {
+            dry_run: self.dry_run,
+            preconditions: Some(Preconditions {
+                uid: object.meta().uid.clone(),
+                resource_version: object.meta().resource_version.clone(),
+            }),
+            ..Default::default()
+        };
+        match self
+            .api::<K>(object.meta().namespace.as_deref())
+            .delete(&object.name_any(), &params)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(response)) if response.code == 404 => Ok(()),
+            Err(err) => Err(err).context(DeleteSnafu {
+                object: object_ref(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use stackable_operator::{
+        k8s_openapi::{
+            api::core::v1::{ConfigMap, Pod},
+            apimachinery::pkg::apis::meta::v1::ObjectMeta,
+        },
+        kube::ResourceExt,
+    };
+
+    use super::{
+        DeleteError, LABEL_INSTANCE, LABEL_MANAGED_BY, OwnedResourceWriter, field_manager,
+        owner_reference, standard_labels,
+    };
+    use crate::testing::RecordingClient;
+
+    const SCOPE: &str = "backend.test";
+
+    fn config_map(labels: &[(&str, &str)]) -> ConfigMap {
+        ConfigMap {
+            metadata: ObjectMeta {
+                name: Some("my-config".to_string()),
+                namespace: Some("my-namespace".to_string()),
+                uid: Some("config-uid".to_string()),
+                resource_version: Some("7".to_string()),
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn standard_labels_should_identify_component_and_instance() {
+        let labels = standard_labels("backend.cert-manager", "tls");
+        assert_eq!(
+            labels.get(LABEL_MANAGED_BY).map(String::as_str),
+            Some("secrets.stackable.tech_backend.cert-manager")
+        );
+        assert_eq!(labels.get(LABEL_INSTANCE).map(String::as_str), Some("tls"));
+        assert_eq!(
+            field_manager("backend.cert-manager"),
+            "secrets.stackable.tech/backend.cert-manager"
+        );
+    }
+
+    #[tokio::test]
+    async fn applied_objects_should_be_labelled_and_owned() {
+        let server = RecordingClient::new();
+        let writer = OwnedResourceWriter::new(server.client(), SCOPE);
+        let owner = owner_reference::<Pod>("my-pod", "pod-uid");
+
+        let applied = writer
+            .apply(config_map(&[("custom", "label")]), "tls", Some(owner))
+            .await
+            .unwrap();
+        assert_eq!(
+            applied.labels().get("custom").map(String::as_str),
+            Some("label")
+        );
+
+        let [request] = &server.requests()[..] else {
+            panic!("expected a single request, got {:?}", server.requests());
+        };
+        assert_eq!(request.method, http::Method::PATCH);
+        assert_eq!(
+            request.path,
+            "/api/v1/namespaces/my-namespace/configmaps/my-config"
+        );
+        assert_eq!(
+            request.query_param("fieldManager"),
+            Some(field_manager(SCOPE))
+        );
+        assert_eq!(request.query_param("force"), Some("true".to_string()));
+        assert!(!request.is_dry_run());
+        server.assert_owned(SCOPE, "tls", Some("pod-uid"));
+    }
+
+    #[tokio::test]
+    async fn dry_run_should_apply_to_every_write() {
+        let server = RecordingClient::new();
+        let writer = OwnedResourceWriter::new(server.client(), SCOPE).dry_run(true);
+        let owned = config_map(&[(LABEL_MANAGED_BY, "secrets.stackable.tech_backend.test")]);
+
+        writer.apply(owned.clone(), "tls", None).await.unwrap();
+        writer
+            .merge_patch::<ConfigMap>("my-namespace", "my-config", &json!({"data": {}}))
+            .await
+            .unwrap();
+        writer.delete(&owned).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        for request in requests {
+            assert!(request.is_dry_run(), "{request:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_should_only_remove_unmodified_owned_objects() {
+        let server = RecordingClient::new();
+        let writer = OwnedResourceWriter::new(server.client(), SCOPE);
+
+        // Created by another component, or by the user
+        for labels in [
+            &[][..],
+            &[(LABEL_MANAGED_BY, "secrets.stackable.tech_backend.other")],
+        ] {
+            let err = writer.delete(&config_map(labels)).await.unwrap_err();
+            assert!(matches!(err, DeleteError::NotOwned { .. }), "{err:?}");
+        }
+        assert_eq!(server.requests().len(), 0);
+
+        writer
+            .delete(&config_map(&[(
+                LABEL_MANAGED_BY,
+                "secrets.stackable.tech_backend.test",
+            )]))
+            .await
+            .unwrap();
+        let [request] = &server.requests()[..] else {
+            panic!("expected a single request, got {:?}", server.requests());
+        };
+        assert_eq!(request.method, http::Method::DELETE);
+        assert_eq!(
+            request.body.as_ref().unwrap()["preconditions"],
+            json!({"uid": "config-uid", "resourceVersion": "7"})
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Recognizes writes that the Kubernetes API server rejected because of the cluster's policies, which won't succeed
+//! no matter how often they are retried.
+//!
+//! The API server only returns the quota or policy that was responsible as part of the error message, so that is
+//! what is parsed here.
+
+use std::fmt::Display;
+
+use stackable_operator::kube::{self, core::ErrorResponse};
+
+/// A write that was rejected by a policy of the cluster, which an administrator must change before retrying.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteRejection {
+    /// Creating the object would have exceeded the `ResourceQuota` `quota`.
+    QuotaExceeded { quota: String },
+
+    /// The admission webhook `webhook` denied the write.
+    DeniedByWebhook { webhook: String },
+
+    /// The `ValidatingAdmissionPolicy` `policy` denied the write.
+    DeniedByPolicy { policy: String },
+}
+
+impl WriteRejection {
+    /// Returns why `err` was rejected, or `None` if it was not rejected by a quota or admission policy (such as when
+    /// the operator lacks the RBAC permissions for the write).
+    pub fn from_error(err: &kube::Error) -> Option<Self> {
+        match err {
+            kube::Error::Api(response) => Self::from_response(response),
+            _ => None,
+        }
+    }
+
+    pub fn from_response(response: &ErrorResponse) -> Option<Self> {
+        let message = &response.message;
+        match response.code {
+            403 if message.contains("exceeded quota: ") => Some(Self::QuotaExceeded {
+                quota: field_after(message, "exceeded quota: ", ',')?,
+            }),
+            // Admission policies may choose to respond with Invalid rather than Forbidden
+            403 | 422 if message.contains("admission webhook \"") => Some(Self::DeniedByWebhook {
+                webhook: field_after(message, "admission webhook \"", '"')?,
+            }),
+            403 | 422 if message.contains("ValidatingAdmissionPolicy '") => {
+                Some(Self::DeniedByPolicy {
+                    policy: field_after(message, "ValidatingAdmissionPolicy '", '\'')?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The text between `prefix` and the next `terminator` (or the end of `message`).
+fn field_after(message: &str, prefix: &str, terminator: char) -> Option<String> {
+    let (_, rest) = message.split_once(prefix)?;
+    let field = rest.split(terminator).next()?.trim();
+    (!field.is_empty()).then(|| field.to_string())
+}
+
+impl Display for WriteRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteRejection::QuotaExceeded { quota } => {
+                write!(f, "rejected for exceeding ResourceQuota {quota:?}")
+            }
+            WriteRejection::DeniedByWebhook { webhook } => {
+                write!(f, "denied by admission webhook {webhook:?}")
+            }
+            WriteRejection::DeniedByPolicy { policy } => {
+                write!(f, "denied by ValidatingAdmissionPolicy {policy:?}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stackable_operator::kube::core::ErrorResponse;
+
+    use super::WriteRejection;
+
+    fn response(code: u16, reason: &str, message: &str) -> ErrorResponse {
+        ErrorResponse {
+            status: "Failure".to_string(),
+            message: message.to_string(),
+            reason: reason.to_string(),
+            code,
+        }
+    }
+
+    #[test]
+    fn policy_rejections_should_name_the_policy() {
+        assert_eq!(
+            WriteRejection::from_response(&response(
+                403,
+                "Forbidden",
+                r#"secrets "tls-ca" is forbidden: exceeded quota: secret-count, requested: secrets=1, used: secrets=10, limited: secrets=10"#,
+            )),
+            Some(WriteRejection::QuotaExceeded {
+                quota: "secret-count".to_string()
+            })
+        );
+        assert_eq!(
+            WriteRejection::from_response(&response(
+                403,
+                "",
+                r#"admission webhook "secrets.policy.example.com" denied the request: secrets must be labelled"#,
+            )),
+            Some(WriteRejection::DeniedByWebhook {
+                webhook: "secrets.policy.example.com".to_string()
+            })
+        );
+        assert_eq!(
+            WriteRejection::from_response(&response(
+                422,
+                "Invalid",
+                r#"secrets "tls-ca" is forbidden: ValidatingAdmissionPolicy 'require-labels' with binding 'require-labels-binding' denied request: failed expression"#,
+            )),
+            Some(WriteRejection::DeniedByPolicy {
+                policy: "require-labels".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn other_errors_should_not_be_rejections() {
+        // Missing RBAC permissions are fixed by redeploying the operator, not by changing the cluster's policies
+        assert_eq!(
+            WriteRejection::from_response(&response(
+                403,
+                "Forbidden",
+                r#"secrets is forbidden: User "system:serviceaccount:stackable:secret-operator" cannot create resource "secrets" in API group "" in the namespace "default""#,
+            )),
+            None
+        );
+        assert_eq!(
+            WriteRejection::from_response(&response(
+                409,
+                "Conflict",
+                r#"Operation cannot be fulfilled on secrets "tls-ca": the object has been modified"#,
+            )),
+            None
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! A recording mock of the Kubernetes API server, for testing which objects secret-operator components write.
+//!
+//! Only available with the `testing` feature, which should only be enabled for `dev-dependencies`.
+
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{Value, json};
+use stackable_operator::kube;
+
+use crate::ownership::standard_labels;
+
+const APPLY_PATCH_CONTENT_TYPE: &str = "application/apply-patch+yaml";
+
+/// A request that was sent to a [`RecordingClient`].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: http::Method,
+    pub path: String,
+    pub query: String,
+    pub content_type: Option<String>,
+    /// The request body, if it was JSON
+    pub body: Option<Value>,
+}
+
+impl RecordedRequest {
+    /// The (decoded) value of the query parameter `name`, if it was set.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| percent_decode(value))
+    }
+
+    /// Whether the request asked the API server to only validate it, see
+    /// [`OwnedResourceWriter::dry_run`](crate::ownership::OwnedResourceWriter::dry_run).
+    ///
+    /// Deletions pass this in the body, everything else in the query.
+    pub fn is_dry_run(&self) -> bool {
+        let in_body = self
+            .body
+            .as_ref()
+            .and_then(|body| body["dryRun"].as_array())
+            .is_some_and(|dry_run| dry_run.contains(&json!("All")));
+        in_body || self.query_param("dryRun").as_deref() == Some("All")
+    }
+
+    /// Whether the request wrote an object (rather than reading or deleting one).
+    fn is_write(&self) -> bool {
+        [http::Method::POST, http::Method::PUT, http::Method::PATCH].contains(&self.method)
+    }
+
+    /// Whether the request wrote a whole object, rather than patching parts of it.
+    fn is_whole_object(&self) -> bool {
+        match self.method {
+            http::Method::POST | http::Method::PUT => true,
+            http::Method::PATCH => self.content_type.as_deref() == Some(APPLY_PATCH_CONTENT_TYPE),
+            _ => false,
+        }
+    }
+}
+
+/// Decodes a query parameter value, which kube encodes as `application/x-www-form-urlencoded`.
+fn percent_decode(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = std::str::from_utf8(&rest[..2]).expect("escape must be ASCII");
+                bytes.push(u8::from_str_radix(hex, 16).expect("escape must be hexadecimal"));
+                rest = &rest[2..];
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).expect("query parameter must be UTF-8")
+}
+
+/// A fake API server that records every request.
+///
+/// Writes are answered with the written object, deletions succeed, and everything else fails with `404 Not Found`.
+#[derive(Clone, Default)]
+pub struct RecordingClient {
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl RecordingClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn client(&self) -> kube::Client {
+        let server = self.clone();
+        kube::Client::new(
+            tower::service_fn(move |req| {
+                let server = server.clone();
+                async move { server.handle(req).await }
+            }),
+            "default",
+        )
+    }
+
+    /// All requests received so far, in the order they were received.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// All objects that were created, replaced or applied so far (but not merge-patched), in the order they were
+    /// written.
+    pub fn written_objects(&self) -> Vec<Value> {
+        self.requests()
+            .into_iter()
+            .filter(RecordedRequest::is_whole_object)
+            .filter_map(|request| request.body)
+            .collect()
+    }
+
+    /// Asserts that every object in [`Self::written_objects`] was created by the component `scope` on behalf of `instance` (see
+    /// [`standard_labels`]), and is owned by exactly the object with the UID `owner_uid` (or nothing, if it is
+    /// `None`).
+    #[track_caller]
+    pub fn assert_owned(&self, scope: &str, instance: &str, owner_uid: Option<&str>) {
+        let objects = self.written_objects();
+        assert!(!objects.is_empty(), "no objects were written");
+        for object in objects {
+            let metadata = &object["metadata"];
+            for (key, value) in standard_labels(scope, instance) {
+                assert_eq!(
+                    metadata["labels"][&key], value,
+                    "{key} is not set correctly on {object}"
+                );
+            }
+            let owner_uids = metadata["ownerReferences"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|owner| owner["uid"].as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                owner_uids,
+                Vec::from_iter(owner_uid.map(Some)),
+                "unexpected owners of {object}"
+            );
+        }
+    }
+
+    async fn handle(
+        &self,
+        req: http::Request<kube::client::Body>,
+    ) -> Result<http::Response<kube::client::Body>, Infallible> {
+        let (parts, body) = req.into_parts();
+        let body = body.collect_bytes().await.unwrap();
+        let request = RecordedRequest {
+            method: parts.method,
+            path: parts.uri.path().to_string(),
+            query: parts.uri.query().unwrap_or_default().to_string(),
+            content_type: parts
+                .headers
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            body: serde_json::from_slice(&body).ok(),
+        };
+        self.requests.lock().unwrap().push(request.clone());
+        let (status, response) = match request.body {
+            Some(object) if request.is_write() => (http::StatusCode::OK, object),
+            _ if request.method == http::Method::DELETE => (
+                http::StatusCode::OK,
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "Status",
+                    "status": "Success",
+                }),
+            ),
+            _ => (
+                http::StatusCode::NOT_FOUND,
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "Status",
+                    "status": "Failure",
+                    "message": format!("{} not found", request.path),
+                    "reason": "NotFound",
+                    "code": 404,
+                }),
+            ),
+        };
+        let mut response = http::Response::new(serde_json::to_vec(&response).unwrap().into());
+        *response.status_mut() = status;
+        Ok(response)
+    }
+}
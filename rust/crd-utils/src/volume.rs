@@ -0,0 +1,798 @@
+//! The volume context that selects what secret-operator provides to a `Pod`, for operators that need to request
+//! secret volumes.
+//!
+//! The volume context is a string map, which is passed to secret-operator as the annotations of the volume's
+//! `PersistentVolumeClaim`. [`SecretVolumeAttributes`] describes it with typed fields, and
+//! [`EphemeralVolumeBuilder`] builds the `Pod` volume that requests it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    num::{ParseFloatError, ParseIntError},
+    str::{FromStr, ParseBoolError},
+};
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{MapAccess, Visitor},
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+use stackable_operator::{
+    k8s_openapi::{
+        api::core::v1::{
+            EphemeralVolumeSource, PersistentVolumeClaimSpec, PersistentVolumeClaimTemplate,
+            Volume, VolumeResourceRequirements,
+        },
+        apimachinery::pkg::{api::resource::Quantity, apis::meta::v1::ObjectMeta},
+    },
+    time::{Duration, DurationParseError},
+};
+
+/// The `StorageClass` that provisions secret volumes.
+pub const STORAGE_CLASS_NAME: &str = "secrets.stackable.tech";
+
+/// The keys of the volume context, see [`SecretVolumeAttributes`] for what they mean.
+pub mod attribute {
+    pub const CLASS: &str = "secrets.stackable.tech/class";
+    pub const SCOPE: &str = "secrets.stackable.tech/scope";
+    pub const FORMAT: &str = "secrets.stackable.tech/format";
+    pub const KERBEROS_SERVICE_NAMES: &str = "secrets.stackable.tech/kerberos.service.names";
+    pub const KERBEROS_KEYTAB_GROUPS: &str = "secrets.stackable.tech/kerberos.keytab-groups";
+    pub const KERBEROS_KEYTAB_GROUPS_COMBINED: &str =
+        "secrets.stackable.tech/kerberos.keytab-groups.combined";
+    pub const TLS_PKCS12_PASSWORD: &str =
+        "secrets.stackable.tech/format.compatibility.tls-pkcs12.password";
+    pub const TLS_JKS_PASSWORD: &str =
+        "secrets.stackable.tech/format.compatibility.tls-jks.password";
+    pub const ENV_FILE_PREFIX: &str = "secrets.stackable.tech/format.env-file.prefix";
+    pub const TLS_PKCS12_KEYSTORE_NAME: &str =
+        "secrets.stackable.tech/format.tls-pkcs12.keystore-name";
+    pub const TLS_PKCS12_TRUSTSTORE_NAME: &str =
+        "secrets.stackable.tech/format.tls-pkcs12.truststore-name";
+    pub const TLS_PEM_CERT_NAME: &str = "secrets.stackable.tech/format.tls-pem.cert-name";
+    pub const TLS_PEM_KEY_NAME: &str = "secrets.stackable.tech/format.tls-pem.key-name";
+    pub const TLS_PEM_CA_NAME: &str = "secrets.stackable.tech/format.tls-pem.ca-name";
+    pub const AUTOTLS_CERT_LIFETIME: &str = "secrets.stackable.tech/backend.autotls.cert.lifetime";
+    pub const AUTOTLS_CERT_RESTART_BUFFER: &str =
+        "secrets.stackable.tech/backend.autotls.cert.restart-buffer";
+    pub const AUTOTLS_CERT_JITTER_FACTOR: &str =
+        "secrets.stackable.tech/backend.autotls.cert.jitter-factor";
+    pub const AUTOTLS_CSR_HANDSHAKE: &str = "secrets.stackable.tech/backend.autotls.csr-handshake";
+    pub const AUTOTLS_CSR_HANDSHAKE_TIMEOUT: &str =
+        "secrets.stackable.tech/backend.autotls.csr-handshake.timeout";
+    pub const CERT_MANAGER_CERT_LIFETIME: &str =
+        "secrets.stackable.tech/backend.cert-manager.cert.lifetime";
+    pub const ALLOW_SELECTOR_CHANGE: &str = "secrets.stackable.tech/allow-selector-change";
+    pub const FILE_MODE: &str = "secrets.stackable.tech/file.mode";
+    pub const FILE_GROUP: &str = "secrets.stackable.tech/file.group";
+}
+
+/// Defines what properties the secret identifies about a pod
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretScope {
+    Node,
+    Pod,
+    Service { name: String },
+    ListenerVolume { name: String },
+}
+impl From<&SecretScope> for SecretScope {
+    fn from(value: &SecretScope) -> Self {
+        value.clone()
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum ParseScopeError {
+    #[snafu(display("unknown scope type {tpe:?}"))]
+    UnknownScopeType { tpe: String },
+
+    #[snafu(display("scope {tpe:?} requires a parameter"))]
+    ScopeRequiresParam { tpe: String },
+
+    #[snafu(display("scope {tpe:?} does not accept a parameter (got {param:?})"))]
+    ScopeDoesNotAcceptParam { tpe: String, param: String },
+}
+
+impl FromStr for SecretScope {
+    type Err = ParseScopeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tpe, mut param) = match s.split_once('=') {
+            Some((tpe, param)) => (tpe, Some(param)),
+            // No param, whole string is tpe
+            None => (s, None),
+        };
+        let scope = match tpe {
+            "node" => Self::Node,
+            "pod" => Self::Pod,
+            "service" => Self::Service {
+                name: param
+                    .take()
+                    .context(parse_scope_error::ScopeRequiresParamSnafu { tpe })?
+                    .to_string(),
+            },
+            "listener-volume" => Self::ListenerVolume {
+                name: param
+                    .take()
+                    .context(parse_scope_error::ScopeRequiresParamSnafu { tpe })?
+                    .to_string(),
+            },
+            _ => return parse_scope_error::UnknownScopeTypeSnafu { tpe }.fail(),
+        };
+        if let Some(param) = param {
+            return parse_scope_error::ScopeDoesNotAcceptParamSnafu { tpe, param }.fail();
+        }
+        Ok(scope)
+    }
+}
+
+impl SecretScope {
+    /// Parses a comma-separated list of scopes, such as `pod,node`.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, ParseScopeError> {
+        s.split(',').map(str::parse).collect()
+    }
+
+    /// Deserializes a comma-separated list of scopes, for use with `#[serde(deserialize_with)]`.
+    pub fn deserialize_vec<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<Self>, D::Error> {
+        let scopes_str = String::deserialize(de)?;
+        Self::parse_list(&scopes_str).map_err(<D::Error as serde::de::Error>::custom)
+    }
+}
+impl Display for SecretScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretScope::Node => write!(f, "node"),
+            SecretScope::Pod => write!(f, "pod"),
+            SecretScope::Service { name } => write!(f, "service={name}"),
+            SecretScope::ListenerVolume { name } => write!(f, "listener-volume={name}"),
+        }
+    }
+}
+
+/// The formats that secret-operator can provide secrets in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SecretFormat {
+    TlsPem,
+    TlsPkcs12,
+    TlsJks,
+    Kerberos,
+    EnvFile,
+}
+
+impl SecretFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SecretFormat::TlsPem => "tls-pem",
+            SecretFormat::TlsPkcs12 => "tls-pkcs12",
+            SecretFormat::TlsJks => "tls-jks",
+            SecretFormat::Kerberos => "kerberos",
+            SecretFormat::EnvFile => "env-file",
+        }
+    }
+}
+impl Display for SecretFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("unknown secret format {format:?}"))]
+pub struct ParseFormatError {
+    format: String,
+}
+
+impl FromStr for SecretFormat {
+    type Err = ParseFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            SecretFormat::TlsPem,
+            SecretFormat::TlsPkcs12,
+            SecretFormat::TlsJks,
+            SecretFormat::Kerberos,
+            SecretFormat::EnvFile,
+        ]
+        .into_iter()
+        .find(|format| format.as_str() == s)
+        .context(ParseFormatSnafu { format: s })
+    }
+}
+
+/// Keytab files that only contain the keys of some of the volume's Kerberos principals, such as when an application
+/// expects a separate keytab for each of its roles.
+///
+/// Maps each file name to the Kerberos service names that its principals are built from (in the same way as for
+/// [`SecretVolumeAttributes::kerberos_service_names`]). In the volume context, this is written as a JSON object, such
+/// as `{"nn.keytab": ["nn", "HTTP"], "dn.keytab": ["dn"]}`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KerberosKeytabGroups(BTreeMap<String, Vec<String>>);
+
+/// The file that the `kerberos` format writes the keys of all of the volume's principals to.
+pub const FILE_KERBEROS_KEYTAB_KEYTAB: &str = "keytab";
+/// The file that the `kerberos` format writes the Kerberos client configuration to.
+pub const FILE_KERBEROS_KEYTAB_KRB5_CONF: &str = "krb5.conf";
+/// The file that the `kerberos` format lists shortened names in, if any names were shortened.
+pub const FILE_KERBEROS_KEYTAB_SHORTENED_NAMES: &str = "shortened-names.json";
+
+/// Files that the `kerberos` format provides regardless of the keytab groups.
+const RESERVED_KEYTAB_GROUP_FILES: [&str; 3] = [
+    FILE_KERBEROS_KEYTAB_KEYTAB,
+    FILE_KERBEROS_KEYTAB_KRB5_CONF,
+    FILE_KERBEROS_KEYTAB_SHORTENED_NAMES,
+];
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum InvalidKeytabGroupsError {
+    #[snafu(display("keytab group {file:?} has no service names"))]
+    EmptyGroup { file: String },
+
+    #[snafu(display("keytab group {file:?} is defined more than once"))]
+    DuplicateFile { file: String },
+
+    #[snafu(display("keytab group {file:?} conflicts with a file of the kerberos format"))]
+    ReservedFile { file: String },
+}
+
+impl KerberosKeytabGroups {
+    pub fn new<K: Into<String>>(
+        groups: impl IntoIterator<Item = (K, Vec<String>)>,
+    ) -> Result<Self, InvalidKeytabGroupsError> {
+        use invalid_keytab_groups_error::*;
+        let mut files = BTreeMap::new();
+        for (file, service_names) in groups {
+            let file = file.into();
+            if RESERVED_KEYTAB_GROUP_FILES.contains(&file.as_str()) {
+                return ReservedFileSnafu { file }.fail();
+            }
+            if service_names.is_empty() {
+                return EmptyGroupSnafu { file }.fail();
+            }
+            if files.contains_key(&file) {
+                return DuplicateFileSnafu { file }.fail();
+            }
+            files.insert(file, service_names);
+        }
+        Ok(Self(files))
+    }
+
+    /// The file name and service names of each group, ordered by file name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.0
+            .iter()
+            .map(|(file, service_names)| (file.as_str(), service_names.as_slice()))
+    }
+
+    /// Every service name that is used by any group, without duplicates.
+    pub fn service_names(&self) -> BTreeSet<&str> {
+        self.0.values().flatten().map(String::as_str).collect()
+    }
+}
+
+impl Display for KerberosKeytabGroups {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            &serde_json::to_string(&self.0).expect("keytab groups must be serializable as JSON"),
+        )
+    }
+}
+
+impl FromStr for KerberosKeytabGroups {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl Serialize for KerberosKeytabGroups {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for KerberosKeytabGroups {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct GroupsVisitor;
+        impl<'de> Visitor<'de> for GroupsVisitor {
+            type Value = KerberosKeytabGroups;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map of file names to lists of Kerberos service names")
+            }
+
+            // Deserializing into a map directly would silently drop duplicate file names
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut groups = Vec::<(String, Vec<String>)>::new();
+                while let Some(group) = map.next_entry()? {
+                    groups.push(group);
+                }
+                KerberosKeytabGroups::new(groups).map_err(<A::Error as serde::de::Error>::custom)
+            }
+        }
+        de.deserialize_map(GroupsVisitor)
+    }
+}
+
+/// The volume context that selects what secret-operator should provide.
+///
+/// Fields that are `None` (or empty) are left unset, so that secret-operator applies its defaults.
+///
+/// (De)serializes from the string map that makes up the volume context. Keys that aren't described here (such as
+/// the ones that are added by Kubelet) are ignored when deserializing.
+///
+/// secret-operator parses the volume context through this as well, so these are exactly the attributes it understands.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecretVolumeAttributes {
+    /// The SecretClass that provides the secret.
+    pub class: String,
+
+    /// What the secret identifies about the `Pod`.
+    pub scope: Vec<SecretScope>,
+
+    /// The format of the mounted secrets, defaults to the native format of the SecretClass' backend.
+    pub format: Option<SecretFormat>,
+
+    /// The Kerberos service names (`SERVICE_NAME/hostname@realm`) to provision keytabs for.
+    pub kerberos_service_names: Vec<String>,
+
+    /// Keytab files that only contain some of the principals, replacing `kerberos_service_names` if set.
+    pub kerberos_keytab_groups: Option<KerberosKeytabGroups>,
+
+    /// Whether the combined `keytab` is provided alongside the `kerberos_keytab_groups`.
+    pub kerberos_keytab_groups_combined: Option<bool>,
+
+    /// The password used to encrypt the TLS PKCS#12 keystore.
+    pub tls_pkcs12_password: Option<String>,
+
+    /// The password used to protect the TLS JKS keystore and truststore.
+    pub tls_jks_password: Option<String>,
+
+    /// A prefix for all variable names of the `env-file` format.
+    pub env_file_prefix: Option<String>,
+
+    pub tls_pkcs12_keystore_name: Option<String>,
+    pub tls_pkcs12_truststore_name: Option<String>,
+    pub tls_pem_cert_name: Option<String>,
+    pub tls_pem_key_name: Option<String>,
+    pub tls_pem_ca_name: Option<String>,
+
+    pub autotls_cert_lifetime: Option<Duration>,
+    pub autotls_cert_restart_buffer: Option<Duration>,
+
+    /// The part of the certificate's lifetime that may be removed for jittering, within 0.0 and 1.0.
+    pub autotls_cert_jitter_factor: Option<f64>,
+
+    /// Whether the `Pod` generates its own private key, and only submits a CSR for secret-operator to sign.
+    pub autotls_csr_handshake: Option<bool>,
+    pub autotls_csr_handshake_timeout: Option<Duration>,
+
+    pub cert_manager_cert_lifetime: Option<Duration>,
+
+    /// Whether the volume may be re-published for a different selector, replacing its previous contents.
+    pub allow_selector_change: Option<bool>,
+
+    /// The Unix permissions of the secret files, at most `0o777`.
+    pub file_mode: Option<u32>,
+
+    /// The numeric ID of the group that should own the secret files.
+    pub file_group: Option<u32>,
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum ParseAttributesError {
+    #[snafu(display("volume context has no {:?}", attribute::CLASS))]
+    MissingClass,
+
+    #[snafu(display("failed to parse {key:?}"))]
+    ParseScope {
+        source: ParseScopeError,
+        key: &'static str,
+    },
+
+    #[snafu(display("failed to parse {key:?}"))]
+    ParseFormat {
+        source: ParseFormatError,
+        key: &'static str,
+    },
+
+    #[snafu(display("failed to parse {key:?} as a duration"))]
+    ParseDuration {
+        source: DurationParseError,
+        key: &'static str,
+    },
+
+    #[snafu(display("failed to parse {key:?} as a number"))]
+    ParseFloat {
+        source: ParseFloatError,
+        key: &'static str,
+    },
+
+    #[snafu(display("failed to parse {key:?} as a bool"))]
+    ParseBool {
+        source: ParseBoolError,
+        key: &'static str,
+    },
+
+    #[snafu(display("failed to parse {key:?} as an integer"))]
+    ParseInt {
+        source: ParseIntError,
+        key: &'static str,
+    },
+
+    #[snafu(display("failed to parse {key:?}"))]
+    ParseKeytabGroups {
+        source: serde_json::Error,
+        key: &'static str,
+    },
+
+    #[snafu(display("{key:?} must be an octal file mode between 0000 and 0777"))]
+    InvalidFileMode { key: &'static str },
+}
+
+impl SecretVolumeAttributes {
+    pub fn new(class: impl Into<String>) -> Self {
+        Self {
+            class: class.into(),
+            ..Self::default()
+        }
+    }
+
+    /// The volume context that selects these attributes.
+    pub fn to_volume_context(&self) -> BTreeMap<String, String> {
+        use attribute::*;
+        let Self {
+            class,
+            scope,
+            format,
+            kerberos_service_names,
+            kerberos_keytab_groups,
+            kerberos_keytab_groups_combined,
+            tls_pkcs12_password,
+            tls_jks_password,
+            env_file_prefix,
+            tls_pkcs12_keystore_name,
+            tls_pkcs12_truststore_name,
+            tls_pem_cert_name,
+            tls_pem_key_name,
+            tls_pem_ca_name,
+            autotls_cert_lifetime,
+            autotls_cert_restart_buffer,
+            autotls_cert_jitter_factor,
+            autotls_csr_handshake,
+            autotls_csr_handshake_timeout,
+            cert_manager_cert_lifetime,
+            allow_selector_change,
+            file_mode,
+            file_group,
+        } = self;
+        let fmt_duration = |duration: &Duration| format!("{}ms", duration.as_millis());
+        let join = |values: Vec<String>| Some(values.join(",")).filter(|joined| !joined.is_empty());
+        [
+            (CLASS, Some(class.clone())),
+            (
+                SCOPE,
+                join(scope.iter().map(SecretScope::to_string).collect()),
+            ),
+            (FORMAT, format.map(|format| format.as_str().to_string())),
+            (KERBEROS_SERVICE_NAMES, join(kerberos_service_names.clone())),
+            (
+                KERBEROS_KEYTAB_GROUPS,
+                kerberos_keytab_groups.as_ref().map(ToString::to_string),
+            ),
+            (
+                KERBEROS_KEYTAB_GROUPS_COMBINED,
+                kerberos_keytab_groups_combined.map(|combined| combined.to_string()),
+            ),
+            (TLS_PKCS12_PASSWORD, tls_pkcs12_password.clone()),
+            (TLS_JKS_PASSWORD, tls_jks_password.clone()),
+            (ENV_FILE_PREFIX, env_file_prefix.clone()),
+            (TLS_PKCS12_KEYSTORE_NAME, tls_pkcs12_keystore_name.clone()),
+            (
+                TLS_PKCS12_TRUSTSTORE_NAME,
+                tls_pkcs12_truststore_name.clone(),
+            ),
+            (TLS_PEM_CERT_NAME, tls_pem_cert_name.clone()),
+            (TLS_PEM_KEY_NAME, tls_pem_key_name.clone()),
+            (TLS_PEM_CA_NAME, tls_pem_ca_name.clone()),
+            (
+                AUTOTLS_CERT_LIFETIME,
+                autotls_cert_lifetime.as_ref().map(fmt_duration),
+            ),
+            (
+                AUTOTLS_CERT_RESTART_BUFFER,
+                autotls_cert_restart_buffer.as_ref().map(fmt_duration),
+            ),
+            (
+                AUTOTLS_CERT_JITTER_FACTOR,
+                autotls_cert_jitter_factor.map(|factor| factor.to_string()),
+            ),
+            (
+                AUTOTLS_CSR_HANDSHAKE,
+                autotls_csr_handshake.map(|enabled| enabled.to_string()),
+            ),
+            (
+                AUTOTLS_CSR_HANDSHAKE_TIMEOUT,
+                autotls_csr_handshake_timeout.as_ref().map(fmt_duration),
+            ),
+            (
+                CERT_MANAGER_CERT_LIFETIME,
+                cert_manager_cert_lifetime.as_ref().map(fmt_duration),
+            ),
+            (
+                ALLOW_SELECTOR_CHANGE,
+                allow_selector_change.map(|allow| allow.to_string()),
+            ),
+            (FILE_MODE, file_mode.map(|mode| format!("{mode:04o}"))),
+            (FILE_GROUP, file_group.map(|gid| gid.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?)))
+        .collect()
+    }
+
+    /// Parses the attributes from a volume context.
+    pub fn from_volume_context(
+        context: &BTreeMap<String, String>,
+    ) -> Result<Self, ParseAttributesError> {
+        use attribute::*;
+        use parse_attributes_error::*;
+        let get = |key: &str| context.get(key).map(String::as_str);
+        let string = |key: &str| get(key).map(str::to_string);
+        let split = |key: &str| {
+            get(key).map_or_else(Vec::new, |values| {
+                values.split(',').map(str::to_string).collect()
+            })
+        };
+        let duration = |key: &'static str| {
+            get(key)
+                .map(|duration| duration.parse().context(ParseDurationSnafu { key }))
+                .transpose()
+        };
+        let bool = |key: &'static str| {
+            get(key)
+                .map(|flag| flag.parse().context(ParseBoolSnafu { key }))
+                .transpose()
+        };
+        Ok(Self {
+            class: string(CLASS).context(MissingClassSnafu)?,
+            scope: get(SCOPE)
+                .map(SecretScope::parse_list)
+                .transpose()
+                .context(ParseScopeSnafu { key: SCOPE })?
+                .unwrap_or_default(),
+            format: get(FORMAT)
+                .map(str::parse)
+                .transpose()
+                .context(ParseFormatSnafu { key: FORMAT })?,
+            kerberos_service_names: split(KERBEROS_SERVICE_NAMES),
+            kerberos_keytab_groups: get(KERBEROS_KEYTAB_GROUPS)
+                .map(str::parse)
+                .transpose()
+                .context(ParseKeytabGroupsSnafu {
+                    key: KERBEROS_KEYTAB_GROUPS,
+                })?,
+            kerberos_keytab_groups_combined: bool(KERBEROS_KEYTAB_GROUPS_COMBINED)?,
+            tls_pkcs12_password: string(TLS_PKCS12_PASSWORD),
+            tls_jks_password: string(TLS_JKS_PASSWORD),
+            env_file_prefix: string(ENV_FILE_PREFIX),
+            tls_pkcs12_keystore_name: string(TLS_PKCS12_KEYSTORE_NAME),
+            tls_pkcs12_truststore_name: string(TLS_PKCS12_TRUSTSTORE_NAME),
+            tls_pem_cert_name: string(TLS_PEM_CERT_NAME),
+            tls_pem_key_name: string(TLS_PEM_KEY_NAME),
+            tls_pem_ca_name: string(TLS_PEM_CA_NAME),
+            autotls_cert_lifetime: duration(AUTOTLS_CERT_LIFETIME)?,
+            autotls_cert_restart_buffer: duration(AUTOTLS_CERT_RESTART_BUFFER)?,
+            autotls_cert_jitter_factor: get(AUTOTLS_CERT_JITTER_FACTOR)
+                .map(str::parse)
+                .transpose()
+                .context(ParseFloatSnafu {
+                    key: AUTOTLS_CERT_JITTER_FACTOR,
+                })?,
+            autotls_csr_handshake: bool(AUTOTLS_CSR_HANDSHAKE)?,
+            autotls_csr_handshake_timeout: duration(AUTOTLS_CSR_HANDSHAKE_TIMEOUT)?,
+            cert_manager_cert_lifetime: duration(CERT_MANAGER_CERT_LIFETIME)?,
+            allow_selector_change: bool(ALLOW_SELECTOR_CHANGE)?,
+            file_mode: get(FILE_MODE)
+                .map(|mode| {
+                    u32::from_str_radix(mode, 8)
+                        .ok()
+                        // Special bits (such as setuid) make no sense for secrets
+                        .filter(|mode| *mode <= 0o777)
+                        .context(InvalidFileModeSnafu { key: FILE_MODE })
+                })
+                .transpose()?,
+            file_group: get(FILE_GROUP)
+                .map(str::parse)
+                .transpose()
+                .context(ParseIntSnafu { key: FILE_GROUP })?,
+        })
+    }
+}
+
+impl From<&SecretVolumeAttributes> for BTreeMap<String, String> {
+    fn from(attributes: &SecretVolumeAttributes) -> Self {
+        attributes.to_volume_context()
+    }
+}
+
+impl TryFrom<&BTreeMap<String, String>> for SecretVolumeAttributes {
+    type Error = ParseAttributesError;
+
+    fn try_from(context: &BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        Self::from_volume_context(context)
+    }
+}
+
+impl Serialize for SecretVolumeAttributes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_volume_context().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretVolumeAttributes {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let context = BTreeMap::<String, String>::deserialize(de)?;
+        Self::from_volume_context(&context).map_err(<D::Error as serde::de::Error>::custom)
+    }
+}
+
+/// Builds a `Pod` volume that mounts a secret provided by secret-operator.
+#[derive(Clone, Debug)]
+pub struct EphemeralVolumeBuilder {
+    name: String,
+    attributes: SecretVolumeAttributes,
+}
+
+impl EphemeralVolumeBuilder {
+    /// `name` is the name of the volume, which the `Pod`'s containers refer to when mounting it.
+    pub fn new(name: impl Into<String>, attributes: SecretVolumeAttributes) -> Self {
+        Self {
+            name: name.into(),
+            attributes,
+        }
+    }
+
+    pub fn build(&self) -> Volume {
+        Volume {
+            name: self.name.clone(),
+            ephemeral: Some(EphemeralVolumeSource {
+                volume_claim_template: Some(PersistentVolumeClaimTemplate {
+                    metadata: Some(ObjectMeta {
+                        annotations: Some(self.attributes.to_volume_context()),
+                        ..ObjectMeta::default()
+                    }),
+                    spec: PersistentVolumeClaimSpec {
+                        storage_class_name: Some(STORAGE_CLASS_NAME.to_string()),
+                        access_modes: Some(vec!["ReadWriteOnce".to_string()]),
+                        // Secret volumes don't consume any storage, but PVCs must request some
+                        resources: Some(VolumeResourceRequirements {
+                            requests: Some(
+                                [("storage".to_string(), Quantity("1".to_string()))].into(),
+                            ),
+                            ..VolumeResourceRequirements::default()
+                        }),
+                        ..PersistentVolumeClaimSpec::default()
+                    },
+                }),
+            }),
+            ..Volume::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use stackable_operator::time::Duration;
+
+    use super::{
+        KerberosKeytabGroups, SecretFormat, SecretScope, SecretVolumeAttributes, attribute,
+    };
+
+    #[test]
+    fn attributes_should_round_trip_through_volume_context() {
+        let attributes = SecretVolumeAttributes {
+            scope: vec![
+                SecretScope::Pod,
+                SecretScope::Service {
+                    name: "nginx".to_string(),
+                },
+            ],
+            format: Some(SecretFormat::TlsPkcs12),
+            tls_pkcs12_password: Some("changeit".to_string()),
+            autotls_cert_lifetime: Some(Duration::from_hours_unchecked(12)),
+            autotls_cert_jitter_factor: Some(0.5),
+            allow_selector_change: Some(false),
+            file_mode: Some(0o440),
+            ..SecretVolumeAttributes::new("tls")
+        };
+        let context = attributes.to_volume_context();
+        assert_eq!(
+            context,
+            BTreeMap::from(
+                [
+                    (attribute::CLASS, "tls"),
+                    (attribute::SCOPE, "pod,service=nginx"),
+                    (attribute::FORMAT, "tls-pkcs12"),
+                    (attribute::TLS_PKCS12_PASSWORD, "changeit"),
+                    (attribute::AUTOTLS_CERT_LIFETIME, "43200000ms"),
+                    (attribute::AUTOTLS_CERT_JITTER_FACTOR, "0.5"),
+                    (attribute::ALLOW_SELECTOR_CHANGE, "false"),
+                    (attribute::FILE_MODE, "0440"),
+                ]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+            )
+        );
+        assert_eq!(
+            SecretVolumeAttributes::from_volume_context(&context).unwrap(),
+            attributes
+        );
+    }
+
+    #[test]
+    fn invalid_volume_context_should_be_rejected() {
+        let context = |key: &str, value: &str| {
+            BTreeMap::from([
+                (attribute::CLASS.to_string(), "tls".to_string()),
+                (key.to_string(), value.to_string()),
+            ])
+        };
+        for (key, value) in [
+            (attribute::SCOPE, "pod,service"),
+            (attribute::FORMAT, "tls"),
+            (attribute::AUTOTLS_CERT_LIFETIME, "soon"),
+            (attribute::AUTOTLS_CSR_HANDSHAKE, "yes"),
+            (attribute::FILE_MODE, "4755"),
+            (attribute::FILE_GROUP, "-1"),
+            (attribute::KERBEROS_KEYTAB_GROUPS, r#"["nn.keytab"]"#),
+        ] {
+            assert!(
+                SecretVolumeAttributes::from_volume_context(&context(key, value)).is_err(),
+                "{key}={value}"
+            );
+        }
+        assert!(SecretVolumeAttributes::from_volume_context(&BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn keytab_groups_should_be_validated() {
+        let groups: KerberosKeytabGroups =
+            r#"{"nn.keytab": ["nn", "HTTP"], "dn.keytab": ["dn", "HTTP"]}"#
+                .parse()
+                .unwrap();
+        assert_eq!(
+            groups.iter().collect::<Vec<_>>(),
+            [
+                ("dn.keytab", &["dn".to_string(), "HTTP".to_string()][..]),
+                ("nn.keytab", &["nn".to_string(), "HTTP".to_string()][..]),
+            ]
+        );
+        assert_eq!(
+            groups.service_names().into_iter().collect::<Vec<_>>(),
+            ["HTTP", "dn", "nn"]
+        );
+        assert_eq!(
+            groups.to_string().parse::<KerberosKeytabGroups>().unwrap(),
+            groups
+        );
+
+        for invalid in [
+            r#"{"nn.keytab": []}"#,
+            r#"{"nn.keytab": ["nn"], "nn.keytab": ["HTTP"]}"#,
+            r#"{"keytab": ["nn"]}"#,
+            r#"{"krb5.conf": ["nn"]}"#,
+            r#"{"shortened-names.json": ["nn"]}"#,
+        ] {
+            assert!(
+                invalid.parse::<KerberosKeytabGroups>().is_err(),
+                "{invalid}"
+            );
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::HashSet,
+    convert::Infallible,
     ffi::{CString, NulError},
     sync::OnceLock,
 };
@@ -8,15 +9,21 @@ use byteorder::{LittleEndian, WriteBytesExt};
 use krb5::{Keyblock, Keytab, KrbContext, Principal, PrincipalUnparseOptions};
 use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
 use rand::{CryptoRng, seq::IndexedRandom};
-use snafu::{OptionExt, ResultExt, Snafu};
-use stackable_krb5_provision_keytab::ActiveDirectorySamAccountNameRules;
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
+use stackable_krb5_provision_keytab::{
+    ActiveDirectorySamAccountNameRules, FailureKind,
+    shortening::{self, NameKind, NameLengthLimits, NameMappings},
+};
 use stackable_operator::{
     k8s_openapi::api::core::v1::Secret,
     kube::{self, runtime::reflector::ObjectRef},
 };
 use stackable_secret_operator_crd_utils::SecretReference;
 
-use crate::credential_cache::{self, CredentialCache};
+use crate::{
+    credential_cache::{self, Credential, CredentialCache},
+    failure::kube_failure_kind,
+};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -71,6 +78,9 @@ pub enum Error {
     #[snafu(display("configured samAccountName prefix is longer than the requested length"))]
     SamAccountNamePrefixLongerThanRequestedLength,
 
+    #[snafu(display("failed to derive samAccountName"))]
+    DeriveSamAccountName { source: shortening::Error },
+
     #[snafu(display("failed to execute LDAP search"))]
     SearchLdap { source: ldap3::LdapError },
 
@@ -79,11 +89,26 @@ pub enum Error {
 
     #[snafu(display("the user did not have an associated kvno"))]
     KvnoNotFound,
+
+    #[snafu(display(
+        "LDAP user {distinguished_name:?} does not exist (anymore), its cached password was discarded so that it is recreated on the next attempt"
+    ))]
+    LdapUserNotFound { distinguished_name: String },
+}
+impl Error {
+    pub fn failure_kind(&self) -> FailureKind {
+        match self {
+            Error::GetLdapTlsCa { source, .. } => kube_failure_kind(source),
+            Error::PasswordCache { source } => source.failure_kind(),
+            _ => FailureKind::Unknown,
+        }
+    }
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 // Result codes are defined by https://www.rfc-editor.org/rfc/rfc4511#appendix-A.1
 const LDAP_RESULT_CODE_CONSTRAINT_VIOLATION: u32 = 19;
+const LDAP_RESULT_CODE_NO_SUCH_OBJECT: u32 = 32;
 const LDAP_RESULT_CODE_ENTRY_ALREADY_EXISTS: u32 = 68;
 
 // Error codes from https://learn.microsoft.com/en-us/windows-server/identity/ad-ds/manage/component-updates/spn-and-upn-uniqueness#symptoms.
@@ -98,6 +123,7 @@ pub struct AdAdmin<'a> {
     user_distinguished_name: String,
     schema_distinguished_name: String,
     generate_sam_account_name: Option<ActiveDirectorySamAccountNameRules>,
+    name_length_limits: NameLengthLimits,
 }
 
 impl<'a> AdAdmin<'a> {
@@ -109,6 +135,7 @@ impl<'a> AdAdmin<'a> {
         user_distinguished_name: String,
         schema_distinguished_name: String,
         generate_sam_account_name: Option<ActiveDirectorySamAccountNameRules>,
+        name_length_limits: NameLengthLimits,
     ) -> Result<AdAdmin<'a>> {
         let kube = kube::Client::try_default().await.context(KubeInitSnafu)?;
         let ldap_tls = native_tls::TlsConnector::builder()
@@ -126,9 +153,16 @@ impl<'a> AdAdmin<'a> {
         ldap.sasl_gssapi_bind(ldap_server)
             .await
             .context(LdapAuthnSnafu)?;
-        let password_cache = CredentialCache::new("AD passwords", kube, password_cache_secret)
-            .await
-            .context(PasswordCacheSnafu)?;
+        let password_cache =
+            CredentialCache::new("AD passwords", kube, password_cache_secret, None)
+                .await
+                .context(PasswordCacheSnafu)?
+                .on_rejected(|rejection| {
+                    tracing::error!(
+                        %rejection,
+                        "saving generated passwords was rejected, provisioning will keep failing until the policy is changed"
+                    )
+                });
         Ok(Self {
             ldap,
             krb,
@@ -136,16 +170,30 @@ impl<'a> AdAdmin<'a> {
             user_distinguished_name,
             schema_distinguished_name,
             generate_sam_account_name,
+            name_length_limits,
         })
     }
 
-    #[tracing::instrument(skip(self, principal, kt), fields(principal = %principal))]
+    #[tracing::instrument(skip(self, principal, kt, name_mappings), fields(principal = %principal))]
     pub async fn create_and_add_principal_to_keytab(
         &mut self,
         principal: &Principal<'_>,
         kt: &mut Keytab<'_>,
+        name_mappings: &mut NameMappings,
     ) -> Result<()> {
-        let princ_name = get_principal_data(principal)?.princ_name;
+        let principal_data = get_principal_data(principal)?;
+        let princ_name = principal_data.princ_name;
+        let derived_sam_account_name = match self.name_length_limits.sam_account_name {
+            Some(limit) => Some(
+                self.derive_sam_account_name(
+                    &principal_data.princ_name_realmless,
+                    limit,
+                    name_mappings,
+                )
+                .await?,
+            ),
+            None => None,
+        };
         let password_cache_key = princ_name.replace(['/', '@'], "__");
         let password = self
             .password_cache
@@ -153,6 +201,12 @@ impl<'a> AdAdmin<'a> {
             // we are by definition the unique writer of this key.
             .get_or_insert(&password_cache_key, |ctx| async {
                 let password = generate_ad_password(40);
+                let sam_account_name =
+                    match (derived_sam_account_name, &self.generate_sam_account_name) {
+                        (Some(derived), _) => Some(derived),
+                        (None, Some(sam_rules)) => Some(generate_sam_account_name(sam_rules)?),
+                        (None, None) => None,
+                    };
                 create_ad_user(
                     &mut self.ldap,
                     principal,
@@ -160,17 +214,28 @@ impl<'a> AdAdmin<'a> {
                     &self.user_distinguished_name,
                     &self.schema_distinguished_name,
                     ctx.cache_ref,
-                    self.generate_sam_account_name.as_ref(),
+                    sam_account_name.as_deref(),
                 )
                 .await?;
-                Ok(password.into_bytes())
+                Ok(Credential::from(password.into_bytes()))
             })
             .await
             // FIXME: What about cases where ldap.add() succeeds but not the cache write?
             .context(PasswordCacheSnafu)??;
         let password_c = CString::new(password).context(DecodePasswordSnafu)?;
 
-        let kvno = get_user_kvno(&mut self.ldap, principal, &self.user_distinguished_name).await?;
+        let kvno =
+            match get_user_kvno(&mut self.ldap, principal, &self.user_distinguished_name).await {
+                // The user was deleted after its password was cached, so the cached password is useless
+                Err(err @ Error::LdapUserNotFound { .. }) => {
+                    self.password_cache
+                        .evict(&password_cache_key)
+                        .await
+                        .context(PasswordCacheSnafu)?;
+                    return Err(err);
+                }
+                kvno => kvno?,
+            };
         if let Some(kvno) = kvno {
             principal
                 .default_salt()
@@ -192,6 +257,35 @@ impl<'a> AdAdmin<'a> {
         }
         Ok(())
     }
+
+    /// Derives the `sAMAccountName` for a principal, which is shortened to `limit` if required.
+    ///
+    /// Derived names are recorded in the password cache, so that two principals that map to the same name are
+    /// rejected, even if they were provisioned by separate requests.
+    async fn derive_sam_account_name(
+        &mut self,
+        princ_name_realmless: &str,
+        limit: usize,
+        name_mappings: &mut NameMappings,
+    ) -> Result<String> {
+        let sam_account_name = shortening::sam_account_name(princ_name_realmless, limit)
+            .context(DeriveSamAccountNameSnafu)?;
+        let recorded_name = self
+            .password_cache
+            .get_or_insert(&format!("samAccountName.{sam_account_name}"), |_| async {
+                Ok::<_, Infallible>(princ_name_realmless.as_bytes().to_vec())
+            })
+            .await
+            .context(PasswordCacheSnafu)?
+            .unwrap_or_else(|never| match never {});
+        let recorded_name = String::from_utf8_lossy(recorded_name).into_owned();
+        for full_name in [&*recorded_name, princ_name_realmless] {
+            name_mappings
+                .record(NameKind::SamAccountName, &sam_account_name, full_name)
+                .context(DeriveSamAccountNameSnafu)?;
+        }
+        Ok(sam_account_name)
+    }
 }
 
 async fn get_ldap_ca_certificate(
@@ -250,6 +344,15 @@ fn generate_username(len: usize) -> String {
     generate_random_string(len, dict)
 }
 
+fn generate_sam_account_name(sam_rules: &ActiveDirectorySamAccountNameRules) -> Result<String> {
+    let mut name = sam_rules.prefix.clone();
+    let random_part_len = usize::from(sam_rules.total_length)
+        .checked_sub(name.len())
+        .context(SamAccountNamePrefixLongerThanRequestedLengthSnafu)?;
+    name += &generate_username(random_part_len);
+    Ok(name)
+}
+
 fn encode_password_for_ad_update(password: &str) -> Vec<u8> {
     let mut pwd_utf16le = Vec::new();
     format!("\"{password}\"").encode_utf16().for_each(|word| {
@@ -267,7 +370,7 @@ async fn create_ad_user(
     user_dn_base: &str,
     schema_dn_base: &str,
     password_cache_ref: SecretReference,
-    generate_sam_account_name: Option<&ActiveDirectorySamAccountNameRules>,
+    sam_account_name: Option<&str>,
 ) -> Result<()> {
     // Flags are a subset of https://learn.microsoft.com/en-us/troubleshoot/windows-server/identity/useraccountcontrol-manipulate-account-properties
     const AD_UAC_NORMAL_ACCOUNT: u32 = 0x0200;
@@ -284,16 +387,6 @@ async fn create_ad_user(
     let principal_cn = principal_data.principal_cn;
     let princ_name_realmless = principal_data.princ_name_realmless;
 
-    let sam_account_name = generate_sam_account_name
-        .map(|sam_rules| {
-            let mut name = sam_rules.prefix.clone();
-            let random_part_len = usize::from(sam_rules.total_length)
-                .checked_sub(name.len())
-                .context(SamAccountNamePrefixLongerThanRequestedLengthSnafu)?;
-            name += &generate_username(random_part_len);
-            Ok(name)
-        })
-        .transpose()?;
     let create_user_result = ldap
         .add(
             &format!("CN={principal_cn},{user_dn_base}"),
@@ -336,7 +429,6 @@ async fn create_ad_user(
             .into_iter()
             .chain(
                 sam_account_name
-                    .as_ref()
                     .map(|san| ("samAccountName".as_bytes(), HashSet::from([san.as_bytes()]))),
             )
             .collect(),
@@ -398,14 +490,17 @@ async fn get_user_kvno(
     tracing::info!("searching for kvno using DN {distinguished_name}");
 
     // Perform search with KVNO attribute
-    let (search_results, _) = ldap
+    let search = ldap
         .search(distinguished_name, Scope::Base, "(objectClass=user)", vec![
             "msDS-KeyVersionNumber",
         ])
         .await
-        .context(SearchLdapSnafu)?
-        .success()
-        .context(SearchLdapSuccessSnafu)?;
+        .context(SearchLdapSnafu)?;
+    ensure!(
+        search.1.rc != LDAP_RESULT_CODE_NO_SUCH_OBJECT,
+        LdapUserNotFoundSnafu { distinguished_name }
+    );
+    let (search_results, _) = search.success().context(SearchLdapSuccessSnafu)?;
 
     let mut kvno = None;
 
@@ -1,18 +1,60 @@
+use std::time::Duration;
+
 use futures::{TryFuture, TryFutureExt};
 use snafu::{OptionExt, ResultExt, Snafu};
+use stackable_krb5_provision_keytab::FailureKind;
 use stackable_operator::{
-    k8s_openapi::{ByteString, api::core::v1::Secret},
-    kube::{
-        self,
-        api::{Patch, PatchParams},
-        runtime::reflector::ObjectRef,
+    k8s_openapi::{
+        ByteString,
+        api::core::v1::Secret,
+        chrono::{DateTime, SecondsFormat, Utc},
     },
+    kube::{self, runtime::reflector::ObjectRef},
+};
+use stackable_secret_operator_crd_utils::{
+    SecretReference, ownership::OwnedResourceWriter, rejection::WriteRejection,
 };
-use stackable_secret_operator_crd_utils::SecretReference;
 
-const OPERATOR_NAME: &str = "secrets.stackable.tech";
+#[cfg(feature = "failpoints")]
+use crate::failpoint;
+use crate::failure::kube_failure_kind;
+
 const FIELD_MANAGER_SCOPE: &str = "krb5-provision-keytab";
 
+/// Prefix of the annotations that record when each credential was generated, see [`annotation_name`].
+///
+/// Kubernetes only allows a single `/` in annotation names, so the key can't be nested below a
+/// `secrets.stackable.tech/` prefix.
+const CACHED_AT_ANNOTATION_PREFIX: &str = "cached-at.secrets.stackable.tech/";
+
+/// Prefix of the annotations that record when each credential expires (see [`Credential::expires_at`] and
+/// [`annotation_name`]).
+const EXPIRES_AT_ANNOTATION_PREFIX: &str = "expires-at.secrets.stackable.tech/";
+
+/// How often saving credentials is attempted before giving up, if the cache keeps being modified concurrently.
+const MAX_SAVE_ATTEMPTS: u32 = 5;
+
+/// Derives the name of the annotation under `prefix` that records a timestamp of the credential `key`.
+///
+/// The name (after the prefix) of an annotation may be at most 63 characters long, while keys may be up to 253
+/// characters long, so the name is the (hex-encoded) first 128 bits of the SHA-256 hash of the key instead.
+fn annotation_name(prefix: &str, key: &str) -> String {
+    let digest = openssl::sha::sha256(key.as_bytes());
+    let hash = digest[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("{prefix}{hash}")
+}
+
+fn cached_at_annotation(key: &str) -> String {
+    annotation_name(CACHED_AT_ANNOTATION_PREFIX, key)
+}
+
+fn expires_at_annotation(key: &str) -> String {
+    annotation_name(EXPIRES_AT_ANNOTATION_PREFIX, key)
+}
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("failed to load initial cache from {cache_ref}"))]
@@ -21,8 +63,29 @@ pub enum Error {
         cache_ref: ObjectRef<Secret>,
     },
 
-    #[snafu(display("failed to save credential {key} to {cache_ref}"))]
+    #[snafu(display("failed to save credentials {keys:?} to {cache_ref}"))]
     SaveToCache {
+        source: kube::Error,
+        keys: Vec<String>,
+        cache_ref: ObjectRef<Secret>,
+    },
+
+    #[snafu(display("failed to reload {cache_ref} after a concurrent modification"))]
+    ReloadCache {
+        source: kube::Error,
+        cache_ref: ObjectRef<Secret>,
+    },
+
+    #[snafu(display(
+        "failed to save credentials {keys:?} to {cache_ref}, since it kept being modified concurrently"
+    ))]
+    Conflict {
+        keys: Vec<String>,
+        cache_ref: ObjectRef<Secret>,
+    },
+
+    #[snafu(display("failed to evict credential {key} from {cache_ref}"))]
+    EvictFromCache {
         source: kube::Error,
         key: String,
         cache_ref: ObjectRef<Secret>,
@@ -34,23 +97,65 @@ pub enum Error {
         cache_ref: ObjectRef<Secret>,
     },
 }
+impl Error {
+    pub fn failure_kind(&self) -> FailureKind {
+        match self {
+            Error::GetInitialCache { source, .. }
+            | Error::SaveToCache { source, .. }
+            | Error::ReloadCache { source, .. }
+            | Error::EvictFromCache { source, .. } => kube_failure_kind(source),
+            Error::Conflict { .. } => FailureKind::Unavailable,
+            Error::SavedKeyNotFound { .. } => FailureKind::Unknown,
+        }
+    }
+}
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// A newly generated credential, see [`CredentialCache::get_or_insert`].
+pub struct Credential {
+    pub value: Vec<u8>,
+
+    /// The credential is regenerated once this has passed, regardless of the cache's TTL.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<Vec<u8>> for Credential {
+    fn from(value: Vec<u8>) -> Self {
+        Self {
+            value,
+            expires_at: None,
+        }
+    }
+}
+
+/// See [`CredentialCache::on_rejected`].
+type RejectionHook = dyn Fn(&WriteRejection) + Send + Sync;
+
 pub struct CredentialCache {
     name: &'static str,
     secrets: kube::Api<Secret>,
+    /// The cache is created by the administrator, so it is only ever patched, never applied or deleted
+    writer: OwnedResourceWriter,
     cache_ref: SecretReference,
+    ttl: Option<Duration>,
     current_state: Secret,
+    on_rejected: Option<Box<RejectionHook>>,
 }
 impl CredentialCache {
+    /// Loads the cache from the Secret `cache_ref`.
+    ///
+    /// If `ttl` is set, credentials are regenerated once they are older than `ttl`. Credentials of unknown age (such
+    /// as those cached before a TTL was configured) are regenerated as well.
     #[tracing::instrument(skip(kube))]
     pub async fn new(
         name: &'static str,
         kube: kube::Client,
         cache_ref: SecretReference,
+        ttl: Option<Duration>,
     ) -> Result<Self> {
-        let secrets = kube::Api::<Secret>::namespaced(kube, &cache_ref.namespace);
+        let secrets = kube::Api::<Secret>::namespaced(kube.clone(), &cache_ref.namespace);
         Ok(Self {
+            writer: OwnedResourceWriter::new(kube, FIELD_MANAGER_SCOPE),
             name,
             current_state: secrets
                 .get(&cache_ref.name)
@@ -59,25 +164,38 @@ impl CredentialCache {
                     cache_ref: &cache_ref,
                 })?,
             cache_ref,
+            ttl,
             secrets,
+            on_rejected: None,
         })
     }
 
+    /// Calls `hook` whenever saving credentials is rejected by a ResourceQuota or admission policy.
+    ///
+    /// Such saves are never retried, since they can only succeed once an administrator has changed the policy.
+    pub fn on_rejected(mut self, hook: impl Fn(&WriteRejection) + Send + Sync + 'static) -> Self {
+        self.on_rejected = Some(Box::new(hook));
+        self
+    }
+
     fn get_if_present(&self, key: &str) -> Option<&[u8]> {
         Some(&self.current_state.data.as_ref()?.get(key)?.0)
     }
 
-    /// Gets the credential named `key` from the cache, or calls `mk_value` if it cannot be found.
+    /// Gets the credential named `key` from the cache, or calls `mk_value` if it cannot be found (or has expired).
     ///
     /// # Concurrency
-    /// There is no locking imposed by `CredentialCache`, in the face of a race condition
-    /// `mk_value` must either fail or be idempotent (returning exactly the same value for all concurrent calls
-    /// for the same key).
+    /// The credential is only saved if the cache has not been modified since it was last loaded. Otherwise the cache is
+    /// reloaded, and if another writer has saved `key` in the meantime then its credential is returned instead of the
+    /// one returned by `mk_value`.
+    ///
+    /// There is no locking imposed by `CredentialCache`, so `mk_value` may still be called concurrently for the same
+    /// key. It must either fail or tolerate its credential being discarded.
     ///
     /// # Errors
     /// There is no negative caching, the result of a failed call to `mk_value` will not be saved.
     #[tracing::instrument(skip(self, mk_value), fields(name = self.name, cache_ref = %self.cache_ref))]
-    pub async fn get_or_insert<F: FnOnce(Ctx) -> Fut, Fut: TryFuture<Ok = Vec<u8>>>(
+    pub async fn get_or_insert<F: FnOnce(Ctx) -> Fut, Fut: TryFuture<Ok = Credential>>(
         &mut self,
         key: &str,
         mk_value: F,
@@ -85,63 +203,790 @@ impl CredentialCache {
     where
         Fut::Error: std::error::Error + 'static,
     {
-        // This should be an if let Some(...) but for some reason Rust considers that borrow to conflict with
-        // us modifying self.current_state in the other branch
-        if self.get_if_present(key).is_some() {
-            tracing::info!("credential found in cache, reusing...");
-            Ok(Ok(self
-                .get_if_present(key)
-                .expect("key was just confirmed to exist in cache")))
-        } else {
-            tracing::info!("credential not found in cache, generating...");
+        let mut values = self.get_or_insert_many([(key, mk_value)]).await?;
+        Ok(values.pop().expect("one value must be returned per key"))
+    }
+
+    /// Gets the credential named by each key from the cache, or calls its `mk_value` if it cannot be found (or has
+    /// expired).
+    ///
+    /// All newly generated credentials are saved using a single patch, and the values are returned in the same order
+    /// as `entries`. Keys must be unique.
+    ///
+    /// See [`Self::get_or_insert`] for the concurrency and error handling caveats.
+    #[tracing::instrument(skip(self, entries), fields(name = self.name, cache_ref = %self.cache_ref))]
+    pub async fn get_or_insert_many<'k, F: FnOnce(Ctx) -> Fut, Fut: TryFuture<Ok = Credential>>(
+        &mut self,
+        entries: impl IntoIterator<Item = (&'k str, F)>,
+    ) -> Result<Vec<Result<&[u8], Fut::Error>>>
+    where
+        Fut::Error: std::error::Error + 'static,
+    {
+        let now = Utc::now();
+        // Generation failures are returned as-is, all other keys are looked up once the cache has been saved
+        let mut results = Vec::<(&str, Option<Fut::Error>)>::new();
+        let mut generated = Vec::<(&str, Credential)>::new();
+        for (key, mk_value) in entries {
+            let is_present = self.get_if_present(key).is_some();
+            if is_present && !is_expired(&self.current_state, key, self.ttl, now) {
+                tracing::info!(key, "credential found in cache, reusing...");
+                results.push((key, None));
+                continue;
+            }
+            if is_present {
+                tracing::info!(key, "cached credential has expired, regenerating...");
+            } else {
+                tracing::info!(key, "credential not found in cache, generating...");
+            }
             match mk_value(Ctx {
                 cache_ref: self.cache_ref.clone(),
             })
             .into_future()
             .await
             {
-                Ok(value) => {
-                    tracing::info!("generated credential successfully, saving...");
-                    self.current_state = self
-                        .secrets
-                        .patch(
-                            &self.cache_ref.name,
-                            &PatchParams {
-                                field_manager: Some(format!(
-                                    "{OPERATOR_NAME}/{FIELD_MANAGER_SCOPE}"
-                                )),
-                                ..Default::default()
-                            },
-                            &Patch::Merge(Secret {
-                                data: Some([(key.to_string(), ByteString(value))].into()),
-                                ..Secret::default()
-                            }),
-                        )
-                        .await
-                        .context(SaveToCacheSnafu {
-                            key,
-                            cache_ref: &self.cache_ref,
-                        })?;
-                    Ok(Ok(self.get_if_present(key).context(
-                        SavedKeyNotFoundSnafu {
-                            key,
-                            cache_ref: &self.cache_ref,
-                        },
-                    )?))
+                Ok(credential) => {
+                    tracing::info!(
+                        key,
+                        credential.expires_at = credential
+                            .expires_at
+                            .map(|expires_at| expires_at.to_rfc3339()),
+                        "generated credential successfully"
+                    );
+                    generated.push((key, credential));
+                    results.push((key, None));
                 }
                 Err(err) => {
                     tracing::warn!(
+                        key,
                         error = &err as &dyn std::error::Error,
                         "failed to generate credential, discarding..."
                     );
-                    Ok(Err(err))
+                    results.push((key, Some(err)));
+                }
+            }
+        }
+
+        if !generated.is_empty() {
+            self.save(generated, now).await?;
+        }
+
+        let mut values = Vec::with_capacity(results.len());
+        for (key, err) in results {
+            values.push(match err {
+                Some(err) => Err(err),
+                None => Ok(self.get_if_present(key).context(SavedKeyNotFoundSnafu {
+                    key,
+                    cache_ref: &self.cache_ref,
+                })?),
+            });
+        }
+        Ok(values)
+    }
+
+    /// Saves the `generated` credentials in a single patch, except for those that another writer saves a fresh credential
+    /// for first.
+    ///
+    /// The patch is conditional on the `resourceVersion` of the last loaded state, so concurrent modifications are
+    /// rejected with `409 Conflict` rather than overwritten.
+    async fn save(
+        &mut self,
+        mut generated: Vec<(&str, Credential)>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        for attempt in 1..=MAX_SAVE_ATTEMPTS {
+            let keys = generated
+                .iter()
+                .map(|(key, _)| key.to_string())
+                .collect::<Vec<_>>();
+            tracing::info!(?keys, attempt, "saving generated credentials...");
+            let mut data = serde_json::Map::new();
+            let mut annotations = serde_json::Map::new();
+            for (key, credential) in &generated {
+                data.insert(
+                    key.to_string(),
+                    serde_json::json!(ByteString(credential.value.clone())),
+                );
+                // Removes any stale timestamp if there is no TTL (anymore)
+                annotations.insert(
+                    cached_at_annotation(key),
+                    serde_json::json!(
+                        self.ttl
+                            .map(|_| now.to_rfc3339_opts(SecondsFormat::Secs, true))
+                    ),
+                );
+                annotations.insert(
+                    expires_at_annotation(key),
+                    serde_json::json!(credential.expires_at.map(|expires_at| {
+                        expires_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+                    })),
+                );
+            }
+            let patch = serde_json::json!({
+                "data": data,
+                "metadata": {
+                    "resourceVersion": self.current_state.metadata.resource_version,
+                    "annotations": annotations,
+                },
+            });
+            #[cfg(feature = "failpoints")]
+            failpoint::eval(failpoint::CREDENTIAL_CACHE_SAVE_BEFORE_PATCH)
+                .await
+                .map_err(|err| kube::Error::Service(Box::new(err)))
+                .context(SaveToCacheSnafu {
+                    keys: keys.clone(),
+                    cache_ref: &self.cache_ref,
+                })?;
+            match self
+                .writer
+                .merge_patch(&self.cache_ref.namespace, &self.cache_ref.name, &patch)
+                .await
+            {
+                Ok(secret) => {
+                    self.current_state = secret;
+                    return Ok(());
+                }
+                Err(kube::Error::Api(response)) if response.code == 409 => {
+                    tracing::info!("cache was modified concurrently, reloading...");
+                    self.current_state =
+                        self.secrets
+                            .get(&self.cache_ref.name)
+                            .await
+                            .context(ReloadCacheSnafu {
+                                cache_ref: &self.cache_ref,
+                            })?;
+                    generated.retain(|(key, _)| {
+                        let saved_concurrently = self.get_if_present(key).is_some()
+                            && !is_expired(&self.current_state, key, self.ttl, now);
+                        if saved_concurrently {
+                            tracing::info!(
+                                key,
+                                "credential was saved concurrently, discarding ours..."
+                            );
+                        }
+                        !saved_concurrently
+                    });
+                    if generated.is_empty() {
+                        return Ok(());
+                    }
+                }
+                Err(err) => {
+                    if let (Some(rejection), Some(on_rejected)) =
+                        (WriteRejection::from_error(&err), &self.on_rejected)
+                    {
+                        on_rejected(&rejection);
+                    }
+                    return Err(err).context(SaveToCacheSnafu {
+                        keys,
+                        cache_ref: &self.cache_ref,
+                    });
                 }
             }
         }
+        ConflictSnafu {
+            keys: generated
+                .iter()
+                .map(|(key, _)| key.to_string())
+                .collect::<Vec<_>>(),
+            cache_ref: &self.cache_ref,
+        }
+        .fail()
+    }
+
+    /// Removes the credential named `key` from the cache, so that the next [`Self::get_or_insert`] generates a new one.
+    ///
+    /// Evicting a key that is not cached is not an error.
+    #[tracing::instrument(skip(self), fields(name = self.name, cache_ref = %self.cache_ref))]
+    pub async fn evict(&mut self, key: &str) -> Result<()> {
+        tracing::info!("evicting credential from cache");
+        self.current_state = self
+            .writer
+            .merge_patch(
+                &self.cache_ref.namespace,
+                &self.cache_ref.name,
+                &serde_json::json!({
+                    "data": { key: null },
+                    "metadata": {
+                        "annotations": {
+                            cached_at_annotation(key): null,
+                            expires_at_annotation(key): null,
+                        },
+                    },
+                }),
+            )
+            .await
+            .context(EvictFromCacheSnafu {
+                key,
+                cache_ref: &self.cache_ref,
+            })?;
+        Ok(())
+    }
+}
+
+/// Whether the credential `key` in `cache` has passed its expiry time, or is older than `ttl`.
+fn is_expired(cache: &Secret, key: &str, ttl: Option<Duration>, now: DateTime<Utc>) -> bool {
+    if annotation_time(cache, &expires_at_annotation(key))
+        .is_some_and(|expires_at| expires_at <= now)
+    {
+        return true;
+    }
+    let Some(ttl) = ttl else {
+        return false;
+    };
+    match annotation_time(cache, &cached_at_annotation(key)) {
+        // to_std fails for negative ages, so credentials written by a node whose clock is ahead are still fresh
+        Some(cached_at) => (now - cached_at).to_std().is_ok_and(|age| age > ttl),
+        None => true,
     }
 }
 
+fn annotation_time(cache: &Secret, annotation: &str) -> Option<DateTime<Utc>> {
+    let time = cache.metadata.annotations.as_ref()?.get(annotation)?;
+    Some(DateTime::parse_from_rfc3339(time).ok()?.with_timezone(&Utc))
+}
+
 /// Information that may be useful for generating error messages in get_or_insert handlers
 pub struct Ctx {
     pub cache_ref: SecretReference,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use serde_json::{Value, json};
+    use stackable_krb5_provision_keytab::FailureKind;
+    use stackable_operator::{
+        k8s_openapi::{
+            api::core::v1::Secret,
+            apimachinery::pkg::apis::meta::v1::ObjectMeta,
+            chrono::{self, DateTime, Utc},
+        },
+        kube,
+    };
+    use stackable_secret_operator_crd_utils::{SecretReference, rejection::WriteRejection};
+
+    use super::{
+        Credential, CredentialCache, cached_at_annotation, expires_at_annotation, is_expired,
+    };
+
+    /// A fake API server that stores a single `Secret`, and applies merge patches to it.
+    ///
+    /// Patches that specify a stale `resourceVersion` are rejected with `409 Conflict`, like the real API server.
+    #[derive(Clone)]
+    struct FakeApiServer {
+        secret: Arc<Mutex<Value>>,
+        /// The query strings of all patch requests, including rejected ones
+        patch_queries: Arc<Mutex<Vec<String>>>,
+        /// If set, patch requests fail with this status code and message
+        patch_rejection: Arc<Mutex<Option<(u16, &'static str)>>>,
+        /// Patches applied (as if by another writer) just before the next patch request is handled
+        concurrent_patches: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl FakeApiServer {
+        fn new() -> Self {
+            Self {
+                secret: Arc::new(Mutex::new(json!({
+                    "apiVersion": "v1",
+                    "kind": "Secret",
+                    "metadata": {
+                        "name": "cache",
+                        "namespace": "default",
+                        "resourceVersion": "1",
+                    },
+                }))),
+                patch_queries: Arc::default(),
+                patch_rejection: Arc::default(),
+                concurrent_patches: Arc::default(),
+            }
+        }
+
+        fn apply_patch(&self, patch: Value) -> Result<(), Value> {
+            let mut secret = self.secret.lock().unwrap();
+            let resource_version = &secret["metadata"]["resourceVersion"];
+            let expected = &patch["metadata"]["resourceVersion"];
+            if !expected.is_null() && expected != resource_version {
+                return Err(json!({
+                    "apiVersion": "v1",
+                    "kind": "Status",
+                    "status": "Failure",
+                    "message": format!(
+                        "Operation cannot be fulfilled on secrets \"cache\": the object has been modified; expected {expected}, found {resource_version}"
+                    ),
+                    "reason": "Conflict",
+                    "code": 409,
+                }));
+            }
+            let next_version = resource_version.as_str().unwrap().parse::<u64>().unwrap() + 1;
+            merge_patch(&mut secret, patch);
+            secret["metadata"]["resourceVersion"] = next_version.to_string().into();
+            Ok(())
+        }
+
+        fn client(&self) -> kube::Client {
+            let server = self.clone();
+            kube::Client::new(
+                tower::service_fn(move |req| {
+                    let server = server.clone();
+                    async move { server.handle(req).await }
+                }),
+                "default",
+            )
+        }
+
+        async fn handle(
+            &self,
+            req: http::Request<kube::client::Body>,
+        ) -> Result<http::Response<kube::client::Body>, Infallible> {
+            let (parts, body) = req.into_parts();
+            match parts.method {
+                http::Method::GET => {}
+                http::Method::PATCH => {
+                    self.patch_queries
+                        .lock()
+                        .unwrap()
+                        .push(parts.uri.query().unwrap_or_default().to_string());
+                    if let Some((code, message)) = *self.patch_rejection.lock().unwrap() {
+                        let status = json!({
+                            "apiVersion": "v1",
+                            "kind": "Status",
+                            "status": "Failure",
+                            "message": message,
+                            "reason": "Forbidden",
+                            "code": code,
+                        });
+                        let mut response =
+                            http::Response::new(serde_json::to_vec(&status).unwrap().into());
+                        *response.status_mut() = http::StatusCode::from_u16(code).unwrap();
+                        return Ok(response);
+                    }
+                    let concurrent_patches =
+                        std::mem::take(&mut *self.concurrent_patches.lock().unwrap());
+                    for patch in concurrent_patches {
+                        self.apply_patch(patch).unwrap();
+                    }
+                    let patch = body.collect_bytes().await.unwrap();
+                    if let Err(status) = self.apply_patch(serde_json::from_slice(&patch).unwrap()) {
+                        let mut response =
+                            http::Response::new(serde_json::to_vec(&status).unwrap().into());
+                        *response.status_mut() = http::StatusCode::CONFLICT;
+                        return Ok(response);
+                    }
+                }
+                method => panic!("unexpected {method} request"),
+            }
+            let secret = serde_json::to_vec(&*self.secret.lock().unwrap()).unwrap();
+            Ok(http::Response::new(secret.into()))
+        }
+    }
+
+    /// Applies a JSON merge patch (RFC 7386), which removes fields that are set to `null`.
+    fn merge_patch(target: &mut Value, patch: Value) {
+        let Value::Object(patch) = patch else {
+            *target = patch;
+            return;
+        };
+        if !target.is_object() {
+            *target = json!({});
+        }
+        let target = target.as_object_mut().unwrap();
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge_patch(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+
+    fn generate(
+        value: &'static str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> impl FnOnce(super::Ctx) -> std::future::Ready<Result<Credential, Infallible>> {
+        move |_| {
+            std::future::ready(Ok(Credential {
+                value: value.as_bytes().to_vec(),
+                expires_at,
+            }))
+        }
+    }
+
+    async fn cache(server: &FakeApiServer, ttl: Option<Duration>) -> CredentialCache {
+        CredentialCache::new(
+            "test",
+            server.client(),
+            SecretReference {
+                namespace: "default".to_string(),
+                name: "cache".to_string(),
+            },
+            ttl,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn credentials_should_be_regenerated_once_they_expire() {
+        let server = FakeApiServer::new();
+        let mut cache = cache(&server, None).await;
+        let past = Some(Utc::now() - chrono::Duration::minutes(1));
+        let future = Some(Utc::now() + chrono::Duration::hours(1));
+
+        let value = cache
+            .get_or_insert("foo", generate("old", past))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"old");
+        let value = cache
+            .get_or_insert("foo", generate("new", future))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"new");
+        let value = cache
+            .get_or_insert("foo", generate("newer", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"new");
+
+        // Credentials without an expiry time are kept forever
+        let value = cache
+            .get_or_insert("bar", generate("old", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"old");
+        assert_eq!(
+            server.secret.lock().unwrap()["metadata"]["annotations"]
+                .get(expires_at_annotation("bar")),
+            None
+        );
+        let value = cache
+            .get_or_insert("bar", generate("new", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"old");
+    }
+
+    #[tokio::test]
+    async fn evicted_credentials_should_be_regenerated() {
+        let server = FakeApiServer::new();
+        let mut cache = cache(&server, Some(Duration::from_secs(3600))).await;
+
+        let value = cache
+            .get_or_insert("foo", generate("old", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"old");
+        let value = cache
+            .get_or_insert("foo", generate("new", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"old");
+
+        cache.evict("foo").await.unwrap();
+        {
+            let secret = server.secret.lock().unwrap();
+            assert_eq!(secret["data"].get("foo"), None);
+            assert_eq!(
+                secret["metadata"]["annotations"].get(cached_at_annotation("foo")),
+                None
+            );
+        }
+        let value = cache
+            .get_or_insert("foo", generate("new", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"new");
+
+        // Evicting a key that was never cached is a no-op
+        cache.evict("bar").await.unwrap();
+        let value = cache
+            .get_or_insert("foo", generate("newer", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"new");
+
+        // Keys are owned by the same field manager, regardless of how they were last modified
+        let patch_queries = server.patch_queries.lock().unwrap();
+        assert_eq!(patch_queries.len(), 4);
+        assert!(
+            patch_queries[0].contains("fieldManager="),
+            "{patch_queries:?}"
+        );
+        assert!(
+            patch_queries.iter().all(|query| query == &patch_queries[0]),
+            "{patch_queries:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn long_keys_should_be_cached() {
+        let server = FakeApiServer::new();
+        let mut cache = cache(&server, Some(Duration::from_secs(3600))).await;
+        let key = "k".repeat(253);
+        let past = Some(Utc::now() - chrono::Duration::minutes(1));
+
+        let value = cache
+            .get_or_insert(&key, generate("old", past))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"old");
+        {
+            let secret = server.secret.lock().unwrap();
+            let annotations = secret["metadata"]["annotations"].as_object().unwrap();
+            assert_eq!(annotations.len(), 2);
+            for name in annotations.keys() {
+                let (_, name) = name.split_once('/').unwrap();
+                assert!(name.len() <= 63, "{name:?} is too long");
+            }
+        }
+        // The expiry time must still be found under the derived name
+        let value = cache
+            .get_or_insert(&key, generate("new", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"new");
+        let value = cache
+            .get_or_insert(&key, generate("newer", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"new");
+    }
+
+    #[tokio::test]
+    async fn missing_credentials_should_be_saved_in_a_single_patch() {
+        let server = FakeApiServer::new();
+        let mut cache = cache(&server, Some(Duration::from_secs(3600))).await;
+        cache
+            .get_or_insert("cached", generate("old", None))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let generate_or_fail = |value: Option<&'static str>| {
+            move |_| {
+                std::future::ready(
+                    value
+                        .map(|value| Credential::from(value.as_bytes().to_vec()))
+                        .ok_or_else(|| std::io::Error::other("generator failed")),
+                )
+            }
+        };
+        let values = cache
+            .get_or_insert_many([
+                ("foo", generate_or_fail(Some("foo"))),
+                ("cached", generate_or_fail(Some("new"))),
+                ("broken", generate_or_fail(None)),
+                ("bar", generate_or_fail(Some("bar"))),
+                ("baz", generate_or_fail(Some("baz"))),
+            ])
+            .await
+            .unwrap();
+        let values = values
+            .into_iter()
+            .map(|value| value.ok())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                Some(&b"foo"[..]),
+                Some(b"old"),
+                None,
+                Some(b"bar"),
+                Some(b"baz"),
+            ]
+        );
+        // One patch for the initial credential, and one for all that were generated afterwards
+        assert_eq!(server.patch_queries.lock().unwrap().len(), 2);
+        {
+            let secret = server.secret.lock().unwrap();
+            assert_eq!(secret["data"].get("broken"), None);
+            assert!(
+                secret["metadata"]["annotations"]
+                    .get(cached_at_annotation("baz"))
+                    .is_some()
+            );
+        }
+
+        // Nothing needs to be saved if every credential is cached already
+        let values = cache
+            .get_or_insert_many([
+                ("foo", generate_or_fail(None)),
+                ("bar", generate_or_fail(None)),
+            ])
+            .await
+            .unwrap();
+        assert!(values.iter().all(Result::is_ok));
+        assert_eq!(server.patch_queries.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_saves_should_be_classified() {
+        let server = FakeApiServer::new();
+        let rejections = Arc::new(Mutex::new(Vec::new()));
+        let mut cache = cache(&server, None).await.on_rejected({
+            let rejections = rejections.clone();
+            move |rejection| rejections.lock().unwrap().push(rejection.clone())
+        });
+
+        *server.patch_rejection.lock().unwrap() = Some((
+            403,
+            r#"secrets "cache" is forbidden: exceeded quota: secret-size, requested: requests.storage=1, used: requests.storage=10, limited: requests.storage=10"#,
+        ));
+        let err = cache
+            .get_or_insert("foo", generate("value", None))
+            .await
+            .unwrap_err();
+        assert_eq!(err.failure_kind(), FailureKind::FailedPrecondition);
+        assert_eq!(
+            rejections.lock().unwrap().pop(),
+            Some(WriteRejection::QuotaExceeded {
+                quota: "secret-size".to_string()
+            })
+        );
+
+        *server.patch_rejection.lock().unwrap() = Some((
+            403,
+            r#"admission webhook "secrets.policy.example.com" denied the request: secrets must be labelled"#,
+        ));
+        let err = cache
+            .get_or_insert("foo", generate("value", None))
+            .await
+            .unwrap_err();
+        assert_eq!(err.failure_kind(), FailureKind::FailedPrecondition);
+        assert_eq!(
+            rejections.lock().unwrap().pop(),
+            Some(WriteRejection::DeniedByWebhook {
+                webhook: "secrets.policy.example.com".to_string()
+            })
+        );
+
+        *server.patch_rejection.lock().unwrap() = Some((
+            403,
+            r#"secrets "cache" is forbidden: User "system:serviceaccount:stackable:secret-operator" cannot patch resource "secrets""#,
+        ));
+        let err = cache
+            .get_or_insert("foo", generate("value", None))
+            .await
+            .unwrap_err();
+        assert_eq!(err.failure_kind(), FailureKind::PermissionDenied);
+        // Missing RBAC permissions are not a cluster policy
+        assert_eq!(rejections.lock().unwrap().pop(), None);
+
+        // None of the rejected saves were retried
+        assert_eq!(server.patch_queries.lock().unwrap().len(), 3);
+
+        // Nothing was saved, so the credential is generated again once the cache can be written to
+        *server.patch_rejection.lock().unwrap() = None;
+        let value = cache
+            .get_or_insert("foo", generate("value", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"value");
+    }
+
+    #[tokio::test]
+    async fn conflicting_saves_should_be_retried() {
+        let server = FakeApiServer::new();
+        let mut cache = cache(&server, None).await;
+
+        // Another writer saves an unrelated credential, so ours is saved once the cache has been reloaded
+        server
+            .concurrent_patches
+            .lock()
+            .unwrap()
+            .push(json!({ "data": { "bar": "b3RoZXI=" } }));
+        let value = cache
+            .get_or_insert("foo", generate("ours", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"ours");
+        {
+            let secret = server.secret.lock().unwrap();
+            assert_eq!(secret["data"]["foo"], "b3Vycw==");
+            assert_eq!(secret["data"]["bar"], "b3RoZXI=");
+        }
+        assert_eq!(server.patch_queries.lock().unwrap().len(), 2);
+
+        // Another writer saves the same credential, so theirs is kept rather than overwritten
+        server
+            .concurrent_patches
+            .lock()
+            .unwrap()
+            .push(json!({ "data": { "baz": "dGhlaXJz" } }));
+        let value = cache
+            .get_or_insert("baz", generate("ours", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"theirs");
+        assert_eq!(server.secret.lock().unwrap()["data"]["baz"], "dGhlaXJz");
+        assert_eq!(server.patch_queries.lock().unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[tokio::test]
+    async fn fault_before_save_should_regenerate_credential() {
+        let server = FakeApiServer::new();
+        let mut cache = cache(&server, None).await;
+        crate::failpoint::configure_for_current_thread(
+            "credential_cache.save.before_patch=1*return",
+        )
+        .unwrap();
+
+        let err = cache
+            .get_or_insert("foo", generate("lost", None))
+            .await
+            .unwrap_err();
+        // The operator retries provisioning for Unavailable
+        assert_eq!(err.failure_kind(), FailureKind::Unavailable);
+        assert!(server.patch_queries.lock().unwrap().is_empty());
+
+        // The lost credential was never saved, so the retry generates a new one
+        let value = cache
+            .get_or_insert("foo", generate("new", None))
+            .await
+            .unwrap();
+        assert_eq!(value.unwrap(), b"new");
+        assert_eq!(server.secret.lock().unwrap()["data"]["foo"], "bmV3");
+    }
+
+    #[test]
+    fn credentials_should_expire_after_ttl() {
+        let cache = Secret {
+            metadata: ObjectMeta {
+                annotations: Some(
+                    [(
+                        cached_at_annotation("foo"),
+                        "2025-01-01T00:00:00Z".to_string(),
+                    )]
+                    .into(),
+                ),
+                ..ObjectMeta::default()
+            },
+            ..Secret::default()
+        };
+        let ttl = Some(Duration::from_secs(3600));
+        let now = |time| {
+            DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        assert!(!is_expired(&cache, "foo", ttl, now("2025-01-01T00:30:00Z")));
+        assert!(is_expired(&cache, "foo", ttl, now("2025-01-01T01:00:01Z")));
+        // Written by a node whose clock is ahead
+        assert!(!is_expired(&cache, "foo", ttl, now("2024-12-31T23:59:00Z")));
+        // Age unknown
+        assert!(is_expired(&cache, "bar", ttl, now("2025-01-01T00:30:00Z")));
+        // Never expires without a TTL
+        assert!(!is_expired(
+            &cache,
+            "foo",
+            None,
+            now("2030-01-01T00:00:00Z")
+        ));
+    }
+}
@@ -0,0 +1,13 @@
+//! Named points at which faults can be injected, to test how the keytab provisioning recovers from them.
+//!
+//! Failpoints only exist if the provisioner is built with the `failpoints` feature, and are compiled out entirely
+//! otherwise. They are configured by setting
+//! [`FAILPOINTS_ENV`](stackable_secret_operator_crd_utils::failpoint::FAILPOINTS_ENV) (which is inherited from the
+//! operator), see [`stackable_secret_operator_crd_utils::failpoint`] for the format.
+
+#[cfg(test)]
+pub use stackable_secret_operator_crd_utils::failpoint::configure_for_current_thread;
+pub use stackable_secret_operator_crd_utils::failpoint::{configure_from_env, eval};
+
+/// Before saving newly generated credentials to their cache.
+pub const CREDENTIAL_CACHE_SAVE_BEFORE_PATCH: &str = "credential_cache.save.before_patch";
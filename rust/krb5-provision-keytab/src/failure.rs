@@ -0,0 +1,98 @@
+//! Classifies libkrb5, libkadm5, and Kubernetes errors, so that the operator can tell why provisioning failed without
+//! having to parse the error message.
+
+use krb5::kadm5;
+use stackable_krb5_provision_keytab::FailureKind;
+use stackable_operator::kube;
+use stackable_secret_operator_crd_utils::rejection::WriteRejection;
+
+pub fn krb5_failure_kind(err: &krb5::Error) -> FailureKind {
+    match err {
+        krb5::Error::Krb5 { reason } => krb5_code_failure_kind(reason.code.0),
+        _ => FailureKind::Unknown,
+    }
+}
+
+pub fn kadm5_failure_kind(err: &kadm5::Error) -> FailureKind {
+    use kadm5::error_code::*;
+    match err.code.0 {
+        UNK_PRINC => FailureKind::NotFound,
+        DUP => FailureKind::AlreadyExists,
+        AUTH_ADD | AUTH_DELETE | AUTH_EXTRACT | AUTH_GET | AUTH_INSUFFICIENT => {
+            FailureKind::PermissionDenied
+        }
+        RPC_ERROR => FailureKind::Unavailable,
+        // libkadm5 passes through libkrb5 errors, such as when the admin credentials are rejected
+        code => i32::try_from(code).map_or(FailureKind::Unknown, krb5_code_failure_kind),
+    }
+}
+
+pub fn kube_failure_kind(err: &kube::Error) -> FailureKind {
+    if WriteRejection::from_error(err).is_some() {
+        // Rejected by a ResourceQuota or admission policy, which an administrator must change first
+        return FailureKind::FailedPrecondition;
+    }
+    match err {
+        kube::Error::Api(response) if response.code == 403 => FailureKind::PermissionDenied,
+        kube::Error::Api(_) => FailureKind::Unknown,
+        _ => FailureKind::Unavailable,
+    }
+}
+
+fn krb5_code_failure_kind(code: i32) -> FailureKind {
+    use krb5::error_code::*;
+    match code {
+        KDC_ERR_C_PRINCIPAL_UNKNOWN
+        | KDC_ERR_CLIENT_REVOKED
+        | KDC_ERR_KEY_EXP
+        | KDC_ERR_PREAUTH_FAILED
+        | KRB_AP_ERR_BAD_INTEGRITY
+        // The admin keytab has no key for the admin principal
+        | KT_NOTFOUND
+        | KT_KVNONOTFOUND => FailureKind::Unauthenticated,
+        KDC_UNREACH | REALM_CANT_RESOLVE => FailureKind::Unavailable,
+        KRB_AP_ERR_SKEW => FailureKind::FailedPrecondition,
+        _ => FailureKind::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use krb5::kadm5::{self, error_code::kadm5_ret_t};
+    use stackable_krb5_provision_keytab::FailureKind;
+
+    use super::kadm5_failure_kind;
+
+    fn kind(code: i64) -> FailureKind {
+        kadm5_failure_kind(&kadm5::Error::from_code(kadm5_ret_t(code)))
+    }
+
+    #[test]
+    fn kadm5_errors_should_be_classified() {
+        assert_eq!(kind(kadm5::error_code::UNK_PRINC), FailureKind::NotFound);
+        assert_eq!(kind(kadm5::error_code::DUP), FailureKind::AlreadyExists);
+        assert_eq!(
+            kind(kadm5::error_code::AUTH_ADD),
+            FailureKind::PermissionDenied
+        );
+        assert_eq!(kind(kadm5::error_code::RPC_ERROR), FailureKind::Unavailable);
+        assert_eq!(kind(kadm5::error_code::BAD_PASSWORD), FailureKind::Unknown);
+    }
+
+    #[test]
+    fn krb5_errors_from_kadm5_should_be_classified() {
+        let kind = |code: i32| kind(code.into());
+        assert_eq!(
+            kind(krb5::error_code::KDC_ERR_PREAUTH_FAILED),
+            FailureKind::Unauthenticated
+        );
+        assert_eq!(
+            kind(krb5::error_code::KDC_UNREACH),
+            FailureKind::Unavailable
+        );
+        assert_eq!(
+            kind(krb5::error_code::KRB_AP_ERR_SKEW),
+            FailureKind::FailedPrecondition
+        );
+    }
+}
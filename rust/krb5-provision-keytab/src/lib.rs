@@ -6,10 +6,13 @@ use std::{
 };
 
 use serde::{Deserialize, Serialize};
+use shortening::{NameLengthLimits, NameMappings};
 use snafu::{ResultExt, Snafu};
 use stackable_secret_operator_crd_utils::SecretReference;
 use tokio::{io::AsyncWriteExt, process::Command};
 
+pub mod shortening;
+
 #[derive(Serialize, Deserialize)]
 pub struct Request {
     pub admin_keytab_path: PathBuf,
@@ -17,7 +20,29 @@ pub struct Request {
     pub pod_keytab_path: PathBuf,
     pub principals: Vec<PrincipalRequest>,
     pub admin_backend: AdminBackend,
+    /// Limits for the names that the provisioner derives itself (such as `sAMAccountName`s).
+    #[serde(default)]
+    pub name_length_limits: NameLengthLimits,
+    /// Purge old keys of the provisioned principals, retaining only the newest key versions.
+    ///
+    /// This must only be set once every volume that may still hold an older key has been refreshed. Only supported by
+    /// [`AdminBackend::Mit`], and only for principals that were created by the provisioner.
+    #[serde(default)]
+    pub purge_old_keys: Option<KeyRetention>,
+}
+/// How many key versions of a principal to retain when purging old keys.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyRetention {
+    /// The number of newest key versions to retain. The current key is always retained, even if this is 0.
+    pub keep_kvnos: u32,
+}
+impl Default for KeyRetention {
+    fn default() -> Self {
+        Self { keep_kvnos: 3 }
+    }
 }
+
 #[derive(Serialize, Deserialize)]
 pub struct PrincipalRequest {
     pub name: String,
@@ -41,7 +66,38 @@ pub struct ActiveDirectorySamAccountNameRules {
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct Response {}
+pub struct Response {
+    /// The names that were shortened to fit [`Request::name_length_limits`].
+    #[serde(default)]
+    pub name_mappings: NameMappings,
+}
+
+/// Why provisioning failed, so that the caller can report an appropriate status.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailureKind {
+    /// A principal that was expected to exist does not.
+    NotFound,
+    /// A principal that was about to be created already exists.
+    AlreadyExists,
+    /// The KDC rejected the admin credentials.
+    Unauthenticated,
+    /// The admin principal is not allowed to perform the operation.
+    PermissionDenied,
+    /// The KDC or admin server could not be reached, or the credential cache kept being modified concurrently.
+    Unavailable,
+    /// The environment must be fixed before retrying, such as when the clocks of the operator and the KDC are out of
+    /// sync, or when a ResourceQuota or admission policy rejects saving a generated credential.
+    FailedPrecondition,
+    #[default]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Failure {
+    pub msg: String,
+    #[serde(default)]
+    pub kind: FailureKind,
+}
 
 #[derive(Snafu, Debug)]
 pub enum Error {
@@ -58,7 +114,7 @@ pub enum Error {
     WaitProvisioner { source: std::io::Error },
 
     #[snafu(display("failed to provision keytab: {msg}"))]
-    RunProvisioner { msg: String },
+    RunProvisioner { msg: String, kind: FailureKind },
 
     #[snafu(display("failed to write request"))]
     WriteRequest { source: std::io::Error },
@@ -89,7 +145,7 @@ pub async fn provision_keytab(krb5_config_path: &Path, req: &Request) -> Result<
         .wait_with_output()
         .await
         .context(WaitProvisionerSnafu)?;
-    serde_json::from_slice::<Result<Response, String>>(&output.stdout)
+    serde_json::from_slice::<Result<Response, Failure>>(&output.stdout)
         .context(DeserializeResponseSnafu)?
-        .map_err(|msg| Error::RunProvisioner { msg })
+        .map_err(|Failure { msg, kind }| Error::RunProvisioner { msg, kind })
 }
@@ -6,11 +6,16 @@ use std::{
 
 use krb5::{Keyblock, Keytab};
 use snafu::{ResultExt, Snafu};
-use stackable_krb5_provision_keytab::{AdminBackend, Request, Response};
+use stackable_krb5_provision_keytab::{
+    AdminBackend, Failure, FailureKind, Request, Response, shortening::NameMappings,
+};
 use tracing::info;
 
 mod active_directory;
 mod credential_cache;
+#[cfg(feature = "failpoints")]
+mod failpoint;
+mod failure;
 mod mit;
 
 #[derive(Debug, Snafu)]
@@ -67,8 +72,25 @@ enum Error {
     RemoveDummyFromKeytab { source: krb5::Error },
 }
 
+impl Error {
+    fn failure_kind(&self) -> FailureKind {
+        match self {
+            Error::KrbInit { source }
+            | Error::ResolvePodKeytab { source }
+            | Error::AddDummyToKeytab { source }
+            | Error::RemoveDummyFromKeytab { source } => failure::krb5_failure_kind(source),
+            Error::MitAdminInit { source } | Error::PreparePrincipalMit { source, .. } => {
+                source.failure_kind()
+            }
+            Error::ActiveDirectoryInit { source }
+            | Error::PreparePrincipalActiveDirectory { source, .. } => source.failure_kind(),
+            _ => FailureKind::Unknown,
+        }
+    }
+}
+
 enum AdminConnection<'a> {
-    Mit(mit::MitAdmin<'a>),
+    Mit(mit::MitAdmin<krb5::kadm5::ServerHandle<'a>>),
     ActiveDirectory(active_directory::AdAdmin<'a>),
 }
 
@@ -104,6 +126,7 @@ async fn run() -> Result<Response, Error> {
                 user_distinguished_name,
                 schema_distinguished_name,
                 generate_sam_account_name,
+                req.name_length_limits,
             )
             .await
             .context(ActiveDirectoryInitSnafu)?,
@@ -126,20 +149,22 @@ async fn run() -> Result<Response, Error> {
             principal: dummy_principal_name,
         })?;
     let dummy_kvno = 0;
+    let dummy_enctype = 0;
     kt.add(
         &dummy_principal,
         dummy_kvno,
         // keyblock len must be >0, or kt.add() will always fail
-        &Keyblock::new(&krb, 0, 1)
+        &Keyblock::new(&krb, dummy_enctype, 1)
             .context(AddDummyToKeytabSnafu)?
             .as_ref(),
     )
     .context(AddDummyToKeytabSnafu)?;
     // Remove dummy key once we have forced the keytab to be created,
     // to avoid tools trying to use it to authenticate
-    kt.remove(&dummy_principal, dummy_kvno)
+    kt.remove(&dummy_principal, dummy_kvno, dummy_enctype)
         .context(RemoveDummyFromKeytabSnafu)?;
 
+    let mut name_mappings = NameMappings::default();
     for princ_req in req.principals {
         let princ = krb
             .parse_principal_name(
@@ -150,15 +175,15 @@ async fn run() -> Result<Response, Error> {
             })?;
         match &mut admin {
             AdminConnection::Mit(mit) => mit
-                .create_and_add_principal_to_keytab(&princ, &mut kt)
+                .create_and_add_principal_to_keytab(&princ, &mut kt, req.purge_old_keys)
                 .context(PreparePrincipalMitSnafu { principal: &princ })?,
             AdminConnection::ActiveDirectory(ad) => ad
-                .create_and_add_principal_to_keytab(&princ, &mut kt)
+                .create_and_add_principal_to_keytab(&princ, &mut kt, &mut name_mappings)
                 .await
                 .context(PreparePrincipalActiveDirectorySnafu { principal: &princ })?,
         }
     }
-    Ok(Response {})
+    Ok(Response { name_mappings })
 }
 
 struct Report<E> {
@@ -190,7 +215,13 @@ async fn main() {
     tracing_subscriber::fmt()
         .with_writer(std::io::stderr)
         .init();
-    let res = run().await.map_err(|err| Report::from(err).to_string());
+    // Failpoints are only enabled by test setups, which should fail loudly if they are invalid
+    #[cfg(feature = "failpoints")]
+    failpoint::configure_from_env().expect("failpoints must be valid");
+    let res = run().await.map_err(|err| Failure {
+        kind: err.failure_kind(),
+        msg: Report::from(err).to_string(),
+    });
     println!("{}", serde_json::to_string_pretty(&res).unwrap());
     std::process::exit(res.is_ok().into());
 }
@@ -0,0 +1,360 @@
+//! An in-memory stand-in for kadmind, for testing code that is generic over [`Kadmin`] without running a KDC.
+
+use std::{
+    cell::{RefCell, RefMut},
+    collections::{BTreeMap, VecDeque},
+    ffi::CStr,
+};
+
+use krb5::{
+    Keyblock, KrbContext, KrbData, Principal, enctype,
+    kadm5::{self, KeyDataRef, KeySaltRef, salttype},
+};
+
+use super::{Kadmin, PrincipalKeys};
+
+/// A fake kadmin server that stores principals in memory.
+///
+/// KVNOs follow MIT's behaviour: new principals start at KVNO 1, and every [`FakeKadmin::randkey`] bumps it by one.
+/// Keys are derived deterministically from the principal name and KVNO, and are reported with the principal's default
+/// salt.
+pub struct FakeKadmin<'a> {
+    krb: &'a KrbContext,
+    state: RefCell<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Principal name -> retained KVNOs, in ascending order
+    principals: BTreeMap<String, Vec<u32>>,
+    /// (Principal name, key) -> value
+    strings: BTreeMap<(String, String), String>,
+    calls: usize,
+    injected_errors: VecDeque<i64>,
+    fail_after: Option<(usize, i64)>,
+}
+
+fn error(code: i64) -> kadm5::Error {
+    kadm5::Error::from_code(kadm5::error_code::kadm5_ret_t(code))
+}
+
+impl<'a> FakeKadmin<'a> {
+    pub fn new(krb: &'a KrbContext) -> Self {
+        Self {
+            krb,
+            state: RefCell::default(),
+        }
+    }
+
+    /// Fail every call after the first `calls` with `code`, such as [`kadm5::error_code::RPC_ERROR`] to simulate a
+    /// lost connection.
+    pub fn fail_after(self, calls: usize, code: i64) -> Self {
+        self.state.borrow_mut().fail_after = Some((calls, code));
+        self
+    }
+
+    /// Fail the next call with `code`, regardless of what it is.
+    ///
+    /// Multiple injected errors are returned in the order that they were injected.
+    pub fn fail_next(&self, code: i64) {
+        self.state.borrow_mut().injected_errors.push_back(code);
+    }
+
+    /// The number of calls made so far, including failed ones.
+    pub fn calls(&self) -> usize {
+        self.state.borrow().calls
+    }
+
+    /// The KVNOs currently retained for `principal`, or [`None`] if it does not exist.
+    pub fn kvnos(&self, principal: &Principal) -> Option<Vec<u32>> {
+        self.state
+            .borrow()
+            .principals
+            .get(&principal.to_string())
+            .cloned()
+    }
+
+    /// Generate a new key for `principal`, optionally retaining the old keys (like `kadmin randkey -keepold`).
+    pub fn randkey(&self, principal: &Principal, keep_old: bool) -> Result<(), kadm5::Error> {
+        let mut state = self.begin_call()?;
+        let kvnos = state
+            .principals
+            .get_mut(&principal.to_string())
+            .ok_or_else(|| error(kadm5::error_code::UNK_PRINC))?;
+        let new_kvno = kvnos.last().map_or(1, |kvno| kvno + 1);
+        if !keep_old {
+            kvnos.clear();
+        }
+        kvnos.push(new_kvno);
+        Ok(())
+    }
+
+    fn begin_call(&self) -> Result<RefMut<'_, State>, kadm5::Error> {
+        let mut state = self.state.borrow_mut();
+        state.calls += 1;
+        if let Some(code) = state.injected_errors.pop_front() {
+            return Err(error(code));
+        }
+        match state.fail_after {
+            Some((calls, code)) if state.calls > calls => Err(error(code)),
+            _ => Ok(state),
+        }
+    }
+
+    fn derive_key(&self, principal: &str, kvno: u32) -> Keyblock<'a> {
+        let mut keyblock = Keyblock::new(self.krb, enctype::AES256_CTS_HMAC_SHA1_96, 32)
+            .expect("failed to allocate fake keyblock");
+        let seed = principal
+            .bytes()
+            .chain(kvno.to_be_bytes())
+            .collect::<Vec<_>>();
+        for (i, (byte, seed_byte)) in keyblock
+            .contents_mut()
+            .expect("failed to access fake keyblock")
+            .iter_mut()
+            .zip(seed.iter().cycle())
+            .enumerate()
+        {
+            *byte = seed_byte.wrapping_add(i as u8);
+        }
+        keyblock
+    }
+}
+
+impl<'a> Kadmin for FakeKadmin<'a> {
+    type Keys<'k>
+        = FakeKeys<'a>
+    where
+        Self: 'k;
+
+    fn create_principal(&self, principal: &Principal) -> Result<(), kadm5::Error> {
+        let mut state = self.begin_call()?;
+        let name = principal.to_string();
+        if state.principals.contains_key(&name) {
+            return Err(error(kadm5::error_code::DUP));
+        }
+        state.principals.insert(name, vec![1]);
+        Ok(())
+    }
+
+    fn get_principal_keys(&self, principal: &Principal) -> Result<Self::Keys<'_>, kadm5::Error> {
+        let name = principal.to_string();
+        let kvnos = self
+            .begin_call()?
+            .principals
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| error(kadm5::error_code::UNK_PRINC))?;
+        let salt = principal
+            .default_salt()
+            .and_then(|salt| KrbData::from_bytes(self.krb, salt.as_bytes()))
+            .expect("failed to compute fake salt");
+        Ok(FakeKeys {
+            keys: kvnos
+                .into_iter()
+                .map(|kvno| (kvno, self.derive_key(&name, kvno)))
+                .collect(),
+            salt,
+        })
+    }
+
+    fn delete_principal(&self, principal: &Principal) -> Result<(), kadm5::Error> {
+        let mut state = self.begin_call()?;
+        let name = principal.to_string();
+        state.strings.retain(|(princ, _), _| *princ != name);
+        state
+            .principals
+            .remove(&name)
+            .map(drop)
+            .ok_or_else(|| error(kadm5::error_code::UNK_PRINC))
+    }
+
+    fn get_string(
+        &self,
+        principal: &Principal,
+        key: &CStr,
+    ) -> Result<Option<String>, kadm5::Error> {
+        let state = self.begin_call()?;
+        let name = principal.to_string();
+        if !state.principals.contains_key(&name) {
+            return Err(error(kadm5::error_code::UNK_PRINC));
+        }
+        Ok(state
+            .strings
+            .get(&(name, key.to_string_lossy().into_owned()))
+            .cloned())
+    }
+
+    fn set_string(
+        &self,
+        principal: &Principal,
+        key: &CStr,
+        value: &CStr,
+    ) -> Result<(), kadm5::Error> {
+        let mut state = self.begin_call()?;
+        let name = principal.to_string();
+        if !state.principals.contains_key(&name) {
+            return Err(error(kadm5::error_code::UNK_PRINC));
+        }
+        state.strings.insert(
+            (name, key.to_string_lossy().into_owned()),
+            value.to_string_lossy().into_owned(),
+        );
+        Ok(())
+    }
+
+    fn purge_keys(&self, principal: &Principal, keep_kvnos: u32) -> Result<(), kadm5::Error> {
+        let mut state = self.begin_call()?;
+        let kvnos = state
+            .principals
+            .get_mut(&principal.to_string())
+            .ok_or_else(|| error(kadm5::error_code::UNK_PRINC))?;
+        // Like ServerHandle::purge_keys, the current key is always retained
+        let current_kvno = kvnos.last().copied().unwrap_or(1);
+        let oldest_kept_kvno = (current_kvno + 1).saturating_sub(keep_kvnos.max(1));
+        kvnos.retain(|&kvno| kvno >= oldest_kept_kvno);
+        Ok(())
+    }
+}
+
+/// The keys returned by [`FakeKadmin::get_principal_keys`].
+pub struct FakeKeys<'a> {
+    keys: Vec<(u32, Keyblock<'a>)>,
+    salt: KrbData<'a>,
+}
+impl PrincipalKeys for FakeKeys<'_> {
+    fn keys(&self) -> impl Iterator<Item = KeyDataRef<'_>> {
+        self.keys.iter().map(|(kvno, keyblock)| KeyDataRef {
+            kvno: *kvno,
+            keyblock: keyblock.as_ref(),
+            salt: KeySaltRef {
+                salttype: salttype::NORMAL,
+                data: self.salt.as_bytes(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use krb5::{
+        KrbContext,
+        kadm5::{error_code, salttype},
+    };
+
+    use super::FakeKadmin;
+    use crate::mit::{Kadmin, PrincipalKeys};
+
+    #[test]
+    fn kvnos_follow_mit_semantics() {
+        let krb = KrbContext::new().unwrap();
+        let kadmin = FakeKadmin::new(&krb);
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+
+        let err = kadmin.randkey(&princ, false).unwrap_err();
+        assert_eq!(err.code.0, error_code::UNK_PRINC);
+        kadmin.create_principal(&princ).unwrap();
+        assert_eq!(kadmin.kvnos(&princ), Some(vec![1]));
+        let err = kadmin.create_principal(&princ).unwrap_err();
+        assert_eq!(err.code.0, error_code::DUP);
+
+        kadmin.randkey(&princ, true).unwrap();
+        kadmin.randkey(&princ, true).unwrap();
+        assert_eq!(kadmin.kvnos(&princ), Some(vec![1, 2, 3]));
+        kadmin.randkey(&princ, false).unwrap();
+        assert_eq!(kadmin.kvnos(&princ), Some(vec![4]));
+
+        kadmin.randkey(&princ, true).unwrap();
+        kadmin.randkey(&princ, true).unwrap();
+        kadmin.purge_keys(&princ, 2).unwrap();
+        assert_eq!(kadmin.kvnos(&princ), Some(vec![5, 6]));
+        kadmin.purge_keys(&princ, 0).unwrap();
+        assert_eq!(kadmin.kvnos(&princ), Some(vec![6]));
+
+        kadmin.delete_principal(&princ).unwrap();
+        assert_eq!(kadmin.kvnos(&princ), None);
+        let err = kadmin.get_principal_keys(&princ).err().unwrap();
+        assert_eq!(err.code.0, error_code::UNK_PRINC);
+    }
+
+    #[test]
+    fn keys_are_deterministic() {
+        let krb = KrbContext::new().unwrap();
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let key_contents = |kadmin: &FakeKadmin| {
+            kadmin
+                .get_principal_keys(&princ)
+                .unwrap()
+                .keys
+                .into_iter()
+                .map(|(kvno, mut keyblock)| (kvno, keyblock.contents_mut().unwrap().to_vec()))
+                .collect::<Vec<_>>()
+        };
+
+        let first = FakeKadmin::new(&krb);
+        first.create_principal(&princ).unwrap();
+        first.randkey(&princ, true).unwrap();
+        let second = FakeKadmin::new(&krb);
+        second.create_principal(&princ).unwrap();
+        second.randkey(&princ, true).unwrap();
+
+        let keys = key_contents(&first);
+        assert_eq!(keys, key_contents(&second));
+        assert_eq!(keys.len(), 2);
+        assert_ne!(keys[0].1, keys[1].1);
+        assert_eq!(first.get_principal_keys(&princ).unwrap().keys().count(), 2);
+    }
+
+    #[test]
+    fn keys_use_default_salt() {
+        let krb = KrbContext::new().unwrap();
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let kadmin = FakeKadmin::new(&krb);
+        kadmin.create_principal(&princ).unwrap();
+
+        let keys = kadmin.get_principal_keys(&princ).unwrap();
+        let key = keys.keys().next().unwrap();
+        assert_eq!(key.salt.salttype, salttype::NORMAL);
+        assert_eq!(key.salt.data, princ.default_salt().unwrap().as_bytes());
+    }
+
+    #[test]
+    fn strings_belong_to_principal() {
+        let krb = KrbContext::new().unwrap();
+        let kadmin = FakeKadmin::new(&krb);
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+
+        let err = kadmin.set_string(&princ, c"key", c"value").unwrap_err();
+        assert_eq!(err.code.0, error_code::UNK_PRINC);
+        kadmin.create_principal(&princ).unwrap();
+        assert_eq!(kadmin.get_string(&princ, c"key").unwrap(), None);
+        kadmin.set_string(&princ, c"key", c"value").unwrap();
+        assert_eq!(
+            kadmin.get_string(&princ, c"key").unwrap().as_deref(),
+            Some("value")
+        );
+
+        // Recreating a principal must not resurrect its old strings
+        kadmin.delete_principal(&princ).unwrap();
+        kadmin.create_principal(&princ).unwrap();
+        assert_eq!(kadmin.get_string(&princ, c"key").unwrap(), None);
+    }
+
+    #[test]
+    fn injected_errors() {
+        let krb = KrbContext::new().unwrap();
+        let kadmin = FakeKadmin::new(&krb).fail_after(3, error_code::RPC_ERROR);
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+
+        kadmin.fail_next(error_code::DUP);
+        let err = kadmin.create_principal(&princ).unwrap_err();
+        assert_eq!(err.code.0, error_code::DUP);
+        assert_eq!(kadmin.kvnos(&princ), None);
+        kadmin.create_principal(&princ).unwrap();
+        kadmin.randkey(&princ, true).unwrap();
+        let err = kadmin.randkey(&princ, true).unwrap_err();
+        assert_eq!(err.code.0, error_code::RPC_ERROR);
+        assert_eq!(kadmin.calls(), 4);
+        assert_eq!(kadmin.kvnos(&princ), Some(vec![1, 2]));
+    }
+}
@@ -0,0 +1,399 @@
+use std::ffi::CStr;
+
+use krb5::{
+    Keytab, Principal,
+    kadm5::{self, KeyDataRef},
+};
+use snafu::{ResultExt, Snafu};
+use stackable_krb5_provision_keytab::{FailureKind, KeyRetention};
+
+use crate::failure::{kadm5_failure_kind, krb5_failure_kind};
+
+#[cfg(test)]
+pub mod fake;
+
+/// The string attribute that marks principals as created by the provisioner, see [`MitAdmin::purge_old_keys`].
+const MANAGED_BY_ATTRIBUTE: &CStr = c"stackable.tech/managed-by";
+const MANAGED_BY_VALUE: &CStr = c"secret-operator";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to initialize kadm5 server handle"))]
+    KadminInit { source: kadm5::Error },
+
+    #[snafu(display("failed to create principal"))]
+    CreatePrincipal { source: kadm5::Error },
+
+    #[snafu(display("failed to mark principal as managed"))]
+    MarkManaged { source: kadm5::Error },
+
+    #[snafu(display("failed to check whether principal is managed"))]
+    GetManagedMarker { source: kadm5::Error },
+
+    #[snafu(display(
+        "refusing to purge old keys of a principal that was not created by the provisioner"
+    ))]
+    UnmanagedPrincipal,
+
+    #[snafu(display("failed to purge old keys"))]
+    PurgeKeys { source: kadm5::Error },
+
+    #[snafu(display("failed to principal's keys"))]
+    GetPrincipalKeys { source: kadm5::Error },
+
+    #[snafu(display("failed to add key to keytab"))]
+    AddToKeytab { source: krb5::Error },
+}
+impl Error {
+    pub fn failure_kind(&self) -> FailureKind {
+        match self {
+            Error::KadminInit { source }
+            | Error::CreatePrincipal { source }
+            | Error::MarkManaged { source }
+            | Error::GetManagedMarker { source }
+            | Error::PurgeKeys { source }
+            | Error::GetPrincipalKeys { source } => kadm5_failure_kind(source),
+            Error::UnmanagedPrincipal => FailureKind::FailedPrecondition,
+            Error::AddToKeytab { source } => krb5_failure_kind(source),
+        }
+    }
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The kadm5 operations required to provision keytabs.
+///
+/// This is implemented by [`kadm5::ServerHandle`], which talks to a real kadmind. Unit tests can use
+/// [`fake::FakeKadmin`] instead, which does not require a KDC.
+pub trait Kadmin {
+    type Keys<'k>: PrincipalKeys
+    where
+        Self: 'k;
+
+    /// Create a new principal, failing with [`kadm5::error_code::DUP`] if it already exists.
+    fn create_principal(&self, principal: &Principal) -> Result<(), kadm5::Error>;
+
+    /// Get all keys of a principal, regardless of KVNO.
+    fn get_principal_keys(&self, principal: &Principal) -> Result<Self::Keys<'_>, kadm5::Error>;
+
+    /// Delete a principal, failing with [`kadm5::error_code::UNK_PRINC`] if it does not exist.
+    fn delete_principal(&self, principal: &Principal) -> Result<(), kadm5::Error>;
+
+    /// Get a string attribute of a principal, or [`None`] if it is not set.
+    fn get_string(&self, principal: &Principal, key: &CStr)
+    -> Result<Option<String>, kadm5::Error>;
+
+    /// Set a string attribute of a principal.
+    fn set_string(
+        &self,
+        principal: &Principal,
+        key: &CStr,
+        value: &CStr,
+    ) -> Result<(), kadm5::Error>;
+
+    /// Remove all but the newest `keep_kvnos` key versions of a principal (always retaining the current key).
+    fn purge_keys(&self, principal: &Principal, keep_kvnos: u32) -> Result<(), kadm5::Error>;
+}
+
+/// A set of keys returned by [`Kadmin::get_principal_keys`].
+pub trait PrincipalKeys {
+    fn keys(&self) -> impl Iterator<Item = KeyDataRef<'_>>;
+}
+
+impl Kadmin for kadm5::ServerHandle<'_> {
+    type Keys<'k>
+        = kadm5::KeyDataVec<'k>
+    where
+        Self: 'k;
+
+    fn create_principal(&self, principal: &Principal) -> Result<(), kadm5::Error> {
+        kadm5::ServerHandle::create_principal(self, principal)
+    }
+
+    fn get_principal_keys(&self, principal: &Principal) -> Result<Self::Keys<'_>, kadm5::Error> {
+        kadm5::ServerHandle::get_principal_keys(self, principal, kadm5::KVNO_ALL)
+    }
+
+    fn delete_principal(&self, principal: &Principal) -> Result<(), kadm5::Error> {
+        kadm5::ServerHandle::delete_principal(self, principal)
+    }
+
+    fn get_string(
+        &self,
+        principal: &Principal,
+        key: &CStr,
+    ) -> Result<Option<String>, kadm5::Error> {
+        kadm5::ServerHandle::get_string(self, principal, key)
+    }
+
+    fn set_string(
+        &self,
+        principal: &Principal,
+        key: &CStr,
+        value: &CStr,
+    ) -> Result<(), kadm5::Error> {
+        kadm5::ServerHandle::set_string(self, principal, key, value)
+    }
+
+    fn purge_keys(&self, principal: &Principal, keep_kvnos: u32) -> Result<(), kadm5::Error> {
+        kadm5::ServerHandle::purge_keys(self, principal, keep_kvnos)
+    }
+}
+
+impl PrincipalKeys for kadm5::KeyDataVec<'_> {
+    fn keys(&self) -> impl Iterator<Item = KeyDataRef<'_>> {
+        kadm5::KeyDataVec::keys(self)
+    }
+}
+
+pub struct MitAdmin<K> {
+    kadmin: K,
+}
+impl<'a> MitAdmin<kadm5::ServerHandle<'a>> {
+    pub fn connect(
+        krb: &'a krb5::KrbContext,
+        admin_principal_name: &CStr,
+        admin_keytab_path: &CStr,
+    ) -> Result<Self> {
+        Ok(Self {
+            kadmin: kadm5::ServerHandle::new(
+                krb,
+                admin_principal_name,
+                None,
+                &krb5::kadm5::Credential::ServiceKey {
+                    keytab: admin_keytab_path.to_owned(),
+                },
+                &kadm5::ConfigParams::default(),
+            )
+            .context(KadminInitSnafu)?,
+        })
+    }
+}
+impl<K: Kadmin> MitAdmin<K> {
+    /// Creates `principal` (unless it already exists) and adds all of its keys to `kt`.
+    ///
+    /// If `purge_old_keys` is set, old keys are purged (see [`Self::purge_old_keys`]) before the keys are added, so
+    /// that `kt` only contains the retained keys.
+    #[tracing::instrument(skip(self, principal, kt), fields(principal = %principal))]
+    pub fn create_and_add_principal_to_keytab(
+        &self,
+        principal: &Principal,
+        kt: &mut Keytab,
+        purge_old_keys: Option<KeyRetention>,
+    ) -> Result<()> {
+        tracing::info!("creating principal");
+        match self.kadmin.create_principal(principal) {
+            Err(err) if err.is_duplicate() => {
+                tracing::info!("principal already exists, reusing")
+            }
+            res => {
+                res.context(CreatePrincipalSnafu)?;
+                self.kadmin
+                    .set_string(principal, MANAGED_BY_ATTRIBUTE, MANAGED_BY_VALUE)
+                    .context(MarkManagedSnafu)?;
+            }
+        }
+        if let Some(retention) = purge_old_keys {
+            self.purge_old_keys(principal, retention)?;
+        }
+        let keys = self
+            .kadmin
+            .get_principal_keys(principal)
+            .context(GetPrincipalKeysSnafu)?;
+        for key in keys.keys() {
+            kt.add(principal, key.kvno, &key.keyblock)
+                .context(AddToKeytabSnafu)?;
+        }
+        Ok(())
+    }
+
+    /// Removes all but the newest [`KeyRetention::keep_kvnos`] key versions of `principal`.
+    ///
+    /// Principals that were not created by the provisioner (and marked as such) are refused, since other keytabs
+    /// that we don't know about may still rely on their old keys.
+    #[tracing::instrument(skip(self, principal), fields(principal = %principal))]
+    pub fn purge_old_keys(&self, principal: &Principal, retention: KeyRetention) -> Result<()> {
+        let managed_by = self
+            .kadmin
+            .get_string(principal, MANAGED_BY_ATTRIBUTE)
+            .context(GetManagedMarkerSnafu)?;
+        if managed_by.as_deref() != MANAGED_BY_VALUE.to_str().ok() {
+            return UnmanagedPrincipalSnafu.fail();
+        }
+        tracing::info!(keep_kvnos = retention.keep_kvnos, "purging old keys");
+        self.kadmin
+            .purge_keys(principal, retention.keep_kvnos)
+            .context(PurgeKeysSnafu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use krb5::{Keytab, KrbContext, kadm5};
+    use stackable_krb5_provision_keytab::KeyRetention;
+
+    use super::{Kadmin, MANAGED_BY_ATTRIBUTE, MitAdmin, fake::FakeKadmin};
+
+    fn memory_keytab<'a>(krb: &'a KrbContext, name: &str) -> Keytab<'a> {
+        Keytab::resolve(krb, &CString::new(format!("MEMORY:{name}")).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn provisions_new_principal() {
+        let krb = KrbContext::new().unwrap();
+        let admin = MitAdmin {
+            kadmin: FakeKadmin::new(&krb),
+        };
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut kt = memory_keytab(&krb, "provisions_new_principal");
+        admin
+            .create_and_add_principal_to_keytab(&princ, &mut kt, None)
+            .unwrap();
+        assert_eq!(admin.kadmin.kvnos(&princ), Some(vec![1]));
+        assert_eq!(
+            admin
+                .kadmin
+                .get_string(&princ, MANAGED_BY_ATTRIBUTE)
+                .unwrap()
+                .as_deref(),
+            Some("secret-operator")
+        );
+    }
+
+    #[test]
+    fn reuses_existing_principal() {
+        let krb = KrbContext::new().unwrap();
+        let admin = MitAdmin {
+            kadmin: FakeKadmin::new(&krb),
+        };
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        // Simulate a principal that has already been provisioned and rotated by someone else
+        admin.kadmin.create_principal(&princ).unwrap();
+        admin.kadmin.randkey(&princ, true).unwrap();
+        let mut kt = memory_keytab(&krb, "reuses_existing_principal");
+        admin
+            .create_and_add_principal_to_keytab(&princ, &mut kt, None)
+            .unwrap();
+        assert_eq!(admin.kadmin.kvnos(&princ), Some(vec![1, 2]));
+        // Reusing the principal must not rotate its keys, or claim it as managed
+        assert_eq!(admin.kadmin.calls(), 4);
+        assert_eq!(
+            admin
+                .kadmin
+                .get_string(&princ, MANAGED_BY_ATTRIBUTE)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn purges_old_keys_of_managed_principals() {
+        let krb = KrbContext::new().unwrap();
+        let admin = MitAdmin {
+            kadmin: FakeKadmin::new(&krb),
+        };
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut kt = memory_keytab(&krb, "purges_old_keys_of_managed_principals");
+        admin
+            .create_and_add_principal_to_keytab(&princ, &mut kt, None)
+            .unwrap();
+        for _ in 0..4 {
+            admin.kadmin.randkey(&princ, true).unwrap();
+        }
+        assert_eq!(admin.kadmin.kvnos(&princ), Some(vec![1, 2, 3, 4, 5]));
+
+        let mut kt = memory_keytab(&krb, "purges_old_keys_of_managed_principals-purged");
+        admin
+            .create_and_add_principal_to_keytab(&princ, &mut kt, Some(KeyRetention::default()))
+            .unwrap();
+        assert_eq!(admin.kadmin.kvnos(&princ), Some(vec![3, 4, 5]));
+        // The keytab only receives the retained keys
+        for kvno in 1..=5 {
+            let entry = kt.get_entry(&princ, kvno, 0).unwrap();
+            assert_eq!(entry.is_some(), kvno >= 3, "kvno {kvno}");
+        }
+
+        admin
+            .purge_old_keys(&princ, KeyRetention { keep_kvnos: 0 })
+            .unwrap();
+        assert_eq!(admin.kadmin.kvnos(&princ), Some(vec![5]));
+    }
+
+    #[test]
+    fn refuses_to_purge_unmanaged_principals() {
+        let krb = KrbContext::new().unwrap();
+        let admin = MitAdmin {
+            kadmin: FakeKadmin::new(&krb),
+        };
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        // Created by someone else, so other keytabs may still rely on the old keys
+        admin.kadmin.create_principal(&princ).unwrap();
+        admin.kadmin.randkey(&princ, true).unwrap();
+        admin
+            .kadmin
+            .set_string(&princ, MANAGED_BY_ATTRIBUTE, c"someone-else")
+            .unwrap();
+        let mut kt = memory_keytab(&krb, "refuses_to_purge_unmanaged_principals");
+        let err = admin
+            .create_and_add_principal_to_keytab(
+                &princ,
+                &mut kt,
+                Some(KeyRetention { keep_kvnos: 1 }),
+            )
+            .unwrap_err();
+        assert!(matches!(&err, super::Error::UnmanagedPrincipal), "{err:?}");
+        assert_eq!(admin.kadmin.kvnos(&princ), Some(vec![1, 2]));
+
+        let unmarked = krb.parse_principal_name(c"HTTP/bar@EXAMPLE.COM").unwrap();
+        admin.kadmin.create_principal(&unmarked).unwrap();
+        admin.kadmin.randkey(&unmarked, true).unwrap();
+        let err = admin
+            .purge_old_keys(&unmarked, KeyRetention::default())
+            .unwrap_err();
+        assert!(matches!(&err, super::Error::UnmanagedPrincipal), "{err:?}");
+        assert_eq!(admin.kadmin.kvnos(&unmarked), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn propagates_rpc_failures() {
+        let krb = KrbContext::new().unwrap();
+        let admin = MitAdmin {
+            // Creating and marking the principal succeeds
+            kadmin: FakeKadmin::new(&krb).fail_after(2, kadm5::error_code::RPC_ERROR),
+        };
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut kt = memory_keytab(&krb, "propagates_rpc_failures");
+        let err = admin
+            .create_and_add_principal_to_keytab(&princ, &mut kt, None)
+            .unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                super::Error::GetPrincipalKeys { source } if source.code.0 == kadm5::error_code::RPC_ERROR
+            ),
+            "{err:?}"
+        );
+        // The principal was still created before the failure
+        assert_eq!(admin.kadmin.kvnos(&princ), Some(vec![1]));
+    }
+
+    #[test]
+    fn injected_errors_take_precedence() {
+        let krb = KrbContext::new().unwrap();
+        let admin = MitAdmin {
+            kadmin: FakeKadmin::new(&krb),
+        };
+        admin.kadmin.fail_next(kadm5::error_code::UNK_PRINC);
+        let princ = krb.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut kt = memory_keytab(&krb, "injected_errors_take_precedence");
+        let err = admin
+            .create_and_add_principal_to_keytab(&princ, &mut kt, None)
+            .unwrap_err();
+        assert!(
+            matches!(&err, super::Error::CreatePrincipal { .. }),
+            "{err:?}"
+        );
+        assert_eq!(admin.kadmin.kvnos(&princ), None);
+    }
+}
@@ -0,0 +1,407 @@
+//! Deterministically shortens names that are derived from Pods and Services, but that must fit into length-limited
+//! fields (such as DNS labels or Active Directory's `sAMAccountName`).
+//!
+//! Names that are too long are truncated, and suffixed with a hash of the full name. The same name always gets the same
+//! short name, so repeated provisioning is stable without consulting the [`NameMappings`] of earlier runs. The mappings
+//! are still recorded, so that the full names can be recovered, and so that collisions are detected rather than
+//! silently sharing one short name between two principals.
+
+use std::{borrow::Cow, collections::BTreeMap, fmt::Display};
+
+use openssl::sha::Sha256;
+use serde::{Deserialize, Serialize};
+use snafu::{Snafu, ensure};
+
+/// The number of base32 characters of the hash that is appended to shortened names.
+pub const HASH_LEN: usize = 6;
+
+/// Active Directory rejects `sAMAccountName`s that are longer than this.
+pub const SAM_ACCOUNT_NAME_MAX_LEN: usize = 20;
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display(
+        "the length limit of {kind} ({limit}) must be at least {min} to fit the hash of shortened names",
+        min = HASH_LEN + 2
+    ))]
+    LimitTooShort { kind: NameKind, limit: usize },
+
+    #[snafu(display(
+        "the length limit of {kind} ({limit}) is longer than the maximum of {SAM_ACCOUNT_NAME_MAX_LEN}"
+    ))]
+    SamAccountNameLimitTooLong { kind: NameKind, limit: usize },
+
+    #[snafu(display("{kind} {short:?} was derived from both {existing:?} and {full:?}"))]
+    Collision {
+        kind: NameKind,
+        short: String,
+        existing: String,
+        full: String,
+    },
+}
+
+/// The consumers of derived names, which may each impose different length limits.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum NameKind {
+    /// The host name component of a principal (such as `my-pod.my-service` in `HTTP/my-pod.my-service@REALM`).
+    PrincipalComponent,
+
+    /// The `sAMAccountName` of the Active Directory user that is created for a principal.
+    SamAccountName,
+
+    /// The service name component of a principal, which is usually mapped to a local username by the workload.
+    LocalUsername,
+}
+impl Display for NameKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            NameKind::PrincipalComponent => "principal component",
+            NameKind::SamAccountName => "sAMAccountName",
+            NameKind::LocalUsername => "local username",
+        })
+    }
+}
+
+/// The maximum length of each [`NameKind`], names are never shortened for kinds without a limit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NameLengthLimits {
+    pub principal_component: Option<usize>,
+    pub sam_account_name: Option<usize>,
+    pub local_username: Option<usize>,
+}
+impl NameLengthLimits {
+    pub fn get(&self, kind: NameKind) -> Option<usize> {
+        match kind {
+            NameKind::PrincipalComponent => self.principal_component,
+            NameKind::SamAccountName => self.sam_account_name,
+            NameKind::LocalUsername => self.local_username,
+        }
+    }
+
+    /// Checks that every configured limit leaves space for the hash of shortened names.
+    pub fn validate(&self) -> Result<(), Error> {
+        for kind in [
+            NameKind::PrincipalComponent,
+            NameKind::SamAccountName,
+            NameKind::LocalUsername,
+        ] {
+            if let Some(limit) = self.get(kind) {
+                ensure!(
+                    limit >= HASH_LEN + 2,
+                    error::LimitTooShortSnafu { kind, limit }
+                );
+            }
+        }
+        if let Some(limit) = self.sam_account_name {
+            ensure!(
+                limit <= SAM_ACCOUNT_NAME_MAX_LEN,
+                error::SamAccountNameLimitTooLongSnafu {
+                    kind: NameKind::SamAccountName,
+                    limit
+                }
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Shortens `name` to at most `limit` bytes, if required.
+///
+/// Shortened names keep as much of the start of `name` as possible, followed by `-` and [`HASH_LEN`] characters of
+/// a hash of the full name.
+pub fn shorten(kind: NameKind, name: &str, limit: usize) -> Result<Cow<'_, str>, Error> {
+    ensure!(
+        limit >= HASH_LEN + 2,
+        error::LimitTooShortSnafu { kind, limit }
+    );
+    if name.len() <= limit {
+        return Ok(Cow::Borrowed(name));
+    }
+    let mut prefix_len = limit - HASH_LEN - 1;
+    while !name.is_char_boundary(prefix_len) {
+        prefix_len -= 1;
+    }
+    // Avoid doubled separators, and names that end in a dot before the hash
+    let prefix = name[..prefix_len].trim_end_matches(['-', '.']);
+    Ok(Cow::Owned(format!("{prefix}-{}", hash(name))))
+}
+
+/// Derives a `sAMAccountName` from the realmless name of a principal (such as `HTTP/my-pod.my-service`).
+///
+/// `sAMAccountName`s may not contain `/` (among others), so such characters are replaced by `-` before shortening.
+pub fn sam_account_name(principal_realmless: &str, limit: usize) -> Result<String, Error> {
+    let sanitized = principal_realmless
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '-',
+        })
+        .collect::<String>();
+    // sAMAccountNames may not end with a dot
+    let sanitized = sanitized.trim_end_matches('.');
+    Ok(shorten(NameKind::SamAccountName, sanitized, limit)?.into_owned())
+}
+
+/// Encodes the first 30 bits of the SHA-256 hash of `name` as lowercase base32 (RFC 4648).
+///
+/// Lowercase letters and digits are valid in DNS labels, usernames, and `sAMAccountName`s alike.
+fn hash(name: &str) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut hasher = Sha256::new();
+    hasher.update(name.as_bytes());
+    let digest = hasher.finish();
+    let bits = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+    (0..HASH_LEN)
+        .map(|i| char::from(ALPHABET[(bits >> (32 - 5 * (i + 1))) as usize & 0x1f]))
+        .collect()
+}
+
+/// The full names of every name that has been shortened, by [`NameKind`] and short name.
+///
+/// Serialized as JSON into the volume (and returned by the provisioner), so that workloads can map short names back.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct NameMappings(BTreeMap<NameKind, BTreeMap<String, String>>);
+impl NameMappings {
+    /// Shortens `name` if it exceeds the limit of `kind`, and records the mapping if it was shortened.
+    pub fn shorten(
+        &mut self,
+        kind: NameKind,
+        name: &str,
+        limits: &NameLengthLimits,
+    ) -> Result<String, Error> {
+        let Some(limit) = limits.get(kind) else {
+            return Ok(name.to_string());
+        };
+        let short = shorten(kind, name, limit)?;
+        if short != name {
+            self.record(kind, &short, name)?;
+        }
+        Ok(short.into_owned())
+    }
+
+    /// Records that `short` was derived from `full`, failing if it was already derived from a different name.
+    pub fn record(&mut self, kind: NameKind, short: &str, full: &str) -> Result<(), Error> {
+        let existing = self
+            .0
+            .entry(kind)
+            .or_default()
+            .entry(short.to_string())
+            .or_insert_with(|| full.to_string());
+        ensure!(
+            existing == full,
+            error::CollisionSnafu {
+                kind,
+                short,
+                existing: existing.clone(),
+                full
+            }
+        );
+        Ok(())
+    }
+
+    /// Records every mapping of `other`, failing on the first collision.
+    pub fn extend(&mut self, other: NameMappings) -> Result<(), Error> {
+        for (kind, names) in other.0 {
+            for (short, full) in names {
+                self.record(kind, &short, &full)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The name that `short` was derived from, if it was shortened.
+    pub fn full_name(&self, kind: NameKind, short: &str) -> Option<&str> {
+        Some(self.0.get(&kind)?.get(short)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.values().all(BTreeMap::is_empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Error, HASH_LEN, NameKind, NameLengthLimits, NameMappings, SAM_ACCOUNT_NAME_MAX_LEN,
+        sam_account_name, shorten,
+    };
+
+    const LONG_HOST: &str =
+        "my-statefulset-with-a-long-name-0.my-headless-service.my-namespace.svc.cluster.local";
+
+    #[test]
+    fn short_names_should_be_kept() {
+        assert_eq!(
+            shorten(NameKind::PrincipalComponent, "my-pod", 63).unwrap(),
+            "my-pod"
+        );
+        assert_eq!(
+            shorten(NameKind::PrincipalComponent, &"a".repeat(63), 63).unwrap(),
+            "a".repeat(63)
+        );
+    }
+
+    #[test]
+    fn shortening_should_be_deterministic() {
+        let short = shorten(NameKind::PrincipalComponent, LONG_HOST, 63).unwrap();
+        assert_eq!(short.len(), 63);
+        assert_eq!(
+            short,
+            shorten(NameKind::PrincipalComponent, LONG_HOST, 63).unwrap()
+        );
+        let (prefix, hash) = short.rsplit_once('-').unwrap();
+        assert!(LONG_HOST.starts_with(prefix), "{short}");
+        assert_eq!(hash.len(), HASH_LEN);
+        assert!(
+            hash.chars()
+                .all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c)),
+            "{hash}"
+        );
+
+        // Names that share a prefix must still get different short names
+        let other = LONG_HOST.replace("-0.", "-1.");
+        assert_ne!(
+            short,
+            shorten(NameKind::PrincipalComponent, &other, 63).unwrap()
+        );
+    }
+
+    #[test]
+    fn limits_should_leave_space_for_the_hash() {
+        assert!(matches!(
+            shorten(NameKind::LocalUsername, LONG_HOST, HASH_LEN + 1),
+            Err(Error::LimitTooShort { .. })
+        ));
+        assert_eq!(
+            shorten(NameKind::LocalUsername, LONG_HOST, HASH_LEN + 2)
+                .unwrap()
+                .len(),
+            HASH_LEN + 2
+        );
+        assert!(
+            NameLengthLimits {
+                local_username: Some(4),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+        assert!(
+            NameLengthLimits {
+                sam_account_name: Some(SAM_ACCOUNT_NAME_MAX_LEN + 1),
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn sam_account_names_should_fit_active_directory() {
+        let name =
+            sam_account_name(&format!("HTTP/{LONG_HOST}"), SAM_ACCOUNT_NAME_MAX_LEN).unwrap();
+        assert_eq!(name.len(), SAM_ACCOUNT_NAME_MAX_LEN);
+        assert!(name.starts_with("HTTP-my-state-"), "{name}");
+        assert!(!name.contains('/'), "{name}");
+        assert_eq!(
+            name,
+            sam_account_name(&format!("HTTP/{LONG_HOST}"), SAM_ACCOUNT_NAME_MAX_LEN).unwrap()
+        );
+        // Short principals are only sanitized
+        assert_eq!(
+            sam_account_name("nn/my-pod", SAM_ACCOUNT_NAME_MAX_LEN).unwrap(),
+            "nn-my-pod"
+        );
+    }
+
+    #[test]
+    fn mappings_should_round_trip() {
+        let limits = NameLengthLimits {
+            principal_component: Some(63),
+            local_username: Some(8),
+            ..Default::default()
+        };
+        let mut mappings = NameMappings::default();
+        let host = mappings
+            .shorten(NameKind::PrincipalComponent, LONG_HOST, &limits)
+            .unwrap();
+        let service = mappings
+            .shorten(NameKind::LocalUsername, "my-service-name", &limits)
+            .unwrap();
+        // Names without a limit, or within their limit, are not recorded
+        assert_eq!(
+            mappings
+                .shorten(NameKind::SamAccountName, LONG_HOST, &limits)
+                .unwrap(),
+            LONG_HOST
+        );
+        mappings
+            .shorten(NameKind::LocalUsername, "nn", &limits)
+            .unwrap();
+
+        let json = serde_json::to_string(&mappings).unwrap();
+        let parsed = serde_json::from_str::<NameMappings>(&json).unwrap();
+        assert_eq!(parsed, mappings);
+        assert_eq!(
+            parsed.full_name(NameKind::PrincipalComponent, &host),
+            Some(LONG_HOST)
+        );
+        assert_eq!(
+            parsed.full_name(NameKind::LocalUsername, &service),
+            Some("my-service-name")
+        );
+        assert_eq!(parsed.full_name(NameKind::LocalUsername, "nn"), None);
+    }
+
+    #[test]
+    fn collisions_should_be_rejected() {
+        let mut mappings = NameMappings::default();
+        mappings
+            .record(
+                NameKind::SamAccountName,
+                "HTTP-my-pod-abcdef",
+                "HTTP/my-pod-1",
+            )
+            .unwrap();
+        // Recording the same mapping again is fine
+        mappings
+            .record(
+                NameKind::SamAccountName,
+                "HTTP-my-pod-abcdef",
+                "HTTP/my-pod-1",
+            )
+            .unwrap();
+        assert!(matches!(
+            mappings.record(
+                NameKind::SamAccountName,
+                "HTTP-my-pod-abcdef",
+                "HTTP/my-pod-2"
+            ),
+            Err(Error::Collision { .. })
+        ));
+        // Different kinds have separate namespaces
+        mappings
+            .record(
+                NameKind::LocalUsername,
+                "HTTP-my-pod-abcdef",
+                "HTTP/my-pod-2",
+            )
+            .unwrap();
+
+        let mut other = NameMappings::default();
+        other
+            .record(
+                NameKind::SamAccountName,
+                "HTTP-my-pod-abcdef",
+                "HTTP/my-pod-3",
+            )
+            .unwrap();
+        assert!(matches!(
+            mappings.extend(other),
+            Err(Error::Collision { .. })
+        ));
+    }
+}
@@ -20,6 +20,7 @@ fn main() {
         .allowlist_var("KRB5_.*")
         .allowlist_var("KADM5_.*")
         .allowlist_var("ENCTYPE_.*")
+        .allowlist_var("PROF_.*")
         // Variadic functions generate bindings that rustc on ARM64 considers FFI-unsafe.
         // We don't actually use them, so we can just blocklist the types, and any function
         // variants that use them.
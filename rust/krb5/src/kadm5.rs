@@ -1,37 +1,115 @@
 use std::{
-    ffi::{CStr, CString, c_char, c_int},
+    ffi::{CStr, CString, c_char, c_int, c_uint},
     fmt::Display,
     slice,
+    time::{Duration, SystemTime},
 };
 
-use crate::{KeyblockRef, KrbContext, Principal};
+use crate::{Keyblock, KeyblockRef, KrbContext, Principal, data_as_bytes};
 
 /// An error generated by libkadm5
 #[derive(Debug)]
 pub struct Error {
     pub code: krb5_sys::kadm5_ret_t,
+    message: String,
+    context: Option<String>,
+    detail: Option<String>,
 }
 impl Error {
+    /// Create an error for a libkadm5 error code, such as one from [`error_code`].
+    pub fn from_code(code: krb5_sys::kadm5_ret_t) -> Self {
+        // copy message into rust str, since error_message may reuse its buffer for unknown codes
+        let message = unsafe { CStr::from_ptr(krb5_sys::error_message(code.0)) }
+            .to_string_lossy()
+            .into_owned();
+        Self {
+            code,
+            message,
+            context: None,
+            detail: None,
+        }
+    }
+
     fn from_ret(code: krb5_sys::kadm5_ret_t) -> Result<(), Self> {
         if code.0 == krb5_sys::kadm5_ret_t(krb5_sys::KADM5_OK.into()).0 {
             Ok(())
         } else {
-            Err(Self { code })
+            Err(Self::from_code(code))
         }
     }
+
+    /// Describe the operation that failed, such as which principal it was about.
+    fn with_context(mut self, context: impl Display) -> Self {
+        self.context = Some(context.to_string());
+        self
+    }
+
+    /// Attach a more detailed explanation provided by libkadm5, such as why a password was rejected.
+    fn with_detail(mut self, detail: &str) -> Self {
+        // libkadm5 wraps its explanations over multiple lines
+        let detail = detail.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !detail.is_empty() {
+            self.detail = Some(detail);
+        }
+        self
+    }
+
+    /// The error message from libkadm5.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The operation that failed, if known.
+    pub fn context(&self) -> Option<&str> {
+        self.context.as_deref()
+    }
+
+    /// A more detailed explanation of the error, if any.
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+
+    /// Whether the principal (or other object) already exists.
+    pub fn is_duplicate(&self) -> bool {
+        self.code.0 == error_code::DUP
+    }
+
+    /// Whether the principal does not exist.
+    pub fn is_unknown_principal(&self) -> bool {
+        self.code.0 == error_code::UNK_PRINC
+    }
+
+    /// Whether the password of a [`Credential::Password`] was rejected.
+    pub fn is_bad_password(&self) -> bool {
+        self.code.0 == error_code::BAD_PASSWORD
+    }
 }
 impl std::error::Error for Error {}
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let msg = unsafe { CStr::from_ptr(krb5_sys::error_message(self.code.0)) };
-        f.write_str(&msg.to_string_lossy())
+        if let Some(context) = &self.context {
+            write!(f, "{context}: ")?;
+        }
+        f.write_str(&self.message)?;
+        if let Some(detail) = &self.detail {
+            write!(f, " ({detail})")?;
+        }
+        Ok(())
     }
 }
 
 /// Well-known error codes. This is not exhaustive.
 pub mod error_code {
     pub use krb5_sys::kadm5_ret_t;
+    pub const AUTH_ADD: i64 = krb5_sys::KADM5_AUTH_ADD as _;
+    pub const AUTH_DELETE: i64 = krb5_sys::KADM5_AUTH_DELETE as _;
+    pub const AUTH_EXTRACT: i64 = krb5_sys::KADM5_AUTH_EXTRACT as _;
+    pub const AUTH_GET: i64 = krb5_sys::KADM5_AUTH_GET as _;
+    pub const AUTH_INSUFFICIENT: i64 = krb5_sys::KADM5_AUTH_INSUFFICIENT as _;
+    pub const BAD_PASSWORD: i64 = krb5_sys::KADM5_BAD_PASSWORD as _;
     pub const DUP: i64 = krb5_sys::KADM5_DUP as _;
+    pub const RPC_ERROR: i64 = krb5_sys::KADM5_RPC_ERROR as _;
+    pub const UNK_PRINC: i64 = krb5_sys::KADM5_UNK_PRINC as _;
 }
 
 /// Credentials that can be used to authenticate to kadm5.
@@ -41,6 +119,31 @@ pub enum Credential {
         /// The path to the keytab containing the key.
         keytab: CString,
     },
+    /// A password.
+    Password {
+        /// The password of the client principal.
+        password: CString,
+    },
+    /// Existing tickets for the client principal, such as from `kinit`.
+    CredentialCache {
+        /// The name of the credential cache, such as `FILE:/tmp/krb5cc_admin`. Leave `None` to use the default cache.
+        ccache: Option<CString>,
+    },
+}
+impl Credential {
+    /// Describes how the client authenticates, without revealing any secrets.
+    fn auth_method(&self) -> String {
+        match self {
+            Credential::ServiceKey { keytab } => format!("keytab {}", keytab.to_string_lossy()),
+            Credential::Password { .. } => "password".to_string(),
+            Credential::CredentialCache {
+                ccache: Some(ccache),
+            } => {
+                format!("credential cache {}", ccache.to_string_lossy())
+            }
+            Credential::CredentialCache { ccache: None } => "default credential cache".to_string(),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -77,6 +180,114 @@ impl ConfigParams {
     }
 }
 
+/// Well-known principal attribute flags, for use with [`PrincipalModification::attributes`]. This is not exhaustive.
+pub mod principal_attributes {
+    use krb5_sys::krb5_flags;
+
+    /// Tickets for the principal may not be forwardable.
+    pub const DISALLOW_FORWARDABLE: krb5_flags = krb5_sys::KRB5_KDB_DISALLOW_FORWARDABLE as _;
+    /// Service tickets for the principal may only be issued by authenticating with its key directly, not using a TGT.
+    pub const DISALLOW_TGT_BASED: krb5_flags = krb5_sys::KRB5_KDB_DISALLOW_TGT_BASED as _;
+    /// No tickets may be issued for the principal at all.
+    pub const DISALLOW_ALL_TIX: krb5_flags = krb5_sys::KRB5_KDB_DISALLOW_ALL_TIX as _;
+    /// The principal must pre-authenticate before it is issued a TGT.
+    pub const REQUIRES_PRE_AUTH: krb5_flags = krb5_sys::KRB5_KDB_REQUIRES_PRE_AUTH as _;
+    /// The principal is trusted for (unconstrained) delegation.
+    pub const OK_AS_DELEGATE: krb5_flags = krb5_sys::KRB5_KDB_OK_AS_DELEGATE as _;
+    /// The principal may impersonate users to itself (S4U2self) and forward those tickets.
+    pub const OK_TO_AUTH_AS_DELEGATE: krb5_flags = krb5_sys::KRB5_KDB_OK_TO_AUTH_AS_DELEGATE as _;
+}
+
+/// A set of changes to apply to a principal's attributes using [`ServerHandle::modify_principal`].
+///
+/// Attributes that are not set are left unchanged.
+#[derive(Debug, Default, Clone)]
+pub struct PrincipalModification {
+    princ_expire_time: Option<krb5_sys::krb5_timestamp>,
+    pw_expiration: Option<krb5_sys::krb5_timestamp>,
+    max_life: Option<krb5_sys::krb5_deltat>,
+    attributes: Option<krb5_sys::krb5_flags>,
+    policy: Option<CString>,
+}
+impl PrincipalModification {
+    /// Set when the principal expires, in seconds since the Unix epoch. 0 means that it never expires.
+    pub fn princ_expire_time(mut self, princ_expire_time: krb5_sys::krb5_timestamp) -> Self {
+        self.princ_expire_time = Some(princ_expire_time);
+        self
+    }
+
+    /// Set when the principal's password expires, in seconds since the Unix epoch. 0 means that it never expires.
+    pub fn pw_expiration(mut self, pw_expiration: krb5_sys::krb5_timestamp) -> Self {
+        self.pw_expiration = Some(pw_expiration);
+        self
+    }
+
+    /// Set the maximum lifetime of tickets issued for the principal, in seconds.
+    pub fn max_life(mut self, max_life: krb5_sys::krb5_deltat) -> Self {
+        self.max_life = Some(max_life);
+        self
+    }
+
+    /// Set the principal's attribute flags, replacing any existing flags.
+    ///
+    /// Some well-known flags are available in [`principal_attributes`].
+    pub fn attributes(mut self, attributes: krb5_sys::krb5_flags) -> Self {
+        self.attributes = Some(attributes);
+        self
+    }
+
+    /// Set the name of the password policy that applies to the principal.
+    pub fn policy(mut self, policy: CString) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Return a [`krb5_sys::_kadm5_principal_ent_t`] view of `self` for `principal`, along with the mask of the
+    /// fields that are set
+    ///
+    /// The returned entry borrows `self` and `principal`, and should be considered unusable as soon as either is
+    /// moved, modified, or dropped.
+    fn as_c(&self, principal: &Principal) -> (krb5_sys::_kadm5_principal_ent_t, i64) {
+        let mut ent = unsafe { std::mem::zeroed::<krb5_sys::_kadm5_principal_ent_t>() };
+        let mut mask = 0;
+        ent.principal = principal.raw;
+        if let Some(princ_expire_time) = self.princ_expire_time {
+            ent.princ_expire_time = princ_expire_time;
+            mask |= i64::from(krb5_sys::KADM5_PRINC_EXPIRE_TIME);
+        }
+        if let Some(pw_expiration) = self.pw_expiration {
+            ent.pw_expiration = pw_expiration;
+            mask |= i64::from(krb5_sys::KADM5_PW_EXPIRATION);
+        }
+        if let Some(max_life) = self.max_life {
+            ent.max_life = max_life;
+            mask |= i64::from(krb5_sys::KADM5_MAX_LIFE);
+        }
+        if let Some(attributes) = self.attributes {
+            ent.attributes = attributes;
+            mask |= i64::from(krb5_sys::KADM5_ATTRIBUTES);
+        }
+        if let Some(policy) = &self.policy {
+            ent.policy = policy.as_ptr() as *mut c_char;
+            mask |= i64::from(krb5_sys::KADM5_POLICY);
+        }
+        (ent, mask)
+    }
+}
+
+/// Signature shared by `kadm5_init_with_skey` and `kadm5_init_with_password`.
+type InitFn = unsafe extern "C" fn(
+    context: krb5_sys::krb5_context,
+    client_name: *mut c_char,
+    secret: *mut c_char,
+    service_name: *mut c_char,
+    params: *mut krb5_sys::kadm5_config_params,
+    struct_version: krb5_sys::krb5_ui_4,
+    api_version: krb5_sys::krb5_ui_4,
+    db_args: *mut *mut c_char,
+    server_handle: *mut *mut std::ffi::c_void,
+) -> krb5_sys::kadm5_ret_t;
+
 /// A kadmin5 client.
 pub struct ServerHandle<'a> {
     ctx: &'a KrbContext,
@@ -98,23 +309,66 @@ impl<'a> ServerHandle<'a> {
     ) -> Result<Self, Error> {
         let mut server_handle = std::ptr::null_mut();
         let mut params = params.as_c();
+        let client_name_ptr = client_name.as_ptr().cast_mut();
+        let service_name = service_name.map_or(std::ptr::null_mut(), |sn| sn.as_ptr().cast_mut());
+        let init_error = |err: Error| {
+            err.with_context(format_args!(
+                "initializing kadmin client {} using {}",
+                client_name.to_string_lossy(),
+                credential.auth_method()
+            ))
+        };
 
-        match credential {
-            Credential::ServiceKey { keytab } => unsafe {
-                Error::from_ret(krb5_sys::kadm5_init_with_skey(
-                    ctx.raw,
-                    client_name.as_ptr().cast_mut(),
-                    keytab.as_ptr().cast_mut(),
-                    service_name
-                        .as_ref()
-                        .map_or(std::ptr::null_mut(), |sn| sn.as_ptr().cast_mut()),
-                    &mut params,
-                    krb5_sys::KADM5_STRUCT_VERSION_1,
-                    krb5_sys::KADM5_API_VERSION_4,
-                    std::ptr::null_mut(),
-                    &mut server_handle,
-                ))?;
-            },
+        // Both secret-based init functions take the same parameters, and only differ in how the secret is interpreted
+        let (init, secret): (InitFn, &CStr) = match credential {
+            Credential::ServiceKey { keytab } => (krb5_sys::kadm5_init_with_skey, keytab),
+            Credential::Password { password } => (krb5_sys::kadm5_init_with_password, password),
+            Credential::CredentialCache { ccache } => {
+                let mut raw_ccache = std::ptr::null_mut();
+                unsafe {
+                    let code = match ccache {
+                        Some(ccache) => {
+                            krb5_sys::krb5_cc_resolve(ctx.raw, ccache.as_ptr(), &mut raw_ccache)
+                        }
+                        None => krb5_sys::krb5_cc_default(ctx.raw, &mut raw_ccache),
+                    };
+                    // libkrb5 and libkadm5 error codes share the same namespace
+                    Error::from_ret(krb5_sys::kadm5_ret_t(code.0.into())).map_err(init_error)?;
+                    let ret = krb5_sys::kadm5_init_with_creds(
+                        ctx.raw,
+                        client_name_ptr,
+                        raw_ccache,
+                        service_name,
+                        &mut params,
+                        krb5_sys::KADM5_STRUCT_VERSION_1,
+                        krb5_sys::KADM5_API_VERSION_4,
+                        std::ptr::null_mut(),
+                        &mut server_handle,
+                    );
+                    // The caller retains ownership of the ccache, kadm5 only reads the tickets from it
+                    let _ = krb5_sys::krb5_cc_close(ctx.raw, raw_ccache);
+                    Error::from_ret(ret).map_err(init_error)?;
+                }
+                return Ok(Self {
+                    ctx,
+                    raw: server_handle,
+                });
+            }
+        };
+        // SAFETY: secret is borrowed from credential, so it stays alive until after init returns
+        unsafe {
+            Error::from_ret(init(
+                ctx.raw,
+                client_name_ptr,
+                secret.as_ptr().cast_mut(),
+                service_name,
+                &mut params,
+                krb5_sys::KADM5_STRUCT_VERSION_1,
+                krb5_sys::KADM5_API_VERSION_4,
+                std::ptr::null_mut(),
+                &mut server_handle,
+            ))
+            .map_err(init_error)?;
         }
         Ok(Self {
             ctx,
@@ -135,6 +389,213 @@ impl<'a> ServerHandle<'a> {
                 std::ptr::null_mut(),
             ))
         }
+        .map_err(|err| err.with_context(format_args!("creating principal {principal}")))
+    }
+
+    /// Get the attributes of a principal.
+    ///
+    /// Returns `None` if the principal does not exist.
+    pub fn get_principal(&self, principal: &Principal) -> Result<Option<PrincipalEntry>, Error> {
+        unsafe {
+            let mut raw: krb5_sys::_kadm5_principal_ent_t = std::mem::zeroed();
+            match Error::from_ret(krb5_sys::kadm5_get_principal(
+                self.raw,
+                principal.raw,
+                &mut raw,
+                krb5_sys::KADM5_PRINCIPAL_NORMAL_MASK.into(),
+            )) {
+                Ok(()) => {}
+                Err(err) if err.is_unknown_principal() => return Ok(None),
+                Err(err) => {
+                    return Err(err.with_context(format_args!("getting principal {principal}")));
+                }
+            }
+            let entry = PrincipalEntry::from_raw(&raw);
+            Error::from_ret(krb5_sys::kadm5_free_principal_ent(self.raw, &mut raw))?;
+            Ok(Some(entry))
+        }
+    }
+
+    /// List the names of all principals that match a glob `expression`, such as `HTTP/*@EXAMPLE.COM`.
+    ///
+    /// All principals are listed if `expression` is `None`.
+    pub fn list_principals(&self, expression: Option<&CStr>) -> Result<Vec<String>, Error> {
+        let mut names = NameList {
+            handle: self,
+            raw: std::ptr::null_mut(),
+            count: 0,
+        };
+        unsafe {
+            Error::from_ret(krb5_sys::kadm5_get_principals(
+                self.raw,
+                expression.map_or(std::ptr::null_mut(), |exp| exp.as_ptr().cast_mut()),
+                &mut names.raw,
+                &mut names.count,
+            ))
+            .map_err(|err| err.with_context("listing principals"))?;
+        }
+        Ok(names
+            .as_slice()
+            .iter()
+            // SAFETY: each name is a null-terminated string owned by names
+            .map(|&name| {
+                unsafe { CStr::from_ptr(name) }
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect())
+    }
+
+    /// Change the attributes of an existing principal.
+    ///
+    /// Only the attributes set in `changes` are modified.
+    pub fn modify_principal(
+        &self,
+        principal: &Principal,
+        changes: PrincipalModification,
+    ) -> Result<(), Error> {
+        let (mut ent, mask) = changes.as_c(principal);
+        unsafe { Error::from_ret(krb5_sys::kadm5_modify_principal(self.raw, &mut ent, mask)) }
+            .map_err(|err| err.with_context(format_args!("modifying principal {principal}")))
+    }
+
+    /// Delete a principal and all of its keys.
+    ///
+    /// Fails with [`error_code::UNK_PRINC`] if the principal does not exist.
+    pub fn delete_principal(&self, principal: &Principal) -> Result<(), Error> {
+        unsafe { Error::from_ret(krb5_sys::kadm5_delete_principal(self.raw, principal.raw)) }
+            .map_err(|err| err.with_context(format_args!("deleting principal {principal}")))
+    }
+
+    /// Rename the principal `from` to `to`.
+    ///
+    /// The keys are not rotated, so existing keytabs keep working under the new name (with the same kvno). `from`
+    /// is deleted atomically with the creation of `to`, so there is no window where both or neither exist.
+    ///
+    /// Both principals must have been created by the same [`KrbContext`] as this handle. Fails with
+    /// [`error_code::UNK_PRINC`] if `from` does not exist, or [`error_code::DUP`] if `to` already exists.
+    pub fn rename_principal(&self, from: &Principal, to: &Principal) -> Result<(), Error> {
+        unsafe { Error::from_ret(krb5_sys::kadm5_rename_principal(self.raw, from.raw, to.raw)) }
+            .map_err(|err| err.with_context(format_args!("renaming principal {from} to {to}")))
+    }
+
+    /// Replace the keys of a principal with new random keys generated by the KDC, and return them.
+    ///
+    /// The principal's KVNO is incremented. The new KVNO is not returned, but can be read using
+    /// [`Self::get_principal`].
+    ///
+    /// If `keepold` is set, keys of previous KVNOs are retained, so that keytabs that contain them keep working.
+    /// Otherwise they are discarded, and such keytabs stop working once any tickets issued for them expire.
+    ///
+    /// `enctypes` selects which types of keys to generate (each using the normal salt). If it is empty, the KDC's
+    /// default enctypes are used.
+    pub fn randkey_principal(
+        &self,
+        principal: &Principal,
+        keepold: bool,
+        enctypes: &[krb5_sys::krb5_enctype],
+    ) -> Result<Vec<Keyblock<'a>>, Error> {
+        let mut ks_tuples = enctypes
+            .iter()
+            .map(|&ks_enctype| krb5_sys::krb5_key_salt_tuple {
+                ks_enctype,
+                ks_salttype: krb5_sys::KRB5_KDB_SALTTYPE_NORMAL as _,
+            })
+            .collect::<Vec<_>>();
+        let mut keyblocks = std::ptr::null_mut();
+        let mut n_keys = 0;
+        unsafe {
+            // ks_tuples outlives the call
+            Error::from_ret(krb5_sys::kadm5_randkey_principal_3(
+                self.raw,
+                principal.raw,
+                keepold.into(),
+                // Cannot overflow, since libkadm5 only knows a handful of enctypes
+                ks_tuples.len() as c_int,
+                if ks_tuples.is_empty() {
+                    std::ptr::null_mut()
+                } else {
+                    ks_tuples.as_mut_ptr()
+                },
+                &mut keyblocks,
+                &mut n_keys,
+            ))
+            .map_err(|err| {
+                err.with_context(format_args!("randomizing keys of principal {principal}"))
+            })?;
+        }
+        let raw_keys: &mut [krb5_sys::krb5_keyblock] = match n_keys {
+            ..=0 => &mut [],
+            // SAFETY: libkadm5 returns an array of n_keys keyblocks
+            _ => unsafe { slice::from_raw_parts_mut(keyblocks, n_keys as usize) },
+        };
+        let keys = raw_keys
+            .iter()
+            .map(|raw| {
+                let mut copy = std::ptr::null_mut();
+                let code = unsafe { krb5_sys::krb5_copy_keyblock(self.ctx.raw, raw, &mut copy) };
+                // libkrb5 and libkadm5 error codes share the same namespace
+                Error::from_ret(krb5_sys::kadm5_ret_t(code.0.into()))
+                    .map_err(|err| err.with_context("copying new keys"))?;
+                Ok(Keyblock {
+                    ctx: self.ctx,
+                    raw: copy,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>();
+        unsafe {
+            for raw in raw_keys.iter_mut() {
+                krb5_sys::krb5_free_keyblock_contents(self.ctx.raw, raw);
+            }
+            // The array itself is a single allocation, starting at the first keyblock (whose contents have already
+            // been freed and cleared)
+            if !keyblocks.is_null() {
+                krb5_sys::krb5_free_keyblock(self.ctx.raw, keyblocks);
+            }
+        }
+        keys
+    }
+
+    /// Change the password of a principal, deriving new keys from it.
+    pub fn change_password(&self, principal: &Principal, new_password: &CStr) -> Result<(), Error> {
+        unsafe {
+            Error::from_ret(krb5_sys::kadm5_chpass_principal(
+                self.raw,
+                principal.raw,
+                new_password.as_ptr().cast_mut(),
+            ))
+        }
+        .map_err(|err| err.with_context(format_args!("changing password of principal {principal}")))
+    }
+
+    /// Change the password of a principal like [`Self::change_password`], explaining why the password was rejected
+    /// if it does not satisfy the principal's password policy.
+    ///
+    /// The explanation is available as [`Error::detail`].
+    pub fn change_password_with_policy_check(
+        &self,
+        principal: &Principal,
+        new_password: &CStr,
+    ) -> Result<(), Error> {
+        // Same size as used by kpasswd
+        let mut msg = [0 as c_char; 1024];
+        let result = unsafe {
+            Error::from_ret(krb5_sys::kadm5_chpass_principal_util(
+                self.raw,
+                principal.raw,
+                new_password.as_ptr().cast_mut(),
+                // Only used to return generated passwords
+                std::ptr::null_mut(),
+                msg.as_mut_ptr(),
+                msg.len() as c_uint,
+            ))
+        };
+        result.map_err(|err| {
+            // SAFETY: msg is zero-initialized, and libkadm5 never fills the last byte
+            let detail = unsafe { CStr::from_ptr(msg.as_ptr()) };
+            err.with_context(format_args!("changing password of principal {principal}"))
+                .with_detail(&detail.to_string_lossy())
+        })
     }
 
     /// Get the keys of a principal.
@@ -154,7 +615,10 @@ impl<'a> ServerHandle<'a> {
                 kvno,
                 &mut key_data,
                 &mut key_count,
-            ))?;
+            ))
+            .map_err(|err| {
+                err.with_context(format_args!("getting keys of principal {principal}"))
+            })?;
         }
         Ok(KeyDataVec {
             ctx: self.ctx,
@@ -162,6 +626,98 @@ impl<'a> ServerHandle<'a> {
             key_count,
         })
     }
+
+    /// Get the value of the string attribute `key` of a principal (like `kadmin get_strings`).
+    ///
+    /// Returns `None` if the attribute is not set. Fails with [`error_code::UNK_PRINC`] if the principal does not
+    /// exist.
+    pub fn get_string(&self, principal: &Principal, key: &CStr) -> Result<Option<String>, Error> {
+        let mut strings = std::ptr::null_mut();
+        let mut count = 0;
+        unsafe {
+            Error::from_ret(krb5_sys::kadm5_get_strings(
+                self.raw,
+                principal.raw,
+                &mut strings,
+                &mut count,
+            ))
+            .map_err(|err| {
+                err.with_context(format_args!(
+                    "getting string attributes of principal {principal}"
+                ))
+            })?;
+        }
+        let attrs: &[krb5_sys::krb5_string_attr] = match count {
+            ..=0 => &[],
+            // SAFETY: libkadm5 returns an array of count attributes
+            _ => unsafe { slice::from_raw_parts(strings, count as usize) },
+        };
+        let value = attrs
+            .iter()
+            // SAFETY: keys and values are null-terminated strings owned by strings
+            .find(|attr| unsafe { CStr::from_ptr(attr.key) } == key)
+            .map(|attr| {
+                unsafe { CStr::from_ptr(attr.value) }
+                    .to_string_lossy()
+                    .into_owned()
+            });
+        unsafe { Error::from_ret(krb5_sys::kadm5_free_strings(self.raw, strings, count))? };
+        Ok(value)
+    }
+
+    /// Set the string attribute `key` of a principal to `value` (like `kadmin set_string`).
+    ///
+    /// String attributes are stored alongside the principal, and are not interpreted by the KDC unless they are one
+    /// of the attributes that it recognizes (such as `session_enctypes`).
+    pub fn set_string(&self, principal: &Principal, key: &CStr, value: &CStr) -> Result<(), Error> {
+        unsafe {
+            Error::from_ret(krb5_sys::kadm5_set_string(
+                self.raw,
+                principal.raw,
+                key.as_ptr(),
+                value.as_ptr(),
+            ))
+        }
+        .map_err(|err| {
+            err.with_context(format_args!(
+                "setting string attribute {} of principal {principal}",
+                key.to_string_lossy()
+            ))
+        })
+    }
+
+    /// Remove old keys from a principal, retaining only the newest `keep_kvnos` key versions.
+    ///
+    /// The current key is always retained, even if `keep_kvnos` is 0.
+    pub fn purge_keys(&self, principal: &Principal, keep_kvnos: u32) -> Result<(), Error> {
+        let current_kvno = unsafe {
+            let mut ent: krb5_sys::_kadm5_principal_ent_t = std::mem::zeroed();
+            Error::from_ret(krb5_sys::kadm5_get_principal(
+                self.raw,
+                principal.raw,
+                &mut ent,
+                krb5_sys::KADM5_KVNO.into(),
+            ))
+            .map_err(|err| err.with_context(format_args!("getting principal {principal}")))?;
+            let kvno = ent.kvno;
+            Error::from_ret(krb5_sys::kadm5_free_principal_ent(self.raw, &mut ent))?;
+            kvno
+        };
+        // kadm5_purgekeys removes all keys older than the given kvno.
+        // Values below 1 are special-cased to mean "keep only the current key", so clamp to avoid hitting that by accident.
+        let oldest_kept_kvno = current_kvno
+            .saturating_add(1)
+            .saturating_sub(keep_kvnos.max(1))
+            .max(1);
+        unsafe {
+            Error::from_ret(krb5_sys::kadm5_purgekeys(
+                self.raw,
+                principal.raw,
+                c_int::try_from(oldest_kept_kvno).unwrap_or(c_int::MAX),
+            ))
+        }
+        .map_err(|err| err.with_context(format_args!("purging keys of principal {principal}")))
+    }
 }
 impl Drop for ServerHandle<'_> {
     fn drop(&mut self) {
@@ -171,6 +727,85 @@ impl Drop for ServerHandle<'_> {
         }
     }
 }
+/// A list of names returned by libkadm5.
+struct NameList<'a> {
+    handle: &'a ServerHandle<'a>,
+    raw: *mut *mut c_char,
+    count: c_int,
+}
+impl NameList<'_> {
+    fn as_slice(&self) -> &[*mut c_char] {
+        match self.count {
+            // raw may be null for empty lists, which from_raw_parts does not allow
+            ..=0 => &[],
+            // SAFETY: raw points to count names, which are owned by self
+            count => unsafe { slice::from_raw_parts(self.raw, count as usize) },
+        }
+    }
+}
+impl Drop for NameList<'_> {
+    fn drop(&mut self) {
+        if !self.raw.is_null() {
+            Error::from_ret(unsafe {
+                krb5_sys::kadm5_free_name_list(self.handle.raw, self.raw, self.count)
+            })
+            .expect("failed to destroy name list")
+        }
+    }
+}
+
+/// The attributes of a principal, as returned by [`ServerHandle::get_principal`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrincipalEntry {
+    /// The current key version of the principal.
+    pub kvno: krb5_sys::krb5_kvno,
+    /// The principal's attribute flags, see [`principal_attributes`].
+    pub attributes: krb5_sys::krb5_flags,
+    /// When the principal expires, or `None` if it never expires.
+    pub expire: Option<SystemTime>,
+    /// When the principal's password expires, or `None` if it never expires.
+    pub pw_expire: Option<SystemTime>,
+    /// When the principal's password (or keys) were last changed, if ever.
+    pub last_pwd_change: Option<SystemTime>,
+    /// The maximum lifetime of tickets issued for the principal, or `None` to only apply the realm's limit.
+    pub max_life: Option<Duration>,
+    /// The maximum renewable lifetime of tickets issued for the principal, or `None` to only apply the realm's limit.
+    pub max_renewable_life: Option<Duration>,
+    /// The password policy that applies to the principal, if any.
+    pub policy: Option<String>,
+}
+impl PrincipalEntry {
+    // SAFETY: raw must have been filled in by kadm5_get_principal
+    unsafe fn from_raw(raw: &krb5_sys::_kadm5_principal_ent_t) -> Self {
+        Self {
+            kvno: raw.kvno,
+            attributes: raw.attributes,
+            expire: timestamp(raw.princ_expire_time),
+            pw_expire: timestamp(raw.pw_expiration),
+            last_pwd_change: timestamp(raw.last_pwd_change),
+            max_life: deltat(raw.max_life),
+            max_renewable_life: deltat(raw.max_renewable_life),
+            policy: (!raw.policy.is_null()).then(|| {
+                unsafe { CStr::from_ptr(raw.policy) }
+                    .to_string_lossy()
+                    .into_owned()
+            }),
+        }
+    }
+}
+
+/// Converts a kadm5 timestamp, where 0 means "never".
+fn timestamp(timestamp: krb5_sys::krb5_timestamp) -> Option<SystemTime> {
+    // libkrb5 treats timestamps as unsigned, to postpone the year 2038 problem
+    (timestamp != 0)
+        .then(|| SystemTime::UNIX_EPOCH + Duration::from_secs(u64::from(timestamp as u32)))
+}
+
+/// Converts a kadm5 duration, where 0 means "unlimited".
+fn deltat(deltat: krb5_sys::krb5_deltat) -> Option<Duration> {
+    (deltat > 0).then(|| Duration::from_secs(deltat.unsigned_abs().into()))
+}
+
 /// Parameter for [`ServerHandle::get_principal_keys`] that returns all keys, regardless of KVNO.
 pub const KVNO_ALL: krb5_sys::krb5_kvno = 0;
 
@@ -179,8 +814,34 @@ pub const KVNO_ALL: krb5_sys::krb5_kvno = 0;
 pub struct KeyDataRef<'a> {
     pub kvno: krb5_sys::krb5_kvno,
     pub keyblock: KeyblockRef<'a>,
-    // salt: krb5_sys::krb5_keysalt,
+    pub salt: KeySaltRef<'a>,
+}
+/// The salt that a key was derived with.
+#[derive(Debug, Clone, Copy)]
+pub struct KeySaltRef<'a> {
+    /// How the salt was chosen, see [`salttype`].
+    pub salttype: krb5_sys::krb5_int16,
+    /// The salt itself.
+    ///
+    /// This may be empty for [`salttype::NORMAL`], in which case the key was derived using the principal's
+    /// [default salt](Principal::default_salt).
+    pub data: &'a [u8],
+}
+
+/// Well-known salt types. This is not exhaustive.
+pub mod salttype {
+    use krb5_sys::krb5_int16;
+
+    /// The principal's default salt: the realm followed by each name component.
+    pub const NORMAL: krb5_int16 = krb5_sys::KRB5_KDB_SALTTYPE_NORMAL as _;
+    /// The principal's name components, without the realm.
+    pub const NOREALM: krb5_int16 = krb5_sys::KRB5_KDB_SALTTYPE_NOREALM as _;
+    /// Only the principal's realm.
+    pub const ONLYREALM: krb5_int16 = krb5_sys::KRB5_KDB_SALTTYPE_ONLYREALM as _;
+    /// An arbitrary salt, stored alongside the key.
+    pub const SPECIAL: krb5_int16 = krb5_sys::KRB5_KDB_SALTTYPE_SPECIAL as _;
 }
+
 /// An owned reference to a set of keys associated with a [`Principal`].
 pub struct KeyDataVec<'a> {
     ctx: &'a KrbContext,
@@ -210,7 +871,11 @@ impl KeyDataVec<'_> {
                 ctx: self.ctx,
                 raw: &raw.key,
             },
-            // salt: raw.salt,
+            salt: KeySaltRef {
+                salttype: raw.salt.type_,
+                // SAFETY: the salt is owned by self
+                data: unsafe { data_as_bytes(&raw.salt.data) },
+            },
         })
     }
 }
@@ -222,3 +887,139 @@ impl Drop for KeyDataVec<'_> {
         .expect("failed to destroy keydata vector")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{
+        Credential, Error, PrincipalEntry, PrincipalModification, error_code, principal_attributes,
+    };
+    use crate::KrbContext;
+
+    #[test]
+    fn principal_modification_only_sets_requested_fields() {
+        let ctx = KrbContext::new().unwrap();
+        let principal = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+
+        let (ent, mask) = PrincipalModification::default()
+            .princ_expire_time(1_700_000_000)
+            .as_c(&principal);
+        assert_eq!(mask, i64::from(krb5_sys::KADM5_PRINC_EXPIRE_TIME));
+        assert_eq!(ent.principal, principal.raw);
+        assert_eq!(ent.princ_expire_time, 1_700_000_000);
+        assert_eq!(ent.pw_expiration, 0);
+        assert_eq!(ent.max_life, 0);
+        assert_eq!(ent.attributes, 0);
+
+        let modification = PrincipalModification::default()
+            .max_life(3600)
+            .attributes(principal_attributes::REQUIRES_PRE_AUTH)
+            .policy(c"services".to_owned());
+        let (ent, mask) = modification.as_c(&principal);
+        assert_eq!(
+            mask,
+            i64::from(
+                krb5_sys::KADM5_MAX_LIFE | krb5_sys::KADM5_ATTRIBUTES | krb5_sys::KADM5_POLICY
+            )
+        );
+        assert_eq!(ent.max_life, 3600);
+        assert_eq!(ent.attributes, principal_attributes::REQUIRES_PRE_AUTH);
+        assert_eq!(unsafe { std::ffi::CStr::from_ptr(ent.policy) }, c"services");
+        assert_eq!(ent.princ_expire_time, 0);
+    }
+
+    #[test]
+    fn error_should_describe_failed_operation() {
+        let err = Error::from_code(error_code::kadm5_ret_t(error_code::DUP));
+        assert!(err.is_duplicate());
+        assert!(!err.is_unknown_principal());
+        assert_eq!(err.context(), None);
+        assert_eq!(err.to_string(), err.message());
+
+        let err = err.with_context("creating principal HTTP/foo@EXAMPLE.COM");
+        assert_eq!(
+            err.to_string(),
+            format!("creating principal HTTP/foo@EXAMPLE.COM: {}", err.message())
+        );
+    }
+
+    #[test]
+    fn error_should_include_policy_rejection() {
+        let err = Error::from_code(error_code::kadm5_ret_t(error_code::BAD_PASSWORD))
+            .with_context("changing password of principal foo@EXAMPLE.COM")
+            .with_detail(
+                "New password is too short.\nPlease choose a password which is at least 8 characters long.\n",
+            );
+        assert_eq!(
+            err.detail(),
+            Some(
+                "New password is too short. Please choose a password which is at least 8 characters long."
+            )
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "changing password of principal foo@EXAMPLE.COM: {} ({})",
+                err.message(),
+                err.detail().unwrap()
+            )
+        );
+
+        // Not every error comes with an explanation
+        assert_eq!(
+            Error::from_code(error_code::kadm5_ret_t(error_code::BAD_PASSWORD))
+                .with_detail("")
+                .detail(),
+            None
+        );
+    }
+
+    #[test]
+    fn principal_entry_should_own_converted_fields() {
+        let policy = c"services";
+        let mut raw: krb5_sys::_kadm5_principal_ent_t = unsafe { std::mem::zeroed() };
+        raw.kvno = 3;
+        raw.princ_expire_time = 1_700_000_000;
+        raw.max_life = 3600;
+        raw.policy = policy.as_ptr().cast_mut();
+        let entry = unsafe { PrincipalEntry::from_raw(&raw) };
+        assert_eq!(
+            entry,
+            PrincipalEntry {
+                kvno: 3,
+                attributes: 0,
+                expire: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+                pw_expire: None,
+                last_pwd_change: None,
+                max_life: Some(Duration::from_secs(3600)),
+                max_renewable_life: None,
+                policy: Some("services".to_string()),
+            }
+        );
+
+        // Timestamps past 2038 wrap around to negative values
+        raw.princ_expire_time = u32::MAX as i32;
+        raw.policy = std::ptr::null_mut();
+        let entry = unsafe { PrincipalEntry::from_raw(&raw) };
+        assert_eq!(
+            entry.expire,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(u32::MAX.into()))
+        );
+        assert_eq!(entry.policy, None);
+    }
+
+    #[test]
+    fn credential_auth_method_should_not_reveal_secrets() {
+        let password = Credential::Password {
+            password: c"hunter2".to_owned(),
+        };
+        assert_eq!(password.auth_method(), "password");
+        let ccache = Credential::CredentialCache {
+            ccache: Some(c"MEMORY:admin".to_owned()),
+        };
+        assert_eq!(ccache.auth_method(), "credential cache MEMORY:admin");
+        let ccache = Credential::CredentialCache { ccache: None };
+        assert_eq!(ccache.auth_method(), "default credential cache");
+    }
+}
@@ -3,9 +3,13 @@
 //! The primary entry point is [`KrbContext`].
 
 use std::{
-    ffi::{CStr, c_char, c_int},
+    ffi::{CStr, CString, OsStr, c_char, c_int, c_uint},
     fmt::{Debug, Display},
+    mem::ManuallyDrop,
     ops::Deref,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
 };
 
 use krb5_sys::krb5_kt_resolve;
@@ -14,6 +18,8 @@ use snafu::{ResultExt, Snafu};
 
 pub mod kadm5;
 pub mod profile;
+#[cfg(feature = "testing-tools")]
+pub mod testing;
 
 /// An error generated by libkrb5, or from interacting with it
 #[derive(Debug, Snafu)]
@@ -26,6 +32,31 @@ pub enum Error {
         source: std::num::TryFromIntError,
         string_name: &'static str,
     },
+
+    #[snafu(display("keytab has no entry for {principal} with KVNO {kvno} and enctype {enctype}"))]
+    EntryNotFound {
+        principal: String,
+        kvno: krb5_sys::krb5_kvno,
+        enctype: krb5_sys::krb5_enctype,
+    },
+
+    #[snafu(display("unknown enctype name {name:?}"))]
+    UnknownEnctypeName { name: String },
+
+    #[snafu(display("unknown enctype {enctype}"))]
+    UnknownEnctype { enctype: krb5_sys::krb5_enctype },
+
+    #[snafu(display("{function} is not supported by the linked libkrb5"))]
+    NotSupported { function: String },
+
+    #[snafu(display("principal must have at least one component"))]
+    NoPrincipalComponents,
+
+    #[snafu(display("failed to replace keytab file {}", path.display()))]
+    ReplaceKeytabFile {
+        source: std::io::Error,
+        path: PathBuf,
+    },
 }
 /// An error generated by libkrb5
 #[derive(Debug)]
@@ -66,6 +97,22 @@ impl Display for Krb5Error {
     }
 }
 
+/// Well-known libkrb5 error codes, for matching against [`Krb5Error::code`]. This is not exhaustive.
+pub mod error_code {
+    pub use krb5_sys::krb5_error_code;
+    pub const KT_NOTFOUND: i32 = krb5_sys::KRB5_KT_NOTFOUND as _;
+    pub const KT_END: i32 = krb5_sys::KRB5_KT_END as _;
+    pub const KT_KVNONOTFOUND: i32 = krb5_sys::KRB5_KT_KVNONOTFOUND as _;
+    pub const KDC_ERR_C_PRINCIPAL_UNKNOWN: i32 = krb5_sys::KRB5KDC_ERR_C_PRINCIPAL_UNKNOWN as _;
+    pub const KDC_ERR_CLIENT_REVOKED: i32 = krb5_sys::KRB5KDC_ERR_CLIENT_REVOKED as _;
+    pub const KDC_ERR_KEY_EXP: i32 = krb5_sys::KRB5KDC_ERR_KEY_EXP as _;
+    pub const KDC_ERR_PREAUTH_FAILED: i32 = krb5_sys::KRB5KDC_ERR_PREAUTH_FAILED as _;
+    pub const KRB_AP_ERR_BAD_INTEGRITY: i32 = krb5_sys::KRB5KRB_AP_ERR_BAD_INTEGRITY as _;
+    pub const KRB_AP_ERR_SKEW: i32 = krb5_sys::KRB5KRB_AP_ERR_SKEW as _;
+    pub const KDC_UNREACH: i32 = krb5_sys::KRB5_KDC_UNREACH as _;
+    pub const REALM_CANT_RESOLVE: i32 = krb5_sys::KRB5_REALM_CANT_RESOLVE as _;
+}
+
 /// An instance of the krb5 client
 ///
 /// Most other `krb5` data structures are linked to a specific `KrbContext`,
@@ -73,9 +120,14 @@ impl Display for Krb5Error {
 /// In Rust-land we represent this as them taking a borrow on their `KrbContext`.
 ///
 /// `KrbContext` is _not_ thread-safe, since it is mutated internally by libkrb5.
+/// It may be moved to another thread, but it cannot be shared between threads.
+/// Use [`KrbContext::copy`] to create a separate context for each thread instead, or wrap it in a [`SyncKrbContext`]
+/// to share it.
 pub struct KrbContext {
     raw: krb5_sys::krb5_context,
 }
+// SAFETY: libkrb5 contexts may be used from any thread, as long as they are never used by multiple threads concurrently
+unsafe impl Send for KrbContext {}
 impl KrbContext {
     /// Create a new context using the default configuration sources.
     pub fn new() -> Result<Self, Error> {
@@ -97,6 +149,17 @@ impl KrbContext {
         Ok(Self { raw: ctx })
     }
 
+    /// Create a new context with the same configuration as `self`.
+    ///
+    /// The copy is fully independent of `self`, and may be moved to a different thread.
+    pub fn copy(&self) -> Result<Self, Error> {
+        let mut ctx = std::ptr::null_mut();
+        unsafe {
+            Error::from_call_result(Some(self), krb5_sys::krb5_copy_context(self.raw, &mut ctx))
+        }?;
+        Ok(Self { raw: ctx })
+    }
+
     /// Parse a Kerberos principal into a [`Principal`].
     ///
     /// This will be done in the scope of the context, for example the context's default realm will be used if
@@ -115,6 +178,85 @@ impl KrbContext {
         })
     }
 
+    /// Build a [`Principal`] from a realm and a list of name components.
+    ///
+    /// Unlike [`Self::parse_principal_name`], all parts are taken literally, so components may contain characters
+    /// such as `/` and `@` without quoting. [`Principal::unparse`] quotes them again, unless
+    /// [`PrincipalUnparseOptions::for_display`] is set.
+    pub fn build_principal(&self, realm: &CStr, components: &[&CStr]) -> Result<Principal, Error> {
+        if components.is_empty() {
+            return NoPrincipalComponentsSnafu.fail();
+        }
+        let mut components = components
+            .iter()
+            .map(|component| borrowed_data(component.to_bytes(), "principal component"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let borrowed = krb5_sys::krb5_principal_data {
+            magic: krb5_sys::krb5_error_code(0),
+            realm: borrowed_data(realm.to_bytes(), "realm")?,
+            // krb5_copy_principal only reads from the input
+            data: components.as_mut_ptr(),
+            length: components.len().try_into().context(StringTooLongSnafu {
+                string_name: "principal components",
+            })?,
+            type_: krb5_sys::KRB5_NT_PRINCIPAL as _,
+        };
+        let mut principal = std::ptr::null_mut();
+        unsafe {
+            Error::from_call_result(
+                Some(self),
+                krb5_sys::krb5_copy_principal(self.raw, &borrowed, &mut principal),
+            )
+        }?;
+        Ok(Principal {
+            ctx: self,
+            raw: principal,
+        })
+    }
+
+    /// Resolve a [`CredentialCache`] by name.
+    ///
+    /// `name` should follow the format `{type}:{residual}`, such as `FILE:/foo/bar`.
+    /// Known types are:
+    /// - `FILE`: A credential cache serialized to a file.
+    /// - `MEMORY`: An in-memory credential cache.
+    ///
+    /// The cache must be [initialized](CredentialCache::initialize) before credentials can be stored in it.
+    pub fn resolve_ccache(&self, name: &CStr) -> Result<CredentialCache, Error> {
+        let mut raw = std::ptr::null_mut();
+        unsafe {
+            Error::from_call_result(
+                Some(self),
+                krb5_sys::krb5_cc_resolve(self.raw, name.as_ptr(), &mut raw),
+            )
+        }?;
+        Ok(CredentialCache { ctx: self, raw })
+    }
+
+    /// Get initial credentials (a TGT) for `client`, authenticating using its key in `keytab`.
+    pub fn get_init_creds_keytab(
+        &self,
+        client: &Principal,
+        keytab: &Keytab,
+    ) -> Result<Credentials, Error> {
+        unsafe {
+            let mut raw = std::mem::zeroed::<krb5_sys::krb5_creds>();
+            Error::from_call_result(
+                Some(self),
+                krb5_sys::krb5_get_init_creds_keytab(
+                    self.raw,
+                    &mut raw,
+                    client.raw,
+                    keytab.raw,
+                    0,
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                ),
+            )?;
+            Ok(Credentials { ctx: self, raw })
+        }
+    }
+
     /// Get the default realm configured for this context.
     pub fn default_realm(&self) -> Result<DefaultRealm, Error> {
         let mut realm: *mut c_char = std::ptr::null_mut();
@@ -138,6 +280,42 @@ impl Drop for KrbContext {
     }
 }
 
+/// A [`KrbContext`] that can be shared between threads (such as behind an [`Arc`](std::sync::Arc)).
+///
+/// Only one thread may use the context at a time, so it must be [locked](Self::lock) first. Objects created from the
+/// context (such as [`Principal`]s) borrow the lock guard, and so must be dropped before the context is unlocked.
+///
+/// Prefer [`KrbContext::copy`] for work that takes long enough that other threads would be blocked.
+pub struct SyncKrbContext {
+    ctx: Mutex<KrbContext>,
+}
+impl SyncKrbContext {
+    /// Create a new shareable context using the default configuration sources.
+    pub fn new() -> Result<Self, Error> {
+        KrbContext::new().map(Self::from)
+    }
+
+    /// Wait for exclusive access to the context.
+    pub fn lock(&self) -> MutexGuard<'_, KrbContext> {
+        // libkrb5 calls are not interrupted by panics in Rust code, so the context is still consistent
+        self.ctx.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run `f` with exclusive access to the context.
+    ///
+    /// `f` cannot leak objects created from the context, since they are tied to the lifetime of the borrow.
+    pub fn with<R>(&self, f: impl FnOnce(&KrbContext) -> R) -> R {
+        f(&self.lock())
+    }
+}
+impl From<KrbContext> for SyncKrbContext {
+    fn from(ctx: KrbContext) -> Self {
+        Self {
+            ctx: Mutex::new(ctx),
+        }
+    }
+}
+
 /// The default realm name for a [`KrbContext`].
 ///
 /// Created by [`KrbContext::default_realm`].
@@ -161,6 +339,10 @@ impl Drop for DefaultRealm<'_> {
 /// A parsed Kerberos principal name.
 ///
 /// Created by [`KrbContext::parse_principal_name`].
+///
+/// Principals are compared using the rules of the [`KrbContext`] of the left-hand side.
+/// Principals from different contexts should only be compared if those contexts share the same configuration
+/// (such as when one was created by [`KrbContext::copy`]-ing the other).
 pub struct Principal<'a> {
     ctx: &'a KrbContext,
     raw: krb5_sys::krb5_principal,
@@ -181,6 +363,58 @@ impl<'a> Principal<'a> {
         }
     }
 
+    /// The realm that the principal belongs to.
+    ///
+    /// This is empty for principals with an explicitly empty realm, such as `HTTP/foo@`.
+    /// The realm is returned as raw bytes, since libkrb5 does not guarantee that it is null-terminated.
+    pub fn realm(&self) -> &[u8] {
+        // SAFETY: self.raw is a valid principal for as long as self is alive
+        unsafe { data_as_bytes(&(*self.raw).realm) }
+    }
+
+    /// The name components of the principal, excluding the realm.
+    ///
+    /// For example, `HTTP/foo@EXAMPLE.COM` has the components `HTTP` and `foo`.
+    /// Like [`Self::realm`], components are returned as raw bytes.
+    pub fn components(&self) -> impl ExactSizeIterator<Item = &[u8]> + '_ {
+        // SAFETY: self.raw is a valid principal for as long as self is alive
+        let components: &[krb5_sys::krb5_data] = unsafe {
+            let raw = &*self.raw;
+            match raw.length {
+                0 => &[],
+                len => std::slice::from_raw_parts(raw.data, len as usize),
+            }
+        };
+        components
+            .iter()
+            // SAFETY: each component is owned by self.raw
+            .map(|component| unsafe { data_as_bytes(component) })
+    }
+
+    /// The number of name components, not counting the realm.
+    pub fn component_count(&self) -> usize {
+        self.components().len()
+    }
+
+    /// Whether `self` and `other` are the same principal. This is equivalent to `self == other`.
+    pub fn matches(&self, other: &Principal) -> bool {
+        // SAFETY: both principals are valid for as long as they are alive, and krb5_principal_compare does not retain them
+        unsafe { krb5_sys::krb5_principal_compare(self.ctx.raw, self.raw, other.raw) != 0 }
+    }
+
+    /// Whether `self` and `other` have the same name components, regardless of their realms.
+    pub fn matches_ignoring_realm(&self, other: &Principal) -> bool {
+        // SAFETY: see matches
+        unsafe {
+            krb5_sys::krb5_principal_compare_flags(
+                self.ctx.raw,
+                self.raw,
+                other.raw,
+                krb5_sys::KRB5_PRINCIPAL_COMPARE_IGNORE_REALM as c_int,
+            ) != 0
+        }
+    }
+
     /// Converts the parsed principal back into a string representation.
     ///
     /// The [`Display`] instance is equivalent to `self.unparse(PrincipalUnparseOptions::default())`.
@@ -205,6 +439,28 @@ impl<'a> Principal<'a> {
         Ok(name)
     }
 }
+/// Borrows the contents of `data`.
+// SAFETY: data must be valid for the returned lifetime
+unsafe fn data_as_bytes(data: &krb5_sys::krb5_data) -> &[u8] {
+    if data.length == 0 {
+        // data may be null for empty data, which from_raw_parts does not allow
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data.data.cast::<u8>(), data.length as usize) }
+    }
+}
+
+/// Wraps `bytes` in a [`krb5_sys::krb5_data`] without copying, for passing to functions that only read from it.
+fn borrowed_data(bytes: &[u8], string_name: &'static str) -> Result<krb5_sys::krb5_data, Error> {
+    Ok(krb5_sys::krb5_data {
+        magic: krb5_sys::krb5_error_code(0),
+        length: bytes
+            .len()
+            .try_into()
+            .context(StringTooLongSnafu { string_name })?,
+        data: bytes.as_ptr().cast::<c_char>().cast_mut(),
+    })
+}
 impl Drop for Principal<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -218,6 +474,12 @@ impl Display for Principal<'_> {
         f.write_str(name.as_deref().unwrap_or("(invalid)"))
     }
 }
+impl PartialEq<Principal<'_>> for Principal<'_> {
+    fn eq(&self, other: &Principal<'_>) -> bool {
+        self.matches(other)
+    }
+}
+impl Eq for Principal<'_> {}
 impl From<&Principal<'_>> for String {
     fn from(princ: &Principal<'_>) -> Self {
         princ.to_string()
@@ -267,6 +529,24 @@ pub struct KeyblockRef<'a> {
     ctx: &'a KrbContext,
     raw: *const krb5_sys::krb5_keyblock,
 }
+impl KeyblockRef<'_> {
+    /// The encryption type of the key.
+    pub fn enctype(&self) -> krb5_sys::krb5_enctype {
+        // SAFETY: raw is valid for as long as self is alive
+        unsafe { (*self.raw).enctype }
+    }
+
+    /// The length of the key, in bytes.
+    pub fn len(&self) -> usize {
+        // SAFETY: raw is valid for as long as self is alive
+        unsafe { (*self.raw).length as usize }
+    }
+
+    /// Whether the key is empty (zero bytes long).
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 
 /// An owned reference to a Kerberos keyblock.
 pub struct Keyblock<'a> {
@@ -331,6 +611,56 @@ impl<'a> Keyblock<'a> {
         Ok(kb)
     }
 
+    /// Generate a new random key.
+    ///
+    /// Some well-known `enctype` values are available in [`enctype`].
+    pub fn from_random(
+        ctx: &'a KrbContext,
+        enctype: krb5_sys::krb5_enctype,
+    ) -> Result<Self, Error> {
+        // krb5_c_make_random_key allocates the contents itself, sized for the enctype
+        let kb = Self::new(ctx, enctype, 0)?;
+        unsafe {
+            Error::from_call_result(
+                Some(ctx),
+                krb5_sys::krb5_c_make_random_key(ctx.raw, enctype, kb.raw),
+            )?;
+        }
+        Ok(kb)
+    }
+
+    /// Create an owned copy of a borrowed keyblock, such as one returned by [`kadm5::KeyDataVec::keys`].
+    pub fn copy_from_ref(ctx: &'a KrbContext, kref: &KeyblockRef<'_>) -> Result<Self, Error> {
+        let mut raw = std::ptr::null_mut();
+        unsafe {
+            Error::from_call_result(
+                Some(ctx),
+                krb5_sys::krb5_copy_keyblock(ctx.raw, kref.raw, &mut raw),
+            )?;
+        }
+        Ok(Self { ctx, raw })
+    }
+
+    /// Create an independent copy of the keyblock.
+    pub fn copy(&self) -> Result<Keyblock<'a>, Error> {
+        Self::copy_from_ref(self.ctx, &self.as_ref())
+    }
+
+    /// The encryption type of the key.
+    pub fn enctype(&self) -> krb5_sys::krb5_enctype {
+        self.as_ref().enctype()
+    }
+
+    /// The length of the key, in bytes.
+    pub fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    /// Whether the key is empty (zero bytes long).
+    pub fn is_empty(&self) -> bool {
+        self.as_ref().is_empty()
+    }
+
     // SAFETY: we own raw, so it is valid for as long as the reference to &śelf
     pub fn contents_mut(&mut self) -> Result<&mut [u8], Error> {
         unsafe {
@@ -368,9 +698,53 @@ impl Drop for Keyblock<'_> {
 }
 
 /// Well-known encryption types. This is not exhaustive.
+///
+/// Use [`parse_enctype`] and [`enctype_name`] to convert between enctypes and their names.
 pub mod enctype {
-    pub const AES256_CTS_HMAC_SHA1_96: krb5_sys::krb5_enctype =
+    use krb5_sys::krb5_enctype;
+
+    pub const AES128_CTS_HMAC_SHA1_96: krb5_enctype =
+        krb5_sys::ENCTYPE_AES128_CTS_HMAC_SHA1_96 as i32;
+    pub const AES256_CTS_HMAC_SHA1_96: krb5_enctype =
         krb5_sys::ENCTYPE_AES256_CTS_HMAC_SHA1_96 as i32;
+    pub const AES128_CTS_HMAC_SHA256_128: krb5_enctype =
+        krb5_sys::ENCTYPE_AES128_CTS_HMAC_SHA256_128 as i32;
+    pub const AES256_CTS_HMAC_SHA384_192: krb5_enctype =
+        krb5_sys::ENCTYPE_AES256_CTS_HMAC_SHA384_192 as i32;
+    pub const CAMELLIA128_CTS_CMAC: krb5_enctype = krb5_sys::ENCTYPE_CAMELLIA128_CTS_CMAC as i32;
+    pub const CAMELLIA256_CTS_CMAC: krb5_enctype = krb5_sys::ENCTYPE_CAMELLIA256_CTS_CMAC as i32;
+    /// RC4-HMAC, only for legacy Active Directory domains.
+    pub const ARCFOUR_HMAC: krb5_enctype = krb5_sys::ENCTYPE_ARCFOUR_HMAC as i32;
+    /// Triple DES, deprecated and disabled by default in modern libkrb5 releases.
+    pub const DES3_CBC_SHA1: krb5_enctype = krb5_sys::ENCTYPE_DES3_CBC_SHA1 as i32;
+}
+
+/// Looks up an enctype by its name (such as `aes256-cts-hmac-sha1-96`) or any of its aliases (such as `aes256-cts`).
+pub fn parse_enctype(name: &CStr) -> Result<krb5_sys::krb5_enctype, Error> {
+    let mut enctype = 0;
+    // krb5_string_to_enctype only fails if the name is unknown
+    let code = unsafe { krb5_sys::krb5_string_to_enctype(name.as_ptr().cast_mut(), &mut enctype) };
+    if code.0 != 0 {
+        return UnknownEnctypeNameSnafu {
+            name: name.to_string_lossy(),
+        }
+        .fail();
+    }
+    Ok(enctype)
+}
+
+/// Returns the canonical name of `enctype`, as accepted by [`parse_enctype`].
+pub fn enctype_name(enctype: krb5_sys::krb5_enctype) -> Result<String, Error> {
+    // Longer than any name that libkrb5 knows about
+    let mut buf = [0 as c_char; 128];
+    // krb5_enctype_to_name only fails if the enctype is unknown, or if the name doesn't fit
+    let code = unsafe { krb5_sys::krb5_enctype_to_name(enctype, 0, buf.as_mut_ptr(), buf.len()) };
+    if code.0 != 0 {
+        return UnknownEnctypeSnafu { enctype }.fail();
+    }
+    Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned())
 }
 
 /// A Kerberos keytab.
@@ -395,6 +769,31 @@ impl<'a> Keytab<'a> {
         Ok(Self { ctx, raw })
     }
 
+    /// Open the default keytab.
+    ///
+    /// This is taken from the `KRB5_KTNAME` environment variable if set, or the `default_keytab_name` setting in
+    /// `krb5.conf` otherwise. See [`Self::default_keytab_name`] for where that is.
+    pub fn default_keytab(ctx: &'a KrbContext) -> Result<Self, Error> {
+        let mut raw = std::ptr::null_mut();
+        unsafe { Error::from_call_result(Some(ctx), krb5_sys::krb5_kt_default(ctx.raw, &mut raw))? }
+        Ok(Self { ctx, raw })
+    }
+
+    /// The name of the keytab that [`Self::default_keytab`] would open, such as `FILE:/etc/krb5.keytab`.
+    pub fn default_keytab_name(ctx: &KrbContext) -> Result<String, Error> {
+        // Matches MAX_KEYTAB_NAME_LEN, libkrb5's own limit for keytab names
+        let mut buf = [0 as c_char; 1100];
+        unsafe {
+            Error::from_call_result(
+                Some(ctx),
+                krb5_sys::krb5_kt_default_name(ctx.raw, buf.as_mut_ptr(), buf.len() as c_int),
+            )?;
+        }
+        Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }
+            .to_string_lossy()
+            .into_owned())
+    }
+
     /// Add the specified key to the keytab.
     pub fn add(
         &mut self,
@@ -416,22 +815,218 @@ impl<'a> Keytab<'a> {
     }
 
     /// Remove the specified key from the keytab.
+    ///
+    /// Fails with [`Error::EntryNotFound`] if there is no matching entry, including if the keytab does not exist.
     pub fn remove(
         &mut self,
         principal: &Principal,
         kvno: krb5_sys::krb5_kvno,
+        enctype: krb5_sys::krb5_enctype,
     ) -> Result<(), Error> {
-        unsafe {
+        let code = unsafe {
             let mut entry: krb5_sys::krb5_keytab_entry = std::mem::zeroed();
             entry.principal = principal.raw;
             entry.vno = kvno;
+            entry.key.enctype = enctype;
+            // SAFETY: krb5_kt_remove_entry does not take ownership of entry
+            krb5_sys::krb5_kt_remove_entry(self.ctx.raw, self.raw, &mut entry)
+        };
+        if code.0 == error_code::KT_NOTFOUND || is_missing_file_error(code) {
+            return EntryNotFoundSnafu {
+                principal,
+                kvno,
+                enctype,
+            }
+            .fail();
+        }
+        unsafe { Error::from_call_result(Some(self.ctx), code) }
+    }
+
+    /// Remove an entry that was previously returned by [`Self::entries`] or [`Self::get_entry`].
+    ///
+    /// Fails with [`Error::EntryNotFound`] if the entry has already been removed.
+    pub fn remove_entry(&mut self, entry: &KeytabEntryRef) -> Result<(), Error> {
+        self.remove(entry.principal(), entry.kvno(), entry.enctype())
+    }
+
+    /// Remove all keys for `principal` from the keytab, regardless of KVNO and enctype.
+    ///
+    /// Returns the number of entries that were removed.
+    pub fn remove_principal(&mut self, principal: &Principal) -> Result<usize, Error> {
+        // The keytab cannot be modified while it is being iterated over
+        let keys = self
+            .entries()?
+            .filter_map(|entry| match entry {
+                Ok(entry) if entry.principal() == principal => {
+                    Some(Ok((entry.kvno(), entry.enctype())))
+                }
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (kvno, enctype) in &keys {
+            self.remove(principal, *kvno, *enctype)?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Replace all existing keys for `principal` with `keyblock`.
+    ///
+    /// `FILE` keytabs are replaced atomically: the new keytab is written to `<path>.tmp` (in the same directory),
+    /// synced to disk, and then renamed over the old one. Other readers observe either all of the old keys or all of
+    /// the new ones, never a keytab without any keys for `principal`. Any leftover `<path>.tmp` is overwritten.
+    pub fn replace(
+        &mut self,
+        principal: &Principal,
+        kvno: krb5_sys::krb5_kvno,
+        keyblock: &KeyblockRef,
+    ) -> Result<(), Error> {
+        let Some(path) = self.file_path()? else {
+            // Other keytab types only live inside this process, so nobody can observe the intermediate state
+            self.remove_principal(principal)?;
+            return self.add(principal, kvno, keyblock);
+        };
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        match std::fs::remove_file(&tmp_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).context(ReplaceKeytabFileSnafu { path });
+            }
+            _ => {}
+        }
+
+        let mut tmp_name = b"FILE:".to_vec();
+        tmp_name.extend_from_slice(tmp_path.as_os_str().as_bytes());
+        let tmp_name = CString::new(tmp_name).expect("keytab path must not contain NUL bytes");
+        let mut tmp = Keytab::resolve(self.ctx, &tmp_name)?;
+        for entry in self.entries()? {
+            let entry = entry?;
+            if entry.principal() != principal {
+                tmp.add(entry.principal(), entry.kvno(), &entry.keyblock())?;
+            }
+        }
+        tmp.add(principal, kvno, keyblock)?;
+        drop(tmp);
+
+        let swap = |path: &Path| {
+            // libkrb5 always creates keytabs that are only readable by the owner
+            if let Ok(metadata) = std::fs::metadata(path) {
+                std::fs::set_permissions(&tmp_path, metadata.permissions())?;
+            }
+            std::fs::File::open(&tmp_path)?.sync_all()?;
+            std::fs::rename(&tmp_path, path)
+        };
+        swap(&path).context(ReplaceKeytabFileSnafu { path })
+    }
+
+    /// The path of the file that the keytab is stored in, or [`None`] if it is not a `FILE` keytab.
+    fn file_path(&self) -> Result<Option<PathBuf>, Error> {
+        let kt_type = unsafe { CStr::from_ptr(krb5_sys::krb5_kt_get_type(self.ctx.raw, self.raw)) };
+        if kt_type != c"FILE" && kt_type != c"WRFILE" {
+            return Ok(None);
+        }
+        // Matches MAX_KEYTAB_NAME_LEN, libkrb5's own limit for keytab names
+        let mut buf = [0 as c_char; 1100];
+        unsafe {
             Error::from_call_result(
                 Some(self.ctx),
-                krb5_sys::krb5_kt_remove_entry(self.ctx.raw, self.raw, &mut entry),
-            )
+                krb5_sys::krb5_kt_get_name(
+                    self.ctx.raw,
+                    self.raw,
+                    buf.as_mut_ptr(),
+                    buf.len() as c_uint,
+                ),
+            )?;
+        }
+        // The name is prefixed with the type, such as FILE:/etc/krb5.keytab
+        let name = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_bytes();
+        let path = name
+            .iter()
+            .position(|&byte| byte == b':')
+            .map_or(name, |colon| &name[colon + 1..]);
+        Ok(Some(PathBuf::from(OsStr::from_bytes(path))))
+    }
+
+    /// Iterate over all entries in the keytab.
+    ///
+    /// A `FILE` keytab that does not exist yet is treated as empty.
+    ///
+    /// The entries themselves do not borrow the keytab, so they can be passed to [`Self::remove_entry`] once the
+    /// iterator has been dropped.
+    pub fn entries(&self) -> Result<KeytabEntries<'_, 'a>, Error> {
+        let mut cursor = std::ptr::null_mut();
+        let code = unsafe { krb5_sys::krb5_kt_start_seq_get(self.ctx.raw, self.raw, &mut cursor) };
+        if is_missing_file_error(code) {
+            return Ok(KeytabEntries {
+                keytab: self,
+                cursor: None,
+            });
+        }
+        unsafe { Error::from_call_result(Some(self.ctx), code) }?;
+        Ok(KeytabEntries {
+            keytab: self,
+            cursor: Some(cursor),
+        })
+    }
+
+    /// Add all entries of `self` to `dest`, skipping entries that `dest` already has a key for.
+    ///
+    /// Entries are considered duplicates if they have the same principal, KVNO, and encryption type.
+    /// A `FILE` keytab is created if it does not exist yet.
+    pub fn copy_to(&self, dest: &mut Keytab) -> Result<KeytabCopyStats, Error> {
+        let mut stats = KeytabCopyStats::default();
+        for entry in self.entries()? {
+            let entry = entry?;
+            let exists = dest
+                .get_entry(entry.principal(), entry.kvno(), entry.enctype())?
+                .is_some();
+            if exists {
+                stats.skipped += 1;
+            } else {
+                dest.add(entry.principal(), entry.kvno(), &entry.keyblock())?;
+                stats.copied += 1;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Look up a single entry in the keytab.
+    ///
+    /// `kvno` may be 0 to get the entry with the highest KVNO, and `enctype` may be 0 to accept any encryption type.
+    /// Returns [`None`] if there is no matching entry, or if the keytab does not exist.
+    pub fn get_entry(
+        &self,
+        principal: &Principal,
+        kvno: krb5_sys::krb5_kvno,
+        enctype: krb5_sys::krb5_enctype,
+    ) -> Result<Option<KeytabEntryRef<'a>>, Error> {
+        unsafe {
+            let mut entry: krb5_sys::krb5_keytab_entry = std::mem::zeroed();
+            let code = krb5_sys::krb5_kt_get_entry(
+                self.ctx.raw,
+                self.raw,
+                principal.raw,
+                kvno,
+                enctype,
+                &mut entry,
+            );
+            if code.0 == error_code::KT_NOTFOUND
+                || code.0 == error_code::KT_KVNONOTFOUND
+                || is_missing_file_error(code)
+            {
+                return Ok(None);
+            }
+            Error::from_call_result(Some(self.ctx), code)?;
+            Ok(Some(KeytabEntryRef::from_raw(self.ctx, entry)))
         }
     }
 }
+
+/// `FILE` keytabs return the OS error as-is if the file cannot be opened.
+fn is_missing_file_error(code: krb5_sys::krb5_error_code) -> bool {
+    std::io::Error::from_raw_os_error(code.0).kind() == std::io::ErrorKind::NotFound
+}
 impl Drop for Keytab<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -444,21 +1039,247 @@ impl Drop for Keytab<'_> {
     }
 }
 
+/// The outcome of [`Keytab::copy_to`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeytabCopyStats {
+    /// The number of entries that were added to the destination.
+    pub copied: usize,
+    /// The number of entries that the destination already had.
+    pub skipped: usize,
+}
+
+/// An iterator over the entries of a [`Keytab`].
+///
+/// Created by [`Keytab::entries`].
+pub struct KeytabEntries<'kt, 'a> {
+    keytab: &'kt Keytab<'a>,
+    /// [`None`] if the keytab does not exist, or once the cursor has been closed.
+    cursor: Option<krb5_sys::krb5_kt_cursor>,
+}
+impl<'a> Iterator for KeytabEntries<'_, 'a> {
+    type Item = Result<KeytabEntryRef<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Keytab { ctx, raw: keytab } = *self.keytab;
+        let cursor = self.cursor.as_mut()?;
+        unsafe {
+            let mut entry: krb5_sys::krb5_keytab_entry = std::mem::zeroed();
+            let code = krb5_sys::krb5_kt_next_entry(ctx.raw, keytab, &mut entry, cursor);
+            if code.0 == error_code::KT_END {
+                self.close();
+                return None;
+            }
+            if let Err(err) = Error::from_call_result(Some(ctx), code) {
+                self.close();
+                return Some(Err(err));
+            }
+            Some(Ok(KeytabEntryRef::from_raw(ctx, entry)))
+        }
+    }
+}
+impl KeytabEntries<'_, '_> {
+    fn close(&mut self) {
+        if let Some(mut cursor) = self.cursor.take() {
+            // There is nothing useful that we could do if closing the cursor fails
+            let _ = unsafe {
+                krb5_sys::krb5_kt_end_seq_get(self.keytab.ctx.raw, self.keytab.raw, &mut cursor)
+            };
+        }
+    }
+}
+impl Drop for KeytabEntries<'_, '_> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// A single key stored in a [`Keytab`].
+///
+/// Created by [`Keytab::entries`] and [`Keytab::get_entry`].
+pub struct KeytabEntryRef<'a> {
+    ctx: &'a KrbContext,
+    // Owned by raw, which is freed when the entry is dropped
+    principal: ManuallyDrop<Principal<'a>>,
+    raw: krb5_sys::krb5_keytab_entry,
+}
+impl<'a> KeytabEntryRef<'a> {
+    // SAFETY: takes ownership of raw, which must have been returned by libkrb5 for ctx
+    unsafe fn from_raw(ctx: &'a KrbContext, raw: krb5_sys::krb5_keytab_entry) -> Self {
+        Self {
+            ctx,
+            principal: ManuallyDrop::new(Principal {
+                ctx,
+                raw: raw.principal,
+            }),
+            raw,
+        }
+    }
+
+    /// The principal that the key belongs to.
+    pub fn principal(&self) -> &Principal<'a> {
+        &self.principal
+    }
+
+    /// The key version number.
+    pub fn kvno(&self) -> krb5_sys::krb5_kvno {
+        self.raw.vno
+    }
+
+    /// The encryption type of the key, see [`enctype`].
+    pub fn enctype(&self) -> krb5_sys::krb5_enctype {
+        self.raw.key.enctype
+    }
+
+    /// When the key was added to the keytab, in seconds since the Unix epoch.
+    pub fn timestamp(&self) -> krb5_sys::krb5_timestamp {
+        self.raw.timestamp
+    }
+
+    /// The key itself.
+    pub fn keyblock(&self) -> KeyblockRef<'_> {
+        KeyblockRef {
+            ctx: self.ctx,
+            raw: &self.raw.key,
+        }
+    }
+}
+impl Drop for KeytabEntryRef<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            // Also frees self.principal
+            let _ = krb5_sys::krb5_free_keytab_entry_contents(self.ctx.raw, &mut self.raw);
+        }
+    }
+}
+
+/// A Kerberos credential cache, which stores tickets for a single client principal.
+///
+/// Created by [`KrbContext::resolve_ccache`].
+pub struct CredentialCache<'a> {
+    ctx: &'a KrbContext,
+    raw: krb5_sys::krb5_ccache,
+}
+impl<'a> CredentialCache<'a> {
+    /// Clear the cache, and set its default principal to `primary`.
+    pub fn initialize(&mut self, primary: &Principal) -> Result<(), Error> {
+        unsafe {
+            Error::from_call_result(
+                Some(self.ctx),
+                krb5_sys::krb5_cc_initialize(self.ctx.raw, self.raw, primary.raw),
+            )
+        }
+    }
+
+    /// Store `creds` in the cache.
+    pub fn store_cred(&mut self, creds: &Credentials) -> Result<(), Error> {
+        // krb5_cc_store_cred takes a mutable pointer, but does not actually modify creds
+        let mut raw = creds.raw;
+        unsafe {
+            Error::from_call_result(
+                Some(self.ctx),
+                krb5_sys::krb5_cc_store_cred(self.ctx.raw, self.raw, &mut raw),
+            )
+        }
+    }
+
+    /// The default principal of the cache, as set by [`Self::initialize`].
+    pub fn principal(&self) -> Result<Principal<'a>, Error> {
+        let mut raw = std::ptr::null_mut();
+        unsafe {
+            Error::from_call_result(
+                Some(self.ctx),
+                krb5_sys::krb5_cc_get_principal(self.ctx.raw, self.raw, &mut raw),
+            )
+        }?;
+        Ok(Principal { ctx: self.ctx, raw })
+    }
+}
+impl Drop for CredentialCache<'_> {
+    fn drop(&mut self) {
+        // There is nothing useful that we could do if closing the cache fails, and panicking in drop could abort
+        let _ = unsafe { krb5_sys::krb5_cc_close(self.ctx.raw, self.raw) };
+    }
+}
+
+/// A set of Kerberos credentials (a ticket and its session key).
+///
+/// Created by [`KrbContext::get_init_creds_keytab`].
+pub struct Credentials<'a> {
+    ctx: &'a KrbContext,
+    raw: krb5_sys::krb5_creds,
+}
+impl Credentials<'_> {
+    /// The ticket's flags, such as [`krb5_sys::TKT_FLG_FORWARDABLE`].
+    pub fn ticket_flags(&self) -> krb5_sys::krb5_flags {
+        self.raw.ticket_flags
+    }
+
+    /// The encryption type of the ticket itself, which is encrypted using the service's key.
+    pub fn ticket_enctype(&self) -> Result<krb5_sys::krb5_enctype, Error> {
+        unsafe {
+            let mut ticket = std::ptr::null_mut();
+            Error::from_call_result(
+                Some(self.ctx),
+                krb5_sys::krb5_decode_ticket(&self.raw.ticket, &mut ticket),
+            )?;
+            let enctype = (*ticket).enc_part.enctype;
+            krb5_sys::krb5_free_ticket(self.ctx.raw, ticket);
+            Ok(enctype)
+        }
+    }
+}
+impl Drop for Credentials<'_> {
+    fn drop(&mut self) {
+        unsafe { krb5_sys::krb5_free_cred_contents(self.ctx.raw, &mut self.raw) }
+    }
+}
+
 /// Opaque Kerberos data
 pub struct KrbData<'a> {
     ctx: &'a KrbContext,
     raw: krb5_sys::krb5_data,
 }
+impl<'a> KrbData<'a> {
+    /// Copies `bytes` into memory owned by libkrb5.
+    pub fn from_bytes(ctx: &'a KrbContext, bytes: &[u8]) -> Result<Self, Error> {
+        // krb5_copy_data only reads from the input
+        let borrowed = borrowed_data(bytes, "data")?;
+        unsafe {
+            let mut copy = std::ptr::null_mut();
+            Error::from_call_result(
+                Some(ctx),
+                krb5_sys::krb5_copy_data(ctx.raw, &borrowed, &mut copy),
+            )?;
+            // Take ownership of the contents, and free the (now empty) outer struct
+            let raw = copy.replace(std::mem::zeroed());
+            krb5_sys::krb5_free_data(ctx.raw, copy);
+            Ok(Self { ctx, raw })
+        }
+    }
+
+    /// The raw contents of the data.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: self.raw is owned by self
+        unsafe { data_as_bytes(&self.raw) }
+    }
+
+    /// Copies the raw contents of the data.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+impl From<&KrbData<'_>> for Vec<u8> {
+    fn from(data: &KrbData<'_>) -> Self {
+        data.to_vec()
+    }
+}
 impl Debug for KrbData<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let slice = unsafe {
-            std::slice::from_raw_parts(
-                self.raw.data.cast::<u8>(),
-                self.raw.length.try_into().unwrap(),
-            )
-        };
-        let s = std::str::from_utf8(slice).unwrap();
-        Debug::fmt(s, f)
+        match std::str::from_utf8(self.as_bytes()) {
+            Ok(s) => Debug::fmt(s, f),
+            // Salts and key material are often binary, so print escaped bytes rather than failing
+            Err(_) => write!(f, "b\"{}\"", self.as_bytes().escape_ascii()),
+        }
     }
 }
 impl Drop for KrbData<'_> {
@@ -466,3 +1287,597 @@ impl Drop for KrbData<'_> {
         unsafe { krb5_sys::krb5_free_data_contents(self.ctx.raw, &mut self.raw) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CString, io::Read, sync::Arc};
+
+    use super::{
+        Credentials, Error, Keyblock, Keytab, KeytabCopyStats, KrbContext, KrbData,
+        PrincipalUnparseOptions, SyncKrbContext, enctype, enctype_name, parse_enctype,
+    };
+
+    #[test]
+    fn copied_context_can_be_used_on_another_thread() {
+        let ctx = KrbContext::new().unwrap();
+        let copy = ctx.copy().unwrap();
+        let name = c"HTTP/foo@EXAMPLE.COM";
+        let copy_principal =
+            std::thread::spawn(move || copy.parse_principal_name(name).unwrap().to_string())
+                .join()
+                .unwrap();
+        assert_eq!(copy_principal, "HTTP/foo@EXAMPLE.COM");
+        assert_eq!(
+            ctx.parse_principal_name(name).unwrap().to_string(),
+            copy_principal
+        );
+    }
+
+    #[test]
+    fn shared_context_can_be_used_from_many_threads() {
+        let ctx = Arc::new(SyncKrbContext::new().unwrap());
+        let threads = (0..16)
+            .map(|thread| {
+                let ctx = ctx.clone();
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let name = format!("HTTP/host-{thread}-{i}@EXAMPLE.COM");
+                        let ctx = ctx.lock();
+                        let principal = ctx
+                            .parse_principal_name(&CString::new(name.clone()).unwrap())
+                            .unwrap();
+                        assert_eq!(principal.to_string(), name);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn shared_context_can_be_borrowed_from_many_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SyncKrbContext>();
+
+        let ctx = Arc::new(SyncKrbContext::new().unwrap());
+        let threads = (0..8)
+            .map(|thread| {
+                let ctx = ctx.clone();
+                std::thread::spawn(move || {
+                    let name = CString::new(format!("HTTP/host-{thread}@EXAMPLE.COM")).unwrap();
+                    // The principal borrows the context, so it must be unparsed before the closure returns
+                    ctx.with(|ctx| ctx.parse_principal_name(&name).unwrap().to_string())
+                })
+            })
+            .collect::<Vec<_>>();
+        for (thread, handle) in threads.into_iter().enumerate() {
+            assert_eq!(
+                handle.join().unwrap(),
+                format!("HTTP/host-{thread}@EXAMPLE.COM")
+            );
+        }
+    }
+
+    #[test]
+    fn principal_realm() {
+        let ctx = KrbContext::new().unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        assert_eq!(princ.realm(), b"EXAMPLE.COM");
+        let princ = ctx.parse_principal_name(c"HTTP/foo@").unwrap();
+        assert_eq!(princ.realm(), b"");
+    }
+
+    #[test]
+    fn principal_eq() {
+        let ctx = KrbContext::new().unwrap();
+        let princ = ctx.parse_principal_name(c"host/foo@BAR").unwrap();
+        assert!(princ == ctx.parse_principal_name(c"host/foo@BAR").unwrap());
+        assert!(princ != ctx.parse_principal_name(c"host/foo@BAZ").unwrap());
+        assert!(princ != ctx.parse_principal_name(c"host/qux@BAR").unwrap());
+
+        // Copies of the same context can compare each other's principals
+        let copy = ctx.copy().unwrap();
+        assert!(princ == copy.parse_principal_name(c"host/foo@BAR").unwrap());
+
+        let other_realm = ctx.parse_principal_name(c"host/foo@BAZ").unwrap();
+        assert!(!princ.matches(&other_realm));
+        assert!(princ.matches_ignoring_realm(&other_realm));
+        let other_name = ctx.parse_principal_name(c"host/qux@BAR").unwrap();
+        assert!(!princ.matches_ignoring_realm(&other_name));
+    }
+
+    #[test]
+    fn principal_components() {
+        let ctx = KrbContext::new().unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/host@EXAMPLE.COM").unwrap();
+        let components = princ.components();
+        assert_eq!(components.len(), 2);
+        assert_eq!(components.collect::<Vec<_>>(), [b"HTTP" as &[u8], b"host"]);
+        let princ = ctx.parse_principal_name(c"admin@EXAMPLE.COM").unwrap();
+        assert_eq!(princ.components().collect::<Vec<_>>(), [b"admin"]);
+
+        // Components are not necessarily valid C strings or UTF-8
+        let princ = ctx
+            .parse_principal_name(c"HTTP/a\\0b\xff@EXAMPLE.COM")
+            .unwrap();
+        assert_eq!(
+            princ.components().collect::<Vec<_>>(),
+            [b"HTTP" as &[u8], b"a\0b\xff"]
+        );
+        assert_eq!(princ.component_count(), 2);
+    }
+
+    #[test]
+    fn build_principal() {
+        let ctx = KrbContext::new().unwrap();
+        let princ = ctx
+            .build_principal(c"EXAMPLE.COM", &[c"HTTP", c"host"])
+            .unwrap();
+        assert!(princ == ctx.parse_principal_name(c"HTTP/host@EXAMPLE.COM").unwrap());
+
+        // Special characters are taken literally, and quoted when unparsing
+        let princ = ctx
+            .build_principal(c"EXAMPLE.COM", &[c"HTTP", c"evil@OTHER.REALM/x"])
+            .unwrap();
+        assert_eq!(princ.realm(), b"EXAMPLE.COM");
+        assert_eq!(princ.component_count(), 2);
+        assert_eq!(princ.to_string(), "HTTP/evil\\@OTHER.REALM\\/x@EXAMPLE.COM");
+        let unparsed = CString::new(princ.to_string()).unwrap();
+        assert!(princ == ctx.parse_principal_name(&unparsed).unwrap());
+        assert_eq!(
+            princ
+                .unparse(PrincipalUnparseOptions {
+                    for_display: true,
+                    ..Default::default()
+                })
+                .unwrap(),
+            "HTTP/evil@OTHER.REALM/x@EXAMPLE.COM"
+        );
+
+        assert!(matches!(
+            ctx.build_principal(c"EXAMPLE.COM", &[]),
+            Err(Error::NoPrincipalComponents)
+        ));
+    }
+
+    #[test]
+    fn enctype_names_round_trip() {
+        for (name, enctype) in [
+            (c"aes128-cts-hmac-sha1-96", enctype::AES128_CTS_HMAC_SHA1_96),
+            (c"aes256-cts-hmac-sha1-96", enctype::AES256_CTS_HMAC_SHA1_96),
+            (
+                c"aes128-cts-hmac-sha256-128",
+                enctype::AES128_CTS_HMAC_SHA256_128,
+            ),
+            (
+                c"aes256-cts-hmac-sha384-192",
+                enctype::AES256_CTS_HMAC_SHA384_192,
+            ),
+            (c"camellia256-cts-cmac", enctype::CAMELLIA256_CTS_CMAC),
+            (c"arcfour-hmac", enctype::ARCFOUR_HMAC),
+        ] {
+            assert_eq!(parse_enctype(name).unwrap(), enctype, "{name:?}");
+            assert_eq!(enctype_name(enctype).unwrap().as_bytes(), name.to_bytes());
+        }
+        // Aliases resolve to the same enctype
+        assert_eq!(
+            parse_enctype(c"aes256-cts").unwrap(),
+            enctype::AES256_CTS_HMAC_SHA1_96
+        );
+    }
+
+    #[test]
+    fn enctype_names_unknown() {
+        let err = parse_enctype(c"aes512-cts").unwrap_err();
+        assert_eq!(err.to_string(), r#"unknown enctype name "aes512-cts""#);
+        assert!(matches!(
+            enctype_name(-1).unwrap_err(),
+            Error::UnknownEnctype { enctype: -1 }
+        ));
+    }
+
+    #[test]
+    fn ccache_initialize() {
+        let ctx = KrbContext::new().unwrap();
+        let mut ccache = ctx.resolve_ccache(c"MEMORY:ccache_initialize").unwrap();
+        let princ = ctx.parse_principal_name(c"alice@EXAMPLE.COM").unwrap();
+        ccache.initialize(&princ).unwrap();
+        assert_eq!(ccache.principal().unwrap().to_string(), "alice@EXAMPLE.COM");
+
+        // Build a fake ticket, since getting a real one would require a KDC
+        let server = ctx
+            .parse_principal_name(c"krbtgt/EXAMPLE.COM@EXAMPLE.COM")
+            .unwrap();
+        let mut raw = unsafe { std::mem::zeroed::<krb5_sys::krb5_creds>() };
+        unsafe {
+            for (princ, dest) in [(&princ, &mut raw.client), (&server, &mut raw.server)] {
+                let code = krb5_sys::krb5_copy_principal(ctx.raw, princ.raw, dest);
+                Error::from_call_result(Some(&ctx), code).unwrap();
+            }
+        }
+        let creds = Credentials { ctx: &ctx, raw };
+        ccache.store_cred(&creds).unwrap();
+
+        // Reinitializing replaces the default principal
+        let other = ctx.parse_principal_name(c"bob@EXAMPLE.COM").unwrap();
+        ccache.initialize(&other).unwrap();
+        assert_eq!(ccache.principal().unwrap().to_string(), "bob@EXAMPLE.COM");
+    }
+
+    #[test]
+    fn keyblock_from_random() {
+        let ctx = KrbContext::new().unwrap();
+        let mut first = Keyblock::from_random(&ctx, enctype::AES256_CTS_HMAC_SHA1_96).unwrap();
+        let mut second = Keyblock::from_random(&ctx, enctype::AES256_CTS_HMAC_SHA1_96).unwrap();
+        assert_eq!(first.contents_mut().unwrap().len(), 32);
+        assert_ne!(
+            first.contents_mut().unwrap(),
+            second.contents_mut().unwrap()
+        );
+        let mut aes128 = Keyblock::from_random(&ctx, enctype::AES128_CTS_HMAC_SHA1_96).unwrap();
+        assert_eq!(aes128.contents_mut().unwrap().len(), 16);
+        assert_eq!(aes128.enctype(), enctype::AES128_CTS_HMAC_SHA1_96);
+        assert_eq!(aes128.len(), 16);
+        assert_eq!(first.as_ref().enctype(), enctype::AES256_CTS_HMAC_SHA1_96);
+        assert_eq!(first.as_ref().len(), 32);
+
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut kt = Keytab::resolve(&ctx, c"MEMORY:keyblock_from_random").unwrap();
+        kt.add(&princ, 1, &first.as_ref()).unwrap();
+        assert_eq!(kt.entries().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn keyblock_copy() {
+        let ctx = KrbContext::new().unwrap();
+        let mut original = Keyblock::from_random(&ctx, enctype::AES256_CTS_HMAC_SHA1_96).unwrap();
+        let mut copy = original.copy().unwrap();
+        assert_eq!(copy.enctype(), enctype::AES256_CTS_HMAC_SHA1_96);
+        assert_eq!(
+            copy.contents_mut().unwrap(),
+            original.contents_mut().unwrap()
+        );
+
+        // The copy owns its own contents
+        let expected = copy.contents_mut().unwrap().to_vec();
+        original.contents_mut().unwrap().fill(0);
+        drop(original);
+        assert_eq!(copy.contents_mut().unwrap(), expected);
+
+        let mut from_ref = Keyblock::copy_from_ref(&ctx, &copy.as_ref()).unwrap();
+        drop(copy);
+        assert_eq!(from_ref.contents_mut().unwrap(), expected);
+    }
+
+    #[test]
+    fn krb_data_round_trip() {
+        let ctx = KrbContext::new().unwrap();
+        let salt = ctx
+            .parse_principal_name(c"HTTP/foo@EXAMPLE.COM")
+            .unwrap()
+            .default_salt()
+            .unwrap();
+        assert_eq!(salt.as_bytes(), b"EXAMPLE.COMHTTPfoo");
+        assert_eq!(format!("{salt:?}"), r#""EXAMPLE.COMHTTPfoo""#);
+
+        let empty = KrbData::from_bytes(&ctx, b"").unwrap();
+        assert_eq!(empty.as_bytes(), b"");
+        assert_eq!(format!("{empty:?}"), r#""""#);
+    }
+
+    #[test]
+    fn krb_data_with_invalid_utf8() {
+        let ctx = KrbContext::new().unwrap();
+        let bytes = b"EXAMPLE.COM\xff\x00salt\"";
+        let salt = KrbData::from_bytes(&ctx, bytes).unwrap();
+        assert_eq!(salt.as_bytes(), bytes);
+        assert_eq!(salt.to_vec(), bytes.to_vec());
+        assert_eq!(Vec::from(&salt), bytes.to_vec());
+        assert_eq!(format!("{salt:?}"), r#"b"EXAMPLE.COM\xff\x00salt\"""#);
+
+        // Binary salts must still be usable for deriving keys
+        let mut key =
+            Keyblock::from_password(&ctx, enctype::AES256_CTS_HMAC_SHA1_96, c"hunter2", &salt)
+                .unwrap();
+        let text_salt = KrbData::from_bytes(&ctx, b"EXAMPLE.COMsalt").unwrap();
+        let mut text_key = Keyblock::from_password(
+            &ctx,
+            enctype::AES256_CTS_HMAC_SHA1_96,
+            c"hunter2",
+            &text_salt,
+        )
+        .unwrap();
+        assert_ne!(
+            key.contents_mut().unwrap(),
+            text_key.contents_mut().unwrap()
+        );
+    }
+
+    #[test]
+    fn keytab_entries() {
+        let ctx = KrbContext::new().unwrap();
+        let mut kt = Keytab::resolve(&ctx, c"MEMORY:keytab_entries").unwrap();
+        assert_eq!(kt.entries().unwrap().count(), 0);
+
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut key = Keyblock::new(&ctx, enctype::AES256_CTS_HMAC_SHA1_96, 32).unwrap();
+        key.contents_mut().unwrap().fill(1);
+        kt.add(&princ, 1, &key.as_ref()).unwrap();
+        key.contents_mut().unwrap().fill(2);
+        kt.add(&princ, 2, &key.as_ref()).unwrap();
+
+        let mut entries = kt
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.principal().to_string(), entry.kvno())
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("HTTP/foo@EXAMPLE.COM".to_string(), 1),
+                ("HTTP/foo@EXAMPLE.COM".to_string(), 2)
+            ]
+        );
+
+        // Abandoning the iterator early must release the cursor, or the keytab could not be modified afterwards
+        assert!(kt.entries().unwrap().next().is_some());
+        kt.add(&princ, 3, &key.as_ref()).unwrap();
+        assert_eq!(kt.entries().unwrap().count(), 3);
+    }
+
+    #[test]
+    fn keytab_entries_of_nonexistent_file() {
+        let ctx = KrbContext::new().unwrap();
+        let dir = std::env::temp_dir().join(format!("krb5-test-{}", std::process::id()));
+        let name = std::ffi::CString::new(format!("FILE:{}/nonexistent", dir.display())).unwrap();
+        let kt = Keytab::resolve(&ctx, &name).unwrap();
+        assert_eq!(kt.entries().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn keytab_get_entry() {
+        let ctx = KrbContext::new().unwrap();
+        let mut kt = Keytab::resolve(&ctx, c"MEMORY:keytab_get_entry").unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let other_princ = ctx.parse_principal_name(c"HTTP/bar@EXAMPLE.COM").unwrap();
+        assert!(kt.get_entry(&princ, 0, 0).unwrap().is_none());
+
+        let mut key = Keyblock::new(&ctx, enctype::AES256_CTS_HMAC_SHA1_96, 32).unwrap();
+        for kvno in [1, 2] {
+            key.contents_mut().unwrap().fill(kvno as u8);
+            kt.add(&princ, kvno, &key.as_ref()).unwrap();
+        }
+
+        let latest = kt.get_entry(&princ, 0, 0).unwrap().unwrap();
+        assert_eq!(latest.principal().to_string(), "HTTP/foo@EXAMPLE.COM");
+        assert_eq!(latest.kvno(), 2);
+        assert_eq!(latest.enctype(), enctype::AES256_CTS_HMAC_SHA1_96);
+        // Keytab::add does not set a timestamp
+        assert_eq!(latest.timestamp(), 0);
+        let old = kt
+            .get_entry(&princ, 1, enctype::AES256_CTS_HMAC_SHA1_96)
+            .unwrap()
+            .unwrap();
+        assert_eq!(old.kvno(), 1);
+        assert!(kt.get_entry(&princ, 3, 0).unwrap().is_none());
+        assert!(kt.get_entry(&other_princ, 0, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn keytab_remove() {
+        let ctx = KrbContext::new().unwrap();
+        let mut kt = Keytab::resolve(&ctx, c"MEMORY:keytab_remove").unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut key = Keyblock::new(&ctx, enctype::AES256_CTS_HMAC_SHA1_96, 32).unwrap();
+        for kvno in [1, 2] {
+            key.contents_mut().unwrap().fill(kvno as u8);
+            kt.add(&princ, kvno, &key.as_ref()).unwrap();
+        }
+
+        kt.remove(&princ, 1, enctype::AES256_CTS_HMAC_SHA1_96)
+            .unwrap();
+        let kvnos = kt
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().kvno())
+            .collect::<Vec<_>>();
+        assert_eq!(kvnos, [2]);
+
+        let err = kt
+            .remove(&princ, 1, enctype::AES256_CTS_HMAC_SHA1_96)
+            .unwrap_err();
+        assert!(matches!(err, Error::EntryNotFound { kvno: 1, .. }), "{err}");
+    }
+
+    #[test]
+    fn keytab_remove_entry() {
+        let ctx = KrbContext::new().unwrap();
+        let mut kt = Keytab::resolve(&ctx, c"MEMORY:keytab_remove_entry").unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut key = Keyblock::new(&ctx, enctype::AES256_CTS_HMAC_SHA1_96, 32).unwrap();
+        for kvno in [1, 2] {
+            key.contents_mut().unwrap().fill(kvno as u8);
+            kt.add(&princ, kvno, &key.as_ref()).unwrap();
+        }
+
+        // Entries must be collected first, since the keytab cannot be modified while it is being iterated over
+        let stale = kt
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|entry| entry.kvno() < 2)
+            .collect::<Vec<_>>();
+        assert_eq!(stale.len(), 1);
+        kt.remove_entry(&stale[0]).unwrap();
+        let kvnos = kt
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().kvno())
+            .collect::<Vec<_>>();
+        assert_eq!(kvnos, [2]);
+
+        let err = kt.remove_entry(&stale[0]).unwrap_err();
+        assert!(matches!(err, Error::EntryNotFound { kvno: 1, .. }), "{err}");
+    }
+
+    #[test]
+    fn keytab_remove_from_nonexistent_file() {
+        let ctx = KrbContext::new().unwrap();
+        let dir = std::env::temp_dir().join(format!("krb5-test-remove-{}", std::process::id()));
+        let name = std::ffi::CString::new(format!("FILE:{}/nonexistent", dir.display())).unwrap();
+        let mut kt = Keytab::resolve(&ctx, &name).unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let err = kt
+            .remove(&princ, 1, enctype::AES256_CTS_HMAC_SHA1_96)
+            .unwrap_err();
+        assert!(matches!(err, Error::EntryNotFound { .. }), "{err}");
+        assert_eq!(kt.remove_principal(&princ).unwrap(), 0);
+    }
+
+    #[test]
+    fn keytab_copy_to() {
+        let ctx = KrbContext::new().unwrap();
+        let mut src = Keytab::resolve(&ctx, c"MEMORY:keytab_copy_to").unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let mut key = Keyblock::new(&ctx, enctype::AES256_CTS_HMAC_SHA1_96, 32).unwrap();
+        for kvno in [1, 2] {
+            key.contents_mut().unwrap().fill(kvno as u8);
+            src.add(&princ, kvno, &key.as_ref()).unwrap();
+        }
+
+        let dir = std::env::temp_dir().join(format!("krb5-test-copy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let name = std::ffi::CString::new(format!("FILE:{}/keytab", dir.display())).unwrap();
+        let mut dest = Keytab::resolve(&ctx, &name).unwrap();
+        let stats = src.copy_to(&mut dest).unwrap();
+        assert_eq!(
+            stats,
+            KeytabCopyStats {
+                copied: 2,
+                skipped: 0
+            }
+        );
+
+        key.contents_mut().unwrap().fill(3);
+        src.add(&princ, 3, &key.as_ref()).unwrap();
+        let stats = src.copy_to(&mut dest).unwrap();
+        assert_eq!(
+            stats,
+            KeytabCopyStats {
+                copied: 1,
+                skipped: 2
+            }
+        );
+        let mut kvnos = dest
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().kvno())
+            .collect::<Vec<_>>();
+        kvnos.sort();
+        assert_eq!(kvnos, [1, 2, 3]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keytab_replace() {
+        let ctx = KrbContext::new().unwrap();
+        let mut kt = Keytab::resolve(&ctx, c"MEMORY:keytab_replace").unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let other_princ = ctx.parse_principal_name(c"HTTP/bar@EXAMPLE.COM").unwrap();
+        let mut key = Keyblock::new(&ctx, enctype::AES256_CTS_HMAC_SHA1_96, 32).unwrap();
+        for kvno in [1, 2] {
+            kt.add(&princ, kvno, &key.as_ref()).unwrap();
+        }
+        kt.add(&other_princ, 1, &key.as_ref()).unwrap();
+
+        key.contents_mut().unwrap().fill(3);
+        kt.replace(&princ, 3, &key.as_ref()).unwrap();
+        let mut entries = kt
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.principal().to_string(), entry.kvno())
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("HTTP/bar@EXAMPLE.COM".to_string(), 1),
+                ("HTTP/foo@EXAMPLE.COM".to_string(), 3)
+            ]
+        );
+
+        assert_eq!(kt.remove_principal(&princ).unwrap(), 1);
+        assert_eq!(kt.remove_principal(&princ).unwrap(), 0);
+        assert_eq!(kt.entries().unwrap().count(), 1);
+    }
+
+    fn sorted_entries(kt: &Keytab) -> Vec<(String, u32)> {
+        let mut entries = kt
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.principal().to_string(), entry.kvno())
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn keytab_replace_file_swaps_atomically() {
+        let ctx = KrbContext::new().unwrap();
+        let dir = std::env::temp_dir().join(format!("krb5-test-replace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("keytab");
+        let name = CString::new(format!("FILE:{}", path.display())).unwrap();
+        let mut kt = Keytab::resolve(&ctx, &name).unwrap();
+        let princ = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        let other_princ = ctx.parse_principal_name(c"HTTP/bar@EXAMPLE.COM").unwrap();
+        let mut key = Keyblock::new(&ctx, enctype::AES256_CTS_HMAC_SHA1_96, 32).unwrap();
+        for kvno in [1, 2] {
+            kt.add(&princ, kvno, &key.as_ref()).unwrap();
+        }
+        kt.add(&other_princ, 1, &key.as_ref()).unwrap();
+        // A stale temporary file from an interrupted replacement must not leak into the new keytab
+        std::fs::write(dir.join("keytab.tmp"), b"garbage").unwrap();
+
+        // Readers that opened the keytab before the swap keep reading the old keytab as a whole
+        let mut reader = std::fs::File::open(&path).unwrap();
+        key.contents_mut().unwrap().fill(3);
+        kt.replace(&princ, 3, &key.as_ref()).unwrap();
+        let mut old_contents = Vec::new();
+        reader.read_to_end(&mut old_contents).unwrap();
+        let old_path = dir.join("old-keytab");
+        std::fs::write(&old_path, old_contents).unwrap();
+        let old_name = CString::new(format!("FILE:{}", old_path.display())).unwrap();
+        let old_kt = Keytab::resolve(&ctx, &old_name).unwrap();
+        assert_eq!(
+            sorted_entries(&old_kt),
+            [
+                ("HTTP/bar@EXAMPLE.COM".to_string(), 1),
+                ("HTTP/foo@EXAMPLE.COM".to_string(), 1),
+                ("HTTP/foo@EXAMPLE.COM".to_string(), 2),
+            ]
+        );
+
+        assert_eq!(
+            sorted_entries(&kt),
+            [
+                ("HTTP/bar@EXAMPLE.COM".to_string(), 1),
+                ("HTTP/foo@EXAMPLE.COM".to_string(), 3)
+            ]
+        );
+        assert!(!dir.join("keytab.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
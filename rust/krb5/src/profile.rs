@@ -54,25 +54,110 @@ impl Profile {
     }
 
     /// Set a configuration value.
+    ///
+    /// `key_path` consists of the section name, any subsection names, and the relation name. For example,
+    /// `[c"realms", c"EXAMPLE.COM", c"kdc"]` sets the KDC of the realm `EXAMPLE.COM`.
     pub fn set(&mut self, key_path: &[&CStr], value: &CStr) -> Result<(), ProfileError> {
-        let mut key_path = key_path
-            .iter()
-            .map(|s| s.as_ptr())
-            // Path is terminated by null pointer
-            .chain([std::ptr::null()])
-            .collect::<Vec<*const c_char>>();
+        let mut key_path = c_key_path(key_path);
         ProfileError::from_code(unsafe {
             krb5_sys::profile_add_relation(self.raw, key_path.as_mut_ptr(), value.as_ptr())
         })
     }
 
+    /// Get the first value of a relation, or `None` if it is not set.
+    ///
+    /// `key_path` has the same format as for [`Self::set`].
+    pub fn get_string(&self, key_path: &[&CStr]) -> Result<Option<String>, ProfileError> {
+        let key_path = c_key_path(key_path);
+        let mut values = std::ptr::null_mut();
+        let code =
+            unsafe { krb5_sys::profile_get_values(self.raw, key_path.as_ptr(), &mut values) };
+        if code == krb5_sys::PROF_NO_SECTION.into() || code == krb5_sys::PROF_NO_RELATION.into() {
+            return Ok(None);
+        }
+        ProfileError::from_code(code)?;
+        // SAFETY: values is a null-terminated list, which contains at least one value if the call succeeded
+        unsafe {
+            let value = CStr::from_ptr(*values).to_string_lossy().into_owned();
+            krb5_sys::profile_free_list(values);
+            Ok(Some(value))
+        }
+    }
+
     /// Save any modifications made to the file, if it was created using [`Self::from_path`].
     pub fn flush(&mut self) -> Result<(), ProfileError> {
         ProfileError::from_code(unsafe { krb5_sys::profile_flush(self.raw) })
     }
+
+    /// Write the profile to the file at `path`, in krb5.conf format.
+    ///
+    /// This can be used to hand a generated profile to other Kerberos clients.
+    pub fn flush_to_file(&self, path: &CStr) -> Result<(), ProfileError> {
+        ProfileError::from_code(unsafe { krb5_sys::profile_flush_to_file(self.raw, path.as_ptr()) })
+    }
 }
+
 impl Drop for Profile {
     fn drop(&mut self) {
         unsafe { krb5_sys::profile_abandon(self.raw) }
     }
 }
+
+/// Converts `key_path` into the null-terminated list of names that libprofile expects.
+fn c_key_path(key_path: &[&CStr]) -> Vec<*const c_char> {
+    key_path
+        .iter()
+        .map(|s| s.as_ptr())
+        .chain([std::ptr::null()])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::Profile;
+    use crate::KrbContext;
+
+    #[test]
+    fn profile_round_trip() {
+        let mut profile = Profile::new().unwrap();
+        profile
+            .set(&[c"libdefaults", c"default_realm"], c"EXAMPLE.COM")
+            .unwrap();
+        profile
+            .set(&[c"realms", c"EXAMPLE.COM", c"kdc"], c"kdc.example.com")
+            .unwrap();
+        assert_eq!(
+            profile
+                .get_string(&[c"realms", c"EXAMPLE.COM", c"kdc"])
+                .unwrap()
+                .as_deref(),
+            Some("kdc.example.com")
+        );
+        assert_eq!(
+            profile
+                .get_string(&[c"realms", c"EXAMPLE.COM", c"admin_server"])
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            profile
+                .get_string(&[c"appdefaults", c"forwardable"])
+                .unwrap(),
+            None
+        );
+
+        let ctx = KrbContext::from_profile(&profile).unwrap();
+        assert_eq!(&*ctx.default_realm().unwrap(), c"EXAMPLE.COM");
+
+        let path = std::env::temp_dir().join(format!("krb5-profile-{}.conf", std::process::id()));
+        profile
+            .flush_to_file(&CString::new(path.to_str().unwrap()).unwrap())
+            .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(written.contains("default_realm = EXAMPLE.COM"), "{written}");
+        assert!(written.contains("kdc = kdc.example.com"), "{written}");
+    }
+}
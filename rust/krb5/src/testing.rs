@@ -0,0 +1,139 @@
+//! Helpers for testing Kerberos setups against a real KDC, such as checking that provisioned keytabs can be used for
+//! constrained delegation.
+//!
+//! Only available with the `testing-tools` feature.
+
+use std::ffi::{CStr, c_void};
+
+use crate::{CredentialCache, Credentials, Error, KrbContext, NotSupportedSnafu, Principal};
+
+/// Get a service ticket for `service`, using the TGT of the default principal in `ccache`.
+///
+/// The ticket is also stored in `ccache`.
+pub fn get_service_ticket<'a>(
+    ctx: &'a KrbContext,
+    ccache: &CredentialCache<'a>,
+    service: &Principal,
+) -> Result<Credentials<'a>, Error> {
+    let client = ccache.principal()?;
+    let mut in_creds = unsafe { std::mem::zeroed::<krb5_sys::krb5_creds>() };
+    // libkrb5 doesn't take ownership of the principals in in_creds
+    in_creds.client = client.raw;
+    in_creds.server = service.raw;
+    let mut out_creds = std::ptr::null_mut();
+    unsafe {
+        Error::from_call_result(
+            Some(ctx),
+            krb5_sys::krb5_get_credentials(ctx.raw, 0, ccache.raw, &mut in_creds, &mut out_creds),
+        )?;
+        Ok(take_creds(ctx, out_creds))
+    }
+}
+
+/// Signature of `krb5_get_credentials_for_user`, which is exported by MIT libkrb5 but not declared in its public
+/// headers.
+type GetCredentialsForUser = unsafe extern "C" fn(
+    context: krb5_sys::krb5_context,
+    options: krb5_sys::krb5_flags,
+    ccache: krb5_sys::krb5_ccache,
+    in_creds: *mut krb5_sys::krb5_creds,
+    subject_cert: *mut krb5_sys::krb5_data,
+    out_creds: *mut *mut krb5_sys::krb5_creds,
+) -> krb5_sys::krb5_error_code;
+
+const GET_CREDENTIALS_FOR_USER: &CStr = c"krb5_get_credentials_for_user";
+
+/// Looks up `krb5_get_credentials_for_user` at runtime, since not all libkrb5 builds provide it.
+fn get_credentials_for_user_fn() -> Option<GetCredentialsForUser> {
+    // SAFETY: the name is null-terminated, and the symbol (if present) has the signature of GetCredentialsForUser
+    unsafe {
+        let sym: *mut c_void = libc::dlsym(libc::RTLD_DEFAULT, GET_CREDENTIALS_FOR_USER.as_ptr());
+        (!sym.is_null()).then(|| std::mem::transmute::<*mut c_void, GetCredentialsForUser>(sym))
+    }
+}
+
+/// Whether the linked libkrb5 supports [`impersonate_user`].
+pub fn is_s4u2self_supported() -> bool {
+    get_credentials_for_user_fn().is_some()
+}
+
+/// Get a service ticket to the default principal in `ccache`, on behalf of `user` (S4U2self).
+///
+/// The service's TGT must already be in `ccache`. The returned ticket's [flags](Credentials::ticket_flags) show
+/// whether it can be forwarded to other services (S4U2proxy).
+///
+/// Fails with [`Error::NotSupported`] if the linked libkrb5 does not implement S4U2self.
+pub fn impersonate_user<'a>(
+    ctx: &'a KrbContext,
+    ccache: &CredentialCache<'a>,
+    user: &Principal,
+) -> Result<Credentials<'a>, Error> {
+    let get_credentials_for_user = get_credentials_for_user_fn().ok_or_else(|| {
+        NotSupportedSnafu {
+            function: GET_CREDENTIALS_FOR_USER.to_string_lossy(),
+        }
+        .build()
+    })?;
+    let service = ccache.principal()?;
+    let mut in_creds = unsafe { std::mem::zeroed::<krb5_sys::krb5_creds>() };
+    // libkrb5 doesn't take ownership of the principals in in_creds
+    in_creds.client = user.raw;
+    in_creds.server = service.raw;
+    let mut out_creds = std::ptr::null_mut();
+    unsafe {
+        Error::from_call_result(
+            Some(ctx),
+            get_credentials_for_user(
+                ctx.raw,
+                0,
+                ccache.raw,
+                &mut in_creds,
+                std::ptr::null_mut(),
+                &mut out_creds,
+            ),
+        )?;
+        Ok(take_creds(ctx, out_creds))
+    }
+}
+
+// SAFETY: takes ownership of raw, which must have been allocated by libkrb5 for ctx
+unsafe fn take_creds(ctx: &KrbContext, raw: *mut krb5_sys::krb5_creds) -> Credentials<'_> {
+    unsafe {
+        let creds = Credentials {
+            ctx,
+            raw: raw.read(),
+        };
+        // The contents are now owned by creds, so they are cleared before libkrb5 frees the outer struct
+        raw.write(std::mem::zeroed());
+        krb5_sys::krb5_free_creds(ctx.raw, raw);
+        creds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_service_ticket, impersonate_user, is_s4u2self_supported};
+    use crate::{Error, KrbContext};
+
+    #[test]
+    fn s4u2self_is_supported_by_mit() {
+        assert!(is_s4u2self_supported());
+    }
+
+    #[test]
+    fn requests_without_tgt_fail_cleanly() {
+        let ctx = KrbContext::new().unwrap();
+        let mut ccache = ctx
+            .resolve_ccache(c"MEMORY:requests_without_tgt_fail_cleanly")
+            .unwrap();
+        let service = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+        ccache.initialize(&service).unwrap();
+        let user = ctx.parse_principal_name(c"alice@EXAMPLE.COM").unwrap();
+
+        // There is no TGT (or KDC) to get tickets from
+        let err = get_service_ticket(&ctx, &ccache, &service).err().unwrap();
+        assert!(matches!(err, Error::Krb5 { .. }), "{err}");
+        let err = impersonate_user(&ctx, &ccache, &user).err().unwrap();
+        assert!(matches!(err, Error::Krb5 { .. }), "{err}");
+    }
+}
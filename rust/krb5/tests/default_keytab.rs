@@ -0,0 +1,27 @@
+//! `KRB5_KTNAME` is process-global, so these tests live in their own test binary.
+
+use krb5::{Keyblock, Keytab, KrbContext, enctype};
+
+#[test]
+fn default_keytab_follows_krb5_ktname() {
+    let dir = std::env::temp_dir().join(format!("krb5-default-keytab-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("krb5.keytab");
+    let name = format!("FILE:{}", path.display());
+    // SAFETY: this is the only test in this binary, so nothing else reads the environment concurrently
+    unsafe { std::env::set_var("KRB5_KTNAME", &name) };
+
+    let ctx = KrbContext::new().unwrap();
+    assert_eq!(Keytab::default_keytab_name(&ctx).unwrap(), name);
+
+    let principal = ctx.parse_principal_name(c"HTTP/foo@EXAMPLE.COM").unwrap();
+    let key = Keyblock::from_random(&ctx, enctype::AES256_CTS_HMAC_SHA1_96).unwrap();
+    let mut keytab = Keytab::default_keytab(&ctx).unwrap();
+    keytab.add(&principal, 1, &key.as_ref()).unwrap();
+    assert!(path.exists());
+
+    let keytab = Keytab::default_keytab(&ctx).unwrap();
+    assert_eq!(keytab.entries().unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
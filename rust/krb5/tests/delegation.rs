@@ -0,0 +1,64 @@
+//! Checks that a provisioned keytab can be used for constrained delegation, against a real KDC.
+//!
+//! Run with `cargo test -p krb5 --features testing-tools --test delegation -- --ignored`, after setting:
+//!
+//! - `KRB5_TEST_SERVICE_PRINCIPAL` and `KRB5_TEST_SERVICE_KEYTAB` to a service principal (in the default realm of
+//!   `KRB5_CONFIG`) that is allowed to delegate (`ok_to_auth_as_delegate`), and a keytab that contains its keys.
+//! - `KRB5_TEST_IMPERSONATED_USER` to an existing user principal that the service should impersonate.
+
+use std::ffi::CString;
+
+use krb5::{
+    Keytab, KrbContext,
+    testing::{get_service_ticket, impersonate_user, is_s4u2self_supported},
+};
+
+/// `TKT_FLG_FORWARDABLE` from `krb5.h`, which krb5-sys does not export.
+const TKT_FLG_FORWARDABLE: krb5_sys::krb5_flags = 0x40000000;
+
+fn env(name: &str) -> CString {
+    let value = std::env::var(name).unwrap_or_else(|_| panic!("{name} must be set"));
+    CString::new(value).unwrap()
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn keytab_principal_can_impersonate_users() {
+    assert!(
+        is_s4u2self_supported(),
+        "krb5_get_credentials_for_user is not supported by the linked libkrb5"
+    );
+    let ctx = KrbContext::new().unwrap();
+    let service = ctx
+        .parse_principal_name(&env("KRB5_TEST_SERVICE_PRINCIPAL"))
+        .unwrap();
+    let mut keytab_name = c"FILE:".to_bytes().to_vec();
+    keytab_name.extend_from_slice(env("KRB5_TEST_SERVICE_KEYTAB").as_bytes());
+    let keytab = Keytab::resolve(&ctx, &CString::new(keytab_name).unwrap()).unwrap();
+    let user = ctx
+        .parse_principal_name(&env("KRB5_TEST_IMPERSONATED_USER"))
+        .unwrap();
+
+    let mut ccache = ctx
+        .resolve_ccache(c"MEMORY:keytab_principal_can_impersonate_users")
+        .unwrap();
+    ccache.initialize(&service).unwrap();
+    let tgt = ctx.get_init_creds_keytab(&service, &keytab).unwrap();
+    ccache.store_cred(&tgt).unwrap();
+
+    // The service can only decrypt tickets issued to it if the keytab has a key of the ticket's enctype
+    let ticket = get_service_ticket(&ctx, &ccache, &service).unwrap();
+    let enctype = ticket.ticket_enctype().unwrap();
+    assert!(
+        keytab.get_entry(&service, 0, enctype).unwrap().is_some(),
+        "keytab has no key for the enctype of the service ticket ({enctype})"
+    );
+
+    // S4U2proxy requires a forwardable evidence ticket from S4U2self
+    let evidence = impersonate_user(&ctx, &ccache, &user).unwrap();
+    assert_ne!(
+        evidence.ticket_flags() & TKT_FLG_FORWARDABLE,
+        0,
+        "S4U2self ticket is not forwardable, so it can't be used for constrained delegation"
+    );
+}
@@ -0,0 +1,253 @@
+//! Tests against a real kadmind, which is not available in most build environments.
+//!
+//! Run with `cargo test -p krb5 --test kadmin -- --ignored`, after setting `KRB5_TEST_ADMIN_PRINCIPAL` and
+//! `KRB5_TEST_ADMIN_KEYTAB` to an admin principal (in the default realm of `KRB5_CONFIG`) that may add, rename,
+//! modify, list and delete principals, and change their passwords.
+
+use std::ffi::{CStr, CString};
+
+use krb5::{
+    KrbContext, Principal,
+    kadm5::{self, ConfigParams, Credential, ServerHandle},
+};
+
+fn env(name: &str) -> CString {
+    let value = std::env::var(name).unwrap_or_else(|_| panic!("{name} must be set"));
+    CString::new(value).unwrap()
+}
+
+fn admin(ctx: &KrbContext) -> ServerHandle<'_> {
+    ServerHandle::new(
+        ctx,
+        &env("KRB5_TEST_ADMIN_PRINCIPAL"),
+        None,
+        &Credential::ServiceKey {
+            keytab: env("KRB5_TEST_ADMIN_KEYTAB"),
+        },
+        &ConfigParams::default(),
+    )
+    .unwrap()
+}
+
+/// A principal name that is unique to this test run, so that concurrent runs against the same KDC don't collide.
+fn principal<'a>(ctx: &'a KrbContext, prefix: &str) -> Principal<'a> {
+    let suffix = std::process::id();
+    ctx.parse_principal_name(&CString::new(format!("{prefix}-{suffix}")).unwrap())
+        .unwrap()
+}
+
+fn kvnos(admin: &ServerHandle, principal: &Principal) -> Vec<u32> {
+    let mut kvnos = admin
+        .get_principal_keys(principal, kadm5::KVNO_ALL)
+        .unwrap()
+        .keys()
+        .map(|key| key.kvno)
+        .collect::<Vec<_>>();
+    kvnos.sort();
+    kvnos.dedup();
+    kvnos
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn rename_principal_keeps_keys_and_removes_old_name() {
+    let ctx = KrbContext::new().unwrap();
+    let admin = admin(&ctx);
+
+    let from = principal(&ctx, "rename-from");
+    let to = principal(&ctx, "rename-to");
+    admin.create_principal(&from).unwrap();
+    let kvno = admin.get_principal(&from).unwrap().unwrap().kvno;
+
+    admin.rename_principal(&from, &to).unwrap();
+    assert!(admin.get_principal(&from).unwrap().is_none());
+    let renamed = admin.get_principal(&to).unwrap().unwrap();
+    assert_eq!(renamed.kvno, kvno, "renaming must not rotate keys");
+
+    let err = admin.rename_principal(&from, &to).unwrap_err();
+    assert!(err.is_unknown_principal(), "{err}");
+
+    admin.delete_principal(&to).unwrap();
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn purge_keys_keeps_newest_kvnos() {
+    let ctx = KrbContext::new().unwrap();
+    let admin = admin(&ctx);
+
+    let princ = principal(&ctx, "purge-keys");
+    admin.create_principal(&princ).unwrap();
+    for _ in 0..3 {
+        admin.randkey_principal(&princ, true, &[]).unwrap();
+    }
+    assert_eq!(kvnos(&admin, &princ), [1, 2, 3, 4]);
+
+    admin.purge_keys(&princ, 2).unwrap();
+    assert_eq!(kvnos(&admin, &princ), [3, 4]);
+    // The current key is always retained
+    admin.purge_keys(&princ, 0).unwrap();
+    assert_eq!(kvnos(&admin, &princ), [4]);
+
+    admin.delete_principal(&princ).unwrap();
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn string_attributes_round_trip() {
+    let ctx = KrbContext::new().unwrap();
+    let admin = admin(&ctx);
+
+    let princ = principal(&ctx, "string-attributes");
+    admin.create_principal(&princ).unwrap();
+    assert_eq!(admin.get_string(&princ, c"test-key").unwrap(), None);
+    admin.set_string(&princ, c"test-key", c"value").unwrap();
+    assert_eq!(
+        admin.get_string(&princ, c"test-key").unwrap().as_deref(),
+        Some("value")
+    );
+
+    admin.delete_principal(&princ).unwrap();
+    let err = admin.get_string(&princ, c"test-key").unwrap_err();
+    assert!(err.is_unknown_principal(), "{err}");
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn password_credentials_authenticate_client() {
+    let ctx = KrbContext::new().unwrap();
+    let admin = admin(&ctx);
+
+    let client = principal(&ctx, "password-client");
+    let password = c"correct horse battery staple";
+    admin.create_principal(&client).unwrap();
+    admin.change_password(&client, password).unwrap();
+    let client_name = CString::new(client.to_string()).unwrap();
+    let connect = |password: &CStr| {
+        ServerHandle::new(
+            &ctx,
+            &client_name,
+            None,
+            &Credential::Password {
+                password: password.to_owned(),
+            },
+            &ConfigParams::default(),
+        )
+    };
+
+    connect(password).unwrap();
+    // libkadm5 reports rejected passwords as KADM5_BAD_PASSWORD, rather than as the underlying libkrb5 error
+    let err = connect(c"wrong password").err().unwrap();
+    assert!(err.is_bad_password(), "{err}");
+    assert_eq!(err.code.0, kadm5::error_code::BAD_PASSWORD);
+
+    admin.delete_principal(&client).unwrap();
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn deleting_principal_twice_reports_unknown_principal() {
+    let ctx = KrbContext::new().unwrap();
+    let admin = admin(&ctx);
+
+    let princ = principal(&ctx, "delete-twice");
+    admin.create_principal(&princ).unwrap();
+    admin.delete_principal(&princ).unwrap();
+
+    // Callers that clean up after failed provisioning runs treat this as already deleted
+    let err = admin.delete_principal(&princ).unwrap_err();
+    assert!(err.is_unknown_principal(), "{err}");
+    assert_eq!(err.code.0, kadm5::error_code::UNK_PRINC);
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn delete_principal_removes_principal_and_keys() {
+    let ctx = KrbContext::new().unwrap();
+    let admin = admin(&ctx);
+
+    let princ = principal(&ctx, "delete");
+    admin.create_principal(&princ).unwrap();
+    assert!(admin.get_principal(&princ).unwrap().is_some());
+
+    admin.delete_principal(&princ).unwrap();
+    assert!(admin.get_principal(&princ).unwrap().is_none());
+    let err = admin
+        .get_principal_keys(&princ, kadm5::KVNO_ALL)
+        .err()
+        .unwrap();
+    assert!(err.is_unknown_principal(), "{err}");
+    // A second delete is not treated as success, so that callers can tell that someone else deleted it first
+    let err = admin.delete_principal(&princ).unwrap_err();
+    assert!(err.is_unknown_principal(), "{err}");
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn randkey_principal_generates_new_keys_and_kvnos() {
+    let ctx = KrbContext::new().unwrap();
+    let admin = admin(&ctx);
+
+    let princ = principal(&ctx, "randkey");
+    admin.create_principal(&princ).unwrap();
+    let randkey = || {
+        let keys = admin
+            .randkey_principal(&princ, false, &[])
+            .unwrap()
+            .into_iter()
+            .map(|mut key| (key.enctype(), key.contents_mut().unwrap().to_vec()))
+            .collect::<Vec<_>>();
+        let kvno = admin.get_principal(&princ).unwrap().unwrap().kvno;
+        (keys, kvno)
+    };
+
+    let (first_keys, first_kvno) = randkey();
+    let (second_keys, second_kvno) = randkey();
+    assert!(!first_keys.is_empty());
+    assert_eq!(second_kvno, first_kvno + 1);
+    assert_eq!(
+        first_keys
+            .iter()
+            .map(|(enctype, _)| enctype)
+            .collect::<Vec<_>>(),
+        second_keys
+            .iter()
+            .map(|(enctype, _)| enctype)
+            .collect::<Vec<_>>(),
+    );
+    for ((enctype, first), (_, second)) in first_keys.iter().zip(&second_keys) {
+        assert_ne!(first, second, "key for enctype {enctype} was not rotated");
+    }
+    // Without keepold, only the newest keys are retained
+    assert_eq!(kvnos(&admin, &princ), [second_kvno]);
+
+    admin.delete_principal(&princ).unwrap();
+}
+
+#[test]
+#[ignore = "requires a KDC"]
+fn list_principals_filters_by_glob() {
+    let ctx = KrbContext::new().unwrap();
+    let admin = admin(&ctx);
+
+    let princ = principal(&ctx, "list");
+    admin.create_principal(&princ).unwrap();
+    let name = princ.to_string();
+
+    let listed = admin
+        .list_principals(Some(&CString::new(name.clone()).unwrap()))
+        .unwrap();
+    assert_eq!(listed, [name.clone()]);
+    let listed = admin
+        .list_principals(Some(
+            &CString::new(format!("list-{}*", std::process::id())).unwrap(),
+        ))
+        .unwrap();
+    assert!(listed.contains(&name), "{name} not in {listed:?}");
+    let listed = admin
+        .list_principals(Some(c"stackable-no-such-principal-*"))
+        .unwrap();
+    assert_eq!(listed, Vec::<String>::new());
+
+    admin.delete_principal(&princ).unwrap();
+}
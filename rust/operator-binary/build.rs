@@ -1,4 +1,4 @@
-//! Compile Rust code from gRPC definition files stored in the vendor/csi directory.
+//! Compile Rust code from gRPC definition files stored in the vendor/csi and proto directories.
 
 use std::path::PathBuf;
 
@@ -6,7 +6,10 @@ fn main() {
     let out_dir = PathBuf::from(std::env::var("OUT_DIR").expect("OUT_DIR is required"));
     tonic_build::configure()
         .file_descriptor_set_path(out_dir.join("file_descriptor_set.bin"))
-        .compile_protos(&["vendor/csi/csi.proto"], &["vendor/csi"])
+        .compile_protos(
+            &["vendor/csi/csi.proto", "proto/identity_api.proto"],
+            &["vendor/csi", "proto"],
+        )
         .unwrap();
     built::write_built_file().unwrap();
 }
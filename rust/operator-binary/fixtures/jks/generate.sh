@@ -0,0 +1,16 @@
+#!/usr/bin/env bash
+# Regenerates the JKS stores that the tests of src/format/jks.rs compare against, using keytool as an independent
+# implementation of the format.
+set -euo pipefail
+cd "$(dirname "$0")"
+rm -f ca.crt ca.key tls.crt tls.key keystore.jks truststore.jks
+openssl req -x509 -newkey rsa:2048 -nodes -days 36500 -subj "/CN=secret-operator test CA" \
+  -keyout ca.key -out ca.crt
+openssl req -newkey rsa:2048 -nodes -subj "/CN=my-pod" -keyout tls.key -out tls.csr
+openssl x509 -req -in tls.csr -CA ca.crt -CAkey ca.key -CAcreateserial -days 36500 -out tls.crt
+rm -f tls.csr ca.srl ca.key
+openssl pkcs12 -export -name tls -inkey tls.key -in tls.crt -certfile ca.crt -passout pass:changeit -out keystore.p12
+keytool -importkeystore -noprompt -srckeystore keystore.p12 -srcstoretype PKCS12 -srcstorepass changeit \
+  -destkeystore keystore.jks -deststoretype JKS -deststorepass changeit -destkeypass changeit
+rm -f keystore.p12
+keytool -importcert -noprompt -alias ca-0 -file ca.crt -keystore truststore.jks -storetype JKS -storepass changeit
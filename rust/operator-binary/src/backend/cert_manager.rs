@@ -7,10 +7,14 @@ use std::collections::HashSet;
 use async_trait::async_trait;
 use snafu::{OptionExt, ResultExt, Snafu};
 use stackable_operator::{
-    k8s_openapi::{ByteString, api::core::v1::Secret},
-    kube::{api::ObjectMeta, runtime::reflector::ObjectRef},
+    k8s_openapi::{
+        ByteString,
+        api::core::v1::{Pod, Secret},
+    },
+    kube::{self, api::ObjectMeta, runtime::reflector::ObjectRef},
     time::Duration,
 };
+use stackable_secret_operator_crd_utils::ownership;
 
 use super::{
     ScopeAddressesError, SecretBackend, SecretBackendError, SecretContents, SecretVolumeSelector,
@@ -52,7 +56,7 @@ pub enum Error {
 
     #[snafu(display("failed to apply {certificate}"))]
     ApplyCertManagerCertificate {
-        source: stackable_operator::client::Error,
+        source: kube::Error,
         certificate: ObjectRef<external_crd::cert_manager::Certificate>,
     },
 
@@ -114,13 +118,12 @@ impl SecretBackend for CertManager {
                 name: Some(cert_name.clone()),
                 namespace: Some(selector.namespace.clone()),
                 labels: Some(
-                    [pod_info
+                    pod_info
                         .scheduling
                         .has_node_scope
-                        .then(|| (LABEL_SCOPE_NODE.to_string(), pod_info.node_name))]
-                    .into_iter()
-                    .flatten()
-                    .collect(),
+                        .then(|| (LABEL_SCOPE_NODE.to_string(), pod_info.node_name))
+                        .into_iter()
+                        .collect(),
                 ),
                 ..Default::default()
             },
@@ -147,13 +150,17 @@ impl SecretBackend for CertManager {
                 },
             },
         };
-        let cert = self
-            .client
-            .apply_patch(FIELD_MANAGER_SCOPE, &cert, &cert)
-            .await
-            .with_context(|_| ApplyCertManagerCertificateSnafu {
-                certificate: ObjectRef::from_obj(&cert),
-            })?;
+        // The Certificate is only needed for as long as the Pod that the volume belongs to
+        let owner = selector
+            .pod_uid
+            .as_deref()
+            .map(|uid| ownership::owner_reference::<Pod>(&selector.pod, uid));
+        let certificate = ObjectRef::from_obj(&cert);
+        let cert =
+            ownership::OwnedResourceWriter::new(self.client.as_kube_client(), FIELD_MANAGER_SCOPE)
+                .apply(cert, &selector.class, owner)
+                .await
+                .context(ApplyCertManagerCertificateSnafu { certificate })?;
 
         let secret = self
             .client
@@ -203,3 +210,96 @@ impl SecretBackend for CertManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{
+        Deserialize,
+        de::{
+            IntoDeserializer,
+            value::{self, MapDeserializer},
+        },
+    };
+    use stackable_operator::utils::cluster_info::KubernetesClusterInfo;
+    use stackable_secret_operator_crd_utils::testing::RecordingClient;
+
+    use super::{CertManager, Error, FIELD_MANAGER_SCOPE};
+    use crate::{
+        backend::{
+            SecretBackend, SecretVolumeSelector,
+            pod_info::{DependencyWait, NodeInfo, PodInfo, SchedulingPodInfo},
+        },
+        utils::Unloggable,
+    };
+
+    fn selector() -> SecretVolumeSelector {
+        SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, value::Error>>(
+            HashMap::from([
+                ("secrets.stackable.tech/class", "my-class"),
+                ("secrets.stackable.tech/internal.pvc.name", "my-pvc"),
+                ("csi.storage.k8s.io/pod.name", "my-pod"),
+                ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+                ("csi.storage.k8s.io/pod.uid", "my-pod-uid"),
+            ])
+            .into_deserializer(),
+        )
+        .unwrap()
+    }
+
+    fn pod_info() -> PodInfo {
+        PodInfo {
+            pod_ips: Vec::new(),
+            service_name: None,
+            node_name: "my-node".to_string(),
+            node_ips: Vec::new(),
+            node: NodeInfo {
+                labels: Default::default(),
+                internal_ips: Vec::new(),
+            },
+            listener_addresses: HashMap::new(),
+            kubernetes_cluster_domain: "cluster.local".parse().unwrap(),
+            scheduling: SchedulingPodInfo {
+                namespace: "my-namespace".to_string(),
+                volume_listener_names: HashMap::new(),
+                has_node_scope: false,
+            },
+            dependency_wait: DependencyWait::none(),
+        }
+    }
+
+    #[tokio::test]
+    async fn certificates_should_be_labelled_and_owned_by_their_pod() {
+        let server = RecordingClient::new();
+        let backend = CertManager {
+            client: Unloggable(stackable_operator::client::Client::new(
+                server.client(),
+                None,
+                "default".to_string(),
+                KubernetesClusterInfo {
+                    cluster_domain: "cluster.local".parse().unwrap(),
+                },
+            )),
+            config: serde_json::from_value(serde_json::json!({
+                "issuer": {"kind": "ClusterIssuer", "name": "my-issuer"},
+            }))
+            .unwrap(),
+        };
+
+        // The recording client never issues the Secret that cert-manager would create
+        let err = backend
+            .get_secret_data(&selector(), pod_info())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::GetSecret { .. }), "{err:?}");
+
+        server.assert_owned(FIELD_MANAGER_SCOPE, "my-class", Some("my-pod-uid"));
+        let [certificate] = &server.written_objects()[..] else {
+            panic!("expected a single Certificate to be applied");
+        };
+        let owner = &certificate["metadata"]["ownerReferences"][0];
+        assert_eq!(owner["kind"], "Pod");
+        assert_eq!(owner["name"], "my-pod");
+    }
+}
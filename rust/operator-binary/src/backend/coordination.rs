@@ -0,0 +1,691 @@
+//! Limits how many expensive backend operations may run at the same time across the whole cluster.
+//!
+//! Rate limits on each node don't help when every node restarts at once (such as during a cluster-wide rolling
+//! restart), since they all hit the same KDC or CA `Secret`. Instead, each expensive operation must hold one of a
+//! fixed number of [`Lease`] "slots" while it is running.
+//!
+//! Coordination is best-effort: if the lease API is unavailable, or no slot frees up in time, the operation runs
+//! anyway.
+
+use std::{future::Future, num::NonZeroU32, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use snafu::Snafu;
+use stackable_operator::{
+    k8s_openapi::{
+        api::coordination::v1::{Lease, LeaseSpec},
+        apimachinery::pkg::apis::meta::v1::MicroTime,
+        chrono::{self, Utc},
+    },
+    kube::{self, api::PostParams},
+};
+use tokio::time::Instant;
+
+use crate::metrics::{self, LeaseAcquireOutcome, LeaseReleaseOutcome};
+#[cfg(feature = "failpoints")]
+use crate::utils::failpoint;
+
+/// How long a slot stays held after it was last renewed. Slots held by crashed nodes are reclaimed after this.
+const LEASE_DURATION: Duration = Duration::from_secs(30);
+
+/// How often slots are renewed while the operation is still running.
+const RENEW_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for a slot to free up before running the operation anyway, unless the pool has an earlier
+/// deadline (see [`LeasePool::with_deadline`]).
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait before retrying if all slots are taken.
+const ACQUIRE_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+const LEASE_NAME_PREFIX: &str = "secret-operator-expensive-operation";
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum LeaseApiError {
+    #[snafu(display("lease was modified concurrently"))]
+    Conflict,
+
+    #[snafu(display("lease API request failed"))]
+    Request { source: kube::Error },
+}
+
+/// Stores [`Lease`] objects, see [`KubeLeaseApi`].
+#[async_trait]
+pub trait LeaseApi: Send + Sync {
+    async fn get(&self, name: &str) -> Result<Option<Lease>, LeaseApiError>;
+
+    /// Creates `lease`, failing with [`LeaseApiError::Conflict`] if it already exists.
+    async fn create(&self, lease: &Lease) -> Result<Lease, LeaseApiError>;
+
+    /// Replaces `lease`, failing with [`LeaseApiError::Conflict`] if it has been modified since it was read.
+    async fn replace(&self, lease: &Lease) -> Result<Lease, LeaseApiError>;
+}
+
+/// Stores [`Lease`] objects in a Kubernetes namespace.
+pub struct KubeLeaseApi(pub kube::Api<Lease>);
+
+impl KubeLeaseApi {
+    fn map_error(err: kube::Error) -> LeaseApiError {
+        match err {
+            kube::Error::Api(resp) if resp.code == 409 => LeaseApiError::Conflict,
+            source => LeaseApiError::Request { source },
+        }
+    }
+}
+
+#[async_trait]
+impl LeaseApi for KubeLeaseApi {
+    async fn get(&self, name: &str) -> Result<Option<Lease>, LeaseApiError> {
+        self.0.get_opt(name).await.map_err(Self::map_error)
+    }
+
+    async fn create(&self, lease: &Lease) -> Result<Lease, LeaseApiError> {
+        self.0
+            .create(&PostParams::default(), lease)
+            .await
+            .map_err(Self::map_error)
+    }
+
+    async fn replace(&self, lease: &Lease) -> Result<Lease, LeaseApiError> {
+        let name = lease.metadata.name.as_deref().unwrap_or_default();
+        self.0
+            .replace(name, &PostParams::default(), lease)
+            .await
+            .map_err(Self::map_error)
+    }
+}
+
+/// A cluster-wide pool of slots for expensive operations, see the [module docs](self).
+///
+/// Cloning a `LeasePool` shares the same underlying pool.
+#[derive(Clone)]
+pub struct LeasePool {
+    inner: Option<Arc<LeasePoolInner>>,
+    /// When to stop waiting for a free slot, if that is before the acquire timeout.
+    deadline: Option<Instant>,
+}
+
+struct LeasePoolInner {
+    api: Box<dyn LeaseApi>,
+    slots: NonZeroU32,
+    /// Identifies this operator instance as the holder of a slot
+    holder: String,
+    lease_duration: Duration,
+    renew_interval: Duration,
+    acquire_timeout: Duration,
+    acquire_retry_interval: Duration,
+}
+
+impl std::fmt::Debug for LeasePool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.inner {
+            Some(inner) => f
+                .debug_struct("LeasePool")
+                .field("slots", &inner.slots)
+                .field("holder", &inner.holder)
+                .field("deadline", &self.deadline)
+                .finish(),
+            None => f.write_str("LeasePool(disabled)"),
+        }
+    }
+}
+
+impl LeasePool {
+    /// A pool that runs all operations immediately, without any coordination.
+    pub fn disabled() -> Self {
+        Self {
+            inner: None,
+            deadline: None,
+        }
+    }
+
+    /// A pool that allows up to `slots` operations to run at the same time, across all instances that use the same
+    /// `api`.
+    ///
+    /// `holder` must be unique for each instance, such as the name of the node that it is running on.
+    pub fn new(api: impl LeaseApi + 'static, slots: NonZeroU32, holder: String) -> Self {
+        Self::from_inner(LeasePoolInner {
+            api: Box::new(api),
+            slots,
+            holder,
+            lease_duration: LEASE_DURATION,
+            renew_interval: RENEW_INTERVAL,
+            acquire_timeout: ACQUIRE_TIMEOUT,
+            acquire_retry_interval: ACQUIRE_RETRY_INTERVAL,
+        })
+    }
+
+    fn from_inner(inner: LeasePoolInner) -> Self {
+        Self {
+            inner: Some(Arc::new(inner)),
+            deadline: None,
+        }
+    }
+
+    /// Shares the same slots, but stops waiting for one to free up at `deadline` (such as when the request that the
+    /// operation is part of would time out), if that comes before the acquire timeout.
+    pub fn with_deadline(&self, deadline: Instant) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            deadline: Some(deadline),
+        }
+    }
+
+    /// Runs `operation` while holding a slot, waiting for one to free up if required.
+    ///
+    /// `operation_name` is used for logging, and to label the metrics that are recorded for the operation.
+    pub async fn run<F: Future>(&self, operation_name: &'static str, operation: F) -> F::Output {
+        let Some(pool) = &self.inner else {
+            return operation.await;
+        };
+        let wait_start = Instant::now();
+        let lease = match pool.acquire(self.deadline).await {
+            Ok(Some(lease)) => {
+                metrics::record_lease_acquire(
+                    operation_name,
+                    LeaseAcquireOutcome::Acquired,
+                    wait_start.elapsed(),
+                );
+                tracing::info!(
+                    operation = operation_name,
+                    lease = lease.metadata.name.as_deref(),
+                    lease.wait_time = ?wait_start.elapsed(),
+                    "acquired lease for expensive operation"
+                );
+                lease
+            }
+            Ok(None) => {
+                metrics::record_lease_acquire(
+                    operation_name,
+                    LeaseAcquireOutcome::TimedOut,
+                    wait_start.elapsed(),
+                );
+                tracing::warn!(
+                    operation = operation_name,
+                    lease.wait_time = ?wait_start.elapsed(),
+                    "timed out waiting for a free lease, running expensive operation without coordination"
+                );
+                return operation.await;
+            }
+            Err(err) => {
+                metrics::record_lease_acquire(
+                    operation_name,
+                    LeaseAcquireOutcome::Unavailable,
+                    wait_start.elapsed(),
+                );
+                tracing::warn!(
+                    operation = operation_name,
+                    error = &err as &dyn std::error::Error,
+                    "lease API is unavailable, running expensive operation without coordination"
+                );
+                return operation.await;
+            }
+        };
+
+        let mut lease = Some(lease);
+        let mut renew =
+            tokio::time::interval_at(Instant::now() + pool.renew_interval, pool.renew_interval);
+        tokio::pin!(operation);
+        let output = loop {
+            tokio::select! {
+                output = &mut operation => break output,
+                _ = renew.tick() => {
+                    let Some(current) = lease.take() else { continue };
+                    match pool.renew(current).await {
+                        Ok(renewed) => lease = Some(renewed),
+                        // The operation is allowed to finish, but the slot may now be reclaimed by someone else
+                        Err(err) => tracing::warn!(
+                            operation = operation_name,
+                            error = &err as &dyn std::error::Error,
+                            "failed to renew lease for expensive operation"
+                        ),
+                    }
+                }
+            }
+        };
+        if let Some(lease) = lease {
+            match pool.release(lease).await {
+                Ok(_) => {
+                    metrics::record_lease_release(operation_name, LeaseReleaseOutcome::Released)
+                }
+                Err(err) => {
+                    metrics::record_lease_release(operation_name, LeaseReleaseOutcome::Failed);
+                    tracing::warn!(
+                        operation = operation_name,
+                        error = &err as &dyn std::error::Error,
+                        "failed to release lease, it will be reclaimed once it expires"
+                    );
+                }
+            }
+        }
+        output
+    }
+}
+
+impl LeasePoolInner {
+    /// Returns [`None`] if no slot became free before the acquire timeout, or before `deadline` if that is earlier.
+    async fn acquire(&self, deadline: Option<Instant>) -> Result<Option<Lease>, LeaseApiError> {
+        #[cfg(feature = "failpoints")]
+        failpoint::eval(failpoint::COORDINATION_LEASE_BEFORE_ACQUIRE)
+            .await
+            .map_err(|err| LeaseApiError::Request {
+                source: kube::Error::Service(Box::new(err)),
+            })?;
+        let timeout_deadline = Instant::now() + self.acquire_timeout;
+        let deadline = deadline.map_or(timeout_deadline, |deadline| deadline.min(timeout_deadline));
+        loop {
+            for slot in 0..self.slots.get() {
+                if let Some(lease) = self
+                    .try_acquire(&format!("{LEASE_NAME_PREFIX}-{slot}"))
+                    .await?
+                {
+                    return Ok(Some(lease));
+                }
+            }
+            if Instant::now() + self.acquire_retry_interval > deadline {
+                return Ok(None);
+            }
+            tokio::time::sleep(self.acquire_retry_interval).await;
+        }
+    }
+
+    /// Returns [`None`] if the slot is held by someone else.
+    async fn try_acquire(&self, name: &str) -> Result<Option<Lease>, LeaseApiError> {
+        let now = Utc::now();
+        let result = match self.api.get(name).await? {
+            None => {
+                let mut lease = Lease::default();
+                lease.metadata.name = Some(name.to_string());
+                self.api.create(&self.take_slot(lease, now)).await
+            }
+            Some(lease) if self.is_free(&lease, now) => {
+                tracing::debug!(
+                    lease = name,
+                    lease.previous_holder = lease
+                        .spec
+                        .as_ref()
+                        .and_then(|spec| spec.holder_identity.as_deref()),
+                    "taking over free lease"
+                );
+                self.api.replace(&self.take_slot(lease, now)).await
+            }
+            Some(_) => return Ok(None),
+        };
+        match result {
+            Ok(lease) => Ok(Some(lease)),
+            // Someone else took the slot first
+            Err(LeaseApiError::Conflict) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Whether the slot is unheld, or held by a holder that has stopped renewing it.
+    fn is_free(&self, lease: &Lease, now: chrono::DateTime<Utc>) -> bool {
+        let Some(spec) = &lease.spec else {
+            return true;
+        };
+        if spec
+            .holder_identity
+            .as_deref()
+            .unwrap_or_default()
+            .is_empty()
+        {
+            return true;
+        }
+        let expires_at = spec.renew_time.as_ref().map(|MicroTime(renewed)| {
+            *renewed
+                + chrono::Duration::seconds(spec.lease_duration_seconds.unwrap_or_default().into())
+        });
+        expires_at.is_none_or(|expires_at| expires_at < now)
+    }
+
+    fn take_slot(&self, mut lease: Lease, now: chrono::DateTime<Utc>) -> Lease {
+        let spec = lease.spec.get_or_insert_with(LeaseSpec::default);
+        spec.holder_identity = Some(self.holder.clone());
+        spec.lease_duration_seconds =
+            Some(self.lease_duration.as_secs().try_into().unwrap_or(i32::MAX));
+        spec.acquire_time = Some(MicroTime(now));
+        spec.renew_time = Some(MicroTime(now));
+        spec.lease_transitions = Some(spec.lease_transitions.unwrap_or_default() + 1);
+        lease
+    }
+
+    async fn renew(&self, mut lease: Lease) -> Result<Lease, LeaseApiError> {
+        lease.spec.get_or_insert_with(LeaseSpec::default).renew_time = Some(MicroTime(Utc::now()));
+        self.api.replace(&lease).await
+    }
+
+    async fn release(&self, mut lease: Lease) -> Result<Lease, LeaseApiError> {
+        let spec = lease.spec.get_or_insert_with(LeaseSpec::default);
+        spec.holder_identity = None;
+        spec.renew_time = None;
+        self.api.replace(&lease).await
+    }
+}
+
+/// Coordination without an API server, for testing code that runs operations through a [`LeasePool`].
+#[cfg(test)]
+pub mod testing {
+    use std::{
+        collections::BTreeMap,
+        num::NonZeroU32,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use stackable_operator::{
+        k8s_openapi::api::coordination::v1::Lease, kube::error::ErrorResponse,
+    };
+
+    use super::{LeaseApi, LeaseApiError, LeasePool, LeasePoolInner};
+
+    /// How long the pools created by [`pool`] wait for a free slot.
+    pub const ACQUIRE_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Stores [`Lease`] objects in memory.
+    #[derive(Default)]
+    pub struct FakeLeaseApi {
+        pub leases: Mutex<BTreeMap<String, Lease>>,
+        pub replace_calls: AtomicUsize,
+        /// Fails every request while set.
+        pub unavailable: AtomicBool,
+    }
+
+    impl FakeLeaseApi {
+        fn check_available(&self) -> Result<(), LeaseApiError> {
+            if self.unavailable.load(Ordering::SeqCst) {
+                Err(LeaseApiError::Request {
+                    source: stackable_operator::kube::Error::Api(ErrorResponse {
+                        status: "Failure".to_string(),
+                        message: "the server is currently unable to handle the request".to_string(),
+                        reason: "ServiceUnavailable".to_string(),
+                        code: 503,
+                    }),
+                })
+            } else {
+                Ok(())
+            }
+        }
+
+        /// Who holds the slot `name`, if anyone.
+        pub fn holder(&self, name: &str) -> Option<String> {
+            self.leases.lock().unwrap()[name]
+                .spec
+                .as_ref()?
+                .holder_identity
+                .clone()
+        }
+    }
+
+    #[async_trait]
+    impl LeaseApi for Arc<FakeLeaseApi> {
+        async fn get(&self, name: &str) -> Result<Option<Lease>, LeaseApiError> {
+            self.check_available()?;
+            Ok(self.leases.lock().unwrap().get(name).cloned())
+        }
+
+        async fn create(&self, lease: &Lease) -> Result<Lease, LeaseApiError> {
+            self.check_available()?;
+            let mut leases = self.leases.lock().unwrap();
+            let name = lease.metadata.name.clone().unwrap();
+            if leases.contains_key(&name) {
+                return Err(LeaseApiError::Conflict);
+            }
+            let mut lease = lease.clone();
+            lease.metadata.resource_version = Some("1".to_string());
+            leases.insert(name, lease.clone());
+            Ok(lease)
+        }
+
+        async fn replace(&self, lease: &Lease) -> Result<Lease, LeaseApiError> {
+            self.check_available()?;
+            self.replace_calls.fetch_add(1, Ordering::SeqCst);
+            let mut leases = self.leases.lock().unwrap();
+            let current = leases
+                .get_mut(lease.metadata.name.as_deref().unwrap())
+                .ok_or(LeaseApiError::Conflict)?;
+            if current.metadata.resource_version != lease.metadata.resource_version {
+                return Err(LeaseApiError::Conflict);
+            }
+            let version: u64 = current
+                .metadata
+                .resource_version
+                .as_deref()
+                .unwrap()
+                .parse()
+                .unwrap();
+            *current = lease.clone();
+            current.metadata.resource_version = Some((version + 1).to_string());
+            Ok(current.clone())
+        }
+    }
+
+    /// A pool of `slots` stored in `api`, with short timeouts.
+    pub fn pool(api: &Arc<FakeLeaseApi>, slots: u32, holder: &str) -> LeasePool {
+        LeasePool::from_inner(LeasePoolInner {
+            api: Box::new(api.clone()),
+            slots: NonZeroU32::new(slots).unwrap(),
+            holder: holder.to_string(),
+            lease_duration: Duration::from_secs(30),
+            renew_interval: Duration::from_millis(20),
+            acquire_timeout: ACQUIRE_TIMEOUT,
+            acquire_retry_interval: Duration::from_millis(10),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, atomic::Ordering},
+        time::Duration,
+    };
+
+    use stackable_operator::{
+        k8s_openapi::{
+            api::coordination::v1::{Lease, LeaseSpec},
+            apimachinery::pkg::apis::meta::v1::MicroTime,
+            chrono::{self, Utc},
+        },
+        kube::api::ObjectMeta,
+    };
+    use tokio::time::Instant;
+
+    use super::{
+        LeasePool,
+        testing::{FakeLeaseApi, pool},
+    };
+    use crate::metrics::{LEASE_ACQUISITIONS, LEASE_RELEASES, LEASE_WAIT_SECONDS};
+
+    const SLOT_0: &str = "secret-operator-expensive-operation-0";
+
+    #[tokio::test]
+    async fn run_should_hold_slot_while_running() {
+        // Metrics are shared by all tests, so each test that checks them uses its own operation name
+        const OPERATION: &str = "test-hold-slot";
+        let api = Arc::new(FakeLeaseApi::default());
+        let node_a = pool(&api, 1, "node-a");
+        let output = node_a
+            .run(OPERATION, async {
+                assert_eq!(api.holder(SLOT_0).as_deref(), Some("node-a"));
+                42
+            })
+            .await;
+        assert_eq!(output, 42);
+        assert_eq!(api.holder(SLOT_0), None);
+
+        // Released slots can be taken by other holders
+        let node_b = pool(&api, 1, "node-b");
+        node_b
+            .run(OPERATION, async {
+                assert_eq!(api.holder(SLOT_0).as_deref(), Some("node-b"));
+            })
+            .await;
+
+        assert_eq!(LEASE_ACQUISITIONS.get(&[OPERATION, "acquired"]), 2.0);
+        assert_eq!(LEASE_WAIT_SECONDS.count(&[OPERATION]), 2);
+        assert_eq!(LEASE_RELEASES.get(&[OPERATION, "released"]), 2.0);
+        assert_eq!(LEASE_RELEASES.get(&[OPERATION, "failed"]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn run_should_wait_for_free_slot() {
+        tokio::time::pause();
+        let api = Arc::new(FakeLeaseApi::default());
+        let node_a = pool(&api, 1, "node-a");
+        let node_b = pool(&api, 1, "node-b");
+        let (release_a, a_released) = tokio::sync::oneshot::channel::<()>();
+        let a = node_a.run("test", async {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            release_a.send(()).unwrap();
+        });
+        let b = node_b.run("test", async {
+            // b may only start once a has finished
+            assert_eq!(api.holder(SLOT_0).as_deref(), Some("node-b"));
+        });
+        let b = async {
+            // Make sure that a gets the slot first
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            b.await;
+            a_released.await.unwrap();
+        };
+        tokio::join!(a, b);
+    }
+
+    #[tokio::test]
+    async fn run_should_renew_long_operations() {
+        tokio::time::pause();
+        let api = Arc::new(FakeLeaseApi::default());
+        let node_a = pool(&api, 1, "node-a");
+        let run = tokio::spawn({
+            let api = api.clone();
+            async move {
+                node_a
+                    .run("test", tokio::time::sleep(Duration::from_millis(110)))
+                    .await;
+                assert_eq!(api.holder(SLOT_0), None);
+            }
+        });
+        // Let the operation acquire its slot before time moves on
+        tokio::task::yield_now().await;
+        assert_eq!(api.holder(SLOT_0).as_deref(), Some("node-a"));
+        // Check between renewals, rather than exactly when they are due
+        tokio::time::advance(Duration::from_millis(5)).await;
+        for renewals in 1..=5 {
+            tokio::time::advance(Duration::from_millis(20)).await;
+            // Let the renewal run
+            tokio::task::yield_now().await;
+            assert_eq!(api.replace_calls.load(Ordering::SeqCst), renewals);
+            assert_eq!(api.holder(SLOT_0).as_deref(), Some("node-a"));
+        }
+        tokio::time::advance(Duration::from_millis(10)).await;
+        run.await.unwrap();
+        // Five renewals, plus the release
+        assert_eq!(api.replace_calls.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn run_should_stop_waiting_at_deadline() {
+        tokio::time::pause();
+        let api = Arc::new(FakeLeaseApi::default());
+        let node_a = pool(&api, 1, "node-a");
+        let node_b = pool(&api, 1, "node-b");
+        node_a
+            .run("test", async {
+                // The deadline is well before the acquire timeout
+                let started = Instant::now();
+                let node_b = node_b.with_deadline(started + Duration::from_millis(30));
+                assert_eq!(node_b.run("test", async { 1 }).await, 1);
+                assert!(started.elapsed() <= Duration::from_millis(30));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn run_should_reclaim_slot_of_crashed_holder() {
+        let api = Arc::new(FakeLeaseApi::default());
+        let crashed_renew_time = Utc::now() - chrono::Duration::minutes(5);
+        api.leases.lock().unwrap().insert(
+            SLOT_0.to_string(),
+            Lease {
+                metadata: ObjectMeta {
+                    name: Some(SLOT_0.to_string()),
+                    resource_version: Some("1".to_string()),
+                    ..Default::default()
+                },
+                spec: Some(LeaseSpec {
+                    holder_identity: Some("crashed-node".to_string()),
+                    lease_duration_seconds: Some(30),
+                    renew_time: Some(MicroTime(crashed_renew_time)),
+                    ..Default::default()
+                }),
+            },
+        );
+        pool(&api, 1, "node-a")
+            .run("test", async {
+                assert_eq!(api.holder(SLOT_0).as_deref(), Some("node-a"));
+            })
+            .await;
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[tokio::test]
+    async fn fault_before_acquire_should_not_leak_slot() {
+        let api = Arc::new(FakeLeaseApi::default());
+        let node_a = pool(&api, 1, "node-a");
+        crate::utils::failpoint::configure_for_current_thread(
+            "coordination.lease.before_acquire=1*return",
+        )
+        .unwrap();
+
+        // The operation still runs, but without taking a slot that would have to be released
+        let output = node_a
+            .run("test", async {
+                assert!(api.leases.lock().unwrap().is_empty());
+                1
+            })
+            .await;
+        assert_eq!(output, 1);
+
+        // Once the fault has cleared, operations are coordinated again
+        node_a
+            .run("test", async {
+                assert_eq!(api.holder(SLOT_0).as_deref(), Some("node-a"));
+            })
+            .await;
+        assert_eq!(api.holder(SLOT_0), None);
+    }
+
+    #[tokio::test]
+    async fn run_should_fall_back_to_uncoordinated() {
+        const OPERATION: &str = "test-fall-back";
+        // All slots are held by a live holder
+        let api = Arc::new(FakeLeaseApi::default());
+        let node_a = pool(&api, 1, "node-a");
+        let node_b = pool(&api, 1, "node-b");
+        node_a
+            .run(OPERATION, async {
+                assert_eq!(node_b.run(OPERATION, async { 1 }).await, 1);
+            })
+            .await;
+        assert_eq!(LEASE_ACQUISITIONS.get(&[OPERATION, "acquired"]), 1.0);
+        assert_eq!(LEASE_ACQUISITIONS.get(&[OPERATION, "timed-out"]), 1.0);
+        assert_eq!(LEASE_RELEASES.get(&[OPERATION, "released"]), 1.0);
+
+        // The lease API is unavailable
+        api.unavailable.store(true, Ordering::SeqCst);
+        assert_eq!(node_b.run(OPERATION, async { 2 }).await, 2);
+        assert_eq!(LEASE_ACQUISITIONS.get(&[OPERATION, "unavailable"]), 1.0);
+
+        // Coordination is disabled
+        assert_eq!(LeasePool::disabled().run(OPERATION, async { 3 }).await, 3);
+        // Every attempt to acquire a lease was timed, but uncoordinated runs never release one
+        assert_eq!(LEASE_WAIT_SECONDS.count(&[OPERATION]), 3);
+        assert_eq!(LEASE_RELEASES.get(&[OPERATION, "released"]), 1.0);
+    }
+}
@@ -0,0 +1,320 @@
+//! Certificate signing requests (CSRs) for certificates whose private key never leaves the `Pod`
+//!
+//! Used by the CSR handshake (see [`SecretVolumeSelector::autotls_csr_handshake`](super::SecretVolumeSelector::autotls_csr_handshake)),
+//! where the `Pod` generates its own key, and the backend only signs the public key.
+
+use std::net::IpAddr;
+
+use openssl::{
+    nid::Nid,
+    pkey::{Id, PKey, Public},
+    x509::{GeneralNameRef, X509Builder, X509Req, X509ReqRef},
+};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu, ensure};
+use stackable_operator::k8s_openapi::chrono::{DateTime, FixedOffset};
+
+use super::{SecretBackendError, SecretIdentity, pod_info::Address};
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum CsrError {
+    #[snafu(display("failed to parse CSR"))]
+    Parse { source: openssl::error::ErrorStack },
+
+    #[snafu(display("failed to verify CSR signature"))]
+    VerifySignature { source: openssl::error::ErrorStack },
+
+    #[snafu(display("CSR is not signed by the key that it requests a certificate for"))]
+    SignatureMismatch,
+
+    #[snafu(display("CSR must be for a {requirements}"))]
+    WeakKey { requirements: CsrKeyRequirements },
+
+    #[snafu(display("failed to read the names requested by the CSR"))]
+    ReadNames { source: openssl::error::ErrorStack },
+
+    #[snafu(display(
+        "CSR subject contains {attribute:?}, only a common name (CN) may be requested"
+    ))]
+    SubjectNotAllowed { attribute: String },
+
+    #[snafu(display("CSR requests the name {name:?}, which is not allowed for this volume"))]
+    NameNotAllowed { name: String },
+}
+
+impl SecretBackendError for CsrError {
+    fn grpc_code(&self) -> tonic::Code {
+        match self {
+            CsrError::Parse { .. } => tonic::Code::InvalidArgument,
+            CsrError::VerifySignature { .. } => tonic::Code::InvalidArgument,
+            CsrError::SignatureMismatch => tonic::Code::InvalidArgument,
+            CsrError::WeakKey { .. } => tonic::Code::InvalidArgument,
+            CsrError::ReadNames { .. } => tonic::Code::InvalidArgument,
+            CsrError::SubjectNotAllowed { .. } => tonic::Code::PermissionDenied,
+            CsrError::NameNotAllowed { .. } => tonic::Code::PermissionDenied,
+        }
+    }
+}
+
+impl CsrError {
+    /// Whether the CSR was rejected for requesting names that the volume is not allowed to have.
+    pub fn is_escalation(&self) -> bool {
+        matches!(
+            self,
+            CsrError::SubjectNotAllowed { .. } | CsrError::NameNotAllowed { .. }
+        )
+    }
+}
+
+/// Describes which certificates the `Pod` may request.
+///
+/// This is written into the volume as JSON, so that the `Pod` knows what to put into its CSR.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsrRequest {
+    /// The DNS names that may be requested, either as subject alternative names or as the subject's common name.
+    pub dns_names: Vec<String>,
+
+    /// The IP addresses that may be requested as subject alternative names.
+    pub ip_addresses: Vec<IpAddr>,
+
+    /// The kind of key that the certificate may be issued for.
+    pub key: CsrKeyRequirements,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "camelCase")]
+pub enum CsrKeyRequirements {
+    Rsa {
+        #[serde(rename = "minLength")]
+        min_length: u32,
+    },
+}
+
+impl std::fmt::Display for CsrKeyRequirements {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsrKeyRequirements::Rsa { min_length } => {
+                write!(f, "RSA key of at least {min_length} bits")
+            }
+        }
+    }
+}
+
+/// A CSR that has been checked against a [`CsrRequest`], see [`CsrRequest::validate`].
+#[derive(Debug)]
+pub struct ValidatedCsr {
+    /// The key that the certificate should be issued for.
+    pub public_key: PKey<Public>,
+
+    /// The names that the certificate should be issued for, all of which are allowed by the [`CsrRequest`].
+    pub names: Vec<Address>,
+}
+
+/// A certificate issued for a [`ValidatedCsr`].
+#[derive(Debug)]
+pub struct SignedCsr {
+    pub certificate_pem: Vec<u8>,
+    pub ca_pem: Vec<u8>,
+    pub expires_after: Option<DateTime<FixedOffset>>,
+    pub identity: SecretIdentity,
+}
+
+impl CsrRequest {
+    /// Checks that the PEM-encoded `csr` is self-consistent and only requests names allowed by `self`.
+    ///
+    /// If the CSR does not request any names at all, all allowed names are used.
+    pub fn validate(&self, csr: &[u8]) -> Result<ValidatedCsr, CsrError> {
+        use csr_error::*;
+        let csr = X509Req::from_pem(csr).context(ParseSnafu)?;
+        let public_key = csr.public_key().context(ParseSnafu)?;
+        // Otherwise anyone could request a certificate for a key that they don't own
+        ensure!(
+            csr.verify(&public_key).context(VerifySignatureSnafu)?,
+            SignatureMismatchSnafu
+        );
+        match self.key {
+            CsrKeyRequirements::Rsa { min_length } => ensure!(
+                public_key.id() == Id::RSA && public_key.bits() >= min_length,
+                WeakKeySnafu {
+                    requirements: self.key
+                }
+            ),
+        }
+
+        let mut names = Vec::new();
+        for entry in csr.subject_name().entries() {
+            let nid = entry.object().nid();
+            ensure!(
+                nid == Nid::COMMONNAME,
+                SubjectNotAllowedSnafu {
+                    attribute: nid.short_name().unwrap_or("unknown attribute"),
+                }
+            );
+            let common_name = entry.data().as_utf8().context(ReadNamesSnafu)?;
+            names.push(Address::Dns(common_name.to_string()));
+        }
+        names.extend(requested_alt_names(&csr)?);
+        if names.is_empty() {
+            names.extend(self.dns_names.iter().cloned().map(Address::Dns));
+            names.extend(self.ip_addresses.iter().copied().map(Address::Ip));
+        }
+        for name in &mut names {
+            if let Address::Dns(dns) = name {
+                // Use the same form as the names generated by the backend, so that duplicates can be removed
+                *dns = dns.trim_end_matches('.').to_ascii_lowercase();
+            }
+            ensure!(
+                self.allows(name),
+                NameNotAllowedSnafu {
+                    name: name.to_string()
+                }
+            );
+        }
+        names.sort_by_key(Address::to_string);
+        names.dedup_by_key(|name| name.to_string());
+        Ok(ValidatedCsr { public_key, names })
+    }
+
+    fn allows(&self, name: &Address) -> bool {
+        match name {
+            // DNS names are case-insensitive
+            Address::Dns(dns) => self
+                .dns_names
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(dns)),
+            Address::Ip(ip) => self.ip_addresses.contains(ip),
+        }
+    }
+}
+
+/// Reads the subject alternative names requested by `csr`.
+fn requested_alt_names(csr: &X509ReqRef) -> Result<Vec<Address>, CsrError> {
+    use csr_error::*;
+    // OpenSSL reports CSRs without any extensions as an error
+    let Ok(extensions) = csr.extensions() else {
+        return Ok(Vec::new());
+    };
+    // OpenSSL can only decode subject alternative names from certificates, so copy the requested extensions
+    // into a throwaway (unsigned) certificate
+    let mut cert = X509Builder::new().context(ReadNamesSnafu)?;
+    for extension in extensions {
+        cert.append_extension(extension).context(ReadNamesSnafu)?;
+    }
+    let Some(alt_names) = cert.build().subject_alt_names() else {
+        return Ok(Vec::new());
+    };
+    alt_names
+        .iter()
+        .map(|name| {
+            alt_name_address(name).ok_or_else(|| CsrError::NameNotAllowed {
+                name: name
+                    .uri()
+                    .or(name.email())
+                    .unwrap_or("unsupported subject alternative name")
+                    .to_string(),
+            })
+        })
+        .collect()
+}
+
+fn alt_name_address(name: &GeneralNameRef) -> Option<Address> {
+    if let Some(dns) = name.dnsname() {
+        return Some(Address::Dns(dns.to_string()));
+    }
+    let ip = match name.ipaddress()? {
+        &[a, b, c, d] => IpAddr::from([a, b, c, d]),
+        ip => IpAddr::from(<[u8; 16]>::try_from(ip).ok()?),
+    };
+    Some(Address::Ip(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::{
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        stack::Stack,
+        x509::{X509NameBuilder, X509ReqBuilder, extension::SubjectAlternativeName},
+    };
+
+    use super::{CsrError, CsrKeyRequirements, CsrRequest};
+
+    fn csr_request() -> CsrRequest {
+        CsrRequest {
+            dns_names: vec!["my-pod.my-svc.my-ns.svc.cluster.local".to_string()],
+            ip_addresses: vec!["10.0.0.1".parse().unwrap()],
+            key: CsrKeyRequirements::Rsa { min_length: 2048 },
+        }
+    }
+
+    fn csr(key: &PKey<Private>, common_name: Option<&str>, dns: &[&str]) -> Vec<u8> {
+        let mut req = X509ReqBuilder::new().unwrap();
+        req.set_pubkey(key).unwrap();
+        if let Some(common_name) = common_name {
+            let mut name = X509NameBuilder::new().unwrap();
+            name.append_entry_by_nid(openssl::nid::Nid::COMMONNAME, common_name)
+                .unwrap();
+            req.set_subject_name(&name.build()).unwrap();
+        }
+        if !dns.is_empty() {
+            let mut san = SubjectAlternativeName::new();
+            for dns in dns {
+                san.dns(dns);
+            }
+            let mut extensions = Stack::new().unwrap();
+            extensions
+                .push(san.build(&req.x509v3_context(None)).unwrap())
+                .unwrap();
+            req.add_extensions(&extensions).unwrap();
+        }
+        req.sign(key, MessageDigest::sha256()).unwrap();
+        req.build().to_pem().unwrap()
+    }
+
+    #[test]
+    fn validate_csr() {
+        let key = PKey::try_from(Rsa::generate(2048).unwrap()).unwrap();
+        let request = csr_request();
+
+        let csr = request
+            .validate(&csr(
+                &key,
+                Some("MY-POD.my-svc.my-ns.svc.cluster.local"),
+                &["my-pod.my-svc.my-ns.svc.cluster.local."],
+            ))
+            .unwrap();
+        assert_eq!(csr.names.len(), 1);
+        assert!(csr.public_key.public_eq(&key));
+
+        // No names requested, so all allowed names are used
+        let csr = request.validate(&csr(&key, None, &[])).unwrap();
+        assert_eq!(csr.names.len(), 2);
+
+        let err = request
+            .validate(&csr(
+                &key,
+                None,
+                &[
+                    "my-pod.my-svc.my-ns.svc.cluster.local",
+                    "kubernetes.default",
+                ],
+            ))
+            .unwrap_err();
+        assert!(
+            matches!(&err, CsrError::NameNotAllowed { name } if name == "kubernetes.default"),
+            "{err:?}"
+        );
+        assert!(err.is_escalation());
+
+        let weak_key = PKey::try_from(Rsa::generate(1024).unwrap()).unwrap();
+        let err = request.validate(&csr(&weak_key, None, &[])).unwrap_err();
+        assert!(matches!(err, CsrError::WeakKey { .. }), "{err:?}");
+        assert!(!err.is_escalation());
+
+        let err = request.validate(b"not a CSR").unwrap_err();
+        assert!(matches!(err, CsrError::Parse { .. }), "{err:?}");
+    }
+}
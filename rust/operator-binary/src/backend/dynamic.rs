@@ -1,23 +1,37 @@
 //! Support code for runtime-configurable dynamic [`SecretBackend`]s
 
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     fmt::{Debug, Display},
 };
 
 use async_trait::async_trait;
-use snafu::{ResultExt, Snafu};
-use stackable_operator::kube::runtime::reflector::ObjectRef;
+use snafu::{ResultExt, Snafu, ensure};
+use stackable_operator::{
+    k8s_openapi::api::core::v1::{Namespace, ObjectReference},
+    kube::{
+        Resource,
+        runtime::{
+            events::{Event, EventType, Recorder, Reporter},
+            reflector::ObjectRef,
+        },
+    },
+};
+use stackable_secret_operator_crd_utils::{ownership, rejection::WriteRejection};
 
 use super::{
     SecretBackend, SecretBackendError, SecretVolumeSelector,
+    coordination::LeasePool,
+    csr::{CsrRequest, SignedCsr, ValidatedCsr},
     kerberos_keytab::{self, KerberosProfile},
     pod_info::{PodInfo, SchedulingPodInfo},
     tls,
 };
+#[cfg(feature = "failpoints")]
+use crate::utils::failpoint;
 use crate::{
     crd::{self, SecretClass},
-    utils::Unloggable,
+    utils::{Unloggable, error_full_message},
 };
 
 pub struct DynError(Box<dyn SecretBackendError>);
@@ -42,6 +56,10 @@ impl SecretBackendError for DynError {
     fn grpc_code(&self) -> tonic::Code {
         self.0.grpc_code()
     }
+
+    fn write_rejection(&self) -> Option<&WriteRejection> {
+        self.0.write_rejection()
+    }
 }
 
 pub struct DynamicAdapter<B>(B);
@@ -61,10 +79,20 @@ impl<B: SecretBackend + Send + Sync> SecretBackend for DynamicAdapter<B> {
         selector: &super::SecretVolumeSelector,
         pod_info: PodInfo,
     ) -> Result<super::SecretContents, Self::Error> {
-        self.0
+        #[cfg(feature = "failpoints")]
+        failpoint::eval(failpoint::BACKEND_GET_SECRET_DATA_BEFORE)
+            .await
+            .map_err(|err| DynError(Box::new(err)))?;
+        let data = self
+            .0
             .get_secret_data(selector, pod_info)
             .await
-            .map_err(|err| DynError(Box::new(err)))
+            .map_err(|err| DynError(Box::new(err)))?;
+        #[cfg(feature = "failpoints")]
+        failpoint::eval(failpoint::BACKEND_GET_SECRET_DATA_AFTER)
+            .await
+            .map_err(|err| DynError(Box::new(err)))?;
+        Ok(data)
     }
 
     async fn get_qualified_node_names(
@@ -77,6 +105,28 @@ impl<B: SecretBackend + Send + Sync> SecretBackend for DynamicAdapter<B> {
             .await
             .map_err(|err| DynError(Box::new(err)))
     }
+
+    async fn get_csr_request(
+        &self,
+        selector: &SecretVolumeSelector,
+        pod_info: PodInfo,
+    ) -> Result<Option<CsrRequest>, Self::Error> {
+        self.0
+            .get_csr_request(selector, pod_info)
+            .await
+            .map_err(|err| DynError(Box::new(err)))
+    }
+
+    async fn sign_csr(
+        &self,
+        selector: &SecretVolumeSelector,
+        csr: &ValidatedCsr,
+    ) -> Result<Option<SignedCsr>, Self::Error> {
+        self.0
+            .sign_csr(selector, csr)
+            .await
+            .map_err(|err| DynError(Box::new(err)))
+    }
 }
 
 pub type Dynamic = dyn SecretBackend<Error = DynError>;
@@ -104,11 +154,19 @@ impl SecretBackendError for FromClassError {
             FromClassError::KerberosKeytab { source } => source.grpc_code(),
         }
     }
+
+    fn write_rejection(&self) -> Option<&WriteRejection> {
+        match self {
+            FromClassError::Tls { source } => source.write_rejection(),
+            FromClassError::KerberosKeytab { source } => source.write_rejection(),
+        }
+    }
 }
 
 pub async fn from_class(
     client: &stackable_operator::client::Client,
     class: SecretClass,
+    leases: &LeasePool,
 ) -> Result<Box<Dynamic>, FromClassError> {
     Ok(match class.spec.backend {
         crd::SecretClassBackend::K8sSearch(crd::K8sSearchBackend { search_namespace }) => {
@@ -127,6 +185,8 @@ pub async fn from_class(
                 &ca,
                 &additional_trust_roots,
                 max_certificate_lifetime,
+                force_rotation(&class),
+                leases,
             )
             .await?,
         ),
@@ -140,6 +200,9 @@ pub async fn from_class(
             admin,
             admin_keytab_secret,
             admin_principal,
+            node_name_source,
+            extra_config,
+            name_length_limits,
         }) => from(
             super::KerberosKeytab::new_from_k8s_keytab(
                 client,
@@ -147,9 +210,13 @@ pub async fn from_class(
                     realm_name,
                     kdc,
                     admin,
+                    extra_config,
                 },
                 &admin_keytab_secret,
                 admin_principal,
+                node_name_source,
+                name_length_limits,
+                leases.clone(),
             )
             .await?,
         ),
@@ -165,6 +232,22 @@ pub enum FromSelectorError {
         class: ObjectRef<SecretClass>,
     },
 
+    #[snafu(display("failed to get {namespace} to evaluate the allowedNamespaces of {class}"))]
+    GetNamespace {
+        source: stackable_operator::client::Error,
+        namespace: ObjectRef<Namespace>,
+        class: ObjectRef<SecretClass>,
+    },
+
+    #[snafu(display(
+        "{class} may not be used from namespace {namespace:?}, since it is not in the allowedNamespaces ({policy:?})"
+    ))]
+    NamespaceNotAllowed {
+        namespace: String,
+        class: ObjectRef<SecretClass>,
+        policy: crd::AllowedNamespaces,
+    },
+
     #[snafu(display("failed to initialize backend for {class}"))]
     FromClass {
         source: FromClassError,
@@ -176,21 +259,233 @@ impl SecretBackendError for FromSelectorError {
     fn grpc_code(&self) -> tonic::Code {
         match self {
             FromSelectorError::GetSecretClass { .. } => tonic::Code::Unavailable,
+            FromSelectorError::GetNamespace { .. } => tonic::Code::Unavailable,
+            FromSelectorError::NamespaceNotAllowed { .. } => tonic::Code::PermissionDenied,
             FromSelectorError::FromClass { source, .. } => source.grpc_code(),
         }
     }
+
+    fn write_rejection(&self) -> Option<&WriteRejection> {
+        match self {
+            FromSelectorError::FromClass { source, .. } => source.write_rejection(),
+            _ => None,
+        }
+    }
+}
+
+/// The forced rotation (see [`tls::FORCE_ROTATION_ANNOTATION`]) that `class` requests, if any.
+pub fn force_rotation(class: &SecretClass) -> Option<String> {
+    class
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(tls::FORCE_ROTATION_ANNOTATION))
+        .cloned()
 }
 
 pub async fn from_selector(
     client: &stackable_operator::client::Client,
     selector: &SecretVolumeSelector,
+    leases: &LeasePool,
 ) -> Result<Box<Dynamic>, FromSelectorError> {
+    from_selector_with_rotation(client, selector, leases)
+        .await
+        .map(|(backend, _)| backend)
+}
+
+/// Like [`from_selector`], but also returns the forced rotation that the SecretClass requested when the backend was
+/// loaded (see [`force_rotation`]).
+pub async fn from_selector_with_rotation(
+    client: &stackable_operator::client::Client,
+    selector: &SecretVolumeSelector,
+    leases: &LeasePool,
+) -> Result<(Box<Dynamic>, Option<String>), FromSelectorError> {
     let class_ref = || ObjectRef::new(&selector.class);
     let class = client
         .get::<SecretClass>(&selector.class, &())
         .await
         .with_context(|_| from_selector_error::GetSecretClassSnafu { class: class_ref() })?;
-    from_class(client, class)
-        .await
-        .with_context(|_| from_selector_error::FromClassSnafu { class: class_ref() })
+    if let Some(policy) = &class.spec.allowed_namespaces {
+        let namespace_labels = if policy.needs_labels() {
+            client
+                .get::<Namespace>(&selector.namespace, &())
+                .await
+                .with_context(|_| from_selector_error::GetNamespaceSnafu {
+                    namespace: ObjectRef::new(&selector.namespace),
+                    class: class_ref(),
+                })?
+                .metadata
+                .labels
+                .unwrap_or_default()
+        } else {
+            BTreeMap::new()
+        };
+        ensure!(
+            policy.allows(&selector.namespace, &namespace_labels),
+            from_selector_error::NamespaceNotAllowedSnafu {
+                namespace: &selector.namespace,
+                class: class_ref(),
+                policy: policy.clone(),
+            }
+        );
+    }
+    let class_object = class.object_ref(&());
+    let rotation = force_rotation(&class);
+    let result = from_class(client, class, leases).await;
+    if let Err(err) = &result {
+        if err.write_rejection().is_some() {
+            publish_rejection_event(client, &class_object, err).await;
+        }
+    }
+    let backend =
+        result.with_context(|_| from_selector_error::FromClassSnafu { class: class_ref() })?;
+    Ok((backend, rotation))
+}
+
+/// Warns about `err` on the `SecretClass`, since it won't go away on its own.
+async fn publish_rejection_event(
+    client: &stackable_operator::client::Client,
+    class: &ObjectReference,
+    err: &FromClassError,
+) {
+    let recorder = Recorder::new(
+        client.as_kube_client(),
+        Reporter {
+            controller: ownership::OPERATOR_NAME.to_string(),
+            instance: None,
+        },
+    );
+    let event = Event {
+        type_: EventType::Warning,
+        reason: "WriteRejected".to_string(),
+        note: Some(error_full_message(err)),
+        action: "LoadBackend".to_string(),
+        secondary: None,
+    };
+    if let Err(publish_err) = recorder.publish(&event, class).await {
+        tracing::warn!(
+            class = class.name.as_deref(),
+            error = &publish_err as &dyn std::error::Error,
+            "failed to publish rejection event"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "failpoints"))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use async_trait::async_trait;
+    use serde::{
+        Deserialize,
+        de::{
+            IntoDeserializer,
+            value::{self, MapDeserializer},
+        },
+    };
+
+    use super::{SecretBackendError, from};
+    use crate::{
+        backend::{
+            SecretBackend, SecretContents, SecretVolumeSelector,
+            pod_info::{DependencyWait, NodeInfo, PodInfo, SchedulingPodInfo},
+        },
+        format::SecretData,
+        utils::failpoint,
+    };
+
+    /// Counts how often it was asked for secret data.
+    #[derive(Debug, Default)]
+    struct CountingBackend {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl SecretBackend for Arc<CountingBackend> {
+        type Error = Infallible;
+
+        async fn get_secret_data(
+            &self,
+            _selector: &SecretVolumeSelector,
+            _pod_info: PodInfo,
+        ) -> Result<SecretContents, Self::Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(SecretContents::new(SecretData::Unknown(HashMap::new())))
+        }
+    }
+
+    fn selector() -> SecretVolumeSelector {
+        SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, value::Error>>(
+            HashMap::from([
+                ("secrets.stackable.tech/class", "my-class"),
+                ("csi.storage.k8s.io/pod.name", "my-pod"),
+                ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+            ])
+            .into_deserializer(),
+        )
+        .unwrap()
+    }
+
+    fn pod_info() -> PodInfo {
+        PodInfo {
+            pod_ips: Vec::new(),
+            service_name: None,
+            node_name: "my-node".to_string(),
+            node_ips: Vec::new(),
+            node: NodeInfo {
+                labels: Default::default(),
+                internal_ips: Vec::new(),
+            },
+            listener_addresses: HashMap::new(),
+            kubernetes_cluster_domain: "cluster.local".parse().unwrap(),
+            scheduling: SchedulingPodInfo {
+                namespace: "my-namespace".to_string(),
+                volume_listener_names: HashMap::new(),
+                has_node_scope: false,
+            },
+            dependency_wait: DependencyWait::none(),
+        }
+    }
+
+    #[tokio::test]
+    async fn fault_before_backend_should_be_retryable() {
+        let inner = Arc::new(CountingBackend::default());
+        let backend = from(inner.clone());
+        failpoint::configure_for_current_thread("backend.get_secret_data.before=1*return").unwrap();
+
+        let err = backend
+            .get_secret_data(&selector(), pod_info())
+            .await
+            .unwrap_err();
+        // Kubelet retries publishing for Unavailable
+        assert_eq!(err.grpc_code(), tonic::Code::Unavailable);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 0);
+
+        backend
+            .get_secret_data(&selector(), pod_info())
+            .await
+            .unwrap();
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn fault_after_backend_should_discard_secret_data() {
+        let inner = Arc::new(CountingBackend::default());
+        let backend = from(inner.clone());
+        failpoint::configure_for_current_thread("backend.get_secret_data.after=return").unwrap();
+
+        let err = backend
+            .get_secret_data(&selector(), pod_info())
+            .await
+            .unwrap_err();
+        assert_eq!(err.grpc_code(), tonic::Code::Unavailable);
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+    }
 }
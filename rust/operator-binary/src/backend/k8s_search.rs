@@ -1,6 +1,9 @@
 //! Queries the Kubernetes API for predefined [`Secret`] objects
 
-use std::collections::{BTreeMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use snafu::{OptionExt, ResultExt, Snafu};
@@ -37,8 +40,13 @@ pub enum Error {
         source: stackable_operator::client::Error,
     },
 
-    #[snafu(display("no Secrets matched label selector {label_selector:?}"))]
-    NoSecret { label_selector: String },
+    #[snafu(display(
+        "no Secrets matched label selector {label_selector:?} (waited {waited:?} for one to be created)"
+    ))]
+    NoSecret {
+        label_selector: String,
+        waited: Duration,
+    },
 
     #[snafu(display("failed to find Listener name for volume {listener_volume}"))]
     NoListener { listener_volume: String },
@@ -52,7 +60,8 @@ impl SecretBackendError for Error {
         match self {
             Error::SecretSelector { .. } => tonic::Code::FailedPrecondition,
             Error::SecretQuery { .. } => tonic::Code::FailedPrecondition,
-            Error::NoSecret { .. } => tonic::Code::FailedPrecondition,
+            // Usually created concurrently with the Pod, so retrying later is likely to succeed
+            Error::NoSecret { .. } => tonic::Code::Unavailable,
             Error::NoListener { .. } => tonic::Code::FailedPrecondition,
             Error::BuildLabel { .. } => tonic::Code::FailedPrecondition,
         }
@@ -86,17 +95,23 @@ impl SecretBackend for K8sSearch {
     ) -> Result<SecretContents, Self::Error> {
         let label_selector =
             build_label_selector_query(selector, LabelSelectorPodInfo::Scheduled(&pod_info))?;
-        let secret = self
-            .client
-            .list::<Secret>(
-                self.search_ns_for_pod(selector),
-                &ListParams::default().labels(&label_selector),
-            )
+        let wait = pod_info.dependency_wait;
+        let secret = wait
+            .poll("secret", || async {
+                self.client
+                    .list::<Secret>(
+                        self.search_ns_for_pod(selector),
+                        &ListParams::default().labels(&label_selector),
+                    )
+                    .await
+                    .map(|secrets| secrets.into_iter().next())
+            })
             .await
             .context(SecretQuerySnafu)?
-            .into_iter()
-            .next()
-            .context(NoSecretSnafu { label_selector })?;
+            .context(NoSecretSnafu {
+                label_selector,
+                waited: wait.budget,
+            })?;
         Ok(SecretContents::new(SecretData::Unknown(
             secret
                 .data
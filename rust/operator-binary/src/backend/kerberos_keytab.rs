@@ -1,9 +1,12 @@
+use std::{collections::BTreeMap, fmt::Display};
+
 use async_trait::async_trait;
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
 use stackable_krb5_provision_keytab::{
     // Some qualified paths get long enough to break rustfmt, alias the crate name to work around that
     self as provision,
     provision_keytab,
+    shortening::{self, NameKind, NameLengthLimits, NameMappings},
 };
 use stackable_operator::{
     commons::networking::{HostName, KerberosRealmName},
@@ -18,12 +21,19 @@ use tokio::{
 };
 
 use super::{
-    ScopeAddressesError, SecretBackend, SecretBackendError, SecretContents, pod_info::Address,
+    ScopeAddressesError, SecretBackend, SecretBackendError, SecretContents, SecretIdentity,
+    SecretVolumeSelector,
+    coordination::LeasePool,
+    keytab,
+    krb5_conf::{self, Krb5Conf},
+    node_name::{self, SystemResolver, resolve_node_hostname},
+    pod_info::{Address, PodInfo},
     scope::SecretScope,
 };
 use crate::{
     crd::{
-        ActiveDirectorySamAccountNameRules, InvalidKerberosPrincipal, KerberosKeytabBackendAdmin,
+        ActiveDirectorySamAccountNameRules, InvalidKerberosPrincipal, KerberosExtraConfig,
+        KerberosKeytabBackendAdmin, KerberosNameLengthLimits, KerberosNodeNameSource,
         KerberosPrincipal,
     },
     format::{SecretData, WellKnownSecretData, well_known},
@@ -38,6 +48,14 @@ pub enum Error {
         scope: SecretScope,
     },
 
+    #[snafu(display(
+        "failed to resolve host name of node using nodeNameSource {node_name_source}"
+    ))]
+    ResolveNodeHostname {
+        source: node_name::Error,
+        node_name_source: KerberosNodeNameSource,
+    },
+
     #[snafu(display("failed to load admin keytab from {secret}"))]
     LoadAdminKeytab {
         source: stackable_operator::client::Error,
@@ -47,6 +65,20 @@ pub enum Error {
     #[snafu(display(r#"admin keytab {secret} does not contain key "keytab""#))]
     NoAdminKeytabKeyInSecret { secret: ObjectRef<Secret> },
 
+    #[snafu(display("invalid extraConfig"))]
+    InvalidExtraConfig { source: krb5_conf::Error },
+
+    #[snafu(display("invalid nameLengthLimits"))]
+    InvalidNameLengthLimits { source: shortening::Error },
+
+    #[snafu(display("nameLengthLimits.samAccountName requires the activeDirectory admin backend"))]
+    SamAccountNameLimitWithoutActiveDirectory,
+
+    #[snafu(display(
+        "nameLengthLimits.samAccountName cannot be combined with experimentalGenerateSamAccountName"
+    ))]
+    ConflictingSamAccountNameRules,
+
     #[snafu(display("failed to create temp dir"))]
     TempSetup { source: std::io::Error },
 
@@ -64,21 +96,51 @@ pub enum Error {
     #[snafu(display("generated invalid Kerberos principal for pod"))]
     PodPrincipal { source: InvalidKerberosPrincipal },
 
+    #[snafu(display("failed to shorten principal name"))]
+    ShortenName { source: shortening::Error },
+
+    #[snafu(display("failed to serialize shortened names"))]
+    SerializeNameMappings { source: serde_json::Error },
+
     #[snafu(display("failed to read keytab"))]
     ReadKeytab { source: std::io::Error },
+
+    #[snafu(display("failed to split keytab into keytab groups"))]
+    SplitKeytab { source: keytab::ParseError },
 }
 impl SecretBackendError for Error {
     fn grpc_code(&self) -> tonic::Code {
         match self {
             Error::LoadAdminKeytab { .. } => tonic::Code::FailedPrecondition,
             Error::NoAdminKeytabKeyInSecret { .. } => tonic::Code::FailedPrecondition,
+            Error::InvalidExtraConfig { .. } => tonic::Code::FailedPrecondition,
+            Error::InvalidNameLengthLimits { .. } => tonic::Code::FailedPrecondition,
+            Error::SamAccountNameLimitWithoutActiveDirectory => tonic::Code::FailedPrecondition,
+            Error::ConflictingSamAccountNameRules => tonic::Code::FailedPrecondition,
             Error::TempSetup { .. } => tonic::Code::Unavailable,
             Error::WriteConfig { .. } => tonic::Code::Unavailable,
             Error::WriteAdminKeytab { .. } => tonic::Code::Unavailable,
+            Error::ProvisionKeytab {
+                source: provision::Error::RunProvisioner { kind, .. },
+            } => match kind {
+                provision::FailureKind::NotFound => tonic::Code::NotFound,
+                provision::FailureKind::AlreadyExists => tonic::Code::AlreadyExists,
+                provision::FailureKind::Unauthenticated => tonic::Code::Unauthenticated,
+                provision::FailureKind::PermissionDenied => tonic::Code::PermissionDenied,
+                provision::FailureKind::Unavailable => tonic::Code::Unavailable,
+                provision::FailureKind::FailedPrecondition => tonic::Code::FailedPrecondition,
+                provision::FailureKind::Unknown => tonic::Code::Unavailable,
+            },
             Error::ProvisionKeytab { .. } => tonic::Code::Unavailable,
             Error::PodPrincipal { .. } => tonic::Code::FailedPrecondition,
+            // Collisions need the limits to be raised (or the Pods to be renamed)
+            Error::ShortenName { .. } => tonic::Code::FailedPrecondition,
+            Error::SerializeNameMappings { .. } => tonic::Code::Internal,
             Error::ReadKeytab { .. } => tonic::Code::Unavailable,
+            Error::SplitKeytab { .. } => tonic::Code::Internal,
             Error::ScopeAddresses { .. } => tonic::Code::Unavailable,
+            // DNS and Node labels may be fixed by the cluster administrator, so retrying later may succeed
+            Error::ResolveNodeHostname { .. } => tonic::Code::Unavailable,
         }
     }
 }
@@ -88,13 +150,53 @@ pub struct KerberosProfile {
     pub realm_name: KerberosRealmName,
     pub kdc: HostName,
     pub admin: KerberosKeytabBackendAdmin,
+    pub extra_config: Vec<KerberosExtraConfig>,
+}
+
+impl KerberosProfile {
+    /// Generates the `krb5.conf` that is used to talk to the KDC, and that is provided to pods.
+    fn krb5_conf(&self) -> Result<String, krb5_conf::Error> {
+        let Self {
+            realm_name,
+            kdc,
+            admin,
+            extra_config,
+        } = self;
+        let mut conf = Krb5Conf::default();
+        conf.section("libdefaults")?
+            .add("default_realm", realm_name)?
+            .add("rdns", false)?
+            .add("dns_canonicalize_hostnames", false)?
+            .add("udp_preference_limit", 1)?;
+        let realm = conf
+            .section("realms")?
+            .subsection(&realm_name.to_string())?;
+        realm.add("kdc", kdc)?;
+        match admin {
+            KerberosKeytabBackendAdmin::Mit { kadmin_server } => {
+                realm.add("admin_server", kadmin_server)?;
+            }
+            KerberosKeytabBackendAdmin::ActiveDirectory { .. } => {}
+        }
+        conf.section("domain_realm")?
+            .add("cluster.local", realm_name)?
+            .add(".cluster.local", realm_name)?;
+        conf.merge(extra_config)?;
+        conf.render()
+    }
 }
 
 #[derive(Debug)]
 pub struct KerberosKeytab {
     profile: KerberosProfile,
+    /// Rendered from `profile` up front, so that invalid extraConfig is reported when loading the SecretClass
+    krb5_conf: String,
     admin_keytab: Unloggable<Vec<u8>>,
     admin_principal: KerberosPrincipal,
+    node_name_source: KerberosNodeNameSource,
+    name_length_limits: NameLengthLimits,
+    /// Limits how many nodes may talk to the KDC at the same time
+    leases: LeasePool,
 }
 
 impl KerberosKeytab {
@@ -103,6 +205,9 @@ impl KerberosKeytab {
         profile: KerberosProfile,
         admin_keytab_secret_ref: &SecretReference,
         admin_principal: KerberosPrincipal,
+        node_name_source: KerberosNodeNameSource,
+        name_length_limits: KerberosNameLengthLimits,
+        leases: LeasePool,
     ) -> Result<Self, Error> {
         let admin_keytab_secret = client
             .get::<Secret>(
@@ -121,10 +226,37 @@ impl KerberosKeytab {
                 secret: admin_keytab_secret_ref.clone(),
             })?
             .0;
+        let krb5_conf = profile.krb5_conf().context(InvalidExtraConfigSnafu)?;
+        let name_length_limits = NameLengthLimits {
+            principal_component: name_length_limits.principal_component.map(usize::from),
+            sam_account_name: name_length_limits.sam_account_name.map(usize::from),
+            local_username: name_length_limits.local_username.map(usize::from),
+        };
+        name_length_limits
+            .validate()
+            .context(InvalidNameLengthLimitsSnafu)?;
+        if name_length_limits.sam_account_name.is_some() {
+            match &profile.admin {
+                KerberosKeytabBackendAdmin::Mit { .. } => {
+                    return SamAccountNameLimitWithoutActiveDirectorySnafu.fail();
+                }
+                KerberosKeytabBackendAdmin::ActiveDirectory {
+                    generate_sam_account_name,
+                    ..
+                } => ensure!(
+                    generate_sam_account_name.is_none(),
+                    ConflictingSamAccountNameRulesSnafu
+                ),
+            }
+        }
         Ok(Self {
             profile,
+            krb5_conf,
             admin_keytab: Unloggable(admin_keytab),
             admin_principal,
+            node_name_source,
+            name_length_limits,
+            leases,
         })
     }
 }
@@ -135,54 +267,29 @@ impl SecretBackend for KerberosKeytab {
 
     async fn get_secret_data(
         &self,
-        selector: &super::SecretVolumeSelector,
-        pod_info: super::pod_info::PodInfo,
-    ) -> Result<super::SecretContents, Self::Error> {
+        selector: &SecretVolumeSelector,
+        pod_info: PodInfo,
+    ) -> Result<SecretContents, Self::Error> {
         let Self {
-            profile:
-                KerberosProfile {
-                    realm_name,
-                    kdc,
-                    admin,
-                },
+            profile: KerberosProfile {
+                admin, realm_name, ..
+            },
+            krb5_conf,
             admin_keytab,
             admin_principal,
+            node_name_source,
+            name_length_limits,
+            leases,
         } = self;
 
-        let admin_server_clause = match admin {
-            KerberosKeytabBackendAdmin::Mit { kadmin_server } => {
-                format!("  admin_server = {kadmin_server}")
-            }
-            KerberosKeytabBackendAdmin::ActiveDirectory { .. } => String::new(),
-        };
-
         let tmp = tempdir().context(TempSetupSnafu)?;
-        let profile = format!(
-            r#"
-[libdefaults]
-default_realm = {realm_name}
-rdns = false
-dns_canonicalize_hostnames = false
-udp_preference_limit = 1
-
-[realms]
-{realm_name} = {{
-  kdc = {kdc}
-{admin_server_clause}
-}}
-
-[domain_realm]
-cluster.local = {realm_name}
-.cluster.local = {realm_name}
-"#
-        );
         let profile_file_path = tmp.path().join("krb5.conf");
         {
             let mut profile_file = File::create(&profile_file_path)
                 .await
                 .context(WriteConfigSnafu)?;
             profile_file
-                .write_all(profile.as_bytes())
+                .write_all(krb5_conf.as_bytes())
                 .await
                 .context(WriteConfigSnafu)?;
         }
@@ -197,77 +304,118 @@ cluster.local = {realm_name}
                 .context(WriteAdminKeytabSnafu)?;
         }
         let keytab_file_path = tmp.path().join("pod-keytab");
-        let mut pod_principals: Vec<KerberosPrincipal> = Vec::new();
-        for service_name in &selector.kerberos_service_names {
-            for scope in &selector.scope {
-                for addr in
-                    selector
-                        .scope_addresses(&pod_info, scope)
-                        .context(ScopeAddressesSnafu {
-                            scope: scope.clone(),
-                        })?
-                {
-                    pod_principals.push(
-                        match addr {
-                            Address::Dns(hostname) => {
-                                format!("{service_name}/{hostname}")
-                            }
-                            Address::Ip(ip) => {
-                                format!("{service_name}/{ip}")
-                            }
-                        }
-                        .try_into()
-                        .context(PodPrincipalSnafu)?,
-                    );
+        let node_hostname = if selector.scope.contains(&SecretScope::Node) {
+            Some(
+                resolve_node_hostname(
+                    node_name_source,
+                    &pod_info.node_name,
+                    &pod_info.node,
+                    &SystemResolver,
+                )
+                .await
+                .context(ResolveNodeHostnameSnafu { node_name_source })?,
+            )
+        } else {
+            None
+        };
+        // Principals that are part of several keytab groups are still only provisioned once, and then copied into
+        // each group's keytab
+        let service_names: Vec<&str> = match &selector.kerberos_keytab_groups {
+            Some(groups) => groups.service_names().into_iter().collect(),
+            None => selector
+                .kerberos_service_names
+                .iter()
+                .map(String::as_str)
+                .collect(),
+        };
+        let mut name_mappings = NameMappings::default();
+        let keytab_groups = selector
+            .kerberos_keytab_groups
+            .iter()
+            .flat_map(|groups| groups.iter())
+            .map(|(file, service_names)| {
+                Ok((
+                    file,
+                    pod_principals(
+                        selector,
+                        service_names.iter().map(String::as_str),
+                        &pod_info,
+                        node_hostname.as_deref(),
+                        name_length_limits,
+                        &mut name_mappings,
+                    )?,
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let pod_principals = pod_principals(
+            selector,
+            service_names,
+            &pod_info,
+            node_hostname.as_deref(),
+            name_length_limits,
+            &mut name_mappings,
+        )?;
+        let identity = SecretIdentity {
+            principals: pod_principals
+                .iter()
+                .map(|princ| format!("{princ}@{realm_name}"))
+                .collect(),
+            ..Default::default()
+        };
+        let provision_request = stackable_krb5_provision_keytab::Request {
+            admin_keytab_path: admin_keytab_file_path,
+            admin_principal_name: admin_principal.to_string(),
+            pod_keytab_path: keytab_file_path.clone(),
+            principals: pod_principals
+                .into_iter()
+                .map(|princ| stackable_krb5_provision_keytab::PrincipalRequest {
+                    name: princ.to_string(),
+                })
+                .collect(),
+            admin_backend: match admin {
+                KerberosKeytabBackendAdmin::Mit { .. } => {
+                    stackable_krb5_provision_keytab::AdminBackend::Mit
                 }
-            }
-        }
-        provision_keytab(
-            &profile_file_path,
-            &stackable_krb5_provision_keytab::Request {
-                admin_keytab_path: admin_keytab_file_path,
-                admin_principal_name: admin_principal.to_string(),
-                pod_keytab_path: keytab_file_path.clone(),
-                principals: pod_principals
-                    .into_iter()
-                    .map(|princ| stackable_krb5_provision_keytab::PrincipalRequest {
-                        name: princ.to_string(),
-                    })
-                    .collect(),
-                admin_backend: match admin {
-                    KerberosKeytabBackendAdmin::Mit { .. } => {
-                        stackable_krb5_provision_keytab::AdminBackend::Mit
-                    }
-                    KerberosKeytabBackendAdmin::ActiveDirectory {
-                        ldap_server,
-                        ldap_tls_ca_secret,
-                        password_cache_secret,
-                        user_distinguished_name,
-                        schema_distinguished_name,
-                        generate_sam_account_name,
-                    } => stackable_krb5_provision_keytab::AdminBackend::ActiveDirectory {
-                        ldap_server: ldap_server.to_string(),
-                        ldap_tls_ca_secret: ldap_tls_ca_secret.clone(),
-                        password_cache_secret: password_cache_secret.clone(),
-                        user_distinguished_name: user_distinguished_name.clone(),
-                        schema_distinguished_name: schema_distinguished_name.clone(),
-                        generate_sam_account_name: generate_sam_account_name.clone().map(
-                            |ActiveDirectorySamAccountNameRules {
-                                 prefix,
-                                 total_length,
-                             }| {
-                                provision::ActiveDirectorySamAccountNameRules {
-                                    prefix,
-                                    total_length,
-                                }
-                            },
-                        ),
-                    },
+                KerberosKeytabBackendAdmin::ActiveDirectory {
+                    ldap_server,
+                    ldap_tls_ca_secret,
+                    password_cache_secret,
+                    user_distinguished_name,
+                    schema_distinguished_name,
+                    generate_sam_account_name,
+                } => stackable_krb5_provision_keytab::AdminBackend::ActiveDirectory {
+                    ldap_server: ldap_server.to_string(),
+                    ldap_tls_ca_secret: ldap_tls_ca_secret.clone(),
+                    password_cache_secret: password_cache_secret.clone(),
+                    user_distinguished_name: user_distinguished_name.clone(),
+                    schema_distinguished_name: schema_distinguished_name.clone(),
+                    generate_sam_account_name: generate_sam_account_name.clone().map(
+                        |ActiveDirectorySamAccountNameRules {
+                             prefix,
+                             total_length,
+                         }| {
+                            provision::ActiveDirectorySamAccountNameRules {
+                                prefix,
+                                total_length,
+                            }
+                        },
+                    ),
                 },
             },
-        )
-        .await
-        .context(ProvisionKeytabSnafu)?;
+            name_length_limits: *name_length_limits,
+            // Volumes are provisioned independently, so a single volume can't tell whether old keys are still in use
+            purge_old_keys: None,
+        };
+        let provision_response = leases
+            .run(
+                "kerberos-provision-keytab",
+                provision_keytab(&profile_file_path, &provision_request),
+            )
+            .await
+            .context(ProvisionKeytabSnafu)?;
+        name_mappings
+            .extend(provision_response.name_mappings)
+            .context(ShortenNameSnafu)?;
         let mut keytab_data = Vec::new();
         let mut keytab_file = File::open(keytab_file_path)
             .await
@@ -276,11 +424,276 @@ cluster.local = {realm_name}
             .read_to_end(&mut keytab_data)
             .await
             .context(ReadKeytabSnafu)?;
-        Ok(SecretContents::new(SecretData::WellKnown(
-            WellKnownSecretData::Kerberos(well_known::Kerberos {
-                keytab: keytab_data,
-                krb5_conf: profile.into_bytes(),
-            }),
-        )))
+        let keytab_groups =
+            split_keytab(&keytab_data, &keytab_groups, realm_name).context(SplitKeytabSnafu)?;
+        let keytab = (keytab_groups.is_empty() || selector.kerberos_keytab_groups_combined)
+            .then_some(keytab_data);
+        Ok(
+            SecretContents::new(SecretData::WellKnown(WellKnownSecretData::Kerberos(
+                well_known::Kerberos {
+                    keytab,
+                    keytab_groups,
+                    krb5_conf: krb5_conf.clone().into_bytes(),
+                    shortened_names: if name_mappings.is_empty() {
+                        None
+                    } else {
+                        Some(
+                            serde_json::to_vec_pretty(&name_mappings)
+                                .context(SerializeNameMappingsSnafu)?,
+                        )
+                    },
+                },
+            )))
+            .identity(identity),
+        )
+    }
+}
+
+/// The principals that the `Pod` should get keys for, one for each combination of Kerberos service name and address
+/// of the selected scopes.
+///
+/// The node's name is replaced by `node_hostname` (if it was resolved), since it isn't necessarily a valid host name.
+fn pod_principals<'a>(
+    selector: &SecretVolumeSelector,
+    service_names: impl IntoIterator<Item = &'a str>,
+    pod_info: &PodInfo,
+    node_hostname: Option<&str>,
+    name_length_limits: &NameLengthLimits,
+    name_mappings: &mut NameMappings,
+) -> Result<Vec<KerberosPrincipal>, Error> {
+    let mut pod_principals = Vec::new();
+    for service_name in service_names {
+        let service_name = name_mappings
+            .shorten(NameKind::LocalUsername, service_name, name_length_limits)
+            .context(ShortenNameSnafu)?;
+        for scope in &selector.scope {
+            for addr in selector
+                .scope_addresses(pod_info, scope)
+                .context(ScopeAddressesSnafu {
+                    scope: scope.clone(),
+                })?
+            {
+                let hostname = match addr {
+                    Address::Dns(hostname)
+                        if *scope == SecretScope::Node && hostname == pod_info.node_name =>
+                    {
+                        node_hostname.map_or(hostname, str::to_string)
+                    }
+                    Address::Dns(hostname) => hostname,
+                    // Shortening an address would make it meaningless
+                    Address::Ip(ip) => {
+                        pod_principals.push(
+                            format!("{service_name}/{ip}")
+                                .try_into()
+                                .context(PodPrincipalSnafu)?,
+                        );
+                        continue;
+                    }
+                };
+                let hostname = name_mappings
+                    .shorten(NameKind::PrincipalComponent, &hostname, name_length_limits)
+                    .context(ShortenNameSnafu)?;
+                pod_principals.push(
+                    format!("{service_name}/{hostname}")
+                        .try_into()
+                        .context(PodPrincipalSnafu)?,
+                );
+            }
+        }
+    }
+    Ok(pod_principals)
+}
+
+/// Splits `keytab` into one keytab for each of the `groups`, which only contains the keys of the group's principals.
+fn split_keytab(
+    keytab: &[u8],
+    groups: &[(&str, Vec<KerberosPrincipal>)],
+    realm_name: &impl Display,
+) -> Result<BTreeMap<String, Vec<u8>>, keytab::ParseError> {
+    let entries = keytab::entries(keytab)?;
+    Ok(groups
+        .iter()
+        .map(|(file, principals)| {
+            let principals = principals
+                .iter()
+                .map(|princ| format!("{princ}@{realm_name}"))
+                .collect::<Vec<_>>();
+            let group_entries = entries
+                .iter()
+                .filter(|entry| principals.contains(&entry.principal));
+            (file.to_string(), keytab::write(group_entries))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{
+        Deserialize,
+        de::{IntoDeserializer, value::MapDeserializer},
+    };
+
+    use stackable_krb5_provision_keytab::shortening::{NameLengthLimits, NameMappings};
+
+    use super::{pod_principals, split_keytab};
+    use crate::backend::{
+        SecretVolumeSelector,
+        keytab::{
+            self,
+            tests::{encode_entry, encode_keytab},
+        },
+        pod_info::{DependencyWait, NodeInfo, PodInfo, SchedulingPodInfo},
+    };
+
+    fn selector(scope: &str) -> SecretVolumeSelector {
+        selector_with(&[("secrets.stackable.tech/scope", scope)])
+    }
+
+    fn selector_with(attributes: &[(&str, &str)]) -> SecretVolumeSelector {
+        let mut attributes = HashMap::from_iter(attributes.iter().copied());
+        for default in [
+            ("secrets.stackable.tech/class", "kerberos"),
+            ("secrets.stackable.tech/kerberos.service.names", "HTTP,HDFS"),
+            ("csi.storage.k8s.io/pod.name", "my-pod"),
+            ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+        ] {
+            attributes.entry(default.0).or_insert(default.1);
+        }
+        SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, serde::de::value::Error>>(
+            attributes.into_deserializer(),
+        )
+        .unwrap()
+    }
+
+    fn pod_info() -> PodInfo {
+        PodInfo {
+            pod_ips: vec!["10.0.0.1".parse().unwrap()],
+            service_name: None,
+            node_name: "my-node".to_string(),
+            node_ips: vec!["10.1.0.1".parse().unwrap()],
+            node: NodeInfo {
+                labels: Default::default(),
+                internal_ips: Vec::new(),
+            },
+            listener_addresses: HashMap::new(),
+            kubernetes_cluster_domain: "cluster.local".parse().unwrap(),
+            scheduling: SchedulingPodInfo {
+                namespace: "my-namespace".to_string(),
+                volume_listener_names: HashMap::new(),
+                has_node_scope: true,
+            },
+            dependency_wait: DependencyWait::none(),
+        }
+    }
+
+    fn principal_names(scope: &str, node_hostname: Option<&str>) -> Vec<String> {
+        let selector = selector(scope);
+        let service_names = selector.kerberos_service_names.iter().map(String::as_str);
+        pod_principals(
+            &selector,
+            service_names,
+            &pod_info(),
+            node_hostname,
+            &NameLengthLimits::default(),
+            &mut NameMappings::default(),
+        )
+        .unwrap()
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+    }
+
+    #[test]
+    fn principals_should_cover_every_service_name_and_address() {
+        assert_eq!(
+            principal_names("node,service=my-svc", Some("my-node.example.com")),
+            [
+                "HTTP/my-node.example.com",
+                "HTTP/10.1.0.1",
+                "HTTP/my-svc.my-namespace.svc.cluster.local",
+                "HDFS/my-node.example.com",
+                "HDFS/10.1.0.1",
+                "HDFS/my-svc.my-namespace.svc.cluster.local",
+            ]
+        );
+    }
+
+    #[test]
+    fn principals_should_fall_back_to_node_name() {
+        assert_eq!(
+            principal_names("node,pod", None),
+            [
+                "HTTP/my-node",
+                "HTTP/10.1.0.1",
+                "HTTP/10.0.0.1",
+                "HDFS/my-node",
+                "HDFS/10.1.0.1",
+                "HDFS/10.0.0.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn keytab_groups_should_only_contain_their_principals() {
+        let selector = selector_with(&[
+            ("secrets.stackable.tech/scope", "node"),
+            (
+                "secrets.stackable.tech/kerberos.keytab-groups",
+                r#"{"nn.keytab": ["nn", "HTTP"], "dn.keytab": ["dn", "HTTP"]}"#,
+            ),
+        ]);
+        let groups = selector
+            .kerberos_keytab_groups
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|(file, service_names)| {
+                let service_names = service_names.iter().map(String::as_str);
+                let principals =
+                    pod_principals(&selector, service_names, &pod_info(), Some("my-node"));
+                (file, principals.unwrap())
+            })
+            .collect::<Vec<_>>();
+        // HTTP is shared by both groups, but only provisioned once
+        let combined = encode_keytab(&[
+            encode_entry("HTTP/my-node@EXAMPLE.COM", 1),
+            encode_entry("HTTP/10.1.0.1@EXAMPLE.COM", 2),
+            encode_entry("nn/my-node@EXAMPLE.COM", 3),
+            encode_entry("nn/10.1.0.1@EXAMPLE.COM", 4),
+            encode_entry("dn/my-node@EXAMPLE.COM", 5),
+            encode_entry("dn/10.1.0.1@EXAMPLE.COM", 6),
+        ]);
+
+        let split = split_keytab(&combined, &groups, &"EXAMPLE.COM").unwrap();
+        let principals = |file: &str| {
+            let mut principals = keytab::entries(&split[file])
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.principal)
+                .collect::<Vec<_>>();
+            principals.sort();
+            principals
+        };
+        assert_eq!(split.len(), 2);
+        assert_eq!(
+            principals("nn.keytab"),
+            [
+                "HTTP/10.1.0.1@EXAMPLE.COM",
+                "HTTP/my-node@EXAMPLE.COM",
+                "nn/10.1.0.1@EXAMPLE.COM",
+                "nn/my-node@EXAMPLE.COM",
+            ]
+        );
+        assert_eq!(
+            principals("dn.keytab"),
+            [
+                "HTTP/10.1.0.1@EXAMPLE.COM",
+                "HTTP/my-node@EXAMPLE.COM",
+                "dn/10.1.0.1@EXAMPLE.COM",
+                "dn/my-node@EXAMPLE.COM",
+            ]
+        );
     }
 }
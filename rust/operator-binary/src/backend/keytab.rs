@@ -0,0 +1,234 @@
+//! Reads and filters Kerberos keytab files, so that keytabs can be split up without going through libkrb5.
+//!
+//! Only version 2 of the [keytab file format] is supported, which is what MIT Kerberos (and so the
+//! provisioner) writes.
+//!
+//! [keytab file format]: https://web.mit.edu/kerberos/krb5-latest/doc/formats/keytab_file_format.html
+
+use snafu::{OptionExt, Snafu, ensure};
+
+const KEYTAB_V2_HEADER: [u8; 2] = [0x05, 0x02];
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum ParseError {
+    #[snafu(display("unsupported keytab version {version:02x?}, only version 2 is supported"))]
+    UnsupportedVersion { version: Vec<u8> },
+
+    #[snafu(display("keytab is truncated at offset {offset}"))]
+    Truncated { offset: usize },
+
+    #[snafu(display("keytab entry at offset {offset} has a non-UTF-8 principal"))]
+    InvalidPrincipal { offset: usize },
+}
+
+/// A single key in a keytab.
+#[derive(Debug)]
+pub struct Entry<'a> {
+    /// The entry's principal, formatted as `component/component@REALM`.
+    pub principal: String,
+    /// The encoded entry, excluding its length prefix.
+    raw: &'a [u8],
+}
+
+/// Reads every entry of `keytab`, skipping over holes that were left by deleted entries.
+pub fn entries(keytab: &[u8]) -> Result<Vec<Entry<'_>>, ParseError> {
+    use parse_error::*;
+    let version = keytab
+        .get(..2)
+        .context(TruncatedSnafu { offset: 0_usize })?;
+    ensure!(
+        version == KEYTAB_V2_HEADER,
+        UnsupportedVersionSnafu { version }
+    );
+    let mut entries = Vec::new();
+    let mut reader = Reader {
+        data: keytab,
+        offset: 2,
+    };
+    while reader.offset < keytab.len() {
+        let size = reader.i32()?;
+        let offset = reader.offset;
+        let raw = reader.bytes(size.unsigned_abs() as usize)?;
+        if size <= 0 {
+            // Holes have a negative size (and a size of 0 terminates the keytab for some implementations)
+            if size == 0 {
+                break;
+            }
+            continue;
+        }
+        entries.push(Entry {
+            principal: principal_name(raw, offset)?,
+            raw,
+        });
+    }
+    Ok(entries)
+}
+
+/// Encodes `entries` as a new keytab.
+pub fn write<'a>(entries: impl IntoIterator<Item = &'a Entry<'a>>) -> Vec<u8> {
+    let mut keytab = KEYTAB_V2_HEADER.to_vec();
+    for entry in entries {
+        let size = i32::try_from(entry.raw.len()).expect("parsed keytab entry must fit its size");
+        keytab.extend_from_slice(&size.to_be_bytes());
+        keytab.extend_from_slice(entry.raw);
+    }
+    keytab
+}
+
+/// Decodes the principal of the entry `raw` (which starts at `offset` of the keytab).
+fn principal_name(raw: &[u8], offset: usize) -> Result<String, ParseError> {
+    let mut reader = Reader {
+        data: raw,
+        offset: 0,
+    };
+    let component_count = reader.u16()?;
+    let realm = reader.counted_string()?;
+    let components = (0..component_count)
+        .map(|_| reader.counted_string())
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut principal = String::new();
+    for component in components {
+        if !principal.is_empty() {
+            principal.push('/');
+        }
+        principal.push_str(
+            std::str::from_utf8(component)
+                .ok()
+                .context(parse_error::InvalidPrincipalSnafu { offset })?,
+        );
+    }
+    principal.push('@');
+    principal.push_str(
+        std::str::from_utf8(realm)
+            .ok()
+            .context(parse_error::InvalidPrincipalSnafu { offset })?,
+    );
+    Ok(principal)
+}
+
+/// Reads big-endian values, which every field of a version 2 keytab is encoded as.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.offset..end))
+            .context(parse_error::TruncatedSnafu {
+                offset: self.offset,
+            })?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, ParseError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, ParseError> {
+        let bytes = self.bytes(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn counted_string(&mut self) -> Result<&'a [u8], ParseError> {
+        let len = self.u16()?;
+        self.bytes(len.into())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{entries, write};
+
+    /// Encodes a keytab entry for `principal` (such as `HTTP/host@REALM`), with a dummy key.
+    pub(crate) fn encode_entry(principal: &str, key: u8) -> Vec<u8> {
+        let (name, realm) = principal.split_once('@').unwrap();
+        let components = name.split('/').collect::<Vec<_>>();
+        let counted = |data: &[u8]| {
+            let mut counted = u16::try_from(data.len()).unwrap().to_be_bytes().to_vec();
+            counted.extend_from_slice(data);
+            counted
+        };
+        let mut entry = u16::try_from(components.len())
+            .unwrap()
+            .to_be_bytes()
+            .to_vec();
+        entry.extend(counted(realm.as_bytes()));
+        for component in components {
+            entry.extend(counted(component.as_bytes()));
+        }
+        // Name type (KRB5_NT_PRINCIPAL), timestamp, and 8-bit KVNO
+        entry.extend(1u32.to_be_bytes());
+        entry.extend(0u32.to_be_bytes());
+        entry.push(1);
+        // Key (aes256-cts-hmac-sha1-96), followed by the 32-bit KVNO
+        entry.extend(18u16.to_be_bytes());
+        entry.extend(counted(&[key; 32]));
+        entry.extend(1u32.to_be_bytes());
+        entry
+    }
+
+    /// Encodes a keytab that contains `entries`.
+    pub(crate) fn encode_keytab(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut keytab = vec![0x05, 0x02];
+        for entry in entries {
+            keytab.extend(i32::try_from(entry.len()).unwrap().to_be_bytes());
+            keytab.extend(entry);
+        }
+        keytab
+    }
+
+    #[test]
+    fn entries_should_be_read_and_written() {
+        let mut original = encode_keytab(&[
+            encode_entry("HTTP/my-node@EXAMPLE.COM", 1),
+            encode_entry("nn/my-node@EXAMPLE.COM", 2),
+        ]);
+        // A hole left behind by a deleted entry
+        original.extend((-8i32).to_be_bytes());
+        original.extend([0; 8]);
+        original.extend(encode_keytab(&[encode_entry("dn/my-node@EXAMPLE.COM", 3)])[2..].iter());
+
+        let parsed = entries(&original).unwrap();
+        assert_eq!(
+            parsed
+                .iter()
+                .map(|entry| entry.principal.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "HTTP/my-node@EXAMPLE.COM",
+                "nn/my-node@EXAMPLE.COM",
+                "dn/my-node@EXAMPLE.COM"
+            ]
+        );
+        let filtered = write(
+            parsed
+                .iter()
+                .filter(|entry| entry.principal != "nn/my-node@EXAMPLE.COM"),
+        );
+        assert_eq!(
+            filtered,
+            encode_keytab(&[
+                encode_entry("HTTP/my-node@EXAMPLE.COM", 1),
+                encode_entry("dn/my-node@EXAMPLE.COM", 3),
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_keytabs_should_be_rejected() {
+        assert!(entries(b"").is_err());
+        assert!(entries(&[0x05, 0x01]).is_err());
+        let mut truncated = encode_keytab(&[encode_entry("HTTP/my-node@EXAMPLE.COM", 1)]);
+        truncated.pop();
+        assert!(entries(&truncated).is_err());
+        // An empty keytab is valid
+        assert!(entries(&[0x05, 0x02]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+//! Builds `krb5.conf` files from a structured representation, so that admin-supplied relations (see
+//! [`KerberosExtraConfig`]) can be merged into the operator's own configuration without concatenating strings.
+
+use std::{
+    collections::HashSet,
+    fmt::{Display, Write},
+};
+
+use snafu::{Snafu, ensure};
+
+use crate::crd::KerberosExtraConfig;
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display(
+        "extra config path {path:?} must consist of at least a section name and a relation name"
+    ))]
+    PathTooShort { path: Vec<String> },
+
+    #[snafu(display(
+        "extra config path {path:?} goes through {name:?}, which is a relation rather than a subsection"
+    ))]
+    NotASubsection { path: Vec<String>, name: String },
+
+    #[snafu(display(
+        "extra config path {path:?} is already set by the operator, set allowOverride to replace it"
+    ))]
+    OverridesOperatorRelation { path: Vec<String> },
+
+    #[snafu(display(
+        "{name:?} is not a valid krb5.conf section or relation name, names must not be empty, start with # or ;, or contain whitespace, control characters, or any of {{}}[]=*"
+    ))]
+    InvalidName { name: String },
+
+    #[snafu(display(
+        "{value:?} is not a valid krb5.conf value for {name:?}, values must not start with {{ or contain control characters"
+    ))]
+    InvalidValue { name: String, value: String },
+}
+
+/// Ensures that `name` is written as a single section or relation name.
+///
+/// Otherwise names could inject their own relations (or subsections), or set relations that are already set by the
+/// operator without being detected by [`Krb5Conf::merge`] (such as `kdc*`, which sets `kdc` and marks it as final).
+fn validate_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && !name.starts_with(['#', ';'])
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "{}[]=*".contains(c));
+    ensure!(valid, error::InvalidNameSnafu { name });
+    Ok(())
+}
+
+/// Ensures that `value` is written as a single value, rather than opening a subsection or adding more lines.
+fn validate_value(name: &str, value: &str) -> Result<(), Error> {
+    let valid = !value.trim_start().starts_with('{') && !value.chars().any(char::is_control);
+    ensure!(valid, error::InvalidValueSnafu { name, value });
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Value(String),
+    Section(Section),
+}
+
+/// A section (or subsection) of a `krb5.conf` file.
+///
+/// Relations may occur multiple times, in which case all of their values apply.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Section {
+    nodes: Vec<(String, Node)>,
+}
+
+impl Section {
+    /// Adds a value to the relation `name`, keeping any existing values.
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Display,
+    ) -> Result<&mut Self, Error> {
+        let name = name.into();
+        let value = value.to_string();
+        validate_name(&name)?;
+        validate_value(&name, &value)?;
+        self.nodes.push((name, Node::Value(value)));
+        Ok(self)
+    }
+
+    /// Returns the subsection `name`, creating it if it does not exist yet.
+    pub fn subsection(&mut self, name: &str) -> Result<&mut Section, Error> {
+        validate_name(name)?;
+        Ok(self
+            .try_subsection(name)
+            .expect("subsection must not have the same name as a relation"))
+    }
+
+    /// Returns the subsection `name`, creating it if it does not exist yet.
+    ///
+    /// Returns `None` if `name` is a relation instead. `name` must already have been validated.
+    fn try_subsection(&mut self, name: &str) -> Option<&mut Section> {
+        let index = match self.nodes.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                self.nodes
+                    .push((name.to_string(), Node::Section(Section::default())));
+                self.nodes.len() - 1
+            }
+        };
+        match &mut self.nodes[index].1 {
+            Node::Section(section) => Some(section),
+            Node::Value(_) => None,
+        }
+    }
+
+    fn get_section(&self, name: &str) -> Option<&Section> {
+        self.nodes.iter().find_map(|(n, node)| match node {
+            Node::Section(section) if n == name => Some(section),
+            _ => None,
+        })
+    }
+
+    fn has_relation(&self, name: &str) -> bool {
+        self.nodes
+            .iter()
+            .any(|(n, node)| n == name && matches!(node, Node::Value(_)))
+    }
+
+    /// Writes the section's nodes to `out`.
+    ///
+    /// Names and values are validated again, since a single invalid node could change the meaning of the whole file.
+    fn write_nodes(&self, out: &mut String, depth: usize) -> Result<(), Error> {
+        let indent = "  ".repeat(depth);
+        for (name, node) in &self.nodes {
+            validate_name(name)?;
+            match node {
+                Node::Value(value) => {
+                    validate_value(name, value)?;
+                    writeln!(out, "{indent}{name} = {value}")
+                }
+                Node::Section(section) => {
+                    writeln!(out, "{indent}{name} = {{").unwrap();
+                    section.write_nodes(out, depth + 1)?;
+                    writeln!(out, "{indent}}}")
+                }
+            }
+            // Writing to a String cannot fail
+            .unwrap();
+        }
+        Ok(())
+    }
+}
+
+/// A `krb5.conf` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Krb5Conf {
+    sections: Section,
+}
+
+impl Krb5Conf {
+    /// Returns the top-level section `name` (such as `libdefaults`), creating it if it does not exist yet.
+    pub fn section(&mut self, name: &str) -> Result<&mut Section, Error> {
+        self.sections.subsection(name)
+    }
+
+    /// Merges admin-supplied relations into the configuration.
+    ///
+    /// Relations that are already set (by the operator) are rejected, unless
+    /// [`KerberosExtraConfig::allow_override`] is set, in which case the existing values are replaced.
+    /// Multiple entries for the same relation are all kept.
+    pub fn merge(&mut self, extra_config: &[KerberosExtraConfig]) -> Result<(), Error> {
+        let operator_config = self.sections.clone();
+        let mut overridden = HashSet::new();
+        for extra in extra_config {
+            // Must be checked before looking for the operator's relations, since invalid names could bypass the check
+            for name in &extra.path {
+                validate_name(name)?;
+            }
+            let (relation, section_path) = match extra.path.split_last() {
+                Some((relation, section_path)) if !section_path.is_empty() => {
+                    (relation, section_path)
+                }
+                _ => {
+                    return error::PathTooShortSnafu {
+                        path: extra.path.clone(),
+                    }
+                    .fail();
+                }
+            };
+
+            let set_by_operator = section_path
+                .iter()
+                .try_fold(&operator_config, |section, name| section.get_section(name))
+                .is_some_and(|section| section.has_relation(relation));
+            if set_by_operator {
+                ensure!(
+                    extra.allow_override,
+                    error::OverridesOperatorRelationSnafu {
+                        path: extra.path.clone(),
+                    }
+                );
+            }
+
+            let mut section = &mut self.sections;
+            for name in section_path {
+                section = section
+                    .try_subsection(name)
+                    .ok_or_else(|| Error::NotASubsection {
+                        path: extra.path.clone(),
+                        name: name.clone(),
+                    })?;
+            }
+            // Only remove the operator's values, not ones added by previous overrides of the same relation
+            if set_by_operator && overridden.insert(&extra.path) {
+                section
+                    .nodes
+                    .retain(|(n, node)| !(n == relation && matches!(node, Node::Value(_))));
+            }
+            for value in &extra.values {
+                section.add(relation, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the configuration as a `krb5.conf` file.
+    pub fn render(&self) -> Result<String, Error> {
+        let mut out = String::new();
+        for (name, node) in &self.sections.nodes {
+            if let Node::Section(section) = node {
+                validate_name(name)?;
+                // Writing to a String cannot fail
+                write!(out, "\n[{name}]\n").unwrap();
+                section.write_nodes(&mut out, 0)?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Krb5Conf};
+    use crate::crd::KerberosExtraConfig;
+
+    fn operator_config() -> Krb5Conf {
+        let mut conf = Krb5Conf::default();
+        conf.section("libdefaults")
+            .unwrap()
+            .add("default_realm", "CLUSTER.LOCAL")
+            .unwrap()
+            .add("rdns", false)
+            .unwrap();
+        conf.section("realms")
+            .unwrap()
+            .subsection("CLUSTER.LOCAL")
+            .unwrap()
+            .add("kdc", "krb5-kdc")
+            .unwrap();
+        conf
+    }
+
+    fn extra(path: &[&str], values: &[&str], allow_override: bool) -> KerberosExtraConfig {
+        KerberosExtraConfig {
+            path: path.iter().map(|s| s.to_string()).collect(),
+            values: values.iter().map(|s| s.to_string()).collect(),
+            allow_override,
+        }
+    }
+
+    #[test]
+    fn render_operator_config() {
+        assert_eq!(
+            operator_config().render().unwrap(),
+            "
+[libdefaults]
+default_realm = CLUSTER.LOCAL
+rdns = false
+
+[realms]
+CLUSTER.LOCAL = {
+  kdc = krb5-kdc
+}
+"
+        );
+    }
+
+    #[test]
+    fn merge_inline_relations() {
+        let mut conf = operator_config();
+        conf.merge(&[
+            extra(&["appdefaults", "pam", "forwardable"], &["true"], false),
+            extra(&["realms", "CLUSTER.LOCAL", "kdc"], &["kdc-1"], true),
+            extra(&["realms", "CLUSTER.LOCAL", "kdc"], &["kdc-2"], true),
+            extra(
+                &["realms", "CLUSTER.LOCAL", "master_kdc"],
+                &["krb5-kdc"],
+                false,
+            ),
+            extra(
+                &["libdefaults", "permitted_enctypes"],
+                &["aes256-cts"],
+                false,
+            ),
+            extra(
+                &["libdefaults", "permitted_enctypes"],
+                &["aes128-cts"],
+                false,
+            ),
+        ])
+        .unwrap();
+        assert_eq!(
+            conf.render().unwrap(),
+            "
+[libdefaults]
+default_realm = CLUSTER.LOCAL
+rdns = false
+permitted_enctypes = aes256-cts
+permitted_enctypes = aes128-cts
+
+[realms]
+CLUSTER.LOCAL = {
+  kdc = kdc-1
+  kdc = kdc-2
+  master_kdc = krb5-kdc
+}
+
+[appdefaults]
+pam = {
+  forwardable = true
+}
+"
+        );
+    }
+
+    #[test]
+    fn reject_operator_overrides() {
+        let mut conf = operator_config();
+        let err = conf
+            .merge(&[extra(
+                &["realms", "CLUSTER.LOCAL", "kdc"],
+                &["evil-kdc"],
+                false,
+            )])
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::OverridesOperatorRelation { .. }),
+            "{err}"
+        );
+
+        let err = conf
+            .merge(&[extra(&["libdefaults", "rdns", "foo"], &["bar"], true)])
+            .unwrap_err();
+        assert!(matches!(err, Error::NotASubsection { .. }), "{err}");
+
+        let err = conf
+            .merge(&[extra(&["forwardable"], &["true"], false)])
+            .unwrap_err();
+        assert!(matches!(err, Error::PathTooShort { .. }), "{err}");
+    }
+
+    #[test]
+    fn reject_invalid_names() {
+        for path in [
+            &["libdefaults", ""][..],
+            &["", "rdns"],
+            &["realms", "CLUSTER.LOCAL", "kdc*"],
+            &["realms", "CLUSTER.LOCAL", "kdc "],
+            &["realms", "CLUSTER.LOCAL\n  kdc"],
+            &["realms", "CLUSTER.LOCAL", "kdc = evil-kdc\n  x"],
+            &["realms", "CLUSTER.LOCAL", "#x"],
+            &["realms", "}", "kdc"],
+            &["libdefaults]\n[realms", "x"],
+            &["libdefaults", "a=b"],
+        ] {
+            let mut conf = operator_config();
+            let err = conf.merge(&[extra(path, &["x"], true)]).unwrap_err();
+            assert!(matches!(err, Error::InvalidName { .. }), "{path:?}: {err}");
+            assert_eq!(conf, operator_config(), "{path:?}");
+        }
+
+        let err = Krb5Conf::default().section("libdefaults]").unwrap_err();
+        assert!(matches!(err, Error::InvalidName { .. }), "{err}");
+    }
+
+    #[test]
+    fn reject_invalid_values() {
+        for value in ["{", "  { kdc = evil-kdc", "true\n  kdc = evil-kdc", "a\tb"] {
+            let err = operator_config()
+                .merge(&[extra(&["appdefaults", "forwardable"], &[value], false)])
+                .unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidValue { .. }),
+                "{value:?}: {err}"
+            );
+        }
+
+        // Values are allowed to contain brackets and equals signs, such as in auth_to_local rules
+        let mut conf = operator_config();
+        conf.merge(&[extra(
+            &["realms", "CLUSTER.LOCAL", "auth_to_local"],
+            &["RULE:[1:$1@$0](.*@CLUSTER.LOCAL)s/@.*//"],
+            false,
+        )])
+        .unwrap();
+        assert!(
+            conf.render()
+                .unwrap()
+                .contains("  auth_to_local = RULE:[1:$1@$0](.*@CLUSTER.LOCAL)s/@.*//\n")
+        );
+    }
+}
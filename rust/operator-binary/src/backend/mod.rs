@@ -1,45 +1,69 @@
 //! Collects or generates secret data based on the request in the Kubernetes `Volume` definition
 
 pub mod cert_manager;
+pub mod coordination;
+pub mod csr;
 pub mod dynamic;
 pub mod k8s_search;
 pub mod kerberos_keytab;
+pub mod keytab;
+pub mod krb5_conf;
+pub mod node_name;
 pub mod pod_info;
 pub mod scope;
 pub mod tls;
 
-use std::{collections::HashSet, convert::Infallible, fmt::Debug};
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::Infallible,
+    fmt::Debug,
+};
 
 use async_trait::async_trait;
 pub use cert_manager::CertManager;
+use csr::{CsrRequest, SignedCsr, ValidatedCsr};
 pub use k8s_search::K8sSearch;
 pub use kerberos_keytab::KerberosKeytab;
+use openssl::sha::Sha256;
 use pod_info::Address;
 use scope::SecretScope;
-use serde::{Deserialize, Deserializer, Serialize, de::Unexpected};
-use snafu::{OptionExt, Snafu};
+use serde::{Deserialize, Deserializer, Serialize, de::value::MapDeserializer};
+use snafu::{OptionExt, ResultExt, Snafu};
 use stackable_operator::{
     k8s_openapi::chrono::{DateTime, FixedOffset},
     time::Duration,
 };
+use stackable_secret_operator_crd_utils::{
+    rejection::WriteRejection,
+    volume::{KerberosKeytabGroups, ParseAttributesError, SecretVolumeAttributes, attribute},
+};
 pub use tls::TlsGenerate;
 
 use self::pod_info::SchedulingPodInfo;
-use crate::format::{
-    SecretData, SecretFormat,
-    well_known::{CompatibilityOptions, NamingOptions},
+use crate::{
+    format::{
+        SecretData, SecretFormat,
+        well_known::{CompatibilityOptions, EnvFileOptions, NamingOptions},
+    },
+    utils::{FmtByteSlice, error_full_message},
 };
 
+/// Prefix of the canonical selector representation hashed by [`SecretVolumeSelector::selector_fingerprint`].
+///
+/// Must be bumped whenever the canonical representation changes.
+const SELECTOR_FINGERPRINT_VERSION: &str = "secret-volume-selector/v1";
+
 /// Configuration provided by the `Volume` selecting what secret data should be provided
 ///
 /// Fields beginning with `csi.storage.k8s.io/` are provided by the Kubelet
-#[derive(Deserialize, Debug)]
+///
+/// Deserializes from the volume context through [`SecretVolumeAttributes`], so that both always agree on which
+/// attributes exist and how they are formatted, see [`Self::from_volume_context`].
+#[derive(Debug)]
 pub struct SecretVolumeSelector {
-    #[serde(flatten)]
     pub internal: InternalSecretVolumeSelectorParams,
 
     /// What kind of secret should be used
-    #[serde(rename = "secrets.stackable.tech/class")]
     pub class: String,
 
     /// Scopes define what the secret identifies about a pod
@@ -50,58 +74,52 @@ pub struct SecretVolumeSelector {
     /// - `service` - A Kubernetes `Service` that the pod is participating in, this takes the name of the service in the format `service=foo`
     ///
     /// Multiple scopes are supported, these should be provided in a comma-separated list (for example: `pod,node`)
-    #[serde(
-        rename = "secrets.stackable.tech/scope",
-        default,
-        deserialize_with = "SecretScope::deserialize_vec"
-    )]
     pub scope: Vec<scope::SecretScope>,
 
     /// The name of the `Pod`, provided by Kubelet
-    #[serde(rename = "csi.storage.k8s.io/pod.name")]
     pub pod: String,
 
     /// The name of the `Pod`'s `Namespace`, provided by Kubelet
-    #[serde(rename = "csi.storage.k8s.io/pod.namespace")]
     pub namespace: String,
 
+    /// The UID of the `Pod`, provided by Kubelet
+    pub pod_uid: Option<String>,
+
     /// The desired format of the mounted secrets
     ///
     /// Currently supported formats:
     /// - `tls-pem` - A Kubernetes-style triple of PEM-encoded certificate files (`tls.crt`, `tls.key`, `ca.crt`).
     /// - `tls-pkcs12` - A PKCS#12 key store named `keystore.p12` and truststore named `truststore.p12`.
+    /// - `tls-jks` - A JKS key store named `keystore.jks` and truststore named `truststore.jks`, along with their
+    ///   password in `keystore.password`.
     /// - `kerberos` - A Kerberos keytab named `keytab`, along with a `krb5.conf`.
+    /// - `env-file` - All files of the secret as shell variable assignments, in a single file named `secrets.env`.
     ///
     /// Defaults to passing through the native format of the secret backend.
-    #[serde(
-        rename = "secrets.stackable.tech/format",
-        deserialize_with = "SecretVolumeSelector::deserialize_some",
-        default
-    )]
     pub format: Option<SecretFormat>,
 
     /// The Kerberos service names (`SERVICE_NAME/hostname@realm`)
-    #[serde(
-        rename = "secrets.stackable.tech/kerberos.service.names",
-        deserialize_with = "SecretVolumeSelector::deserialize_str_vec",
-        default = "SecretVolumeSelector::default_kerberos_service_names"
-    )]
     pub kerberos_service_names: Vec<String>,
 
+    /// Separate keytab files that each only contain the principals of some service names.
+    ///
+    /// If set, these replace the service names of `secrets.stackable.tech/kerberos.service.names`.
+    pub kerberos_keytab_groups: Option<KerberosKeytabGroups>,
+
+    /// Whether the combined `keytab` (containing every principal) is provided alongside the keytab groups.
+    pub kerberos_keytab_groups_combined: bool,
+
     /// Compatibility options used by (legacy) applications.
-    #[serde(flatten)]
     pub compat: CompatibilityOptions,
 
     /// The (custom) filenames used by secrets.
-    #[serde(flatten)]
     pub names: NamingOptions,
 
+    /// Options for the `env-file` format.
+    pub env_file: EnvFileOptions,
+
     /// The TLS cert lifetime (when using the [`tls`] backend).
     /// The format is documented in <https://docs.stackable.tech/home/nightly/concepts/duration>.
-    #[serde(
-        rename = "secrets.stackable.tech/backend.autotls.cert.lifetime",
-        default = "default_cert_lifetime"
-    )]
     pub autotls_cert_lifetime: Duration,
 
     /// The amount of time the Pod using the cert gets restarted before the cert expires.
@@ -109,30 +127,44 @@ pub struct SecretVolumeSelector {
     /// shut down at the same time. It can take some hours until all Pods are restarted
     /// in a rolling fashion.
     /// The format is documented in <https://docs.stackable.tech/home/nightly/concepts/duration>.
-    #[serde(
-        rename = "secrets.stackable.tech/backend.autotls.cert.restart-buffer",
-        default = "default_cert_restart_buffer"
-    )]
     pub autotls_cert_restart_buffer: Duration,
 
     /// The part of the certificate's lifetime that may be removed for jittering.
     /// Must be within 0.0 and 1.0.
-    #[serde(
-        rename = "secrets.stackable.tech/backend.autotls.cert.jitter-factor",
-        deserialize_with = "SecretVolumeSelector::deserialize_str_as_f64",
-        default = "default_cert_jitter_factor"
-    )]
     pub autotls_cert_jitter_factor: f64,
 
+    /// Whether the `Pod` generates its own private key, and only submits a CSR for the backend to sign
+    /// (when using the [`tls`] backend).
+    ///
+    /// The volume is published with only a `csr-request.json`, describing what the CSR may request. Once the `Pod`
+    /// has written its CSR to `request.csr`, the signed certificate and CA are added to the volume, followed by a
+    /// `ready` marker file.
+    pub autotls_csr_handshake: bool,
+
+    /// How long to wait for the `Pod` to write its CSR, when [`Self::autotls_csr_handshake`] is enabled.
+    /// The format is documented in <https://docs.stackable.tech/home/nightly/concepts/duration>.
+    pub autotls_csr_handshake_timeout: Duration,
+
     /// The TLS cert lifetime (when using the [`cert_manager`] backend).
     ///
     /// The format is documented in <https://docs.stackable.tech/home/nightly/concepts/duration>.
-    #[serde(
-        rename = "secrets.stackable.tech/backend.cert-manager.cert.lifetime",
-        deserialize_with = "SecretVolumeSelector::deserialize_some",
-        default
-    )]
     pub cert_manager_cert_lifetime: Option<Duration>,
+
+    /// Whether the volume may be re-published for a different selector, replacing its previous contents.
+    ///
+    /// By default, Kubelet re-publishing an existing volume with a changed selector is rejected, and the `Pod`
+    /// must be recreated instead.
+    pub allow_selector_change: bool,
+
+    /// The Unix permissions of the secret files, in octal (such as `0400`).
+    ///
+    /// Defaults to `0640`, or `0600` for the `env-file` format.
+    pub file_mode: Option<u32>,
+
+    /// The numeric ID of the group that should own the secret files.
+    ///
+    /// Defaults to the group of the secret-operator process.
+    pub file_group: Option<u32>,
 }
 
 /// Internal parameters of [`SecretVolumeSelector`] managed by secret-operator itself.
@@ -164,6 +196,45 @@ fn default_cert_jitter_factor() -> f64 {
     tls::DEFAULT_CERT_JITTER_FACTOR
 }
 
+fn default_csr_handshake_timeout() -> Duration {
+    tls::DEFAULT_CSR_HANDSHAKE_TIMEOUT
+}
+
+/// Volume context keys that are provided by Kubelet, rather than by the `Volume`.
+mod kubelet_attribute {
+    pub const POD_NAME: &str = "csi.storage.k8s.io/pod.name";
+    pub const POD_NAMESPACE: &str = "csi.storage.k8s.io/pod.namespace";
+    pub const POD_UID: &str = "csi.storage.k8s.io/pod.uid";
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum FromVolumeContextError {
+    #[snafu(display("failed to parse volume attributes"))]
+    ParseAttributes { source: ParseAttributesError },
+
+    #[snafu(display("volume context has no {key:?}, which should be provided by Kubelet"))]
+    MissingKubeletAttribute { key: &'static str },
+
+    #[snafu(display("failed to parse internal parameters"))]
+    ParseInternalParams { source: serde::de::value::Error },
+}
+
+impl<'de> Deserialize<'de> for SecretVolumeSelector {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let context = BTreeMap::<String, String>::deserialize(de)?;
+        Self::from_volume_context(&context).map_err(|err| match err {
+            FromVolumeContextError::ParseAttributes {
+                source: ParseAttributesError::MissingClass,
+            } => <D::Error as serde::de::Error>::missing_field(attribute::CLASS),
+            FromVolumeContextError::MissingKubeletAttribute { key } => {
+                <D::Error as serde::de::Error>::missing_field(key)
+            }
+            _ => <D::Error as serde::de::Error>::custom(error_full_message(&err)),
+        })
+    }
+}
+
 #[derive(Snafu, Debug)]
 #[snafu(module)]
 pub enum ScopeAddressesError {
@@ -172,6 +243,98 @@ pub enum ScopeAddressesError {
 }
 
 impl SecretVolumeSelector {
+    /// Parses the selector from a volume context, and applies the defaults of any attributes that aren't set.
+    ///
+    /// The attributes of the `Volume` are parsed by [`SecretVolumeAttributes`], which is what users of crd-utils build
+    /// their volumes with.
+    pub fn from_volume_context(
+        context: &BTreeMap<String, String>,
+    ) -> Result<Self, FromVolumeContextError> {
+        use from_volume_context_error::*;
+        let kubelet_provided = |key: &'static str| {
+            context
+                .get(key)
+                .cloned()
+                .context(MissingKubeletAttributeSnafu { key })
+        };
+        let internal_params = MapDeserializer::<_, serde::de::value::Error>::new(
+            context.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+        );
+        let internal = InternalSecretVolumeSelectorParams::deserialize(internal_params)
+            .context(ParseInternalParamsSnafu)?;
+        // Destructured exhaustively, so that new attributes can't be forgotten here
+        let SecretVolumeAttributes {
+            class,
+            scope,
+            format,
+            kerberos_service_names,
+            kerberos_keytab_groups,
+            kerberos_keytab_groups_combined,
+            tls_pkcs12_password,
+            tls_jks_password,
+            env_file_prefix,
+            tls_pkcs12_keystore_name,
+            tls_pkcs12_truststore_name,
+            tls_pem_cert_name,
+            tls_pem_key_name,
+            tls_pem_ca_name,
+            autotls_cert_lifetime,
+            autotls_cert_restart_buffer,
+            autotls_cert_jitter_factor,
+            autotls_csr_handshake,
+            autotls_csr_handshake_timeout,
+            cert_manager_cert_lifetime,
+            allow_selector_change,
+            file_mode,
+            file_group,
+        } = SecretVolumeAttributes::from_volume_context(context).context(ParseAttributesSnafu)?;
+        let default_names = NamingOptions::default();
+        Ok(Self {
+            internal,
+            class,
+            scope,
+            pod: kubelet_provided(kubelet_attribute::POD_NAME)?,
+            namespace: kubelet_provided(kubelet_attribute::POD_NAMESPACE)?,
+            pod_uid: context.get(kubelet_attribute::POD_UID).cloned(),
+            format,
+            kerberos_service_names: if kerberos_service_names.is_empty() {
+                Self::default_kerberos_service_names()
+            } else {
+                kerberos_service_names
+            },
+            kerberos_keytab_groups,
+            kerberos_keytab_groups_combined: kerberos_keytab_groups_combined.unwrap_or_default(),
+            compat: CompatibilityOptions {
+                tls_pkcs12_password,
+                tls_jks_password,
+            },
+            names: NamingOptions {
+                tls_pkcs12_keystore_name: tls_pkcs12_keystore_name
+                    .unwrap_or(default_names.tls_pkcs12_keystore_name),
+                tls_pkcs12_truststore_name: tls_pkcs12_truststore_name
+                    .unwrap_or(default_names.tls_pkcs12_truststore_name),
+                tls_pem_cert_name: tls_pem_cert_name.unwrap_or(default_names.tls_pem_cert_name),
+                tls_pem_key_name: tls_pem_key_name.unwrap_or(default_names.tls_pem_key_name),
+                tls_pem_ca_name: tls_pem_ca_name.unwrap_or(default_names.tls_pem_ca_name),
+            },
+            env_file: EnvFileOptions {
+                prefix: env_file_prefix,
+            },
+            autotls_cert_lifetime: autotls_cert_lifetime.unwrap_or_else(default_cert_lifetime),
+            autotls_cert_restart_buffer: autotls_cert_restart_buffer
+                .unwrap_or_else(default_cert_restart_buffer),
+            autotls_cert_jitter_factor: autotls_cert_jitter_factor
+                .unwrap_or_else(default_cert_jitter_factor),
+            autotls_csr_handshake: autotls_csr_handshake.unwrap_or_default(),
+            autotls_csr_handshake_timeout: autotls_csr_handshake_timeout
+                .unwrap_or_else(default_csr_handshake_timeout),
+            cert_manager_cert_lifetime,
+            allow_selector_change: allow_selector_change.unwrap_or_default(),
+            file_mode,
+            file_group,
+        })
+    }
+
     /// Returns all addresses associated with a certain [`SecretScope`]
     fn scope_addresses<'a>(
         &'a self,
@@ -212,6 +375,170 @@ impl SecretVolumeSelector {
         })
     }
 
+    /// Returns a stable identifier for the secret requested by this selector.
+    ///
+    /// The fingerprint only depends on the parsed values (including defaults), so it is not affected by
+    /// the order of the volume attributes, or by whether default values were provided explicitly.
+    pub fn selector_fingerprint(&self) -> String {
+        let canonical = serde_json::to_string(&self.canonical_fields())
+            .expect("canonical selector fields must be serializable as JSON");
+        let mut hasher = Sha256::new();
+        hasher.update(SELECTOR_FINGERPRINT_VERSION.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(canonical.as_bytes());
+        format!("{:x}", FmtByteSlice(&hasher.finish()))
+    }
+
+    /// All fields of the selector, normalized into their volume attribute representation.
+    fn canonical_fields(&self) -> BTreeMap<&'static str, String> {
+        let Self {
+            internal: InternalSecretVolumeSelectorParams { pvc_name },
+            class,
+            scope,
+            pod,
+            namespace,
+            // Only used for reporting which identities were issued
+            pod_uid: _,
+            format,
+            kerberos_service_names,
+            kerberos_keytab_groups,
+            kerberos_keytab_groups_combined,
+            compat:
+                CompatibilityOptions {
+                    tls_pkcs12_password,
+                    tls_jks_password,
+                },
+            names:
+                NamingOptions {
+                    tls_pkcs12_keystore_name,
+                    tls_pkcs12_truststore_name,
+                    tls_pem_cert_name,
+                    tls_pem_key_name,
+                    tls_pem_ca_name,
+                },
+            env_file: EnvFileOptions { prefix },
+            autotls_cert_lifetime,
+            autotls_cert_restart_buffer,
+            autotls_cert_jitter_factor,
+            autotls_csr_handshake,
+            autotls_csr_handshake_timeout,
+            cert_manager_cert_lifetime,
+            // Only controls how changes to the other fields are handled
+            allow_selector_change: _,
+            file_mode,
+            file_group,
+        } = self;
+        let fmt_duration = |duration: &Duration| format!("{}ms", duration.as_millis());
+        let mut fields = BTreeMap::from([
+            ("secrets.stackable.tech/class", class.clone()),
+            (
+                "secrets.stackable.tech/scope",
+                scope
+                    .iter()
+                    .map(SecretScope::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            ("csi.storage.k8s.io/pod.name", pod.clone()),
+            ("csi.storage.k8s.io/pod.namespace", namespace.clone()),
+            (
+                "secrets.stackable.tech/kerberos.service.names",
+                kerberos_service_names.join(","),
+            ),
+            (
+                "secrets.stackable.tech/format.tls-pkcs12.keystore-name",
+                tls_pkcs12_keystore_name.clone(),
+            ),
+            (
+                "secrets.stackable.tech/format.tls-pkcs12.truststore-name",
+                tls_pkcs12_truststore_name.clone(),
+            ),
+            (
+                "secrets.stackable.tech/format.tls-pem.cert-name",
+                tls_pem_cert_name.clone(),
+            ),
+            (
+                "secrets.stackable.tech/format.tls-pem.key-name",
+                tls_pem_key_name.clone(),
+            ),
+            (
+                "secrets.stackable.tech/format.tls-pem.ca-name",
+                tls_pem_ca_name.clone(),
+            ),
+            (
+                "secrets.stackable.tech/backend.autotls.cert.lifetime",
+                fmt_duration(autotls_cert_lifetime),
+            ),
+            (
+                "secrets.stackable.tech/backend.autotls.cert.restart-buffer",
+                fmt_duration(autotls_cert_restart_buffer),
+            ),
+            (
+                "secrets.stackable.tech/backend.autotls.cert.jitter-factor",
+                autotls_cert_jitter_factor.to_string(),
+            ),
+        ]);
+        if let Some(pvc_name) = pvc_name {
+            fields.insert("secrets.stackable.tech/internal.pvc.name", pvc_name.clone());
+        }
+        if let Some(format) = format {
+            fields.insert("secrets.stackable.tech/format", format.to_string());
+        }
+        if let Some(groups) = kerberos_keytab_groups {
+            fields.insert(
+                "secrets.stackable.tech/kerberos.keytab-groups",
+                groups.to_string(),
+            );
+        }
+        if *kerberos_keytab_groups_combined {
+            fields.insert(
+                "secrets.stackable.tech/kerberos.keytab-groups.combined",
+                kerberos_keytab_groups_combined.to_string(),
+            );
+        }
+        if let Some(password) = tls_pkcs12_password {
+            fields.insert(
+                "secrets.stackable.tech/format.compatibility.tls-pkcs12.password",
+                password.clone(),
+            );
+        }
+        if let Some(password) = tls_jks_password {
+            fields.insert(
+                "secrets.stackable.tech/format.compatibility.tls-jks.password",
+                password.clone(),
+            );
+        }
+        if let Some(prefix) = prefix {
+            fields.insert(
+                "secrets.stackable.tech/format.env-file.prefix",
+                prefix.clone(),
+            );
+        }
+        if *autotls_csr_handshake {
+            fields.insert(
+                "secrets.stackable.tech/backend.autotls.csr-handshake",
+                autotls_csr_handshake.to_string(),
+            );
+            fields.insert(
+                "secrets.stackable.tech/backend.autotls.csr-handshake.timeout",
+                fmt_duration(autotls_csr_handshake_timeout),
+            );
+        }
+        if let Some(lifetime) = cert_manager_cert_lifetime {
+            fields.insert(
+                "secrets.stackable.tech/backend.cert-manager.cert.lifetime",
+                fmt_duration(lifetime),
+            );
+        }
+        if let Some(mode) = file_mode {
+            fields.insert("secrets.stackable.tech/file.mode", format!("{mode:04o}"));
+        }
+        if let Some(gid) = file_group {
+            fields.insert("secrets.stackable.tech/file.group", gid.to_string());
+        }
+        fields
+    }
+
     fn default_kerberos_service_names() -> Vec<String> {
         vec!["HTTP".to_string()]
     }
@@ -221,27 +548,13 @@ impl SecretVolumeSelector {
     ) -> Result<Option<T>, D::Error> {
         T::deserialize(de).map(Some)
     }
-
-    fn deserialize_str_vec<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<String>, D::Error> {
-        let full_str = String::deserialize(de)?;
-        Ok(full_str.split(',').map(str::to_string).collect())
-    }
-
-    fn deserialize_str_as_f64<'de, D: Deserializer<'de>>(de: D) -> Result<f64, D::Error> {
-        let str = String::deserialize(de)?;
-        str.parse().map_err(|_| {
-            <D::Error as serde::de::Error>::invalid_value(
-                Unexpected::Str(&str),
-                &"a string containing a f64",
-            )
-        })
-    }
 }
 
 #[derive(Debug)]
 pub struct SecretContents {
     pub data: SecretData,
     pub expires_after: Option<DateTime<FixedOffset>>,
+    pub identity: SecretIdentity,
 }
 
 impl SecretContents {
@@ -249,6 +562,7 @@ impl SecretContents {
         Self {
             data,
             expires_after: None,
+            identity: SecretIdentity::default(),
         }
     }
 
@@ -256,6 +570,27 @@ impl SecretContents {
         self.expires_after = Some(deadline);
         self
     }
+
+    fn identity(mut self, identity: SecretIdentity) -> Self {
+        self.identity = identity;
+        self
+    }
+}
+
+/// What a secret identifies its holder as, for reporting to other controllers.
+///
+/// This must never contain anything sensitive, such as key material.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretIdentity {
+    /// The subject alternative names of any issued certificates.
+    pub subject_alt_names: Vec<String>,
+
+    /// The serial numbers of any issued certificates, in hexadecimal.
+    pub certificate_serials: Vec<String>,
+
+    /// The Kerberos principals that any issued keys belong to.
+    pub principals: Vec<String>,
 }
 
 /// This trait needs to be implemented by all secret providers.
@@ -286,10 +621,43 @@ pub trait SecretBackend: Debug + Send + Sync {
         let _ = (selector, pod_info);
         Ok(None)
     }
+
+    /// Describe which certificate the `Pod` may request in the CSR handshake (see
+    /// [`SecretVolumeSelector::autotls_csr_handshake`]).
+    ///
+    /// Should return `None` if the backend is unable to sign CSRs, which is what the default stub implementation does.
+    async fn get_csr_request(
+        &self,
+        selector: &SecretVolumeSelector,
+        pod_info: pod_info::PodInfo,
+    ) -> Result<Option<CsrRequest>, Self::Error> {
+        let _ = (selector, pod_info);
+        Ok(None)
+    }
+
+    /// Sign a CSR that was submitted by the `Pod`, after it has been validated against the [`CsrRequest`] returned by
+    /// [`Self::get_csr_request`].
+    ///
+    /// Should return `None` if the backend is unable to sign CSRs, which is what the default stub implementation does.
+    async fn sign_csr(
+        &self,
+        selector: &SecretVolumeSelector,
+        csr: &ValidatedCsr,
+    ) -> Result<Option<SignedCsr>, Self::Error> {
+        let _ = (selector, csr);
+        Ok(None)
+    }
 }
 
 pub trait SecretBackendError: std::error::Error + Send + Sync + 'static {
     fn grpc_code(&self) -> tonic::Code;
+
+    /// The cluster policy that rejected a write, if that is why the error occurred.
+    ///
+    /// The `SecretClass` is warned about these, since an administrator must change the policy before retrying helps.
+    fn write_rejection(&self) -> Option<&WriteRejection> {
+        None
+    }
 }
 
 impl SecretBackendError for Infallible {
@@ -303,6 +671,9 @@ mod tests {
     use std::collections::HashMap;
 
     use serde::de::{IntoDeserializer, value::MapDeserializer};
+    use stackable_secret_operator_crd_utils::volume::{
+        EphemeralVolumeBuilder, SecretVolumeAttributes, attribute,
+    };
 
     use super::*;
 
@@ -348,4 +719,333 @@ mod tests {
             )
             .unwrap();
     }
+
+    #[test]
+    fn deserialize_selector_requires_class() {
+        let mut map = required_fields_map();
+        map.remove("secrets.stackable.tech/class");
+
+        let err = SecretVolumeSelector::deserialize::<
+            MapDeserializer<'_, _, serde::de::value::Error>,
+        >(map.into_deserializer())
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "missing field `secrets.stackable.tech/class`"
+        );
+    }
+
+    fn parse_selector<'a>(
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> SecretVolumeSelector {
+        SecretVolumeSelector::deserialize(MapDeserializer::<_, serde::de::value::Error>::new(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned())),
+        ))
+        .unwrap()
+    }
+
+    const FIXTURE_FIELDS: &[(&str, &str)] = &[
+        ("secrets.stackable.tech/class", "tls"),
+        ("secrets.stackable.tech/scope", "pod,node,service=foo"),
+        ("csi.storage.k8s.io/pod.name", "my-pod"),
+        ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+        ("secrets.stackable.tech/format", "tls-pkcs12"),
+        (
+            "secrets.stackable.tech/format.compatibility.tls-pkcs12.password",
+            "supersecret",
+        ),
+        ("secrets.stackable.tech/backend.autotls.cert.lifetime", "7d"),
+        ("secrets.stackable.tech/internal.pvc.name", "my-pvc"),
+    ];
+
+    #[test]
+    fn selector_fingerprint_is_pinned() {
+        // These must only change when SELECTOR_FINGERPRINT_VERSION is bumped
+        assert_eq!(
+            parse_selector([
+                ("secrets.stackable.tech/class", "my-class"),
+                ("csi.storage.k8s.io/pod.name", "my-pod"),
+                ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+            ])
+            .selector_fingerprint(),
+            "cc805198f8992bac44785f2bb766b05285710a79cb0f237793f2f1b98be5fe7b"
+        );
+        assert_eq!(
+            parse_selector(FIXTURE_FIELDS.iter().copied()).selector_fingerprint(),
+            "3887d1191f876699c6a4f4b33869b06fed8374ed10c0cc7a66758b275c0fa202"
+        );
+    }
+
+    #[test]
+    fn selector_fingerprint_ignores_field_order() {
+        let expected = parse_selector(FIXTURE_FIELDS.iter().copied()).selector_fingerprint();
+        let mut fields = FIXTURE_FIELDS.to_vec();
+        for rotation in 0..fields.len() {
+            fields.rotate_left(1);
+            assert_eq!(
+                parse_selector(fields.iter().copied()).selector_fingerprint(),
+                expected,
+                "rotation {rotation}"
+            );
+            fields.reverse();
+            assert_eq!(
+                parse_selector(fields.iter().copied()).selector_fingerprint(),
+                expected,
+                "reversed rotation {rotation}"
+            );
+        }
+    }
+
+    #[test]
+    fn selector_fingerprint_normalizes_defaults() {
+        let implicit = parse_selector(FIXTURE_FIELDS.iter().copied());
+        let explicit = parse_selector(FIXTURE_FIELDS.iter().copied().chain([
+            ("secrets.stackable.tech/kerberos.service.names", "HTTP"),
+            (
+                "secrets.stackable.tech/format.tls-pkcs12.keystore-name",
+                "keystore.p12",
+            ),
+            (
+                "secrets.stackable.tech/backend.autotls.cert.restart-buffer",
+                "6h",
+            ),
+            (
+                "secrets.stackable.tech/backend.autotls.cert.jitter-factor",
+                "0.2",
+            ),
+        ]));
+        assert_eq!(
+            implicit.selector_fingerprint(),
+            explicit.selector_fingerprint()
+        );
+    }
+
+    #[test]
+    fn selector_fingerprint_distinguishes_values() {
+        let base = parse_selector(FIXTURE_FIELDS.iter().copied());
+        let other_pod = parse_selector(FIXTURE_FIELDS.iter().map(|&(k, v)| {
+            (
+                k,
+                if k == "csi.storage.k8s.io/pod.name" {
+                    "other-pod"
+                } else {
+                    v
+                },
+            )
+        }));
+        assert_ne!(
+            base.selector_fingerprint(),
+            other_pod.selector_fingerprint()
+        );
+    }
+
+    #[test]
+    fn deserialize_file_options() {
+        let selector = parse_selector(FIXTURE_FIELDS.iter().copied());
+        assert_eq!(selector.file_mode, None);
+        assert_eq!(selector.file_group, None);
+
+        let selector = parse_selector(FIXTURE_FIELDS.iter().copied().chain([
+            ("secrets.stackable.tech/file.mode", "0400"),
+            ("secrets.stackable.tech/file.group", "1000"),
+        ]));
+        assert_eq!(selector.file_mode, Some(0o400));
+        assert_eq!(selector.file_group, Some(1000));
+
+        for mode in ["0800", "4755", "rw-------", ""] {
+            let mut map = required_fields_map();
+            map.insert(
+                "secrets.stackable.tech/file.mode".to_owned(),
+                mode.to_owned(),
+            );
+            SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, serde::de::value::Error>>(
+                map.into_deserializer(),
+            )
+            .expect_err(mode);
+        }
+    }
+
+    #[test]
+    fn selector_fingerprint_ignores_allow_selector_change() {
+        let base = parse_selector(FIXTURE_FIELDS.iter().copied());
+        let allowed = parse_selector(
+            FIXTURE_FIELDS
+                .iter()
+                .copied()
+                .chain([("secrets.stackable.tech/allow-selector-change", "true")]),
+        );
+        assert!(!base.allow_selector_change);
+        assert!(allowed.allow_selector_change);
+        assert_eq!(base.selector_fingerprint(), allowed.selector_fingerprint());
+    }
+
+    /// Sets every attribute to a value that isn't its default.
+    ///
+    /// Doesn't use `..SecretVolumeAttributes::default()`, so that new attributes must be added here.
+    fn all_attributes() -> SecretVolumeAttributes {
+        SecretVolumeAttributes {
+            class: "tls".to_string(),
+            scope: vec![
+                SecretScope::Node,
+                SecretScope::ListenerVolume {
+                    name: "listener".to_string(),
+                },
+            ],
+            format: Some(SecretFormat::TlsPem),
+            kerberos_service_names: vec!["HTTP".to_string(), "HDFS".to_string()],
+            kerberos_keytab_groups: Some(
+                KerberosKeytabGroups::new([
+                    ("nn.keytab", vec!["nn".to_string(), "HTTP".to_string()]),
+                    ("dn.keytab", vec!["dn".to_string()]),
+                ])
+                .unwrap(),
+            ),
+            kerberos_keytab_groups_combined: Some(true),
+            tls_pkcs12_password: Some("supersecret".to_string()),
+            tls_jks_password: Some("changeit".to_string()),
+            env_file_prefix: Some("TLS_".to_string()),
+            tls_pkcs12_keystore_name: Some("ks.p12".to_string()),
+            tls_pkcs12_truststore_name: Some("ts.p12".to_string()),
+            tls_pem_cert_name: Some("cert.pem".to_string()),
+            tls_pem_key_name: Some("key.pem".to_string()),
+            tls_pem_ca_name: Some("ca.pem".to_string()),
+            autotls_cert_lifetime: Some(Duration::from_days_unchecked(7)),
+            autotls_cert_restart_buffer: Some(Duration::from_hours_unchecked(1)),
+            autotls_cert_jitter_factor: Some(0.1),
+            autotls_csr_handshake: Some(true),
+            autotls_csr_handshake_timeout: Some(Duration::from_minutes_unchecked(2)),
+            cert_manager_cert_lifetime: Some(Duration::from_days_unchecked(2)),
+            allow_selector_change: Some(true),
+            file_mode: Some(0o400),
+            file_group: Some(1000),
+        }
+    }
+
+    /// The volume context that Kubelet passes for a volume built from `attributes`.
+    fn volume_context(attributes: &SecretVolumeAttributes) -> BTreeMap<String, String> {
+        let volume = EphemeralVolumeBuilder::new("tls", attributes.clone()).build();
+        let mut context = volume
+            .ephemeral
+            .and_then(|ephemeral| ephemeral.volume_claim_template?.metadata?.annotations)
+            .unwrap();
+        // Added by Kubelet
+        context.insert(
+            "csi.storage.k8s.io/pod.name".to_string(),
+            "my-pod".to_string(),
+        );
+        context.insert(
+            "csi.storage.k8s.io/pod.namespace".to_string(),
+            "my-namespace".to_string(),
+        );
+        context
+    }
+
+    #[test]
+    fn volume_attributes_should_be_parsed_by_selector() {
+        let attributes = all_attributes();
+        let context = volume_context(&attributes);
+
+        let selector = parse_selector(context.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        assert_eq!(selector.class, attributes.class);
+        assert_eq!(selector.scope, attributes.scope);
+        assert_eq!(selector.format, attributes.format);
+        assert_eq!(
+            selector.kerberos_service_names,
+            attributes.kerberos_service_names
+        );
+        assert_eq!(
+            selector.kerberos_keytab_groups,
+            attributes.kerberos_keytab_groups
+        );
+        assert_eq!(
+            Some(selector.kerberos_keytab_groups_combined),
+            attributes.kerberos_keytab_groups_combined
+        );
+        assert_eq!(
+            selector.compat.tls_pkcs12_password,
+            attributes.tls_pkcs12_password
+        );
+        assert_eq!(
+            selector.compat.tls_jks_password,
+            attributes.tls_jks_password
+        );
+        assert_eq!(selector.env_file.prefix, attributes.env_file_prefix);
+        assert_eq!(
+            Some(selector.names.tls_pkcs12_keystore_name),
+            attributes.tls_pkcs12_keystore_name
+        );
+        assert_eq!(
+            Some(selector.names.tls_pkcs12_truststore_name),
+            attributes.tls_pkcs12_truststore_name
+        );
+        assert_eq!(
+            Some(selector.names.tls_pem_cert_name),
+            attributes.tls_pem_cert_name
+        );
+        assert_eq!(
+            Some(selector.names.tls_pem_key_name),
+            attributes.tls_pem_key_name
+        );
+        assert_eq!(
+            Some(selector.names.tls_pem_ca_name),
+            attributes.tls_pem_ca_name
+        );
+        assert_eq!(
+            Some(selector.autotls_cert_lifetime),
+            attributes.autotls_cert_lifetime
+        );
+        assert_eq!(
+            Some(selector.autotls_cert_restart_buffer),
+            attributes.autotls_cert_restart_buffer
+        );
+        assert_eq!(
+            Some(selector.autotls_cert_jitter_factor),
+            attributes.autotls_cert_jitter_factor
+        );
+        assert_eq!(
+            Some(selector.autotls_csr_handshake),
+            attributes.autotls_csr_handshake
+        );
+        assert_eq!(
+            Some(selector.autotls_csr_handshake_timeout),
+            attributes.autotls_csr_handshake_timeout
+        );
+        assert_eq!(
+            selector.cert_manager_cert_lifetime,
+            attributes.cert_manager_cert_lifetime
+        );
+        assert_eq!(
+            Some(selector.allow_selector_change),
+            attributes.allow_selector_change
+        );
+        assert_eq!(selector.file_mode, attributes.file_mode);
+        assert_eq!(selector.file_group, attributes.file_group);
+    }
+
+    #[test]
+    fn selector_fingerprint_should_cover_every_attribute() {
+        let context = volume_context(&all_attributes());
+        let expected = parse_selector(context.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .selector_fingerprint();
+        for key in context.keys() {
+            if [
+                attribute::CLASS,
+                // Only controls how changes to the other attributes are handled
+                attribute::ALLOW_SELECTOR_CHANGE,
+            ]
+            .contains(&key.as_str())
+            {
+                continue;
+            }
+            let selector = parse_selector(
+                context
+                    .iter()
+                    .filter(|(k, _)| *k != key)
+                    .map(|(k, v)| (k.as_str(), v.as_str())),
+            );
+            assert_ne!(selector.selector_fingerprint(), expected, "{key}");
+        }
+    }
 }
@@ -0,0 +1,313 @@
+//! Resolves the host name that node-scoped Kerberos principals are issued for, see [`KerberosNodeNameSource`].
+
+use std::{ffi::CStr, io, net::IpAddr};
+
+use async_trait::async_trait;
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
+use socket2::SockAddr;
+use stackable_operator::{k8s_openapi::api::core::v1::Node, kube::runtime::reflector::ObjectRef};
+
+use super::pod_info::NodeInfo;
+use crate::crd::KerberosNodeNameSource;
+
+/// The buffer size required to hold any host name returned by `getnameinfo`, see `NI_MAXHOST` in getnameinfo(3).
+const MAX_HOST_NAME_LEN: usize = 1025;
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("{node} has no label {label:?}"))]
+    NoLabel {
+        node: ObjectRef<Node>,
+        label: String,
+    },
+
+    #[snafu(display("{node} has no InternalIP address"))]
+    NoInternalIp { node: ObjectRef<Node> },
+
+    #[snafu(display("failed to look up host name of {node}'s address {ip}"))]
+    ReverseLookup {
+        source: io::Error,
+        node: ObjectRef<Node>,
+        ip: IpAddr,
+    },
+
+    #[snafu(display("failed to look up addresses of {hostname:?}"))]
+    ForwardLookup { source: io::Error, hostname: String },
+
+    #[snafu(display(
+        "none of the host names of {node}'s InternalIP addresses ({hostnames:?}) resolve back to those addresses"
+    ))]
+    Unconfirmed {
+        node: ObjectRef<Node>,
+        hostnames: Vec<String>,
+    },
+}
+
+/// Looks up host names in DNS.
+#[async_trait]
+pub trait HostResolver: Send + Sync {
+    /// Looks up the host name that `ip` is registered as (its PTR record).
+    async fn lookup_addr(&self, ip: IpAddr) -> io::Result<String>;
+
+    /// Looks up all addresses that `hostname` resolves to.
+    async fn lookup_host(&self, hostname: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+/// Resolves host names using the operating system's resolver configuration.
+#[derive(Debug)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl HostResolver for SystemResolver {
+    async fn lookup_addr(&self, ip: IpAddr) -> io::Result<String> {
+        tokio::task::spawn_blocking(move || getnameinfo(ip)).await?
+    }
+
+    async fn lookup_host(&self, hostname: &str) -> io::Result<Vec<IpAddr>> {
+        Ok(tokio::net::lookup_host((hostname, 0))
+            .await?
+            .map(|addr| addr.ip())
+            .collect())
+    }
+}
+
+fn getnameinfo(ip: IpAddr) -> io::Result<String> {
+    let addr = SockAddr::from(std::net::SocketAddr::new(ip, 0));
+    let mut host = [0; MAX_HOST_NAME_LEN];
+    // SAFETY: addr and host are valid for the lengths that are passed along with them
+    let status = unsafe {
+        libc::getnameinfo(
+            addr.as_ptr().cast(),
+            addr.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if status != 0 {
+        // SAFETY: gai_strerror returns a static string for all error codes
+        let message = unsafe { CStr::from_ptr(libc::gai_strerror(status)) };
+        return Err(io::Error::other(message.to_string_lossy().into_owned()));
+    }
+    // SAFETY: getnameinfo writes a null-terminated string on success
+    let host = unsafe { CStr::from_ptr(host.as_ptr()) };
+    Ok(host.to_string_lossy().into_owned())
+}
+
+/// Resolves the host name of the node `node_name`, according to `source`.
+pub async fn resolve_node_hostname(
+    source: &KerberosNodeNameSource,
+    node_name: &str,
+    node: &NodeInfo,
+    resolver: &dyn HostResolver,
+) -> Result<String, Error> {
+    use error::*;
+    let node_ref = || ObjectRef::<Node>::new(node_name);
+    match source {
+        KerberosNodeNameSource::KubeletNodeName => Ok(node_name.to_string()),
+        KerberosNodeNameSource::NodeLabel(label) => {
+            node.labels.get(label).cloned().context(NoLabelSnafu {
+                node: node_ref(),
+                label,
+            })
+        }
+        KerberosNodeNameSource::NodeAddressFqdn => {
+            ensure!(
+                !node.internal_ips.is_empty(),
+                NoInternalIpSnafu { node: node_ref() }
+            );
+            let mut hostnames = Vec::new();
+            for ip in &node.internal_ips {
+                let hostname = resolver
+                    .lookup_addr(*ip)
+                    .await
+                    .context(ReverseLookupSnafu {
+                        node: node_ref(),
+                        ip: *ip,
+                    })?;
+                let hostname = hostname.trim_end_matches('.').to_string();
+                // Only trust the PTR record if the name's owner agrees that it belongs to the node
+                let addresses =
+                    resolver
+                        .lookup_host(&hostname)
+                        .await
+                        .context(ForwardLookupSnafu {
+                            hostname: &hostname,
+                        })?;
+                if addresses.contains(ip) {
+                    return Ok(hostname);
+                }
+                hostnames.push(hostname);
+            }
+            UnconfirmedSnafu {
+                node: node_ref(),
+                hostnames,
+            }
+            .fail()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io, net::IpAddr};
+
+    use async_trait::async_trait;
+    use stackable_operator::{
+        k8s_openapi::api::core::v1::{Node, NodeAddress, NodeStatus},
+        kube::api::ObjectMeta,
+    };
+
+    use super::{Error, HostResolver, resolve_node_hostname};
+    use crate::{backend::pod_info::NodeInfo, crd::KerberosNodeNameSource};
+
+    #[derive(Default)]
+    struct FakeResolver {
+        ptr: BTreeMap<IpAddr, String>,
+        hosts: BTreeMap<String, Vec<IpAddr>>,
+    }
+
+    #[async_trait]
+    impl HostResolver for FakeResolver {
+        async fn lookup_addr(&self, ip: IpAddr) -> io::Result<String> {
+            self.ptr
+                .get(&ip)
+                .cloned()
+                .ok_or_else(|| io::Error::other("Name or service not known"))
+        }
+
+        async fn lookup_host(&self, hostname: &str) -> io::Result<Vec<IpAddr>> {
+            Ok(self.hosts.get(hostname).cloned().unwrap_or_default())
+        }
+    }
+
+    fn node(labels: &[(&str, &str)], addresses: &[(&str, &str)]) -> NodeInfo {
+        NodeInfo::from_node(&Node {
+            metadata: ObjectMeta {
+                name: Some("ip-10-0-0-1".to_string()),
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            status: Some(NodeStatus {
+                addresses: Some(
+                    addresses
+                        .iter()
+                        .map(|(type_, address)| NodeAddress {
+                            type_: type_.to_string(),
+                            address: address.to_string(),
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    async fn resolve(
+        source: KerberosNodeNameSource,
+        node: &NodeInfo,
+        resolver: &FakeResolver,
+    ) -> Result<String, Error> {
+        resolve_node_hostname(&source, "ip-10-0-0-1", node, resolver).await
+    }
+
+    #[tokio::test]
+    async fn kubelet_node_name() {
+        let node = node(&[], &[("InternalIP", "10.0.0.1")]);
+        assert_eq!(
+            resolve(
+                KerberosNodeNameSource::KubeletNodeName,
+                &node,
+                &FakeResolver::default()
+            )
+            .await
+            .unwrap(),
+            "ip-10-0-0-1"
+        );
+    }
+
+    #[tokio::test]
+    async fn node_label() {
+        let source = KerberosNodeNameSource::NodeLabel("example.com/fqdn".to_string());
+        let labelled = node(
+            &[("example.com/fqdn", "worker-1.corp.example.com")],
+            &[("InternalIP", "10.0.0.1")],
+        );
+        assert_eq!(
+            resolve(source.clone(), &labelled, &FakeResolver::default())
+                .await
+                .unwrap(),
+            "worker-1.corp.example.com"
+        );
+
+        let unlabelled = node(&[], &[("InternalIP", "10.0.0.1")]);
+        let err = resolve(source, &unlabelled, &FakeResolver::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, Error::NoLabel { label, .. } if label == "example.com/fqdn"));
+    }
+
+    #[tokio::test]
+    async fn node_address_fqdn_is_forward_confirmed() {
+        let ip = "10.0.0.1".parse().unwrap();
+        let node = node(
+            &[],
+            &[("ExternalIP", "203.0.113.1"), ("InternalIP", "10.0.0.1")],
+        );
+        let mut resolver = FakeResolver {
+            // Resolvers usually return the name as absolute
+            ptr: [(ip, "worker-1.corp.example.com.".to_string())].into(),
+            hosts: [("worker-1.corp.example.com".to_string(), vec![ip])].into(),
+        };
+        assert_eq!(
+            resolve(KerberosNodeNameSource::NodeAddressFqdn, &node, &resolver)
+                .await
+                .unwrap(),
+            "worker-1.corp.example.com"
+        );
+
+        // A PTR record that points somewhere else must not be trusted
+        resolver.hosts.insert(
+            "worker-1.corp.example.com".to_string(),
+            vec!["10.0.0.2".parse().unwrap()],
+        );
+        let err = resolve(KerberosNodeNameSource::NodeAddressFqdn, &node, &resolver)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Unconfirmed { hostnames, .. } if hostnames == &["worker-1.corp.example.com"])
+        );
+
+        let err = resolve(
+            KerberosNodeNameSource::NodeAddressFqdn,
+            &node,
+            &FakeResolver::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(&err, Error::ReverseLookup { ip: failed_ip, .. } if *failed_ip == ip));
+    }
+
+    #[tokio::test]
+    async fn node_address_fqdn_requires_internal_ip() {
+        let node = node(&[], &[("ExternalIP", "203.0.113.1")]);
+        let err = resolve(
+            KerberosNodeNameSource::NodeAddressFqdn,
+            &node,
+            &FakeResolver::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::NoInternalIp { .. }));
+    }
+}
@@ -2,7 +2,9 @@
 
 use std::{
     collections::{BTreeMap, HashMap},
+    future::Future,
     net::{AddrParseError, IpAddr},
+    time::Duration,
 };
 
 use futures::{StreamExt, TryStreamExt};
@@ -12,12 +14,16 @@ use stackable_operator::{
         listener::{AddressType, Listener, ListenerClass, PodListeners, ServiceType},
         networking::DomainName,
     },
-    k8s_openapi::api::core::v1::{Node, PersistentVolumeClaim, Pod},
+    k8s_openapi::api::core::v1::{Node, PersistentVolumeClaim, Pod, Service},
     kube::runtime::reflector::ObjectRef,
 };
+use tokio::time::Instant;
 
 use super::scope::SecretScope;
-use crate::utils::trystream_any;
+use crate::{
+    metrics::{self, DependencyWaitOutcome},
+    utils::{poll_until_some, trystream_any},
+};
 
 const LISTENER_PVC_ANNOTATION_LISTENER_NAME: &str = "listeners.stackable.tech/listener-name";
 const LISTENER_PVC_ANNOTATION_LISTENER_CLASS: &str = "listeners.stackable.tech/listener-class";
@@ -67,6 +73,15 @@ pub enum FromPodError {
         listener: ObjectRef<Listener>,
     },
 
+    #[snafu(display(
+        "{listener} for volume {listener_volume} does not exist (waited {waited:?} for it to be created)"
+    ))]
+    ListenerNotFound {
+        listener_volume: String,
+        listener: ObjectRef<Listener>,
+        waited: Duration,
+    },
+
     #[snafu(display("failed to get {listener_class} for volume {listener_volume}"))]
     GetListenerClass {
         source: stackable_operator::client::Error,
@@ -95,11 +110,121 @@ pub enum FromPodError {
         pod: ObjectRef<Pod>,
     },
 
+    #[snafu(display(
+        "{pod_listeners} for {pod} does not exist (waited {waited:?} for it to be created)"
+    ))]
+    PodListenersNotFound {
+        pod_listeners: ObjectRef<PodListeners>,
+        pod: ObjectRef<Pod>,
+        waited: Duration,
+    },
+
     #[snafu(display("{pod_listeners} has no addresses for listener {listener} yet"))]
     NoPodListenerAddresses {
         pod_listeners: ObjectRef<PodListeners>,
         listener: String,
     },
+
+    #[snafu(display("failed to get {service}"))]
+    GetService {
+        source: stackable_operator::client::Error,
+        service: ObjectRef<Service>,
+    },
+
+    #[snafu(display("{service} does not exist (waited {waited:?} for it to be created)"))]
+    ServiceNotFound {
+        service: ObjectRef<Service>,
+        waited: Duration,
+    },
+}
+
+impl FromPodError {
+    pub fn grpc_code(&self) -> tonic::Code {
+        match self {
+            // Objects that are usually created concurrently with the Pod, so retrying later is likely to succeed
+            FromPodError::ListenerNotFound { .. } => tonic::Code::Unavailable,
+            FromPodError::PodListenersNotFound { .. } => tonic::Code::Unavailable,
+            FromPodError::NoPodListenerAddresses { .. } => tonic::Code::Unavailable,
+            FromPodError::ServiceNotFound { .. } => tonic::Code::Unavailable,
+            _ => tonic::Code::FailedPrecondition,
+        }
+    }
+}
+
+/// Controls how long to wait for objects that a volume depends on (such as its [`Listener`]) to be created.
+///
+/// Users commonly create all objects at once, so the Pod may be scheduled before its dependencies exist.
+/// Waiting for them here usually avoids a round-trip through kubelet's retry backoff.
+///
+/// All dependencies of a request share the same deadline, so waiting for several of them never takes longer than
+/// the request's budget.
+#[derive(Debug, Clone, Copy)]
+pub struct DependencyWait {
+    /// When to stop waiting for missing dependencies.
+    pub deadline: Instant,
+
+    /// The total time that may be spent waiting, for reporting why a dependency is missing.
+    pub budget: Duration,
+
+    /// How often to check whether a missing dependency has been created.
+    pub poll_interval: Duration,
+}
+
+impl DependencyWait {
+    const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// Fail immediately if a dependency is missing.
+    pub fn none() -> Self {
+        Self {
+            deadline: Instant::now(),
+            budget: Duration::ZERO,
+            poll_interval: Duration::ZERO,
+        }
+    }
+
+    /// Waits for at most `fraction` of the request's `deadline`, starting now.
+    pub fn for_deadline(deadline: Duration, fraction: f64) -> Self {
+        let budget = deadline.mul_f64(fraction.clamp(0.0, 1.0));
+        Self {
+            deadline: Instant::now() + budget,
+            budget,
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Calls `lookup` until it returns `Some`, giving up once the deadline has passed.
+    ///
+    /// The outcome is recorded in [`metrics::DEPENDENCY_WAITS`] under `kind` (such as `service`).
+    pub async fn poll<T, E, F, Fut>(
+        &self,
+        kind: &'static str,
+        mut lookup: F,
+    ) -> Result<Option<T>, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Option<T>, E>>,
+    {
+        let started = Instant::now();
+        let mut attempts = 0;
+        let result = poll_until_some(
+            self.deadline.saturating_duration_since(started),
+            self.poll_interval,
+            || {
+                attempts += 1;
+                lookup()
+            },
+        )
+        .await;
+        if let Ok(found) = &result {
+            let outcome = match found {
+                Some(_) if attempts == 1 => DependencyWaitOutcome::Present,
+                Some(_) => DependencyWaitOutcome::Appeared,
+                None => DependencyWaitOutcome::TimedOut,
+            };
+            metrics::record_dependency_wait(kind, outcome, started.elapsed());
+        }
+        result
+    }
 }
 
 /// Validated metadata about a scheduled [`Pod`]
@@ -109,9 +234,14 @@ pub struct PodInfo {
     pub service_name: Option<String>,
     pub node_name: String,
     pub node_ips: Vec<IpAddr>,
+    pub node: NodeInfo,
     pub listener_addresses: HashMap<String, Vec<Address>>,
     pub kubernetes_cluster_domain: DomainName,
     pub scheduling: SchedulingPodInfo,
+
+    /// The remaining wait for objects that the backend depends on, such as the `Secret`s that
+    /// [`K8sSearch`](super::K8sSearch) searches for.
+    pub dependency_wait: DependencyWait,
 }
 
 impl PodInfo {
@@ -119,6 +249,7 @@ impl PodInfo {
         client: &stackable_operator::client::Client,
         pod: Pod,
         scopes: &[SecretScope],
+        wait: DependencyWait,
     ) -> Result<Self, FromPodError> {
         use from_pod_error::*;
         let node_name = pod
@@ -132,9 +263,19 @@ impl PodInfo {
             .with_context(|_| GetNodeSnafu {
                 node: ObjectRef::new(&node_name),
             })?;
-        let scheduling = SchedulingPodInfo::from_pod(client, &pod, scopes).await?;
+        let namespace = pod
+            .metadata
+            .namespace
+            .as_deref()
+            .context(NoNamespaceSnafu)?;
+        for scope in scopes {
+            if let SecretScope::Service { name } = scope {
+                wait_for_service(client, name, namespace, wait).await?;
+            }
+        }
+        let scheduling = SchedulingPodInfo::from_pod(client, &pod, scopes, wait).await?;
         let listener_addresses = if !scheduling.volume_listener_names.is_empty() {
-            pod_listener_addresses(client, &pod, &scheduling, scopes).await?
+            pod_listener_addresses(client, &pod, &scheduling, scopes, wait).await?
         } else {
             // We don't care about the listener addresses if there is no listener scope, so we can save the API call
             HashMap::new()
@@ -168,9 +309,42 @@ impl PodInfo {
                         })
                 })
                 .collect::<Result<_, _>>()?,
+            node: NodeInfo::from_node(&node)?,
             listener_addresses,
             kubernetes_cluster_domain: client.kubernetes_cluster_info.cluster_domain.clone(),
             scheduling,
+            dependency_wait: wait,
+        })
+    }
+}
+
+/// Validated metadata about the [`Node`] that a [`Pod`] is scheduled to
+#[derive(Debug)]
+pub struct NodeInfo {
+    pub labels: BTreeMap<String, String>,
+
+    /// The node's addresses inside of the cluster network, excluding any external addresses.
+    pub internal_ips: Vec<IpAddr>,
+}
+
+impl NodeInfo {
+    pub fn from_node(node: &Node) -> Result<Self, FromPodError> {
+        Ok(Self {
+            labels: node.metadata.labels.clone().unwrap_or_default(),
+            internal_ips: node
+                .status
+                .iter()
+                .flat_map(|status| status.addresses.as_deref())
+                .flatten()
+                .filter(|addr| addr.type_ == "InternalIP")
+                .map(|ip| {
+                    ip.address
+                        .parse()
+                        .context(from_pod_error::IllegalAddressSnafu {
+                            address: &ip.address,
+                        })
+                })
+                .collect::<Result<_, _>>()?,
         })
     }
 }
@@ -180,6 +354,14 @@ pub enum Address {
     Dns(String),
     Ip(IpAddr),
 }
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Dns(dns) => f.write_str(dns),
+            Address::Ip(ip) => ip.fmt(f),
+        }
+    }
+}
 impl TryFrom<(AddressType, &str)> for Address {
     type Error = AddrParseError;
 
@@ -210,6 +392,7 @@ impl SchedulingPodInfo {
         client: &stackable_operator::client::Client,
         pod: &Pod,
         scopes: &[SecretScope],
+        wait: DependencyWait,
     ) -> Result<Self, FromPodError> {
         use from_pod_error::*;
         let pod_name = pod.metadata.name.clone().context(NoPodNameSnafu)?;
@@ -276,7 +459,7 @@ impl SchedulingPodInfo {
         let has_node_scope = scopes.contains(&SecretScope::Node)
             || trystream_any(futures::stream::iter(volume_listener_pvcs).then(
                 |(listener_volume, _, pvc)| {
-                    listener_pvc_is_node_scoped(client, &namespace, listener_volume, pvc)
+                    listener_pvc_is_node_scoped(client, &namespace, listener_volume, pvc, wait)
                 },
             ))
             .await?;
@@ -288,11 +471,32 @@ impl SchedulingPodInfo {
     }
 }
 
+/// Waits for the [`Service`] of a `service` scope, since the names that it issues are meaningless without it.
+async fn wait_for_service(
+    client: &stackable_operator::client::Client,
+    name: &str,
+    namespace: &str,
+    wait: DependencyWait,
+) -> Result<Service, FromPodError> {
+    use from_pod_error::*;
+    let service_ref = ObjectRef::<Service>::new(name).within(namespace);
+    wait.poll("service", || client.get_opt::<Service>(name, namespace))
+        .await
+        .context(GetServiceSnafu {
+            service: service_ref.clone(),
+        })?
+        .context(ServiceNotFoundSnafu {
+            service: service_ref,
+            waited: wait.budget,
+        })
+}
+
 async fn listener_pvc_is_node_scoped(
     client: &stackable_operator::client::Client,
     namespace: &str,
     listener_volume: &str,
     pvc: PersistentVolumeClaim,
+    wait: DependencyWait,
 ) -> Result<bool, FromPodError> {
     use from_pod_error::*;
     let empty = BTreeMap::new();
@@ -303,12 +507,20 @@ async fn listener_pvc_is_node_scoped(
     {
         cn
     } else if let Some(listener_name) = pvc_annotations.get(LISTENER_PVC_ANNOTATION_LISTENER_NAME) {
-        listener = client
-            .get::<Listener>(listener_name, namespace)
+        let listener_ref = ObjectRef::<Listener>::new(listener_name).within(namespace);
+        listener = wait
+            .poll("listener", || {
+                client.get_opt::<Listener>(listener_name, namespace)
+            })
             .await
             .context(GetListenerSnafu {
                 listener_volume,
-                listener: ObjectRef::<Listener>::new(listener_name).within(namespace),
+                listener: listener_ref.clone(),
+            })?
+            .context(ListenerNotFoundSnafu {
+                listener_volume,
+                listener: listener_ref,
+                waited: wait.budget,
             })?;
         listener
             .spec
@@ -340,27 +552,63 @@ async fn pod_listener_addresses(
     pod: &Pod,
     pod_info: &SchedulingPodInfo,
     scopes: &[SecretScope],
+    wait: DependencyWait,
 ) -> Result<HashMap<String, Vec<Address>>, FromPodError> {
     use from_pod_error::*;
     let pod_listeners_name = format!(
         "pod-{}",
         pod.metadata.uid.as_deref().context(NoPodUidSnafu)?
     );
-    let listeners = client
-        .get::<PodListeners>(&pod_listeners_name, &pod_info.namespace)
-        .await
-        .context(GetPodListenersSnafu {
-            pod_listeners: ObjectRef::<PodListeners>::new(&pod_listeners_name)
-                .within(&pod_info.namespace),
-            pod: ObjectRef::from_obj(pod),
-        })?;
-    let listeners_ref = ObjectRef::from_obj(&listeners);
-    scopes
+    let pod_listeners_ref =
+        ObjectRef::<PodListeners>::new(&pod_listeners_name).within(&pod_info.namespace);
+    let listener_scopes = scopes
         .iter()
         .filter_map(|scope| match scope {
             SecretScope::ListenerVolume { name } => Some(name),
             _ => None,
         })
+        .collect::<Vec<_>>();
+    let has_all_addresses = |listeners: &PodListeners| {
+        listener_scopes.iter().all(|listener| {
+            listeners
+                .spec
+                .listeners
+                .get(*listener)
+                .is_some_and(|ingresses| ingresses.ingress_addresses.is_some())
+        })
+    };
+    // Listener addresses are filled in asynchronously by listener-operator, so wait until all of them are known
+    let ready_listeners = wait
+        .poll("pod-listeners", || async {
+            client
+                .get_opt::<PodListeners>(&pod_listeners_name, &pod_info.namespace)
+                .await
+                .map(|listeners| listeners.filter(|listeners| has_all_addresses(listeners)))
+        })
+        .await
+        .context(GetPodListenersSnafu {
+            pod_listeners: pod_listeners_ref.clone(),
+            pod: ObjectRef::from_obj(pod),
+        })?;
+    let listeners = match ready_listeners {
+        Some(listeners) => listeners,
+        // Timed out, look up the final state to report what is actually missing
+        None => client
+            .get_opt::<PodListeners>(&pod_listeners_name, &pod_info.namespace)
+            .await
+            .context(GetPodListenersSnafu {
+                pod_listeners: pod_listeners_ref.clone(),
+                pod: ObjectRef::from_obj(pod),
+            })?
+            .context(PodListenersNotFoundSnafu {
+                pod_listeners: pod_listeners_ref,
+                pod: ObjectRef::from_obj(pod),
+                waited: wait.budget,
+            })?,
+    };
+    let listeners_ref = ObjectRef::from_obj(&listeners);
+    listener_scopes
+        .into_iter()
         .map(|listener| {
             let addresses = listeners
                 .spec
@@ -387,3 +635,71 @@ async fn pod_listener_addresses(
         })
         .collect::<Result<HashMap<_, _>, FromPodError>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use tokio::time::Instant;
+
+    use super::DependencyWait;
+    use crate::metrics;
+
+    #[tokio::test(start_paused = true)]
+    async fn dependency_wait_should_find_dependencies_created_while_waiting() {
+        let wait = DependencyWait::for_deadline(Duration::from_secs(10), 0.5);
+        let started = Instant::now();
+        let mut attempts = 0;
+        let found = wait
+            .poll("test-appeared", || {
+                attempts += 1;
+                // Only appears on the third attempt
+                let found = (attempts >= 3).then_some("service");
+                async move { Ok::<_, Infallible>(found) }
+            })
+            .await;
+        assert_eq!(found, Ok(Some("service")));
+        assert_eq!(started.elapsed(), wait.poll_interval * 2);
+        assert_eq!(
+            metrics::DEPENDENCY_WAITS.get(&["test-appeared", "appeared"]),
+            1.0
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dependency_waits_should_share_one_deadline() {
+        let wait = DependencyWait::for_deadline(Duration::from_secs(10), 0.5);
+        let started = Instant::now();
+        let first = wait
+            .poll("test-shared-first", || async move {
+                Ok::<_, Infallible>((started.elapsed() >= Duration::from_secs(4)).then_some(()))
+            })
+            .await;
+        assert_eq!(first, Ok(Some(())));
+        let second = wait
+            .poll("test-shared-second", || async {
+                Ok::<Option<()>, Infallible>(None)
+            })
+            .await;
+        assert_eq!(second, Ok(None));
+        // The second dependency only gets what the first one left of the budget
+        assert_eq!(started.elapsed(), wait.budget);
+        assert_eq!(
+            metrics::DEPENDENCY_WAITS.get(&["test-shared-second", "timed-out"]),
+            1.0
+        );
+    }
+
+    #[tokio::test]
+    async fn no_dependency_wait_should_give_up_immediately() {
+        let mut attempts = 0;
+        let found = DependencyWait::none()
+            .poll("test-none", || {
+                attempts += 1;
+                async { Ok::<Option<()>, Infallible>(None) }
+            })
+            .await;
+        assert_eq!(found, Ok(None));
+        assert_eq!(attempts, 1);
+    }
+}
@@ -31,16 +31,29 @@ use stackable_operator::{
     },
     time::Duration,
 };
-use stackable_secret_operator_crd_utils::{ConfigMapReference, SecretReference};
+use stackable_secret_operator_crd_utils::{
+    ConfigMapReference, SecretReference, rejection::WriteRejection,
+};
 use time::OffsetDateTime;
 use tracing::{info, info_span, warn};
 
 use crate::{
-    backend::SecretBackendError,
+    backend::{SecretBackendError, coordination::LeasePool},
     crd::{AdditionalTrustRoot, CertificateKeyGeneration},
     utils::{Asn1TimeParseError, Unloggable, asn1time_to_offsetdatetime},
 };
 
+/// Annotation on the `SecretClass` that requests the CA to be replaced immediately, such as after it has been
+/// compromised.
+///
+/// The value is an opaque token (conventionally the current timestamp). Each new value triggers exactly one
+/// rotation, setting the same value again has no effect. Every node also refreshes its existing volumes of the
+/// `SecretClass`, so that they are reissued by the new CA, see [`crate::csi_server::rotation`].
+pub const FORCE_ROTATION_ANNOTATION: &str = "secrets.stackable.tech/force-rotation";
+
+/// Annotation on the CA `Secret` that records the [`FORCE_ROTATION_ANNOTATION`] value that was last applied.
+const ROTATED_FOR_ANNOTATION: &str = "secrets.stackable.tech/rotated-for";
+
 /// v1 format: support a single cert/pkey pair
 mod secret_v1_keys {
     pub const CERTIFICATE: &str = "ca.crt";
@@ -113,6 +126,13 @@ pub enum Error {
         secret: ObjectRef<Secret>,
     },
 
+    #[snafu(display("saving CA certificate to {secret} was {rejection}"))]
+    SaveCaCertificateRejected {
+        source: entry::CommitError,
+        rejection: WriteRejection,
+        secret: ObjectRef<Secret>,
+    },
+
     #[snafu(display("CA save was requested but automatic management is disabled"))]
     SaveRequestedButForbidden,
 }
@@ -132,9 +152,18 @@ impl SecretBackendError for Error {
             Error::BuildCertificate { .. } => tonic::Code::FailedPrecondition,
             Error::SerializeCertificate { .. } => tonic::Code::FailedPrecondition,
             Error::SaveCaCertificate { .. } => tonic::Code::Unavailable,
+            // Retrying won't help until the cluster's policies are changed
+            Error::SaveCaCertificateRejected { .. } => tonic::Code::FailedPrecondition,
             Error::SaveRequestedButForbidden { .. } => tonic::Code::FailedPrecondition,
         }
     }
+
+    fn write_rejection(&self) -> Option<&WriteRejection> {
+        match self {
+            Error::SaveCaCertificateRejected { rejection, .. } => Some(rejection),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Snafu)]
@@ -174,6 +203,12 @@ pub struct Config {
     /// and smaller than [`Self::ca_certificate_lifetime`].
     pub rotate_if_ca_expires_before: Option<Duration>,
 
+    /// The value of [`FORCE_ROTATION_ANNOTATION`], if set.
+    ///
+    /// Unlike regular rotation, forced rotation discards all existing CAs, so certificates issued by them stop
+    /// being trusted by new `Pod`s.
+    pub force_rotation: Option<String>,
+
     /// Configuration how TLS private keys should be created.
     pub key_generation: CertificateKeyGeneration,
 }
@@ -308,6 +343,14 @@ impl CertificateAuthority {
     }
 }
 
+/// The cluster policy that rejected saving a Secret, if any.
+fn commit_rejection(err: &entry::CommitError) -> Option<WriteRejection> {
+    match err {
+        entry::CommitError::Save(err) => WriteRejection::from_error(err),
+        entry::CommitError::Validate(_) => None,
+    }
+}
+
 /// Manages multiple [`CertificateAuthorities`](`CertificateAuthority`), rotating them as needed.
 #[derive(Debug)]
 pub struct Manager {
@@ -321,6 +364,7 @@ impl Manager {
         secret_ref: &SecretReference,
         additional_trust_roots: &[AdditionalTrustRoot],
         config: &Config,
+        leases: &LeasePool,
     ) -> Result<Self> {
         // Use entry API rather than apply so that we crash and retry on conflicts (to avoid creating spurious certs that we throw away immediately)
         let secrets_api = &client.get_api::<Secret>(&secret_ref.namespace);
@@ -391,6 +435,35 @@ impl Manager {
                 return CaNotFoundAndGenDisabledSnafu { secret: secret_ref }.fail();
             }
         };
+        let rotated_for = match &ca_secret {
+            Entry::Occupied(ca_secret) => ca_secret.get().metadata.annotations.as_ref(),
+            Entry::Vacant(_) => None,
+        }
+        .and_then(|annotations| annotations.get(ROTATED_FOR_ANNOTATION));
+        let force_rotation = config
+            .force_rotation
+            .as_ref()
+            .filter(|&requested| rotated_for != Some(requested));
+        if let Some(requested) = force_rotation {
+            if config.manage_ca {
+                update_ca_secret = true;
+                info!(
+                    secret = %secret_ref,
+                    rotation = requested,
+                    previous_rotation = rotated_for,
+                    "Replacing all CA certificates, because rotation was forced"
+                );
+                certificate_authorities = vec![CertificateAuthority::new_self_signed(config)?];
+            } else {
+                warn!(
+                    secret = %secret_ref,
+                    rotation = requested,
+                    "CA rotation was forced, but automatic management is disabled, please provision a new CA"
+                );
+            }
+        }
+        let force_rotation = force_rotation.cloned();
+
         // Check whether CA should be rotated
         let newest_ca = certificate_authorities.iter().max_by_key(|ca| ca.not_after);
         if let (Some(cutoff_duration), Some(newest_ca)) =
@@ -426,6 +499,16 @@ impl Manager {
                 // Sort CAs by age to avoid spurious writes
                 certificate_authorities.sort_by_key(|ca| ca.not_after);
                 let mut ca_secret = ca_secret.or_insert(Secret::default);
+                if let Some(rotation) = force_rotation {
+                    // Conflicting saves are retried from scratch, and will then see that the rotation has already
+                    // been applied
+                    ca_secret
+                        .get_mut()
+                        .metadata
+                        .annotations
+                        .get_or_insert_with(BTreeMap::new)
+                        .insert(ROTATED_FOR_ANNOTATION.to_string(), rotation);
+                }
                 ca_secret.get_mut().data = Some(
                     certificate_authorities
                         .iter()
@@ -454,10 +537,20 @@ impl Manager {
                         })
                         .collect::<Result<_>>()?,
                 );
-                ca_secret
-                    .commit(&PostParams::default())
+                leases
+                    .run("tls-save-ca", ca_secret.commit(&PostParams::default()))
                     .await
-                    .context(SaveCaCertificateSnafu { secret: secret_ref })?;
+                    .map_err(|source| match commit_rejection(&source) {
+                        Some(rejection) => Error::SaveCaCertificateRejected {
+                            source,
+                            rejection,
+                            secret: secret_ref.into(),
+                        },
+                        None => Error::SaveCaCertificate {
+                            source,
+                            secret: secret_ref.into(),
+                        },
+                    })?;
             } else {
                 return SaveRequestedButForbiddenSnafu.fail();
             }
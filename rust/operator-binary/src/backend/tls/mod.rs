@@ -9,10 +9,10 @@ use openssl::{
     conf::{Conf, ConfMethod},
     hash::MessageDigest,
     nid::Nid,
-    pkey::PKey,
+    pkey::{HasPublic, PKey, PKeyRef},
     rsa::Rsa,
     x509::{
-        X509Builder, X509NameBuilder,
+        X509, X509Builder, X509NameBuilder,
         extension::{
             AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage,
             SubjectAlternativeName, SubjectKeyIdentifier,
@@ -25,10 +25,13 @@ use stackable_operator::{
     k8s_openapi::chrono::{self, FixedOffset, TimeZone},
     time::Duration,
 };
+use stackable_secret_operator_crd_utils::rejection::WriteRejection;
 use time::OffsetDateTime;
 
 use super::{
-    ScopeAddressesError, SecretBackend, SecretBackendError, SecretContents,
+    ScopeAddressesError, SecretBackend, SecretBackendError, SecretContents, SecretIdentity,
+    coordination::LeasePool,
+    csr::{CsrKeyRequirements, CsrRequest, SignedCsr, ValidatedCsr},
     pod_info::{Address, PodInfo},
     scope::SecretScope,
 };
@@ -40,6 +43,8 @@ use crate::{
 
 mod ca;
 
+pub use ca::FORCE_ROTATION_ANNOTATION;
+
 /// How long CA certificates should last for. Also used for calculating when they should be rotated.
 /// [`DEFAULT_MAX_CERT_LIFETIME`] must be less than half of [`DEFAULT_CA_CERT_LIFETIME`].
 pub const DEFAULT_CA_CERT_LIFETIME: Duration = Duration::from_days_unchecked(365);
@@ -62,6 +67,9 @@ pub const DEFAULT_CERT_LIFETIME: Duration = Duration::from_hours_unchecked(24);
 /// - which is the purpose of the buffer.
 pub const DEFAULT_CERT_RESTART_BUFFER: Duration = Duration::from_hours_unchecked(6);
 
+/// How long to wait for the Pod to submit its CSR, when using the CSR handshake.
+pub const DEFAULT_CSR_HANDSHAKE_TIMEOUT: Duration = Duration::from_minutes_unchecked(5);
+
 /// We randomize the certificate lifetimes slightly, in order to avoid all pods of a set restarting/failing at the same time.
 pub const DEFAULT_CERT_JITTER_FACTOR: f64 = 0.2;
 
@@ -127,6 +135,13 @@ impl SecretBackendError for Error {
             Error::JitterOutOfRange { .. } => tonic::Code::InvalidArgument,
         }
     }
+
+    fn write_rejection(&self) -> Option<&WriteRejection> {
+        match self {
+            Error::LoadCa { source } => source.write_rejection(),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -153,6 +168,8 @@ impl TlsGenerate {
         }: &crd::AutoTlsCa,
         additional_trust_roots: &[AdditionalTrustRoot],
         max_cert_lifetime: Duration,
+        force_rotation: Option<String>,
+        leases: &LeasePool,
     ) -> Result<Self> {
         Ok(Self {
             ca_manager: ca::Manager::load_or_create(
@@ -163,8 +180,10 @@ impl TlsGenerate {
                     manage_ca: *auto_generate_ca,
                     ca_certificate_lifetime: *ca_certificate_lifetime,
                     rotate_if_ca_expires_before: Some(*ca_certificate_lifetime / 2),
+                    force_rotation,
                     key_generation: key_generation.clone(),
                 },
+                leases,
             )
             .await
             .context(LoadCaSnafu)?,
@@ -174,17 +193,17 @@ impl TlsGenerate {
     }
 }
 
-#[async_trait]
-impl SecretBackend for TlsGenerate {
-    type Error = Error;
+/// The period during which a certificate is valid.
+struct CertValidity {
+    not_before: OffsetDateTime,
+    not_after: OffsetDateTime,
+    /// When the Pod should be restarted to pick up a new certificate.
+    expire_pod_after: OffsetDateTime,
+}
 
-    /// Generate a key pair and sign it with the CA key.
-    /// Then add the ca certificate and return these files for provisioning to the volume.
-    async fn get_secret_data(
-        &self,
-        selector: &super::SecretVolumeSelector,
-        pod_info: PodInfo,
-    ) -> Result<SecretContents, Self::Error> {
+impl TlsGenerate {
+    /// Picks the validity period of a certificate requested by `selector`.
+    fn cert_validity(&self, selector: &super::SecretVolumeSelector) -> Result<CertValidity> {
         let now = OffsetDateTime::now_utc();
         let not_before = now - Duration::from_minutes_unchecked(5);
 
@@ -238,37 +257,26 @@ impl SecretBackend for TlsGenerate {
             }
             .fail()?;
         }
+        Ok(CertValidity {
+            not_before,
+            not_after,
+            expire_pod_after,
+        })
+    }
 
+    /// Issues a certificate for the public key `pod_key`, signed by the CA.
+    fn issue_certificate<T: HasPublic>(
+        &self,
+        pod_key: &PKeyRef<T>,
+        addresses: &[Address],
+        validity: &CertValidity,
+    ) -> Result<X509> {
         let conf = Conf::new(ConfMethod::default()).unwrap();
-
-        let pod_key_length = match self.key_generation {
-            CertificateKeyGeneration::Rsa { length } => length,
-        };
-
-        let pod_key = Rsa::generate(pod_key_length)
-            .and_then(PKey::try_from)
-            .context(GenerateKeySnafu)?;
-        let mut addresses = Vec::new();
-        for scope in &selector.scope {
-            addresses.extend(
-                selector
-                    .scope_addresses(&pod_info, scope)
-                    .context(ScopeAddressesSnafu { scope })?,
-            );
-        }
-        for address in &mut addresses {
-            if let Address::Dns(dns) = address {
-                // Turn FQDNs into bare domain names by removing the trailing dot
-                if dns.ends_with('.') {
-                    dns.pop();
-                }
-            }
-        }
         let ca = self
             .ca_manager
-            .find_certificate_authority_for_signing(not_after)
+            .find_certificate_authority_for_signing(validity.not_after)
             .context(PickCaSnafu)?;
-        let pod_cert = X509Builder::new()
+        X509Builder::new()
             .and_then(|mut x509| {
                 let subject_name = X509NameBuilder::new()
                     .and_then(|mut name| {
@@ -278,9 +286,13 @@ impl SecretBackend for TlsGenerate {
                     .build();
                 x509.set_subject_name(&subject_name)?;
                 x509.set_issuer_name(ca.certificate.subject_name())?;
-                x509.set_not_before(Asn1Time::from_unix(not_before.unix_timestamp())?.as_ref())?;
-                x509.set_not_after(Asn1Time::from_unix(not_after.unix_timestamp())?.as_ref())?;
-                x509.set_pubkey(&pod_key)?;
+                x509.set_not_before(
+                    Asn1Time::from_unix(validity.not_before.unix_timestamp())?.as_ref(),
+                )?;
+                x509.set_not_after(
+                    Asn1Time::from_unix(validity.not_after.unix_timestamp())?.as_ref(),
+                )?;
+                x509.set_pubkey(pod_key)?;
                 x509.set_version(
                     3 - 1, // zero-indexed
                 )?;
@@ -310,7 +322,7 @@ impl SecretBackend for TlsGenerate {
                 for addr in addresses {
                     has_san = true;
                     match addr {
-                        Address::Dns(dns) => san_ext.dns(&dns),
+                        Address::Dns(dns) => san_ext.dns(dns),
                         Address::Ip(ip) => san_ext.ip(&ip.to_string()),
                     };
                 }
@@ -323,17 +335,69 @@ impl SecretBackend for TlsGenerate {
                 x509.sign(&ca.private_key, MessageDigest::sha256())?;
                 Ok(x509)
             })
-            .context(BuildCertificateSnafu)?
-            .build();
+            .context(BuildCertificateSnafu)
+            .map(X509Builder::build)
+    }
+
+    fn trust_roots_pem(&self) -> Result<Vec<u8>> {
+        iterator_try_concat_bytes(self.ca_manager.trust_roots().into_iter().map(|ca| {
+            ca.to_pem()
+                .context(SerializeCertificateSnafu { tpe: CertType::Ca })
+        }))
+    }
+}
+
+/// Returns all addresses that the certificate for `selector` should be valid for.
+fn selector_addresses(
+    selector: &super::SecretVolumeSelector,
+    pod_info: &PodInfo,
+) -> Result<Vec<Address>> {
+    let mut addresses = Vec::new();
+    for scope in &selector.scope {
+        addresses.extend(
+            selector
+                .scope_addresses(pod_info, scope)
+                .context(ScopeAddressesSnafu { scope })?,
+        );
+    }
+    for address in &mut addresses {
+        if let Address::Dns(dns) = address {
+            // Turn FQDNs into bare domain names by removing the trailing dot
+            if dns.ends_with('.') {
+                dns.pop();
+            }
+        }
+    }
+    Ok(addresses)
+}
+
+#[async_trait]
+impl SecretBackend for TlsGenerate {
+    type Error = Error;
+
+    /// Generate a key pair and sign it with the CA key.
+    /// Then add the ca certificate and return these files for provisioning to the volume.
+    async fn get_secret_data(
+        &self,
+        selector: &super::SecretVolumeSelector,
+        pod_info: PodInfo,
+    ) -> Result<SecretContents, Self::Error> {
+        let validity = self.cert_validity(selector)?;
+
+        let pod_key_length = match self.key_generation {
+            CertificateKeyGeneration::Rsa { length } => length,
+        };
+
+        let pod_key = Rsa::generate(pod_key_length)
+            .and_then(PKey::try_from)
+            .context(GenerateKeySnafu)?;
+        let addresses = selector_addresses(selector, &pod_info)?;
+        let pod_cert = self.issue_certificate(&pod_key, &addresses, &validity)?;
+        let identity = certificate_identity(&addresses, &pod_cert);
         Ok(
             SecretContents::new(SecretData::WellKnown(WellKnownSecretData::TlsPem(
                 well_known::TlsPem {
-                    ca_pem: iterator_try_concat_bytes(
-                        self.ca_manager.trust_roots().into_iter().map(|ca| {
-                            ca.to_pem()
-                                .context(SerializeCertificateSnafu { tpe: CertType::Ca })
-                        }),
-                    )?,
+                    ca_pem: self.trust_roots_pem()?,
                     certificate_pem: pod_cert
                         .to_pem()
                         .context(SerializeCertificateSnafu { tpe: CertType::Pod })?,
@@ -343,10 +407,73 @@ impl SecretBackend for TlsGenerate {
                 },
             )))
             .expires_after(
-                time_datetime_to_chrono(expire_pod_after).context(InvalidCertLifetimeSnafu)?,
-            ),
+                time_datetime_to_chrono(validity.expire_pod_after)
+                    .context(InvalidCertLifetimeSnafu)?,
+            )
+            .identity(identity),
         )
     }
+
+    async fn get_csr_request(
+        &self,
+        selector: &super::SecretVolumeSelector,
+        pod_info: PodInfo,
+    ) -> Result<Option<CsrRequest>, Self::Error> {
+        let mut dns_names = Vec::new();
+        let mut ip_addresses = Vec::new();
+        for address in selector_addresses(selector, &pod_info)? {
+            match address {
+                Address::Dns(dns) => dns_names.push(dns),
+                Address::Ip(ip) => ip_addresses.push(ip),
+            }
+        }
+        Ok(Some(CsrRequest {
+            dns_names,
+            ip_addresses,
+            // Keys generated by the Pod must be at least as strong as the ones we would have generated ourselves
+            key: match self.key_generation {
+                CertificateKeyGeneration::Rsa { length } => {
+                    CsrKeyRequirements::Rsa { min_length: length }
+                }
+            },
+        }))
+    }
+
+    /// Sign the Pod's own key with the CA key.
+    async fn sign_csr(
+        &self,
+        selector: &super::SecretVolumeSelector,
+        csr: &ValidatedCsr,
+    ) -> Result<Option<SignedCsr>, Self::Error> {
+        let validity = self.cert_validity(selector)?;
+        let pod_cert = self.issue_certificate(&csr.public_key, &csr.names, &validity)?;
+        Ok(Some(SignedCsr {
+            certificate_pem: pod_cert
+                .to_pem()
+                .context(SerializeCertificateSnafu { tpe: CertType::Pod })?,
+            ca_pem: self.trust_roots_pem()?,
+            expires_after: Some(
+                time_datetime_to_chrono(validity.expire_pod_after)
+                    .context(InvalidCertLifetimeSnafu)?,
+            ),
+            identity: certificate_identity(&csr.names, &pod_cert),
+        }))
+    }
+}
+
+/// Describes the certificate `cert` that was issued for `addresses`.
+fn certificate_identity(addresses: &[Address], cert: &X509) -> SecretIdentity {
+    SecretIdentity {
+        subject_alt_names: addresses.iter().map(Address::to_string).collect(),
+        // The serial is only informational, so don't fail the whole request if it can't be read
+        certificate_serials: cert
+            .serial_number()
+            .to_bn()
+            .and_then(|serial| serial.to_hex_str().map(|hex| hex.to_string()))
+            .into_iter()
+            .collect(),
+        principals: Vec::new(),
+    }
 }
 
 #[derive(Snafu, Debug)]
@@ -0,0 +1,437 @@
+//! Layers a configuration file under the command-line flags of `run`.
+//!
+//! Each option is taken from the first of these that sets it: flags, environment variables, the configuration file,
+//! and finally the built-in default.
+//!
+//! The configuration file is a YAML mapping from flag names (without the leading `--`) to values, such as:
+//!
+//! ```yaml
+//! node-name: worker-1
+//! publish-dependency-wait-fraction: 0.25
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgMatches, Command, parser::ValueSource};
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
+
+use crate::utils::fs::{self, FsError};
+
+/// The environment variable that may be used instead of `--config`.
+pub const CONFIG_ENV: &str = "SECRET_OPERATOR_CONFIG";
+
+const CONFIG_FLAG: &str = "--config";
+
+/// The subcommand that the configuration file applies to.
+pub const RUN_SUBCOMMAND: &str = "run";
+
+/// Options whose names contain any of these are never printed.
+const SENSITIVE_NAME_FRAGMENTS: &[&str] = &["password", "secret", "token", "private-key"];
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to read config file"))]
+    ReadConfigFile { source: FsError },
+
+    #[snafu(display("failed to parse config file {path:?}"))]
+    ParseConfigFile {
+        source: serde_yaml::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display(
+        "config file {path:?} sets {key:?}, which must be a single string, number, or boolean"
+    ))]
+    NonScalarValue { key: String, path: PathBuf },
+
+    #[snafu(display("config file {path:?} sets unknown option {key:?}"))]
+    UnknownOption { key: String, path: PathBuf },
+
+    #[snafu(display("config file {path:?} sets an invalid value for {key:?}"))]
+    InvalidValue {
+        source: clap::Error,
+        key: String,
+        path: PathBuf,
+    },
+}
+
+/// Finds the configuration file requested by `args` or `env` (the value of [`CONFIG_ENV`]).
+///
+/// This only looks for `--config`, so that the rest of the command line can be parsed once the file's values are known.
+pub fn find_config_path(args: &[OsString], env: Option<OsString>) -> Option<PathBuf> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == CONFIG_FLAG {
+            return args.next().map(PathBuf::from);
+        } else if let Some(path) = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix(CONFIG_FLAG)?.strip_prefix('='))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+    env.map(PathBuf::from)
+}
+
+/// The values set by a configuration file.
+#[derive(Debug)]
+pub struct ConfigFile {
+    path: PathBuf,
+    /// Flag name -> value
+    values: BTreeMap<String, String>,
+}
+
+impl ConfigFile {
+    pub async fn load(path: PathBuf) -> Result<Self, Error> {
+        let contents = fs::read_to_string(&path)
+            .await
+            .context(ReadConfigFileSnafu)?;
+        Self::parse(path, &contents)
+    }
+
+    fn parse(path: PathBuf, contents: &str) -> Result<Self, Error> {
+        let raw_values =
+            serde_yaml::from_str::<Option<BTreeMap<String, serde_yaml::Value>>>(contents)
+                .context(ParseConfigFileSnafu { path: &path })?
+                .unwrap_or_default();
+        let values = raw_values
+            .into_iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    serde_yaml::Value::String(value) => value,
+                    serde_yaml::Value::Bool(value) => value.to_string(),
+                    serde_yaml::Value::Number(value) => value.to_string(),
+                    _ => return NonScalarValueSnafu { key, path: &path }.fail(),
+                };
+                Ok((key, value))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { path, values })
+    }
+
+    /// Uses the file's values as the defaults of the [`RUN_SUBCOMMAND`] in `cmd`.
+    ///
+    /// Options whose environment variable is set (as looked up by `env`, usually [`std::env::var_os`]) keep the
+    /// environment's value instead.
+    /// Fails if the file sets an option that does not exist, or if any value is invalid for its option.
+    pub fn apply(
+        &self,
+        cmd: Command,
+        env: impl Fn(&OsStr) -> Option<OsString>,
+    ) -> Result<Command, Error> {
+        let run = cmd
+            .find_subcommand(RUN_SUBCOMMAND)
+            .expect("run subcommand must exist");
+        let mut ids = Vec::new();
+        for (key, value) in &self.values {
+            let arg = run
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()))
+                .context(UnknownOptionSnafu {
+                    key,
+                    path: &self.path,
+                })?;
+            ensure!(
+                arg.get_id() != "config",
+                UnknownOptionSnafu {
+                    key,
+                    path: &self.path
+                }
+            );
+            // Parse the value in isolation, so that errors can be attributed to the file rather than the flag
+            Command::new("config")
+                .no_binary_name(true)
+                .arg(
+                    arg.clone()
+                        .env(None)
+                        .required(false)
+                        .default_value(value.clone()),
+                )
+                .try_get_matches_from(Vec::<OsString>::new())
+                .context(InvalidValueSnafu {
+                    key,
+                    path: &self.path,
+                })?;
+            ids.push((arg.get_id().clone(), OsString::from(value)));
+        }
+        // Environment variables become defaults as well, so that they don't depend on the process environment that
+        // clap would read. Later defaults replace earlier ones, so they take precedence over the file.
+        ids.extend(run.get_arguments().filter_map(|arg| {
+            let env_value = env(arg.get_env()?)?;
+            Some((arg.get_id().clone(), env_value))
+        }));
+        Ok(cmd.mut_subcommand(RUN_SUBCOMMAND, |mut run| {
+            for (id, value) in ids {
+                run = run.mut_arg(id, |arg| arg.required(false).default_value(value));
+            }
+            run
+        }))
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Renders the resolved options of `matches` as YAML, annotated with where each value came from.
+///
+/// `run` and `matches` are the [`RUN_SUBCOMMAND`]'s command and parsed arguments, and `env` must be the same lookup
+/// that was passed to [`ConfigFile::apply`]. Values of sensitive options are redacted.
+pub fn effective_config(
+    run: &Command,
+    matches: &ArgMatches,
+    file: Option<&ConfigFile>,
+    env: impl Fn(&OsStr) -> Option<OsString>,
+) -> String {
+    let mut out = String::new();
+    if let Some(file) = file {
+        writeln!(out, "# Config file: {:?}", file.path()).unwrap();
+    }
+    for arg in run.get_arguments() {
+        let (Some(long), id) = (arg.get_long(), arg.get_id().as_str()) else {
+            continue;
+        };
+        if ["help", "version", "config", "print_effective_config"].contains(&id) {
+            continue;
+        }
+        let Some(raw_values) = matches.get_raw(id) else {
+            writeln!(out, "{long}: null  # unset").unwrap();
+            continue;
+        };
+        let value = if SENSITIVE_NAME_FRAGMENTS
+            .iter()
+            .any(|fragment| long.contains(fragment))
+        {
+            REDACTED.to_string()
+        } else {
+            raw_values
+                .map(|value| value.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        // ConfigFile::apply turns set environment variables into defaults, so they can't be told apart by clap
+        let set_env_name = arg.get_env().filter(|env_name| env(env_name).is_some());
+        let source = match (matches.value_source(id), set_env_name) {
+            (Some(ValueSource::CommandLine), _) => format!("from --{long}"),
+            (Some(ValueSource::EnvVariable | ValueSource::DefaultValue), Some(env_name)) => {
+                format!("from environment variable {}", env_name.to_string_lossy())
+            }
+            (Some(ValueSource::EnvVariable), None) => "from environment".to_string(),
+            (Some(ValueSource::DefaultValue), None)
+                if file.is_some_and(|file| file.contains(long)) =>
+            {
+                "from config file".to_string()
+            }
+            _ => "default".to_string(),
+        };
+        writeln!(out, "{long}: {value:?}  # {source}").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{OsStr, OsString},
+        path::PathBuf,
+    };
+
+    use clap::{ArgMatches, Command, CommandFactory, FromArgMatches};
+
+    use super::{ConfigFile, Error, RUN_SUBCOMMAND, effective_config, find_config_path};
+
+    #[derive(clap::Parser)]
+    struct Opts {
+        #[clap(subcommand)]
+        cmd: Cmd,
+    }
+
+    #[derive(clap::Subcommand)]
+    enum Cmd {
+        Run(Run),
+    }
+
+    #[derive(clap::Parser)]
+    struct Run {
+        #[clap(long)]
+        node_name: String,
+
+        #[clap(long)]
+        privileged: bool,
+
+        #[clap(long, default_value_t = 0.5)]
+        wait_fraction: f64,
+
+        #[clap(long)]
+        admin_password: Option<String>,
+
+        #[clap(long, env = "SECRET_OPERATOR_CONFIG_TEST_DRIVER_NAME")]
+        driver_name: Option<String>,
+
+        #[clap(long)]
+        config: Option<PathBuf>,
+    }
+
+    fn file(contents: &str) -> ConfigFile {
+        ConfigFile::parse(PathBuf::from("/etc/config.yaml"), contents).unwrap()
+    }
+
+    /// An environment that only contains `vars`.
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&OsStr) -> Option<OsString> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(key, _)| name == *key)
+                .map(|(_, value)| value.into())
+        }
+    }
+
+    fn parse(file: &ConfigFile, args: &[&str]) -> (Run, ArgMatches) {
+        parse_with_env(file, &[], args)
+    }
+
+    fn parse_with_env(
+        file: &ConfigFile,
+        vars: &[(&str, &str)],
+        args: &[&str],
+    ) -> (Run, ArgMatches) {
+        let matches = file
+            .apply(Opts::command(), env(vars))
+            .unwrap()
+            .try_get_matches_from(["secret-operator", RUN_SUBCOMMAND].iter().chain(args))
+            .unwrap();
+        let Cmd::Run(run) = Opts::from_arg_matches(&matches).unwrap().cmd;
+        let run_matches = matches.subcommand_matches(RUN_SUBCOMMAND).unwrap().clone();
+        (run, run_matches)
+    }
+
+    #[test]
+    fn flags_override_file_and_file_overrides_defaults() {
+        let config = file("node-name: from-file\nwait-fraction: 0.25\nprivileged: true\n");
+        let (run, _) = parse(&config, &[]);
+        assert_eq!(run.node_name, "from-file");
+        assert_eq!(run.wait_fraction, 0.25);
+        assert!(run.privileged);
+
+        let (run, _) = parse(
+            &config,
+            &["--node-name", "from-flag", "--wait-fraction=0.75"],
+        );
+        assert_eq!(run.node_name, "from-flag");
+        assert_eq!(run.wait_fraction, 0.75);
+
+        let (run, _) = parse(&file("node-name: from-file"), &[]);
+        assert_eq!(run.wait_fraction, 0.5);
+        assert!(!run.privileged);
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let config = file("node-name: foo\ndriver-name: from-file");
+        let (run, _) = parse(&config, &[]);
+        assert_eq!(run.driver_name.as_deref(), Some("from-file"));
+
+        let vars = [("SECRET_OPERATOR_CONFIG_TEST_DRIVER_NAME", "from-env")];
+        let (run, matches) = parse_with_env(&config, &vars, &[]);
+        assert_eq!(run.driver_name.as_deref(), Some("from-env"));
+        let cmd = config.apply(Opts::command(), env(&vars)).unwrap();
+        let printed = effective_config(
+            cmd.find_subcommand(RUN_SUBCOMMAND).unwrap(),
+            &matches,
+            Some(&config),
+            env(&vars),
+        );
+        let expected = r#"driver-name: "from-env"  # from environment variable SECRET_OPERATOR_CONFIG_TEST_DRIVER_NAME"#;
+        assert!(printed.contains(expected), "{expected:?} not in {printed}");
+
+        let (run, _) = parse_with_env(&config, &vars, &["--driver-name", "from-flag"]);
+        assert_eq!(run.driver_name.as_deref(), Some("from-flag"));
+
+        // Also for options that the file doesn't set
+        let (run, _) = parse_with_env(&file(""), &vars, &["--node-name", "foo"]);
+        assert_eq!(run.driver_name.as_deref(), Some("from-env"));
+    }
+
+    #[test]
+    fn file_errors_name_the_key() {
+        let err = file("wait-fraction: lots")
+            .apply(Opts::command(), env(&[]))
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidValue { key, .. } if key == "wait-fraction"),
+            "{err:?}"
+        );
+        let err = file("wait-fractoin: 0.1")
+            .apply(Opts::command(), env(&[]))
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::UnknownOption { key, .. } if key == "wait-fractoin"),
+            "{err:?}"
+        );
+        let err =
+            ConfigFile::parse(PathBuf::from("/etc/config.yaml"), "node-name: [a, b]").unwrap_err();
+        assert!(
+            matches!(&err, Error::NonScalarValue { key, .. } if key == "node-name"),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn effective_config_redacts_sensitive_values() {
+        let config = file("node-name: from-file\nadmin-password: hunter2");
+        let (_, matches) = parse(&config, &["--privileged"]);
+        let cmd: Command = config.apply(Opts::command(), env(&[])).unwrap();
+        let printed = effective_config(
+            cmd.find_subcommand(RUN_SUBCOMMAND).unwrap(),
+            &matches,
+            Some(&config),
+            env(&[]),
+        );
+        assert!(!printed.contains("hunter2"), "{printed}");
+        for expected in [
+            r#"admin-password: "<redacted>"  # from config file"#,
+            r#"node-name: "from-file"  # from config file"#,
+            r#"privileged: "true"  # from --privileged"#,
+            r#"wait-fraction: "0.5"  # default"#,
+            "driver-name: null  # unset",
+        ] {
+            assert!(printed.contains(expected), "{expected:?} not in {printed}");
+        }
+    }
+
+    #[test]
+    fn config_path_is_found_before_parsing() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            find_config_path(&args(&["op", "run", "--config", "/a.yaml"]), None),
+            Some(PathBuf::from("/a.yaml"))
+        );
+        assert_eq!(
+            find_config_path(
+                &args(&["op", "run", "--config=/b.yaml"]),
+                Some("/c.yaml".into())
+            ),
+            Some(PathBuf::from("/b.yaml"))
+        );
+        assert_eq!(
+            find_config_path(&args(&["op", "run"]), Some("/c.yaml".into())),
+            Some(PathBuf::from("/c.yaml"))
+        );
+        assert_eq!(
+            find_config_path(&args(&["op", "run", "--", "--config", "/d.yaml"]), None),
+            None
+        );
+    }
+}
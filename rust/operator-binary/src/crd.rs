@@ -1,9 +1,10 @@
-use std::{fmt::Display, ops::Deref};
+use std::{collections::BTreeMap, fmt::Display, ops::Deref};
 
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use stackable_operator::{
     commons::networking::{HostName, KerberosRealmName},
+    k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement},
     kube::CustomResource,
     schemars::{self, JsonSchema, schema::Schema},
     time::Duration,
@@ -19,6 +20,7 @@ use crate::backend;
     group = "secrets.stackable.tech",
     version = "v1alpha1",
     kind = "SecretClass",
+    status = "SecretClassStatus",
     crates(
         kube_core = "stackable_operator::kube::core",
         k8s_openapi = "stackable_operator::k8s_openapi",
@@ -31,6 +33,100 @@ pub struct SecretClassSpec {
     /// [backend](DOCS_BASE_URL_PLACEHOLDER/secret-operator/secretclass#backend),
     /// which dictates the mechanism for issuing that kind of Secret.
     pub backend: SecretClassBackend,
+
+    /// Restricts which namespaces may use this SecretClass.
+    ///
+    /// If not set, all namespaces are allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_namespaces: Option<AllowedNamespaces>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretClassStatus {
+    /// How far each node has come with refreshing its volumes for the last forced rotation (requested by the
+    /// `secrets.stackable.tech/force-rotation` annotation), by node name.
+    #[serde(default)]
+    pub rotation_progress: BTreeMap<String, NodeRotationProgress>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeRotationProgress {
+    /// The value of the `secrets.stackable.tech/force-rotation` annotation that the volumes are being refreshed for.
+    pub rotation: String,
+
+    /// How many volumes of the SecretClass are published on the node.
+    pub total: u32,
+
+    /// How many of the volumes have been refreshed for `rotation`.
+    pub refreshed: u32,
+
+    /// How many of the volumes failed to be refreshed, they are retried later.
+    pub failed: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowedNamespaces {
+    /// Namespaces with any of these names are allowed.
+    #[serde(default)]
+    pub names: Vec<String>,
+
+    /// Namespaces with labels matching this selector are allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<LabelSelector>,
+}
+
+impl AllowedNamespaces {
+    /// Whether the namespace called `name` with the labels `labels` may use the SecretClass.
+    ///
+    /// A policy without any names or selector allows all namespaces.
+    pub fn allows(&self, name: &str, labels: &BTreeMap<String, String>) -> bool {
+        if self.names.is_empty() && self.selector.is_none() {
+            return true;
+        }
+        self.names.iter().any(|allowed| allowed == name)
+            || self
+                .selector
+                .as_ref()
+                .is_some_and(|selector| label_selector_matches(selector, labels))
+    }
+
+    /// Whether evaluating the policy requires the namespace's labels.
+    pub fn needs_labels(&self) -> bool {
+        self.selector.is_some()
+    }
+}
+
+/// Evaluates a Kubernetes [`LabelSelector`] against a set of labels.
+///
+/// Requirements with unknown operators never match.
+fn label_selector_matches(selector: &LabelSelector, labels: &BTreeMap<String, String>) -> bool {
+    let labels_match = selector
+        .match_labels
+        .iter()
+        .flatten()
+        .all(|(key, value)| labels.get(key) == Some(value));
+    let expressions_match = selector.match_expressions.iter().flatten().all(
+        |LabelSelectorRequirement {
+             key,
+             operator,
+             values,
+         }| {
+            let label = labels.get(key);
+            let in_values =
+                || label.is_some_and(|label| values.iter().flatten().any(|value| value == label));
+            match operator.as_str() {
+                "In" => in_values(),
+                "NotIn" => !in_values(),
+                "Exists" => label.is_some(),
+                "DoesNotExist" => label.is_none(),
+                _ => false,
+            }
+        },
+    );
+    labels_match && expressions_match
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -278,6 +374,70 @@ pub struct KerberosKeytabBackend {
 
     /// The admin principal.
     pub admin_principal: KerberosPrincipal,
+
+    /// Where the host name of node-scoped principals (such as `HTTP/<node>@REALM`) is taken from.
+    ///
+    /// - `kubeletNodeName` uses the name of the Node object, as reported by the kubelet.
+    /// - `nodeAddressFqdn` uses the name that the node's `InternalIP` address resolves to in DNS.
+    ///   The name must also resolve back to the same address.
+    /// - `nodeLabel:<label-key>` uses the value of the label `<label-key>` on the Node object.
+    ///
+    /// Defaults to `kubeletNodeName`.
+    #[serde(default)]
+    #[schemars(with = "String")]
+    pub node_name_source: KerberosNodeNameSource,
+
+    /// Additional relations to add to the generated `krb5.conf`, such as `appdefaults` or plugin settings.
+    ///
+    /// Relations that the operator sets itself (such as the realm's `kdc`) are rejected, unless `allowOverride` is set.
+    #[serde(default)]
+    pub extra_config: Vec<KerberosExtraConfig>,
+
+    /// Length limits for names that are derived from Pods and Services.
+    ///
+    /// Names that exceed their limit are truncated and suffixed with a hash of the full name, and the original names
+    /// are listed in the volume's `shortened-names.json`. Names are not shortened by default.
+    #[serde(default)]
+    pub name_length_limits: KerberosNameLengthLimits,
+}
+
+/// The maximum lengths of names that are derived for provisioned principals.
+///
+/// Every limit must be at least `8`, to leave space for the hash.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KerberosNameLengthLimits {
+    /// The maximum length of the host name component of principals (such as `my-pod.my-service` in
+    /// `HTTP/my-pod.my-service@REALM`), for example `63` if the principal must also be a valid DNS label.
+    pub principal_component: Option<u16>,
+
+    /// The maximum length of the service name component of principals, for workloads that use it as a local username.
+    pub local_username: Option<u16>,
+
+    /// Derive the `sAMAccountName` of Active Directory users from their principal, shortened to at most this length
+    /// (which may be at most `20`).
+    ///
+    /// Only valid for the `activeDirectory` admin backend, and cannot be combined with
+    /// `experimentalGenerateSamAccountName`.
+    pub sam_account_name: Option<u8>,
+}
+
+/// A relation to add to the generated `krb5.conf`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KerberosExtraConfig {
+    /// The path to the relation, starting with the section name and ending with the relation name.
+    /// For example, `[appdefaults, pam, forwardable]` sets `forwardable` in the `pam` subsection of `[appdefaults]`.
+    pub path: Vec<String>,
+
+    /// The values of the relation. Relations with multiple values are written once for each value.
+    pub values: Vec<String>,
+
+    /// Whether to replace the values that the operator sets for the relation, if any.
+    ///
+    /// Defaults to `false`, which rejects relations that the operator already sets.
+    #[serde(default)]
+    pub allow_override: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -395,6 +555,55 @@ impl Deref for KerberosPrincipal {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum KerberosNodeNameSource {
+    #[default]
+    KubeletNodeName,
+    NodeAddressFqdn,
+    NodeLabel(String),
+}
+#[derive(Debug, Snafu)]
+#[snafu(display(
+    "invalid node name source {value:?} (expected kubeletNodeName, nodeAddressFqdn, or nodeLabel:<label-key>)"
+))]
+pub struct InvalidKerberosNodeNameSource {
+    value: String,
+}
+impl KerberosNodeNameSource {
+    const KUBELET_NODE_NAME: &'static str = "kubeletNodeName";
+    const NODE_ADDRESS_FQDN: &'static str = "nodeAddressFqdn";
+    const NODE_LABEL_PREFIX: &'static str = "nodeLabel:";
+}
+impl TryFrom<String> for KerberosNodeNameSource {
+    type Error = InvalidKerberosNodeNameSource;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            Self::KUBELET_NODE_NAME => Ok(Self::KubeletNodeName),
+            Self::NODE_ADDRESS_FQDN => Ok(Self::NodeAddressFqdn),
+            _ => match value.strip_prefix(Self::NODE_LABEL_PREFIX) {
+                Some(label) if !label.is_empty() => Ok(Self::NodeLabel(label.to_string())),
+                _ => InvalidKerberosNodeNameSourceSnafu { value }.fail(),
+            },
+        }
+    }
+}
+impl From<KerberosNodeNameSource> for String {
+    fn from(value: KerberosNodeNameSource) -> Self {
+        value.to_string()
+    }
+}
+impl Display for KerberosNodeNameSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KubeletNodeName => f.write_str(Self::KUBELET_NODE_NAME),
+            Self::NodeAddressFqdn => f.write_str(Self::NODE_ADDRESS_FQDN),
+            Self::NodeLabel(label) => write!(f, "{}{label}", Self::NODE_LABEL_PREFIX),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -439,7 +648,8 @@ mod test {
                 },
                 additional_trust_roots: vec![],
                 max_certificate_lifetime: DEFAULT_MAX_CERT_LIFETIME,
-            })
+            }),
+            allowed_namespaces: None,
         });
 
         let input: &str = r#"
@@ -490,7 +700,132 @@ mod test {
                     })
                 ],
                 max_certificate_lifetime: Duration::from_days_unchecked(31),
-            })
+            }),
+            allowed_namespaces: None,
         });
     }
+
+    fn parse_allowed_namespaces(input: &str) -> AllowedNamespaces {
+        let input = format!(
+            r#"
+        apiVersion: secrets.stackable.tech/v1alpha1
+        kind: SecretClass
+        metadata:
+          name: kerberos
+        spec:
+          backend:
+            k8sSearch:
+              searchNamespace:
+                pod: {{}}
+          allowedNamespaces:
+{input}
+        "#
+        );
+        let deserializer = serde_yaml::Deserializer::from_str(&input);
+        let secret_class: SecretClass =
+            serde_yaml::with::singleton_map_recursive::deserialize(deserializer).unwrap();
+        secret_class.spec.allowed_namespaces.unwrap()
+    }
+
+    fn labels<const N: usize>(labels: [(&str, &str); N]) -> BTreeMap<String, String> {
+        labels
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn allowed_namespaces_empty_allows_all() {
+        let policy = parse_allowed_namespaces("            names: []");
+        assert!(policy.allows("dev", &labels([])));
+    }
+
+    #[test]
+    fn allowed_namespaces_by_name() {
+        let policy = parse_allowed_namespaces(
+            r#"
+            names:
+              - kerberos-admin
+              - prod"#,
+        );
+        assert!(!policy.needs_labels());
+        assert!(policy.allows("prod", &labels([])));
+        assert!(policy.allows("kerberos-admin", &labels([])));
+        assert!(!policy.allows("dev", &labels([("env", "prod")])));
+    }
+
+    #[test]
+    fn allowed_namespaces_by_selector() {
+        let policy = parse_allowed_namespaces(
+            r#"
+            names:
+              - kerberos-admin
+            selector:
+              matchLabels:
+                env: prod
+              matchExpressions:
+                - key: team
+                  operator: In
+                  values: [data, platform]
+                - key: quarantined
+                  operator: DoesNotExist"#,
+        );
+        assert!(policy.needs_labels());
+        let prod_data = labels([("env", "prod"), ("team", "data")]);
+        assert!(policy.allows("analytics", &prod_data));
+        assert!(policy.allows("kerberos-admin", &labels([])));
+        assert!(!policy.allows("analytics", &labels([("env", "dev"), ("team", "data")])));
+        assert!(!policy.allows("analytics", &labels([("env", "prod"), ("team", "web")])));
+        assert!(!policy.allows("analytics", &labels([("env", "prod")])));
+    }
+
+    #[test]
+    fn allowed_namespaces_follows_label_changes() {
+        let policy = parse_allowed_namespaces(
+            r#"
+            selector:
+              matchExpressions:
+                - key: env
+                  operator: NotIn
+                  values: [dev]"#,
+        );
+        let mut namespace_labels = labels([("env", "prod")]);
+        assert!(policy.allows("analytics", &namespace_labels));
+        namespace_labels.insert("env".to_string(), "dev".to_string());
+        assert!(!policy.allows("analytics", &namespace_labels));
+        namespace_labels.remove("env");
+        assert!(policy.allows("analytics", &namespace_labels));
+    }
+
+    #[test]
+    fn allowed_namespaces_rejects_unknown_operators() {
+        let policy = parse_allowed_namespaces(
+            r#"
+            selector:
+              matchExpressions:
+                - key: env
+                  operator: Gt
+                  values: ["1"]"#,
+        );
+        assert!(!policy.allows("analytics", &labels([("env", "2")])));
+    }
+
+    #[test]
+    fn kerberos_node_name_source_round_trips() {
+        for (input, expected) in [
+            ("kubeletNodeName", KerberosNodeNameSource::KubeletNodeName),
+            ("nodeAddressFqdn", KerberosNodeNameSource::NodeAddressFqdn),
+            (
+                "nodeLabel:example.com/fqdn",
+                KerberosNodeNameSource::NodeLabel("example.com/fqdn".to_string()),
+            ),
+        ] {
+            let source = KerberosNodeNameSource::try_from(input.to_string()).unwrap();
+            assert_eq!(source, expected);
+            assert_eq!(source.to_string(), input);
+        }
+        for invalid in ["", "nodeLabel:", "nodeName", "nodelabel:foo"] {
+            KerberosNodeNameSource::try_from(invalid.to_string()).unwrap_err();
+        }
+    }
 }
@@ -12,7 +12,8 @@ use uuid::Uuid;
 use crate::{
     backend::{
         self, InternalSecretVolumeSelectorParams, SecretBackendError, SecretVolumeSelector,
-        pod_info::{self, SchedulingPodInfo},
+        coordination::LeasePool,
+        pod_info::{self, DependencyWait, SchedulingPodInfo},
     },
     grpc::csi::{
         self,
@@ -79,7 +80,7 @@ impl From<CreateVolumeError> for Status {
             CreateVolumeError::FindPvc { .. } => Status::unavailable(full_msg),
             CreateVolumeError::ResolveOwnerPod { .. } => Status::failed_precondition(full_msg),
             CreateVolumeError::GetPod { .. } => Status::unavailable(full_msg),
-            CreateVolumeError::ParsePod { .. } => Status::failed_precondition(full_msg),
+            CreateVolumeError::ParsePod { source } => Status::new(source.grpc_code(), full_msg),
             CreateVolumeError::InvalidSecretSelector { .. } => {
                 Status::failed_precondition(full_msg)
             }
@@ -92,6 +93,7 @@ impl From<CreateVolumeError> for Status {
 
 pub struct SecretProvisionerController {
     pub client: stackable_operator::client::Client,
+    pub leases: LeasePool,
 }
 
 impl SecretProvisionerController {
@@ -194,11 +196,17 @@ impl Controller for SecretProvisionerController {
             .get::<Pod>(&selector.pod, &selector.namespace)
             .await
             .context(GetPodSnafu)?;
-        let pod_info = SchedulingPodInfo::from_pod(&self.client, &pod, &selector.scope)
-            .await
-            .context(ParsePodSnafu)?;
-
-        let backend = backend::dynamic::from_selector(&self.client, &selector)
+        // Only NodePublishVolume waits for missing dependencies, the external-provisioner will retry us anyway
+        let pod_info = SchedulingPodInfo::from_pod(
+            &self.client,
+            &pod,
+            &selector.scope,
+            DependencyWait::none(),
+        )
+        .await
+        .context(ParsePodSnafu)?;
+
+        let backend = backend::dynamic::from_selector(&self.client, &selector, &self.leases)
             .await
             .context(create_volume_error::InitBackendSnafu)?;
         let accessible_topology = match backend
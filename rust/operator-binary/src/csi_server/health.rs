@@ -0,0 +1,106 @@
+//! Serves the health of the plugin over the standard [gRPC health checking protocol][grpc-health], so that generic
+//! gRPC tooling can monitor it in addition to the CSI `Probe` call.
+//!
+//! [grpc-health]: https://github.com/grpc/grpc/blob/master/doc/health-checking.md
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use tonic_health::{
+    ServingStatus,
+    pb::health_server::{Health, HealthServer},
+    server::HealthReporter,
+};
+
+/// Tracks whether the plugin is able to serve requests.
+///
+/// The status is published through the [`HealthServer`] returned by [`PluginHealth::new`] (as the overall server
+/// status, the empty service name), and answers the CSI `Probe` call.
+#[derive(Clone)]
+pub struct PluginHealth {
+    reporter: HealthReporter,
+    // HealthReporter can only be written to, so keep a copy around for Probe
+    serving: Arc<AtomicBool>,
+}
+
+impl PluginHealth {
+    /// Creates a healthy plugin status, along with the gRPC service that serves it.
+    pub fn new() -> (Self, HealthServer<impl Health>) {
+        let (reporter, server) = tonic_health::server::health_reporter();
+        let health = Self {
+            reporter,
+            serving: Arc::new(AtomicBool::new(true)),
+        };
+        (health, server)
+    }
+
+    /// Whether the plugin is currently able to serve requests.
+    pub fn is_serving(&self) -> bool {
+        self.serving.load(Ordering::Relaxed)
+    }
+
+    /// Updates whether the plugin is able to serve requests, notifying any health watchers.
+    pub async fn set_serving(&self, serving: bool) {
+        self.serving.store(serving, Ordering::Relaxed);
+        let status = if serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        // Clones of a HealthReporter share the same statuses
+        self.reporter.clone().set_service_status("", status).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Channel, Endpoint, Server};
+    use tonic_health::pb::{
+        HealthCheckRequest, health_check_response::ServingStatus, health_client::HealthClient,
+    };
+
+    use super::PluginHealth;
+
+    async fn check(client: &mut HealthClient<Channel>) -> ServingStatus {
+        client
+            .check(HealthCheckRequest {
+                service: String::new(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn health_service_should_follow_plugin_status() {
+        let (health, health_server) = PluginHealth::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(health_server)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = Endpoint::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+
+        assert!(health.is_serving());
+        assert_eq!(check(&mut client).await, ServingStatus::Serving);
+
+        health.set_serving(false).await;
+        assert!(!health.is_serving());
+        assert_eq!(check(&mut client).await, ServingStatus::NotServing);
+
+        health.set_serving(true).await;
+        assert_eq!(check(&mut client).await, ServingStatus::Serving);
+    }
+}
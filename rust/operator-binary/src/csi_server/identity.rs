@@ -3,13 +3,16 @@ use std::collections::HashMap;
 use clap::crate_version;
 use tonic::{Request, Response, Status};
 
+use super::health::PluginHealth;
 use crate::grpc::csi::v1::{
     GetPluginCapabilitiesRequest, GetPluginCapabilitiesResponse, GetPluginInfoRequest,
     GetPluginInfoResponse, PluginCapability, ProbeRequest, ProbeResponse,
     identity_server::Identity, plugin_capability,
 };
 
-pub struct SecretProvisionerIdentity;
+pub struct SecretProvisionerIdentity {
+    pub health: PluginHealth,
+}
 
 // The identity services are mandatory to implement, we deliver some minimal responses here
 // https://github.com/container-storage-interface/spec/blob/master/spec.md#rpc-interface
@@ -58,6 +61,8 @@ impl Identity for SecretProvisionerIdentity {
         &self,
         _request: Request<ProbeRequest>,
     ) -> Result<Response<ProbeResponse>, Status> {
-        Ok(Response::new(ProbeResponse { ready: Some(true) }))
+        Ok(Response::new(ProbeResponse {
+            ready: Some(self.health.is_serving()),
+        }))
     }
 }
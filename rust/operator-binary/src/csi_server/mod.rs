@@ -1,3 +1,5 @@
 pub mod controller;
+pub mod health;
 pub mod identity;
 pub mod node;
+pub mod rotation;
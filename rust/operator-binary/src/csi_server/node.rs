@@ -1,49 +1,64 @@
 use std::{
-    fs::Permissions,
-    os::unix::prelude::PermissionsExt,
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    io::ErrorKind,
+    num::ParseIntError,
     path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
 };
 
 use openssl::sha::Sha256;
-use serde::{Deserialize, de::IntoDeserializer};
-use snafu::{ResultExt, Snafu, ensure};
+use serde::{Deserialize, Serialize, de::IntoDeserializer};
+use snafu::{OptionExt, ResultExt, Snafu, ensure};
 use stackable_operator::{
     builder::meta::ObjectMetaBuilder,
-    k8s_openapi::api::core::v1::Pod,
-    kube::runtime::reflector::ObjectRef,
+    k8s_openapi::{
+        api::core::v1::{ObjectReference, Pod},
+        chrono::{DateTime, FixedOffset},
+    },
+    kube::runtime::{
+        events::{Event, EventType, Recorder, Reporter},
+        reflector::ObjectRef,
+    },
     kvp::{AnnotationError, Annotations},
 };
-use sys_mount::{Mount, MountFlags, UnmountFlags, unmount};
-use tokio::{
-    fs::{OpenOptions, create_dir_all},
-    io::AsyncWriteExt,
-};
-use tonic::{Request, Response, Status};
+use stackable_secret_operator_crd_utils::ownership;
+use tokio::task::AbortHandle;
+use tonic::{Request, Response, Status, metadata::MetadataMap};
 
 use super::controller::TOPOLOGY_NODE;
+#[cfg(feature = "failpoints")]
+use crate::utils::failpoint;
 use crate::{
     backend::{
         self, SecretBackendError, SecretContents, SecretVolumeSelector,
-        pod_info::{self, PodInfo},
-    },
-    format::{
-        self, SecretFormat,
-        well_known::{CompatibilityOptions, NamingOptions},
+        coordination::LeasePool,
+        csr::{CsrError, CsrRequest, SignedCsr},
+        dynamic::{DynError, Dynamic},
+        pod_info::{self, DependencyWait, PodInfo},
     },
+    format::{self, SecretFormat},
     grpc::csi::v1::{
         NodeExpandVolumeRequest, NodeExpandVolumeResponse, NodeGetCapabilitiesRequest,
         NodeGetCapabilitiesResponse, NodeGetInfoRequest, NodeGetInfoResponse,
         NodeGetVolumeStatsRequest, NodeGetVolumeStatsResponse, NodePublishVolumeRequest,
-        NodePublishVolumeResponse, NodeStageVolumeRequest, NodeStageVolumeResponse,
-        NodeUnpublishVolumeRequest, NodeUnpublishVolumeResponse, NodeUnstageVolumeRequest,
-        NodeUnstageVolumeResponse, Topology, node_server::Node,
+        NodePublishVolumeResponse, NodeServiceCapability, NodeStageVolumeRequest,
+        NodeStageVolumeResponse, NodeUnpublishVolumeRequest, NodeUnpublishVolumeResponse,
+        NodeUnstageVolumeRequest, NodeUnstageVolumeResponse, Topology, VolumeCapability,
+        VolumeCondition, VolumeUsage, node_server::Node, node_service_capability,
+        volume_capability, volume_usage,
+    },
+    identity_api::{self, IssuedIdentities, VolumeIdentity},
+    utils::{
+        FmtByteSlice, error_full_message,
+        fs::{self, FsError},
     },
-    utils::{FmtByteSlice, error_full_message},
 };
 
 #[derive(Snafu, Debug)]
 #[snafu(module)]
-enum PublishError {
+pub(super) enum PublishError {
     #[snafu(display("failed to parse selector from volume context"))]
     InvalidSelector { source: serde::de::value::Error },
 
@@ -63,45 +78,24 @@ enum PublishError {
     #[snafu(display("backend failed to get secret data"))]
     BackendGetSecretData { source: backend::dynamic::DynError },
 
-    #[snafu(display("failed to create secret parent dir {path:?}"))]
-    CreateDir {
-        source: std::io::Error,
-        path: PathBuf,
-    },
-
-    #[snafu(display("failed to mount volume mount directory {path:?}"))]
-    Mount {
-        source: std::io::Error,
-        path: PathBuf,
-    },
+    #[snafu(transparent)]
+    Fs { source: FsError },
 
     #[snafu(display("failed to convert secret data into desired format"))]
     FormatData { source: format::IntoFilesError },
 
-    #[snafu(display("failed to set volume permissions for {path:?}"))]
-    SetDirPermissions {
-        source: std::io::Error,
-        path: PathBuf,
-    },
-
-    #[snafu(display("failed to create secret file {path:?}"))]
-    CreateFile {
-        source: std::io::Error,
-        path: PathBuf,
-    },
-
-    #[snafu(display("failed to write secret file {path:?}"))]
-    WriteFile {
-        source: std::io::Error,
-        path: PathBuf,
-    },
-
     #[snafu(display("file path {path:?} must only contain normal components"))]
     InvalidComponents { path: PathBuf },
 
     #[snafu(display("file path {path:?} must not be absolute"))]
     InvalidAbsolutePath { path: PathBuf },
 
+    #[snafu(display(
+        "file path {path:?} must not be inside of the reserved directory {:?}",
+        fs::StagingDir::NAME
+    ))]
+    ReservedFileName { path: PathBuf },
+
     #[snafu(display("failed to tag pod with expiry metadata"))]
     TagPod {
         source: stackable_operator::client::Error,
@@ -109,6 +103,57 @@ enum PublishError {
 
     #[snafu(display("failed to build annotation"))]
     BuildAnnotation { source: AnnotationError },
+
+    #[snafu(display(
+        "volume was already published for a different selector (fingerprint {previous}, requested {requested}), the Pod must be recreated to apply the change (or set secrets.stackable.tech/allow-selector-change to replace the volume's contents)"
+    ))]
+    SelectorChanged { previous: String, requested: String },
+
+    #[snafu(display(
+        "failed to clean up volume before reprovisioning it for the changed selector"
+    ))]
+    CleanChangedVolume { source: UnpublishError },
+
+    #[snafu(display("the CSR handshake can only be used with the tls-pem format, not {format:?}"))]
+    CsrHandshakeRequiresTlsPem { format: SecretFormat },
+
+    #[snafu(display("backend failed to describe the CSR that the Pod may request"))]
+    BackendGetCsrRequest { source: backend::dynamic::DynError },
+
+    #[snafu(display("backend does not support the CSR handshake"))]
+    CsrHandshakeUnsupported,
+
+    #[snafu(display("failed to serialize CSR request"))]
+    SerializeCsrRequest { source: serde_json::Error },
+
+    #[snafu(display("failed to serialize pending CSR handshake"))]
+    SerializePendingCsrHandshake { source: serde_json::Error },
+
+    #[snafu(display("failed to serialize volume manifest"))]
+    SerializeVolumeManifest { source: serde_json::Error },
+
+    #[snafu(display("failed to serialize refreshable volume"))]
+    SerializeRefreshableVolume { source: serde_json::Error },
+
+    #[snafu(display("failed to read refreshable volume"))]
+    ReadRefreshableVolume { source: ReadRefreshableVolumeError },
+
+    #[snafu(display("failed to check volume layout"))]
+    VolumeLayout { source: VolumeLayoutError },
+
+    #[snafu(display("volume mount group {group:?} must be a numeric group ID"))]
+    InvalidVolumeMountGroup {
+        source: ParseIntError,
+        group: String,
+    },
+
+    #[snafu(display("failed to record issued identity"))]
+    RecordIdentity { source: identity_api::RecordError },
+
+    #[snafu(display(
+        "not allowed to give the secret files to group {group}, secret-operator must run as root to use groups that it is not a member of"
+    ))]
+    ChangeGroupDenied { source: FsError, group: u32 },
 }
 
 // Useful since all service calls return a [Result<tonic::Response<T>, tonic::Status>]
@@ -119,39 +164,56 @@ impl From<PublishError> for Status {
         match err {
             PublishError::InvalidSelector { .. } => Status::invalid_argument(full_msg),
             PublishError::GetPod { .. } => Status::failed_precondition(full_msg),
-            PublishError::ParsePod { .. } => Status::failed_precondition(full_msg),
+            PublishError::ParsePod { source } => Status::new(source.grpc_code(), full_msg),
             PublishError::InitBackend { source } => Status::new(source.grpc_code(), full_msg),
             PublishError::BackendGetSecretData { source } => {
                 Status::new(source.grpc_code(), full_msg)
             }
-            PublishError::CreateDir { .. } => Status::unavailable(full_msg),
-            PublishError::Mount { .. } => Status::unavailable(full_msg),
-            PublishError::FormatData { .. } => Status::unavailable(full_msg),
-            PublishError::SetDirPermissions { .. } => Status::unavailable(full_msg),
-            PublishError::CreateFile { .. } => Status::unavailable(full_msg),
-            PublishError::WriteFile { .. } => Status::unavailable(full_msg),
+            PublishError::Fs { .. } => Status::unavailable(full_msg),
+            PublishError::FormatData { source } => Status::new(source.grpc_code(), full_msg),
             PublishError::InvalidComponents { .. } => Status::unavailable(full_msg),
             PublishError::InvalidAbsolutePath { .. } => Status::unavailable(full_msg),
+            PublishError::ReservedFileName { .. } => Status::unavailable(full_msg),
             PublishError::TagPod { .. } => Status::unavailable(full_msg),
             PublishError::BuildAnnotation { .. } => Status::unavailable(full_msg),
+            PublishError::SelectorChanged { .. } => Status::failed_precondition(full_msg),
+            PublishError::CleanChangedVolume { .. } => Status::unavailable(full_msg),
+            PublishError::CsrHandshakeRequiresTlsPem { .. } => Status::invalid_argument(full_msg),
+            PublishError::BackendGetCsrRequest { source } => {
+                Status::new(source.grpc_code(), full_msg)
+            }
+            PublishError::CsrHandshakeUnsupported => Status::invalid_argument(full_msg),
+            PublishError::SerializeCsrRequest { .. } => Status::internal(full_msg),
+            PublishError::SerializePendingCsrHandshake { .. } => Status::internal(full_msg),
+            PublishError::SerializeVolumeManifest { .. } => Status::internal(full_msg),
+            PublishError::SerializeRefreshableVolume { .. } => Status::internal(full_msg),
+            PublishError::ReadRefreshableVolume { .. } => Status::unavailable(full_msg),
+            PublishError::VolumeLayout { source } => Status::new(source.grpc_code(), full_msg),
+            PublishError::InvalidVolumeMountGroup { .. } => Status::invalid_argument(full_msg),
+            PublishError::RecordIdentity { .. } => Status::unavailable(full_msg),
+            PublishError::ChangeGroupDenied { .. } => Status::failed_precondition(full_msg),
         }
     }
 }
 
 #[derive(Snafu, Debug)]
 #[snafu(module)]
-enum UnpublishError {
-    #[snafu(display("failed to unmount volume mount directory {}", path.display()))]
-    Unmount {
-        source: std::io::Error,
-        path: PathBuf,
-    },
+pub(super) enum UnpublishError {
+    #[snafu(transparent)]
+    Fs { source: FsError },
 
-    #[snafu(display("failed to delete volume mount directory {}", path.display()))]
-    Delete {
-        source: std::io::Error,
-        path: PathBuf,
-    },
+    #[snafu(display(
+        "refusing to delete volume path {path:?}, since it is not inside the volume root {volume_root:?}"
+    ))]
+    OutsideVolumeRoot { path: PathBuf, volume_root: PathBuf },
+
+    #[snafu(display(
+        "volume path {path:?} is a mountpoint, which must be unmounted first, but unmounting requires privileged mode"
+    ))]
+    UnmountUnprivileged { path: PathBuf },
+
+    #[snafu(display("failed to check volume layout"))]
+    VolumeLayout { source: VolumeLayoutError },
 }
 
 // Useful since all service calls return a [Result<tonic::Response<T>, tonic::Status>]
@@ -160,215 +222,435 @@ impl From<UnpublishError> for Status {
         let full_msg = error_full_message(&err);
         // Convert to an appropriate tonic::Status representation and include full error message
         match err {
-            UnpublishError::Unmount { .. } => Status::unavailable(full_msg),
-            UnpublishError::Delete { .. } => Status::unavailable(full_msg),
+            UnpublishError::Fs { .. } => Status::unavailable(full_msg),
+            UnpublishError::OutsideVolumeRoot { .. } => Status::invalid_argument(full_msg),
+            UnpublishError::UnmountUnprivileged { .. } => Status::failed_precondition(full_msg),
+            UnpublishError::VolumeLayout { source } => Status::new(source.grpc_code(), full_msg),
+        }
+    }
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum VolumeStatsError {
+    #[snafu(display("volume path must not be empty"))]
+    EmptyVolumePath,
+
+    #[snafu(transparent)]
+    Fs { source: FsError },
+}
+
+impl From<VolumeStatsError> for Status {
+    fn from(err: VolumeStatsError) -> Self {
+        let full_msg = error_full_message(&err);
+        match err {
+            VolumeStatsError::EmptyVolumePath => Status::invalid_argument(full_msg),
+            VolumeStatsError::Fs { source } if source.kind() == ErrorKind::NotFound => {
+                Status::not_found(full_msg)
+            }
+            VolumeStatsError::Fs { .. } => Status::unavailable(full_msg),
+        }
+    }
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum CsrHandshakeError {
+    #[snafu(display(
+        "timed out after {timeout:?} waiting for the Pod to write its CSR to {CSR_FILE_NAME}"
+    ))]
+    Timeout { timeout: Duration },
+
+    #[snafu(display("rejected the CSR written by the Pod"))]
+    InvalidCsr { source: CsrError },
+
+    #[snafu(display("backend failed to sign CSR"))]
+    Sign { source: DynError },
+
+    #[snafu(display("backend does not support signing CSRs"))]
+    SigningUnsupported,
+
+    #[snafu(display("failed to save signed certificate"))]
+    SaveCertificate { source: PublishError },
+
+    #[snafu(display("failed to tag pod with expiry metadata"))]
+    TagPod { source: PublishError },
+
+    #[snafu(display("failed to record issued identity"))]
+    RecordIdentity { source: identity_api::RecordError },
+
+    #[snafu(transparent)]
+    Fs { source: FsError },
+}
+
+impl CsrHandshakeError {
+    /// The reason of the `Event` that reports the error to the `Pod`'s owner.
+    fn event_reason(&self) -> &'static str {
+        match self {
+            CsrHandshakeError::Timeout { .. } => "CsrTimeout",
+            CsrHandshakeError::InvalidCsr { source } if source.is_escalation() => {
+                "CsrNameEscalation"
+            }
+            CsrHandshakeError::InvalidCsr { .. } => "CsrInvalid",
+            CsrHandshakeError::Sign { .. } => "CsrSigningFailed",
+            CsrHandshakeError::SigningUnsupported => "CsrSigningFailed",
+            CsrHandshakeError::SaveCertificate { .. } => "CsrHandshakeFailed",
+            CsrHandshakeError::TagPod { .. } => "CsrHandshakeFailed",
+            CsrHandshakeError::RecordIdentity { .. } => "CsrHandshakeFailed",
+            CsrHandshakeError::Fs { .. } => "CsrHandshakeFailed",
+        }
+    }
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub(super) enum ReadRefreshableVolumeError {
+    #[snafu(transparent)]
+    Fs { source: FsError },
+
+    #[snafu(display("failed to parse refreshable volume {path:?}"))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub(super) enum VolumeLayoutError {
+    #[snafu(transparent)]
+    Fs { source: FsError },
+
+    #[snafu(display("failed to parse volume layout generation {path:?}"))]
+    Parse {
+        source: ParseIntError,
+        path: PathBuf,
+    },
+
+    #[snafu(display(
+        "volume has layout generation {generation}, but this version of secret-operator only understands up to generation {}, was it downgraded?",
+        VOLUME_LAYOUT_GENERATION
+    ))]
+    UnsupportedGeneration { generation: u32 },
+}
+
+impl VolumeLayoutError {
+    fn grpc_code(&self) -> tonic::Code {
+        match self {
+            VolumeLayoutError::Fs { .. } => tonic::Code::Unavailable,
+            VolumeLayoutError::Parse { .. } => tonic::Code::FailedPrecondition,
+            VolumeLayoutError::UnsupportedGeneration { .. } => tonic::Code::FailedPrecondition,
         }
     }
 }
 
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum ResumeCsrHandshakeError {
+    #[snafu(transparent)]
+    Fs { source: FsError },
+
+    #[snafu(display("failed to parse pending CSR handshake {path:?}"))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("failed to parse selector from volume context"))]
+    InvalidSelector { source: serde::de::value::Error },
+
+    #[snafu(display("failed to initialize backend"))]
+    InitBackend {
+        source: backend::dynamic::FromSelectorError,
+    },
+}
+
+/// Written into CSR handshake volumes when they are published, describing what the `Pod`'s CSR may request.
+const CSR_REQUEST_FILE_NAME: &str = "csr-request.json";
+/// Where the `Pod` writes its CSR during the CSR handshake.
+const CSR_FILE_NAME: &str = "request.csr";
+/// Created once the CSR handshake is complete, and the certificate can be used.
+const CSR_READY_FILE_NAME: &str = "ready";
+/// How often to check whether the `Pod` has written its CSR yet.
+const CSR_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Appended to the name of a volume's target path to get the file that its pending CSR handshake is stored in, see
+/// [`PendingCsrHandshake`].
+const PENDING_CSR_HANDSHAKE_SUFFIX: &str = ".csr-handshake.json";
+/// Appended to the name of a volume's target path to get the file that its [`VolumeManifest`] is stored in.
+const VOLUME_MANIFEST_SUFFIX: &str = ".manifest.json";
+/// Appended to the name of a volume's target path to get the file that its [`RefreshableVolume`] is stored in.
+const REFRESHABLE_VOLUME_SUFFIX: &str = ".refreshable.json";
+/// Appended to the name of a volume's target path to get the file that its layout generation is stored in, see
+/// [`VOLUME_LAYOUT_GENERATION`].
+const VOLUME_LAYOUT_SUFFIX: &str = ".layout-generation";
+/// The layout of the files that are written into and next to published volumes.
+///
+/// - 1: published before the layout generation was recorded, any of the files next to the volume may be missing
+/// - 2: adds the [`VolumeManifest`], [`RefreshableVolume`], [`PendingCsrHandshake`], and identity files
+///
+/// Volumes of older generations need no migration, since each of these files is optional. Bump this whenever older
+/// versions of secret-operator would misinterpret a volume, so that they refuse to touch it (such as after a downgrade).
+const VOLUME_LAYOUT_GENERATION: u32 = 2;
+/// The generation of volumes that have no recorded layout generation, see [`VOLUME_LAYOUT_GENERATION`].
+const LEGACY_VOLUME_LAYOUT_GENERATION: u32 = 1;
+
+/// Kubelet's timeout for CSI calls, used if the request does not specify a deadline.
+const DEFAULT_REQUEST_DEADLINE: Duration = Duration::from_secs(120);
+
 // The actual provisioner that is run on all nodes and in charge of provisioning and storing
 // secrets for pods that get scheduled on that node.
 pub struct SecretProvisionerNode {
     pub client: stackable_operator::client::Client,
     pub node_name: String,
     pub privileged: bool,
+    /// The directory that kubelet publishes volumes into, secret-operator never deletes volumes outside of it.
+    pub volume_root: PathBuf,
+    /// The fraction of each publish request's deadline that may be spent waiting for dependent objects to be created, or
+    /// for a free coordination slot.
+    pub dependency_wait_fraction: f64,
+    pub leases: LeasePool,
+    pub issued_identities: IssuedIdentities,
+    pub csr_handshakes: CsrHandshakes,
+    pub volume_locks: VolumeLocks,
 }
 
 impl SecretProvisionerNode {
-    async fn get_pod_info(&self, selector: &SecretVolumeSelector) -> Result<PodInfo, PublishError> {
+    /// Resumes the CSR handshakes of the volumes in [`Self::volume_root`] that were still pending when the provisioner
+    /// stopped, such as during an upgrade.
+    ///
+    /// The timeout of each resumed handshake starts over, since the `Pod` may have been waiting for us in the meantime.
+    /// Handshakes that can't be resumed are skipped, the `Pod` will then time out waiting for its certificate.
+    pub async fn resume_csr_handshakes(&self) {
+        for (path, target_path) in
+            identity_api::find_next_to_volumes(&self.volume_root, PENDING_CSR_HANDSHAKE_SUFFIX)
+                .await
+        {
+            if let Err(err) = self.resume_csr_handshake(&path, target_path).await {
+                tracing::warn!(
+                    csr_handshake.path = %path.display(),
+                    error = &err as &dyn std::error::Error,
+                    "failed to resume CSR handshake, skipping..."
+                );
+            }
+        }
+    }
+
+    async fn resume_csr_handshake(
+        &self,
+        path: &Path,
+        target_path: PathBuf,
+    ) -> Result<(), ResumeCsrHandshakeError> {
+        use resume_csr_handshake_error::*;
+        // The provisioner stopped after the handshake completed, but before it could clean up after itself
+        if fs::try_exists(&target_path.join(CSR_READY_FILE_NAME)).await? {
+            return Ok(fs::remove_file(path).await?);
+        }
+        let pending = serde_json::from_slice::<PendingCsrHandshake>(&fs::read(path).await?)
+            .context(ParseSnafu { path })?;
+        let mut selector =
+            SecretVolumeSelector::deserialize(pending.volume_context.into_deserializer())
+                .context(InvalidSelectorSnafu)?;
+        if selector.file_group.is_none() {
+            selector.file_group = pending.volume_mount_group;
+        }
+        let backend = backend::dynamic::from_selector(&self.client, &selector, &self.leases)
+            .await
+            .context(InitBackendSnafu)?;
+        tracing::info!(
+            pod = %ObjectRef::<Pod>::new(&selector.pod).within(&selector.namespace),
+            volume.path = %target_path.display(),
+            "resuming CSR handshake"
+        );
+        self.csr_handshakes.spawn(
+            pending.volume_id.clone(),
+            complete_csr_handshake(
+                self.client.clone(),
+                self.node_name.clone(),
+                self.issued_identities.clone(),
+                pending.volume_id,
+                target_path,
+                selector,
+                backend,
+                pending.csr_request,
+            ),
+        );
+        Ok(())
+    }
+
+    async fn get_pod_info(
+        &self,
+        selector: &SecretVolumeSelector,
+        wait: DependencyWait,
+    ) -> Result<PodInfo, PublishError> {
         let pod = self
             .client
             .get::<Pod>(&selector.pod, &selector.namespace)
             .await
             .context(publish_error::GetPodSnafu)?;
-        PodInfo::from_pod(&self.client, pod, &selector.scope)
+        PodInfo::from_pod(&self.client, pod, &selector.scope, wait)
             .await
             .context(publish_error::ParsePodSnafu)
     }
 
-    async fn prepare_secret_dir(&self, target_path: &Path) -> Result<(), PublishError> {
-        match tokio::fs::create_dir(target_path).await {
-            Ok(_) => {}
-            Err(err) => match err.kind() {
-                std::io::ErrorKind::AlreadyExists => {
-                    tracing::warn!(volume.path = %target_path.display(), "Tried to create volume path that already exists");
-                }
-                _ => return Err(err).context(publish_error::CreateDirSnafu { path: target_path }),
-            },
-        }
-        if self.privileged {
-            Mount::builder()
-                .fstype("tmpfs")
-                .flags(MountFlags::NODEV | MountFlags::NOEXEC | MountFlags::NOSUID)
-                .mount("", target_path)
-                .context(publish_error::MountSnafu { path: target_path })?;
-        } else {
-            tracing::info!("Running in unprivileged mode, not creating mount for secret volume");
-        }
-        // User: root/secret-operator
-        // Group: Controlled by Pod.securityContext.fsGroup, the actual application
-        // (when running as unprivileged user)
-        tokio::fs::set_permissions(target_path, Permissions::from_mode(0o750))
+    /// Reissues the secret of the `volume` published at `target_path`, replacing its files in place, and records the
+    /// forced rotation that the SecretClass requested when the new secret was issued.
+    ///
+    /// Returns `false` without changing anything if the volume has been unpublished since it was found.
+    pub(super) async fn refresh_volume(
+        &self,
+        target_path: &Path,
+        volume: &RefreshableVolume,
+    ) -> Result<bool, PublishError> {
+        // Otherwise the volume could be unpublished while it is being refreshed
+        let _lock = self.volume_locks.lock(&volume.volume_id).await;
+        // Re-read, since the volume may have been unpublished (or republished with a different selector) since then
+        let Some(volume) = RefreshableVolume::read_published(target_path)
             .await
-            .context(publish_error::SetDirPermissionsSnafu { path: target_path })?;
-        Ok(())
+            .context(publish_error::ReadRefreshableVolumeSnafu)?
+            .filter(|published| published.volume_id == volume.volume_id)
+        else {
+            tracing::debug!(
+                volume.path = %target_path.display(),
+                "volume was unpublished, skipping refresh"
+            );
+            return Ok(false);
+        };
+        ensure_volume_layout_supported(target_path)
+            .await
+            .context(publish_error::VolumeLayoutSnafu)?;
+        let selector = volume
+            .selector()
+            .context(publish_error::InvalidSelectorSnafu)?;
+        // The Pod is already running, so its dependencies must exist by now
+        let pod_info = self.get_pod_info(&selector, DependencyWait::none()).await?;
+        let (backend, rotation) =
+            backend::dynamic::from_selector_with_rotation(&self.client, &selector, &self.leases)
+                .await
+                .context(publish_error::InitBackendSnafu)?;
+        self.reissue_volume(target_path, volume, selector, &backend, pod_info, rotation)
+            .await?;
+        Ok(true)
     }
 
-    // Takes a path and list of filenames and content.
-    // Writes all files to the target directory.
-    async fn save_secret_data(
+    /// Issues a new secret for the `volume` published at `target_path`, and records that it was issued for `rotation`.
+    ///
+    /// This doesn't take a coordination slot itself, since backends already take one for their expensive operations (such
+    /// as provisioning keytabs), and nested slots from the same pool would have to wait for each other.
+    async fn reissue_volume(
         &self,
         target_path: &Path,
-        data: SecretContents,
-        format: Option<SecretFormat>,
-        names: NamingOptions,
-        compat: CompatibilityOptions,
+        volume: RefreshableVolume,
+        selector: SecretVolumeSelector,
+        backend: &Dynamic,
+        pod_info: PodInfo,
+        rotation: Option<String>,
     ) -> Result<(), PublishError> {
-        let create_secret = {
-            let mut opts = OpenOptions::new();
-            opts.create(true)
-                .write(true)
-                // User: root/secret-operator
-                // Group: Controlled by Pod.securityContext.fsGroup, the actual application
-                // (when running as unprivileged user)
-                .mode(0o640);
-            opts
-        };
-        for (k, v) in data
-            .data
-            .into_files(format, names, compat)
-            .context(publish_error::FormatDataSnafu)?
-        {
-            // The following few lines of code do some basic checks against
-            // unwanted path traversals. In the future, we want to leverage
-            // capability based filesystem operations (openat) to prevent these
-            // traversals.
-
-            // First, let's turn the (potentially custom) file path into a path.
-            let file_path = PathBuf::from(k);
-
-            // Next, ensure the path is not absolute (does not contain root),
-            // because joining an absolute path with a different path will
-            // replace the exiting path entirely.
-            ensure!(
-                !file_path.has_root(),
-                publish_error::InvalidAbsolutePathSnafu { path: &file_path }
-            );
-
-            // Ensure that the file path only contains normal components. This
-            // prevents any path traversals up the path using '..'.
-            ensure!(
-                file_path
-                    .components()
-                    .all(|c| matches!(c, Component::Normal(_))),
-                publish_error::InvalidComponentsSnafu { path: &file_path }
-            );
-
-            // Now, we can join the base and file path
-            let item_path = target_path.join(file_path);
-
-            if let Some(item_path_parent) = item_path.parent() {
-                create_dir_all(item_path_parent)
-                    .await
-                    .context(publish_error::CreateDirSnafu {
-                        path: item_path_parent,
-                    })?;
-            }
-            create_secret
-                .open(&item_path)
-                .await
-                .context(publish_error::CreateFileSnafu { path: &item_path })?
-                .write_all(&v)
-                .await
-                .context(publish_error::WriteFileSnafu { path: item_path })?;
+        let data = backend
+            .get_secret_data(&selector, pod_info)
+            .await
+            .context(publish_error::BackendGetSecretDataSnafu)?;
+        tag_pod(
+            &self.client,
+            &volume.volume_id,
+            &selector,
+            data.expires_after,
+        )
+        .await?;
+        let issued_identity = VolumeIdentity::new(
+            &volume.volume_id,
+            &selector,
+            data.identity.clone(),
+            data.expires_after,
+        );
+        save_secret_data(target_path, data, selector).await?;
+        self.issued_identities
+            .record(target_path, issued_identity)
+            .await
+            .context(publish_error::RecordIdentitySnafu)?;
+        RefreshableVolume {
+            rotated_for: rotation,
+            ..volume
         }
-        Ok(())
+        .record(target_path)
+        .await?;
+        // Everything has been rewritten, so any older layout has been replaced
+        Ok(write_volume_layout(target_path).await?)
     }
 
-    async fn tag_pod(
+    async fn prepare_secret_dir(
         &self,
-        client: &stackable_operator::client::Client,
-        volume_id: &str,
+        target_path: &Path,
         selector: &SecretVolumeSelector,
-        data: &SecretContents,
     ) -> Result<(), PublishError> {
-        // Each volume must have a unique tag, so that multiple markers of the same type can coexist on the same pod
-        // Each tag needs to be simple and unique-ish per volume
-        let mut volume_tag_hasher = Sha256::new();
-        volume_tag_hasher.update("secrets.stackable.tech/volume:".as_bytes());
-        volume_tag_hasher.update(volume_id.as_bytes());
-        let volume_tag = volume_tag_hasher.finish();
-        // Truncating sha256 hashes opens up some collision vulnerabilities
-        // (https://csrc.nist.gov/CSRC/media/Events/First-Cryptographic-Hash-Workshop/documents/Kelsey_Truncation.pdf)
-        // however, we mostly just care about preventing accidental hashes here, for which plain byte truncation should be "good enough".
-        let volume_tag = &volume_tag[..16];
-
-        let mut annotations = Annotations::new();
-
-        if let Some(expires_after) = data.expires_after {
-            annotations
-                .parse_insert((
-                    format!(
-                        "restarter.stackable.tech/expires-at.{:x}",
-                        FmtByteSlice(volume_tag)
-                    ),
-                    expires_after.to_rfc3339(),
-                ))
-                .context(publish_error::BuildAnnotationSnafu)?;
+        match fs::create_dir(target_path).await {
+            Ok(_) => {}
+            Err(err) => match err.kind() {
+                ErrorKind::AlreadyExists => {
+                    tracing::warn!(volume.path = %target_path.display(), "Tried to create volume path that already exists");
+                }
+                _ => return Err(err.into()),
+            },
         }
-
-        if !annotations.is_empty() {
-            let tagged_pod = Pod {
-                metadata: ObjectMetaBuilder::new()
-                    .name(&selector.pod)
-                    .namespace(&selector.namespace)
-                    .annotations(annotations)
-                    .build(),
-                ..Pod::default()
-            };
-            client
-                .merge_patch(&tagged_pod, &tagged_pod)
-                .await
-                .context(publish_error::TagPodSnafu)?;
+        if self.privileged {
+            fs::mount_tmpfs(target_path)?;
+        } else {
+            tracing::info!("Running in unprivileged mode, not creating mount for secret volume");
+        }
+        // User: root/secret-operator
+        // Group: Controlled by secrets.stackable.tech/file.group if set, otherwise by
+        // Pod.securityContext.fsGroup, the actual application (when running as unprivileged user)
+        let file_mode = selector
+            .file_mode
+            .unwrap_or(default_file_mode(selector.format));
+        fs::set_mode(target_path, dir_mode(file_mode)).await?;
+        if let Some(group) = selector.file_group {
+            set_volume_group(target_path, group)?;
         }
         Ok(())
     }
 
-    async fn clean_secret_dir(&self, target_path: &Path) -> Result<(), UnpublishError> {
-        // unmount() fails unconditionally with PermissionDenied when running in an unprivileged container,
-        // even if it wouldn't be sensible to even try anyway (such as when there is no volume mount).
-        if self.privileged {
-            match unmount(target_path, UnmountFlags::empty()) {
-                Ok(_) => {}
-                Err(err) => match err.kind() {
-                    std::io::ErrorKind::NotFound => {
-                        tracing::warn!(volume.path = %target_path.display(), "Tried to unmount volume path that does not exist, assuming it was already deleted");
-                        return Ok(());
-                    }
-                    std::io::ErrorKind::InvalidInput => {
-                        tracing::warn!(volume.path = %target_path.display(), "Tried to unmount volume path that is not mounted, trying to delete it anyway");
-                    }
-                    _ => {
-                        return Err(err)
-                            .context(unpublish_error::UnmountSnafu { path: target_path });
-                    }
-                },
-            };
-        }
-        // There is no mount in unprivileged mode, so we need to remove all contents in that case.
-        // This may still apply to privileged mode, in case users are migrating from unprivileged to privileged mode.
-        match tokio::fs::remove_dir_all(&target_path).await {
-            Ok(_) => Ok(()),
-            // We already catch this above when running in privileged mode, but in unprivileged mode this is still possible
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                tracing::warn!(volume.path = %target_path.display(), "Tried to delete volume path that does not exist, assuming it was already deleted");
-                Ok(())
-            }
-            Err(err) => Err(err).context(unpublish_error::DeleteSnafu { path: target_path }),
+    /// Whether secret files can be given to any group, rather than only to the groups that secret-operator is a member
+    /// of.
+    fn can_change_file_group(&self) -> bool {
+        // SAFETY: geteuid always succeeds
+        self.privileged || unsafe { libc::geteuid() } == 0
+    }
+}
+
+/// Gives the volume directory at `target_path` to `group`.
+fn set_volume_group(target_path: &Path, group: u32) -> Result<(), PublishError> {
+    match fs::set_group(target_path, group) {
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            Err(err).context(publish_error::ChangeGroupDeniedSnafu { group })
         }
+        result => Ok(result?),
     }
 }
 
+/// The optional node RPCs that are advertised to kubelet.
+///
+/// Staging is not advertised, since [`Node::node_stage_volume`] and [`Node::node_unstage_volume`] are not
+/// implemented (kubelet would otherwise call them before every publish, failing the mount).
+const NODE_CAPABILITIES: &[node_service_capability::rpc::Type] = &[
+    node_service_capability::rpc::Type::GetVolumeStats,
+    node_service_capability::rpc::Type::VolumeCondition,
+    // Otherwise kubelet applies the Pod's fsGroup by making every file group-writable
+    // Only advertised if we can change file groups, see node_capabilities
+    node_service_capability::rpc::Type::VolumeMountGroup,
+];
+
+/// The [`NODE_CAPABILITIES`] that can be advertised.
+///
+/// Kubelet delegates applying the Pod's fsGroup to us if `VolumeMountGroup` is advertised, which requires giving the
+/// secret files to groups that we are not a member of. Otherwise kubelet keeps applying the fsGroup itself.
+fn node_capabilities(
+    can_change_file_group: bool,
+) -> impl Iterator<Item = node_service_capability::rpc::Type> {
+    NODE_CAPABILITIES.iter().copied().filter(move |rpc| {
+        *rpc != node_service_capability::rpc::Type::VolumeMountGroup || can_change_file_group
+    })
+}
+
 // Most of the services are not yet implemented, most of them will never be, because they are
 // not needed for this use case.
 // The main two services are publish_volume und unpublish_volume, which get called whenever a
@@ -398,37 +680,138 @@ impl Node for SecretProvisionerNode {
         log_if_endpoint_error(
             "failed to publish volume",
             async move {
+                let dependency_wait = DependencyWait::for_deadline(
+                    grpc_timeout(request.metadata()).unwrap_or(DEFAULT_REQUEST_DEADLINE),
+                    self.dependency_wait_fraction,
+                );
                 let request = request.into_inner();
                 let target_path = PathBuf::from(request.target_path);
                 tracing::info!(
                     volume.path = %target_path.display(),
                     "Received NodePublishVolume request"
                 );
-                let selector =
-                    SecretVolumeSelector::deserialize(request.volume_context.into_deserializer())
-                        .context(publish_error::InvalidSelectorSnafu)?;
-                let pod_info = self.get_pod_info(&selector).await?;
-                let backend = backend::dynamic::from_selector(&self.client, &selector)
-                    .await
-                    .context(publish_error::InitBackendSnafu)?;
-                let pod_ref = ObjectRef::<Pod>::new(&selector.pod).within(&selector.namespace);
-                tracing::info!(pod = %pod_ref, ?selector, ?pod_info, ?backend, "issuing secret for Pod");
-                let data = backend
-                    .get_secret_data(&selector, pod_info)
+                let _lock = self.volume_locks.lock(&request.volume_id).await;
+                let mut selector = SecretVolumeSelector::deserialize(
+                    request.volume_context.clone().into_deserializer(),
+                )
+                .context(publish_error::InvalidSelectorSnafu)?;
+                let selector_fingerprint = selector.selector_fingerprint();
+                // Kubelet delegates applying the Pod's fsGroup to us, see node_get_capabilities
+                let volume_mount_group = volume_mount_group(request.volume_capability.as_ref())?;
+                if selector.file_group.is_none() {
+                    selector.file_group = volume_mount_group;
+                }
+                ensure_volume_layout_supported(&target_path)
                     .await
-                    .context(publish_error::BackendGetSecretDataSnafu)?;
-                self.tag_pod(&self.client, &request.volume_id, &selector, &data)
-                    .await?;
-                self.prepare_secret_dir(&target_path).await?;
-                self.save_secret_data(
+                    .context(publish_error::VolumeLayoutSnafu)?;
+                ensure_selector_unchanged(
                     &target_path,
-                    data,
-                    // NOTE (@Techassi): At this point, we might want to pass the whole selector instead
-                    selector.format,
-                    selector.names,
-                    selector.compat,
+                    &selector_fingerprint,
+                    selector.allow_selector_change,
+                    self.privileged,
                 )
                 .await?;
+                let pod_info = self.get_pod_info(&selector, dependency_wait).await?;
+                // Waiting for a free slot shares the budget for waiting on dependencies
+                let leases = self.leases.with_deadline(dependency_wait.deadline);
+                let (backend, rotation) =
+                    backend::dynamic::from_selector_with_rotation(&self.client, &selector, &leases)
+                        .await
+                        .context(publish_error::InitBackendSnafu)?;
+                let pod_ref = ObjectRef::<Pod>::new(&selector.pod).within(&selector.namespace);
+                tracing::info!(
+                    pod = %pod_ref,
+                    ?selector,
+                    selector.fingerprint = selector_fingerprint,
+                    ?pod_info,
+                    ?backend,
+                    "issuing secret for Pod"
+                );
+                let mut issued_identity = None;
+                let csr_handshake = if selector.autotls_csr_handshake {
+                    if let Some(format) = selector.format {
+                        ensure!(
+                            format == SecretFormat::TlsPem,
+                            publish_error::CsrHandshakeRequiresTlsPemSnafu { format }
+                        );
+                    }
+                    let csr_request = backend
+                        .get_csr_request(&selector, pod_info)
+                        .await
+                        .context(publish_error::BackendGetCsrRequestSnafu)?
+                        .context(publish_error::CsrHandshakeUnsupportedSnafu)?;
+                    self.prepare_secret_dir(&target_path, &selector).await?;
+                    write_csr_request(&target_path, &csr_request, &selector).await?;
+                    write_pending_csr_handshake(
+                        &target_path,
+                        &PendingCsrHandshake {
+                            volume_id: request.volume_id.clone(),
+                            volume_context: request.volume_context,
+                            volume_mount_group,
+                            csr_request: csr_request.clone(),
+                        },
+                    )
+                    .await?;
+                    Some((selector, backend, csr_request))
+                } else {
+                    let data = backend
+                        .get_secret_data(&selector, pod_info)
+                        .await
+                        .context(publish_error::BackendGetSecretDataSnafu)?;
+                    tag_pod(
+                        &self.client,
+                        &request.volume_id,
+                        &selector,
+                        data.expires_after,
+                    )
+                    .await?;
+                    issued_identity = Some(VolumeIdentity::new(
+                        &request.volume_id,
+                        &selector,
+                        data.identity.clone(),
+                        data.expires_after,
+                    ));
+                    self.prepare_secret_dir(&target_path, &selector).await?;
+                    save_secret_data(&target_path, data, selector).await?;
+                    RefreshableVolume {
+                        volume_id: request.volume_id.clone(),
+                        volume_context: request.volume_context,
+                        volume_mount_group,
+                        // The backend was loaded after the rotation was requested, so it was already applied
+                        rotated_for: rotation,
+                    }
+                    .record(&target_path)
+                    .await?;
+                    None
+                };
+                write_volume_layout(&target_path)
+                    .await
+                    .map_err(PublishError::from)?;
+                write_selector_fingerprint(&target_path, &selector_fingerprint)
+                    .await
+                    .map_err(PublishError::from)?;
+                if let Some(issued_identity) = issued_identity {
+                    self.issued_identities
+                        .record(&target_path, issued_identity)
+                        .await
+                        .context(publish_error::RecordIdentitySnafu)?;
+                }
+                // Only start once publishing has succeeded, since Kubelet retries failed requests from scratch
+                if let Some((selector, backend, csr_request)) = csr_handshake {
+                    self.csr_handshakes.spawn(
+                        request.volume_id.clone(),
+                        complete_csr_handshake(
+                            self.client.clone(),
+                            self.node_name.clone(),
+                            self.issued_identities.clone(),
+                            request.volume_id,
+                            target_path,
+                            selector,
+                            backend,
+                            csr_request,
+                        ),
+                    );
+                }
                 Ok(Response::new(NodePublishVolumeResponse {}))
             }
             .await,
@@ -452,7 +835,14 @@ impl Node for SecretProvisionerNode {
                     volume.path = %target_path.display(),
                     "Received NodeUnpublishVolume request"
                 );
-                self.clean_secret_dir(&target_path).await?;
+                ensure_within_volume_root(&target_path, &self.volume_root)?;
+                // Otherwise a refresh could recreate the files next to the volume after they have been removed, which
+                // would stop kubelet from removing the volume's directory
+                let _lock = self.volume_locks.lock(&request.volume_id).await;
+                // Otherwise the handshake could write into the volume while it is being removed
+                self.csr_handshakes.cancel(&request.volume_id);
+                clean_secret_dir(&target_path, self.privileged).await?;
+                self.issued_identities.forget(&request.volume_id);
                 Ok(Response::new(NodeUnpublishVolumeResponse {}))
             }
             .await,
@@ -461,9 +851,22 @@ impl Node for SecretProvisionerNode {
 
     async fn node_get_volume_stats(
         &self,
-        _request: Request<NodeGetVolumeStatsRequest>,
+        request: Request<NodeGetVolumeStatsRequest>,
     ) -> Result<Response<NodeGetVolumeStatsResponse>, Status> {
-        Err(Status::unimplemented("endpoint not implemented"))
+        log_if_endpoint_error(
+            "failed to get volume stats",
+            async move {
+                let request = request.into_inner();
+                let usage = get_volume_usage(&request.volume_path)?;
+                let volume_condition =
+                    get_volume_condition(Path::new(&request.volume_path), self.privileged).await?;
+                Ok(Response::new(NodeGetVolumeStatsResponse {
+                    usage,
+                    volume_condition: Some(volume_condition),
+                }))
+            }
+            .await,
+        )
     }
 
     async fn node_expand_volume(
@@ -478,7 +881,13 @@ impl Node for SecretProvisionerNode {
         _request: Request<NodeGetCapabilitiesRequest>,
     ) -> Result<Response<NodeGetCapabilitiesResponse>, Status> {
         Ok(Response::new(NodeGetCapabilitiesResponse {
-            capabilities: vec![],
+            capabilities: node_capabilities(self.can_change_file_group())
+                .map(|rpc| NodeServiceCapability {
+                    r#type: Some(node_service_capability::Type::Rpc(
+                        node_service_capability::Rpc { r#type: rpc.into() },
+                    )),
+                })
+                .collect(),
         }))
     }
 
@@ -496,12 +905,2512 @@ impl Node for SecretProvisionerNode {
     }
 }
 
-fn log_if_endpoint_error<T, E: std::error::Error + 'static>(
-    error_msg: &str,
-    res: Result<T, E>,
-) -> Result<T, E> {
-    if let Err(err) = &res {
-        tracing::warn!(error = err as &dyn std::error::Error, "{error_msg}");
+async fn tag_pod(
+    client: &stackable_operator::client::Client,
+    volume_id: &str,
+    selector: &SecretVolumeSelector,
+    expires_after: Option<DateTime<FixedOffset>>,
+) -> Result<(), PublishError> {
+    // Each volume must have a unique tag, so that multiple markers of the same type can coexist on the same pod
+    // Each tag needs to be simple and unique-ish per volume
+    let mut volume_tag_hasher = Sha256::new();
+    volume_tag_hasher.update("secrets.stackable.tech/volume:".as_bytes());
+    volume_tag_hasher.update(volume_id.as_bytes());
+    let volume_tag = volume_tag_hasher.finish();
+    // Truncating sha256 hashes opens up some collision vulnerabilities
+    // (https://csrc.nist.gov/CSRC/media/Events/First-Cryptographic-Hash-Workshop/documents/Kelsey_Truncation.pdf)
+    // however, we mostly just care about preventing accidental hashes here, for which plain byte truncation should be "good enough".
+    let volume_tag = &volume_tag[..16];
+
+    let mut annotations = Annotations::new();
+
+    if let Some(expires_after) = expires_after {
+        annotations
+            .parse_insert((
+                format!(
+                    "restarter.stackable.tech/expires-at.{:x}",
+                    FmtByteSlice(volume_tag)
+                ),
+                expires_after.to_rfc3339(),
+            ))
+            .context(publish_error::BuildAnnotationSnafu)?;
+    }
+
+    if !annotations.is_empty() {
+        let tagged_pod = Pod {
+            metadata: ObjectMetaBuilder::new()
+                .name(&selector.pod)
+                .namespace(&selector.namespace)
+                .annotations(annotations)
+                .build(),
+            ..Pod::default()
+        };
+        client
+            .merge_patch(&tagged_pod, &tagged_pod)
+            .await
+            .context(publish_error::TagPodSnafu)?;
+    }
+    Ok(())
+}
+
+// Takes a path and list of filenames and content.
+// Writes all files to the target directory, in the format requested by the selector.
+async fn save_secret_data(
+    target_path: &Path,
+    data: SecretContents,
+    selector: SecretVolumeSelector,
+) -> Result<(), PublishError> {
+    let SecretVolumeSelector {
+        format,
+        names,
+        compat,
+        env_file,
+        file_mode,
+        file_group,
+        ..
+    } = selector;
+    let mode = file_mode.unwrap_or(default_file_mode(format));
+    let files = data
+        .data
+        .into_files(format, names, compat, env_file)
+        .context(publish_error::FormatDataSnafu)?;
+    write_secret_files(
+        target_path,
+        files.iter().map(|(k, v)| (k.as_str(), v.as_slice())),
+        mode,
+        file_group,
+    )
+    .await
+}
+
+/// The permissions of secret files, unless overridden by [`SecretVolumeSelector::file_mode`].
+fn default_file_mode(format: Option<SecretFormat>) -> u32 {
+    // Env files bundle all of the secret's key material into a single file
+    if format == Some(SecretFormat::EnvFile) {
+        0o600
+    } else {
+        0o640
+    }
+}
+
+/// The permissions of the directories that contain secret files with `file_mode`.
+///
+/// Directories can be listed and traversed by everyone who can read the files, but are only writable by their owner.
+fn dir_mode(file_mode: u32) -> u32 {
+    let readable = file_mode & 0o044;
+    0o700 | readable | (readable >> 2)
+}
+
+/// The group that kubelet asks us to apply instead of the Pod's `fsGroup`, if any.
+fn volume_mount_group(capability: Option<&VolumeCapability>) -> Result<Option<u32>, PublishError> {
+    match capability.and_then(|capability| capability.access_type.as_ref()) {
+        Some(volume_capability::AccessType::Mount(mount))
+            if !mount.volume_mount_group.is_empty() =>
+        {
+            let group = &mount.volume_mount_group;
+            group
+                .parse()
+                .map(Some)
+                .context(publish_error::InvalidVolumeMountGroupSnafu { group })
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Writes the secret files into the volume at `target_path`, replacing any previous versions.
+///
+/// Every file is staged in a hidden directory inside of the volume before any of them replaces its previous version,
+/// and the previous versions are restored if any of them can't be replaced, so that a failure doesn't leave behind a
+/// partially updated volume.
+async fn write_secret_files<'a>(
+    target_path: &Path,
+    files: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    mode: u32,
+    group: Option<u32>,
+) -> Result<(), PublishError> {
+    let mut staging = fs::StagingDir::create(target_path).await?;
+    let mut written = VolumeManifest::default();
+    for (file_name, contents) in files {
+        if let Err(err) = stage_secret_file(
+            &mut staging,
+            &mut written,
+            target_path,
+            file_name,
+            contents,
+            mode,
+            group,
+        )
+        .await
+        {
+            staging.discard().await;
+            written.remove_dirs(target_path);
+            return Err(err);
+        }
+    }
+    if let Err(err) = staging.commit().await {
+        written.remove_dirs(target_path);
+        return Err(err.into());
+    }
+    written.record(target_path).await
+}
+
+/// Stages the secret file `file_name` in `staging`, creating any parent directories in the volume at `target_path`.
+///
+/// The file and the directories that were created for it are added to `written`.
+async fn stage_secret_file(
+    staging: &mut fs::StagingDir,
+    written: &mut VolumeManifest,
+    target_path: &Path,
+    file_name: &str,
+    contents: &[u8],
+    mode: u32,
+    group: Option<u32>,
+) -> Result<(), PublishError> {
+    // The following few lines of code do some basic checks against
+    // unwanted path traversals. In the future, we want to leverage
+    // capability based filesystem operations (openat) to prevent these
+    // traversals.
+
+    // First, let's turn the (potentially custom) file path into a path.
+    let file_path = PathBuf::from(file_name);
+
+    // Next, ensure the path is not absolute (does not contain root),
+    // because joining an absolute path with a different path will
+    // replace the exiting path entirely.
+    ensure!(
+        !file_path.has_root(),
+        publish_error::InvalidAbsolutePathSnafu { path: &file_path }
+    );
+
+    // Ensure that the file path only contains normal components. This
+    // prevents any path traversals up the path using '..'.
+    ensure!(
+        file_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_))),
+        publish_error::InvalidComponentsSnafu { path: &file_path }
+    );
+
+    // The staging directory is replaced on every write, so it can't hold any secret files itself
+    ensure!(
+        !file_path.starts_with(fs::StagingDir::NAME),
+        publish_error::ReservedFileNameSnafu { path: &file_path }
+    );
+
+    // Now, we can join the base and file path
+    let item_path = target_path.join(&file_path);
+
+    if let Some(item_path_parent) = item_path.parent() {
+        // Same permissions as the volume root, see prepare_secret_dir
+        let created = fs::create_dir_all(item_path_parent, dir_mode(mode), group).await?;
+        written.dirs.extend(
+            created
+                .iter()
+                .filter_map(|dir| dir.strip_prefix(target_path).ok())
+                .map(Path::to_path_buf),
+        );
+    }
+    written.files.insert(file_path.clone());
+    // User: root/secret-operator
+    // Group: Controlled by secrets.stackable.tech/file.group if set, otherwise by
+    // Pod.securityContext.fsGroup, the actual application (when running as unprivileged user)
+    Ok(staging
+        .write_file(&file_path, mode, group, contents)
+        .await?)
+}
+
+/// Where the [`VolumeManifest`] of the volume published at `target_path` is stored.
+///
+/// This is kept next to the volume (rather than inside of it), so that it is not visible to the `Pod`.
+fn volume_manifest_path(target_path: &Path) -> PathBuf {
+    let mut file_name = target_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(VOLUME_MANIFEST_SUFFIX);
+    target_path.with_file_name(file_name)
+}
+
+/// The secret files that have been written into a volume, and the directories that were created for them.
+///
+/// Paths are relative to the volume. Directories that already existed (such as ones created by the `Pod`) are not
+/// recorded.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VolumeManifest {
+    files: BTreeSet<PathBuf>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl VolumeManifest {
+    /// Reads the manifest of the volume at `target_path`.
+    ///
+    /// Volumes published by older versions of secret-operator have no manifest, and are treated as empty.
+    async fn read(target_path: &Path) -> Result<Self, FsError> {
+        let path = volume_manifest_path(target_path);
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        Ok(serde_json::from_slice(&contents).unwrap_or_else(|err| {
+            // The volume is still removed entirely by remove_secret_dir, the manifest only tells us what to remove first
+            tracing::warn!(
+                volume.path = %target_path.display(),
+                error = &err as &dyn std::error::Error,
+                "Ignoring invalid volume manifest"
+            );
+            Self::default()
+        }))
+    }
+
+    /// Adds these files and directories to the manifest of the volume at `target_path`.
+    async fn record(self, target_path: &Path) -> Result<(), PublishError> {
+        let mut manifest = Self::read(target_path).await?;
+        manifest.files.extend(self.files);
+        manifest.dirs.extend(self.dirs);
+        let contents = serde_json::to_vec_pretty(&manifest)
+            .context(publish_error::SerializeVolumeManifestSnafu)?;
+        fs::write_file(&volume_manifest_path(target_path), 0o600, None, &contents).await?;
+        Ok(())
+    }
+
+    /// Removes the recorded files, and then the recorded directories that are left empty, see [`Self::remove_dirs`].
+    fn remove_from(&self, target_path: &Path) {
+        for file in &self.files {
+            remove_recorded(target_path, file, false);
+        }
+        self.remove_dirs(target_path);
+    }
+
+    /// Removes the recorded directories that are empty, deepest first.
+    fn remove_dirs(&self, target_path: &Path) {
+        // Paths are ordered by their components, so every directory comes after its parents
+        for dir in self.dirs.iter().rev() {
+            remove_recorded(target_path, dir, true);
+        }
+    }
+}
+
+/// Removes the file (or empty directory) at `path` in the volume at `target_path`, if possible.
+///
+/// The `Pod` may have added to or replaced anything in the volume, so failures are only logged.
+fn remove_recorded(target_path: &Path, path: &Path, is_dir: bool) {
+    match fs::remove_beneath(target_path, path, is_dir) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => tracing::debug!(
+            volume.path = %target_path.display(),
+            error = &err as &dyn std::error::Error,
+            "Keeping recorded path in volume"
+        ),
+    }
+}
+
+/// Starts the CSR handshake (see [`SecretVolumeSelector::autotls_csr_handshake`]) by telling the `Pod` what its CSR
+/// may request.
+async fn write_csr_request(
+    target_path: &Path,
+    csr_request: &CsrRequest,
+    selector: &SecretVolumeSelector,
+) -> Result<(), PublishError> {
+    // The Pod's init container must be able to add its CSR to the volume
+    fs::set_mode(target_path, 0o770).await?;
+    let csr_request =
+        serde_json::to_vec_pretty(csr_request).context(publish_error::SerializeCsrRequestSnafu)?;
+    fs::write_file(
+        &target_path.join(CSR_REQUEST_FILE_NAME),
+        0o644,
+        selector.file_group,
+        &csr_request,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Where the [`PendingCsrHandshake`] of the volume published at `target_path` is stored.
+///
+/// This is kept next to the volume (rather than inside of it), since the `Pod` can write to the volume during the
+/// handshake.
+fn pending_csr_handshake_path(target_path: &Path) -> PathBuf {
+    let mut file_name = target_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(PENDING_CSR_HANDSHAKE_SUFFIX);
+    target_path.with_file_name(file_name)
+}
+
+/// Everything that is needed to resume a CSR handshake that was interrupted by a restart, see
+/// [`SecretProvisionerNode::resume_csr_handshakes`].
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingCsrHandshake {
+    volume_id: String,
+    volume_context: HashMap<String, String>,
+    /// The group that kubelet asked us to apply, see [`volume_mount_group`].
+    volume_mount_group: Option<u32>,
+    csr_request: CsrRequest,
+}
+
+/// Records the CSR handshake of the volume at `target_path` until it has completed.
+async fn write_pending_csr_handshake(
+    target_path: &Path,
+    pending: &PendingCsrHandshake,
+) -> Result<(), PublishError> {
+    let contents = serde_json::to_vec_pretty(pending)
+        .context(publish_error::SerializePendingCsrHandshakeSnafu)?;
+    fs::write_file(
+        &pending_csr_handshake_path(target_path),
+        0o600,
+        None,
+        &contents,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Where the [`RefreshableVolume`] of the volume published at `target_path` is stored.
+fn refreshable_volume_path(target_path: &Path) -> PathBuf {
+    let mut file_name = target_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(REFRESHABLE_VOLUME_SUFFIX);
+    target_path.with_file_name(file_name)
+}
+
+/// Everything that is needed to reissue the secret of a published volume, see
+/// [`SecretProvisionerNode::refresh_volume`].
+///
+/// Also records how far the volume has come with the forced rotations of its SecretClass, see [`super::rotation`].
+/// CSR handshake volumes are not recorded, since only the `Pod` can request a new certificate for its key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct RefreshableVolume {
+    pub volume_id: String,
+    pub volume_context: HashMap<String, String>,
+    /// The group that kubelet asked us to apply, see [`volume_mount_group`].
+    pub volume_mount_group: Option<u32>,
+    /// The forced rotation that the volume's secret was last issued for, if any.
+    pub rotated_for: Option<String>,
+}
+
+impl RefreshableVolume {
+    /// Finds the recorded volumes that are still published in `volume_root`, along with their target paths.
+    ///
+    /// Records that can't be read are skipped.
+    pub async fn find(volume_root: &Path) -> Vec<(PathBuf, Self)> {
+        let mut found = Vec::new();
+        for (path, target_path) in
+            identity_api::find_next_to_volumes(volume_root, REFRESHABLE_VOLUME_SUFFIX).await
+        {
+            match Self::read(&path).await {
+                Ok(volume) => found.push((target_path, volume)),
+                Err(err) => tracing::warn!(
+                    refreshable_volume.path = %path.display(),
+                    error = &err as &dyn std::error::Error,
+                    "failed to read refreshable volume, skipping..."
+                ),
+            }
+        }
+        found
+    }
+
+    async fn read(path: &Path) -> Result<Self, ReadRefreshableVolumeError> {
+        let contents = fs::read(path).await?;
+        serde_json::from_slice(&contents)
+            .context(read_refreshable_volume_error::ParseSnafu { path })
+    }
+
+    /// Reads the record of the volume published at `target_path`, or [`None`] if it is no longer published.
+    async fn read_published(
+        target_path: &Path,
+    ) -> Result<Option<Self>, ReadRefreshableVolumeError> {
+        // The record may be left over if unpublishing failed after the volume itself was removed
+        if !fs::try_exists(target_path).await? {
+            return Ok(None);
+        }
+        match Self::read(&refreshable_volume_path(target_path)).await {
+            Ok(volume) => Ok(Some(volume)),
+            Err(ReadRefreshableVolumeError::Fs { source })
+                if source.kind() == ErrorKind::NotFound =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The selector that the volume was published with.
+    pub fn selector(&self) -> Result<SecretVolumeSelector, serde::de::value::Error> {
+        let mut selector =
+            SecretVolumeSelector::deserialize(self.volume_context.clone().into_deserializer())?;
+        if selector.file_group.is_none() {
+            selector.file_group = self.volume_mount_group;
+        }
+        Ok(selector)
+    }
+
+    /// Records the volume published at `target_path`, replacing any previous record.
+    pub async fn record(&self, target_path: &Path) -> Result<(), PublishError> {
+        let contents = serde_json::to_vec_pretty(self)
+            .context(publish_error::SerializeRefreshableVolumeSnafu)?;
+        fs::write_file(
+            &refreshable_volume_path(target_path),
+            0o600,
+            None,
+            &contents,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// The CSR handshakes that are running in the background, by volume ID.
+#[derive(Debug, Clone, Default)]
+pub struct CsrHandshakes(Arc<Mutex<HashMap<String, AbortHandle>>>);
+
+impl CsrHandshakes {
+    /// Runs `handshake` for the volume `volume_id` in the background, cancelling any previous handshake of the same
+    /// volume.
+    fn spawn(&self, volume_id: String, handshake: impl Future<Output = ()> + Send + 'static) {
+        let handshakes = self.clone();
+        // Held until the task is registered, so that it can't finish (and unregister itself) before that
+        let mut running = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let task = tokio::spawn({
+            let volume_id = volume_id.clone();
+            async move {
+                handshake.await;
+                handshakes.finished(&volume_id, tokio::task::id());
+            }
+        });
+        if let Some(previous) = running.insert(volume_id, task.abort_handle()) {
+            previous.abort();
+        }
+    }
+
+    /// Unregisters the handshake `task` of the volume `volume_id`, unless it has already been replaced.
+    fn finished(&self, volume_id: &str, task: tokio::task::Id) {
+        let mut running = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if running
+            .get(volume_id)
+            .is_some_and(|handshake| handshake.id() == task)
+        {
+            running.remove(volume_id);
+        }
+    }
+
+    /// Cancels the handshake of the volume `volume_id`, if it is still running.
+    pub fn cancel(&self, volume_id: &str) {
+        let handshake = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(volume_id);
+        if let Some(handshake) = handshake {
+            handshake.abort();
+        }
+    }
+
+    /// Cancels all handshakes that are still running, such as once another provisioner has taken over.
+    pub fn cancel_all(&self) {
+        for (_, handshake) in self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain()
+        {
+            handshake.abort();
+        }
+    }
+
+    /// Whether a handshake is running for the volume `volume_id`.
+    #[cfg(test)]
+    fn is_running(&self, volume_id: &str) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(volume_id)
+    }
+}
+
+/// Serializes publishing, unpublishing and refreshing each volume, by volume ID.
+#[derive(Debug, Default)]
+pub struct VolumeLocks(Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>);
+
+impl VolumeLocks {
+    /// Waits until no other operation holds the lock of the volume `volume_id`, and takes it until the guard is dropped.
+    async fn lock(&self, volume_id: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            // Forget the locks that are neither held nor waited for, rather than keeping one for every volume ever seen
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(volume_id.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+/// Completes the CSR handshake of a published volume in the background, reporting the outcome as an `Event` on the `Pod`.
+async fn complete_csr_handshake(
+    client: stackable_operator::client::Client,
+    node_name: String,
+    issued_identities: IssuedIdentities,
+    volume_id: String,
+    target_path: PathBuf,
+    selector: SecretVolumeSelector,
+    backend: Box<Dynamic>,
+    csr_request: CsrRequest,
+) {
+    let pod_ref = ObjectRef::<Pod>::new(&selector.pod).within(&selector.namespace);
+    let result = run_csr_handshake(
+        &target_path,
+        &*backend,
+        &selector,
+        &csr_request,
+        CSR_POLL_INTERVAL,
+        async |signed| {
+            tag_pod(&client, &volume_id, &selector, signed.expires_after)
+                .await
+                .context(csr_handshake_error::TagPodSnafu)?;
+            let identity = VolumeIdentity::new(
+                &volume_id,
+                &selector,
+                signed.identity.clone(),
+                signed.expires_after,
+            );
+            issued_identities
+                .record(&target_path, identity)
+                .await
+                .context(csr_handshake_error::RecordIdentitySnafu)
+        },
+    )
+    .await;
+    // The handshake has either finished or failed for good, so it must not be resumed after a restart
+    match fs::remove_file(&pending_csr_handshake_path(&target_path)).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => tracing::warn!(
+            pod = %pod_ref,
+            error = &err as &dyn std::error::Error,
+            "failed to remove pending CSR handshake"
+        ),
+    }
+    let (type_, reason, note) = match result {
+        Ok(true) => {
+            tracing::info!(
+                pod = %pod_ref,
+                volume.path = %target_path.display(),
+                "Signed CSR for Pod"
+            );
+            (
+                EventType::Normal,
+                "CsrSigned",
+                format!("signed the CSR written to {CSR_FILE_NAME}"),
+            )
+        }
+        Ok(false) => {
+            tracing::info!(
+                pod = %pod_ref,
+                volume.path = %target_path.display(),
+                "Volume was removed before the Pod wrote its CSR, cancelling CSR handshake"
+            );
+            return;
+        }
+        Err(err) => {
+            tracing::warn!(
+                pod = %pod_ref,
+                volume.path = %target_path.display(),
+                error = &err as &dyn std::error::Error,
+                "CSR handshake failed"
+            );
+            (
+                EventType::Warning,
+                err.event_reason(),
+                error_full_message(&err),
+            )
+        }
+    };
+    let recorder = Recorder::new(
+        client.as_kube_client(),
+        Reporter {
+            controller: ownership::OPERATOR_NAME.to_string(),
+            instance: Some(node_name),
+        },
+    );
+    let event = Event {
+        type_,
+        reason: reason.to_string(),
+        note: Some(note),
+        action: "SignCsr".to_string(),
+        secondary: None,
+    };
+    let pod = ObjectReference {
+        api_version: Some("v1".to_string()),
+        kind: Some("Pod".to_string()),
+        name: Some(selector.pod.clone()),
+        namespace: Some(selector.namespace.clone()),
+        ..ObjectReference::default()
+    };
+    if let Err(err) = recorder.publish(&event, &pod).await {
+        tracing::warn!(
+            pod = %pod_ref,
+            error = &err as &dyn std::error::Error,
+            "failed to publish CSR handshake event"
+        );
+    }
+}
+
+/// Waits for the `Pod` to write its CSR, signs it, and adds the certificate to the volume.
+///
+/// `on_signed` is called before the volume is marked as ready, such as to tag the `Pod` so that it is restarted
+/// before the certificate expires.
+///
+/// Returns `false` if the volume was removed before the `Pod` wrote its CSR.
+async fn run_csr_handshake(
+    target_path: &Path,
+    backend: &Dynamic,
+    selector: &SecretVolumeSelector,
+    csr_request: &CsrRequest,
+    poll_interval: Duration,
+    on_signed: impl AsyncFnOnce(&SignedCsr) -> Result<(), CsrHandshakeError>,
+) -> Result<bool, CsrHandshakeError> {
+    use csr_handshake_error::*;
+    let Some(csr) = wait_for_csr(
+        target_path,
+        *selector.autotls_csr_handshake_timeout,
+        poll_interval,
+    )
+    .await?
+    else {
+        return Ok(false);
+    };
+    let csr = csr_request.validate(&csr).context(InvalidCsrSnafu)?;
+    let signed = backend
+        .sign_csr(selector, &csr)
+        .await
+        .context(SignSnafu)?
+        .context(SigningUnsupportedSnafu)?;
+    let mode = selector
+        .file_mode
+        .unwrap_or(default_file_mode(Some(SecretFormat::TlsPem)));
+    write_secret_files(
+        target_path,
+        [
+            (
+                selector.names.tls_pem_ca_name.as_str(),
+                signed.ca_pem.as_slice(),
+            ),
+            (
+                selector.names.tls_pem_cert_name.as_str(),
+                signed.certificate_pem.as_slice(),
+            ),
+        ],
+        mode,
+        selector.file_group,
+    )
+    .await
+    .context(SaveCertificateSnafu)?;
+    on_signed(&signed).await?;
+    let ready_path = target_path.join(CSR_READY_FILE_NAME);
+    #[cfg(feature = "failpoints")]
+    fs::fail_point(
+        failpoint::NODE_CSR_HANDSHAKE_BEFORE_READY,
+        fs::FsOperation::CreateFile,
+        &ready_path,
+    )
+    .await?;
+    fs::write_file(&ready_path, 0o644, selector.file_group, b"").await?;
+    Ok(true)
+}
+
+/// Waits for the `Pod` to write a complete CSR into the volume.
+///
+/// Returns `None` if the volume is removed in the meantime (such as when the `Pod` is deleted).
+async fn wait_for_csr(
+    target_path: &Path,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<Option<Vec<u8>>, CsrHandshakeError> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let csr_path = target_path.join(CSR_FILE_NAME);
+    loop {
+        match fs::read(&csr_path).await {
+            // Otherwise we might read a CSR that the Pod is still in the middle of writing
+            Ok(csr) if csr.trim_ascii_end().ends_with(b"REQUEST-----") => return Ok(Some(csr)),
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                if !fs::try_exists(target_path).await? {
+                    return Ok(None);
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
+        ensure!(
+            tokio::time::Instant::now() < deadline,
+            csr_handshake_error::TimeoutSnafu { timeout }
+        );
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Where the layout generation of the volume published at `target_path` is stored, see [`VOLUME_LAYOUT_GENERATION`].
+fn volume_layout_path(target_path: &Path) -> PathBuf {
+    let mut file_name = target_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(VOLUME_LAYOUT_SUFFIX);
+    target_path.with_file_name(file_name)
+}
+
+/// Returns the layout generation of the volume published at `target_path`, failing if it is newer than
+/// [`VOLUME_LAYOUT_GENERATION`], since we can't know what its files mean.
+async fn ensure_volume_layout_supported(target_path: &Path) -> Result<u32, VolumeLayoutError> {
+    use volume_layout_error::*;
+    let path = volume_layout_path(target_path);
+    let generation = match fs::read_to_string(&path).await {
+        Ok(generation) => generation.trim().parse().context(ParseSnafu { path })?,
+        // Either a new volume, or published by an older version of secret-operator
+        Err(err) if err.kind() == ErrorKind::NotFound => LEGACY_VOLUME_LAYOUT_GENERATION,
+        Err(err) => return Err(err.into()),
+    };
+    ensure!(
+        generation <= VOLUME_LAYOUT_GENERATION,
+        UnsupportedGenerationSnafu { generation }
+    );
+    Ok(generation)
+}
+
+/// Records that the volume published at `target_path` uses the current [`VOLUME_LAYOUT_GENERATION`].
+async fn write_volume_layout(target_path: &Path) -> Result<(), FsError> {
+    fs::write_file(
+        &volume_layout_path(target_path),
+        0o600,
+        None,
+        VOLUME_LAYOUT_GENERATION.to_string().as_bytes(),
+    )
+    .await
+}
+
+/// Where the [`SecretVolumeSelector::selector_fingerprint`] of a published volume is stored.
+///
+/// This is kept next to the volume (rather than inside of it), so that it is not visible to the `Pod`.
+fn selector_fingerprint_path(target_path: &Path) -> PathBuf {
+    let mut file_name = target_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".selector-fingerprint");
+    target_path.with_file_name(file_name)
+}
+
+/// Records the selector `fingerprint` of the volume published at `target_path`.
+async fn write_selector_fingerprint(target_path: &Path, fingerprint: &str) -> Result<(), FsError> {
+    let path = selector_fingerprint_path(target_path);
+    #[cfg(feature = "failpoints")]
+    fs::fail_point(
+        failpoint::NODE_PUBLISH_BEFORE_FINGERPRINT,
+        fs::FsOperation::WriteFile,
+        &path,
+    )
+    .await?;
+    fs::write_file(&path, 0o600, None, fingerprint.as_bytes()).await
+}
+
+/// Ensures that a volume that is being re-published is not silently replaced with a secret for a different selector.
+///
+/// If `allow_change` is set, the previous volume is cleaned up instead, so that it can be reprovisioned from scratch.
+async fn ensure_selector_unchanged(
+    target_path: &Path,
+    fingerprint: &str,
+    allow_change: bool,
+    privileged: bool,
+) -> Result<(), PublishError> {
+    let previous = match fs::read_to_string(&selector_fingerprint_path(target_path)).await {
+        Ok(previous) => previous,
+        // Either a new volume, or published by an older version of secret-operator
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if previous == fingerprint {
+        return Ok(());
+    }
+    ensure!(
+        allow_change,
+        publish_error::SelectorChangedSnafu {
+            previous,
+            requested: fingerprint,
+        }
+    );
+    tracing::warn!(
+        volume.path = %target_path.display(),
+        selector.previous_fingerprint = previous,
+        selector.fingerprint = fingerprint,
+        "Volume selector changed, removing previous contents before reprovisioning"
+    );
+    clean_secret_dir(target_path, privileged)
+        .await
+        .context(publish_error::CleanChangedVolumeSnafu)
+}
+
+async fn clean_secret_dir(target_path: &Path, privileged: bool) -> Result<(), UnpublishError> {
+    ensure_volume_layout_supported(target_path)
+        .await
+        .context(unpublish_error::VolumeLayoutSnafu)?;
+    remove_secret_dir(target_path, privileged).await?;
+    match fs::remove_file(&identity_api::identity_path(target_path)).await {
+        Ok(_) => {}
+        // Not every volume has an identity, such as CSR handshake volumes that were never signed
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    match fs::remove_file(&pending_csr_handshake_path(target_path)).await {
+        Ok(_) => {}
+        // Only pending CSR handshakes are recorded
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    match fs::remove_file(&volume_manifest_path(target_path)).await {
+        Ok(_) => {}
+        // Volumes published by older versions of secret-operator do not have a manifest
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    match fs::remove_file(&refreshable_volume_path(target_path)).await {
+        Ok(_) => {}
+        // CSR handshake volumes and volumes published by older versions of secret-operator are not recorded
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    match fs::remove_file(&volume_layout_path(target_path)).await {
+        Ok(_) => {}
+        // Volumes published by older versions of secret-operator do not have a layout generation
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    let fingerprint_path = selector_fingerprint_path(target_path);
+    #[cfg(feature = "failpoints")]
+    fs::fail_point(
+        failpoint::NODE_UNPUBLISH_BEFORE_REMOVE_FINGERPRINT,
+        fs::FsOperation::Delete,
+        &fingerprint_path,
+    )
+    .await?;
+    // Kubelet expects to be able to delete the parent directory once we're done
+    match fs::remove_file(&fingerprint_path).await {
+        Ok(_) => Ok(()),
+        // Volumes published by older versions of secret-operator do not have a fingerprint
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Ensures that `target_path` is strictly inside `volume_root`, so that a misconfigured kubelet can't make us
+/// recursively delete unrelated directories (such as `/`).
+fn ensure_within_volume_root(target_path: &Path, volume_root: &Path) -> Result<(), UnpublishError> {
+    let is_within = target_path.is_absolute()
+        && target_path.starts_with(volume_root)
+        && target_path != volume_root
+        && !target_path
+            .components()
+            .any(|component| component == Component::ParentDir);
+    ensure!(
+        is_within,
+        unpublish_error::OutsideVolumeRootSnafu {
+            path: target_path,
+            volume_root,
+        }
+    );
+    Ok(())
+}
+
+async fn remove_secret_dir(target_path: &Path, privileged: bool) -> Result<(), UnpublishError> {
+    // Kubelet retries unpublishing after restarts, by which point the volume may already have been cleaned up
+    let is_mountpoint = match fs::is_mountpoint(target_path).await {
+        Ok(is_mountpoint) => is_mountpoint,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            tracing::warn!(volume.path = %target_path.display(), "Tried to delete volume path that does not exist, assuming it was already deleted");
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if is_mountpoint {
+        // Deleting the contents of a mount would just leave an empty mount behind
+        ensure!(
+            privileged,
+            unpublish_error::UnmountUnprivilegedSnafu { path: target_path }
+        );
+        fs::unmount(target_path)?;
+    } else {
+        // Remove our own files and directories deepest first, so that only whatever the Pod added is left over
+        VolumeManifest::read(target_path)
+            .await?
+            .remove_from(target_path);
+    }
+    // There is no mount in unprivileged mode, so we need to remove all contents in that case.
+    // This may still apply to privileged mode, in case users are migrating from unprivileged to privileged mode.
+    match fs::remove_dir_all(target_path).await {
+        Ok(_) => Ok(()),
+        // Another unpublish may have raced us
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            tracing::warn!(volume.path = %target_path.display(), "Tried to delete volume path that does not exist, assuming it was already deleted");
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reports the space and inode usage of the volume published at `volume_path`.
+fn get_volume_usage(volume_path: &str) -> Result<Vec<VolumeUsage>, VolumeStatsError> {
+    ensure!(
+        !volume_path.is_empty(),
+        volume_stats_error::EmptyVolumePathSnafu
+    );
+    let usage = fs::statvfs(Path::new(volume_path))?;
+    // Values that don't fit are clamped, since they are only informational
+    let to_i64 = |value: u64| i64::try_from(value).unwrap_or(i64::MAX);
+    Ok(vec![
+        VolumeUsage {
+            available: to_i64(usage.available_bytes),
+            total: to_i64(usage.total_bytes),
+            used: to_i64(usage.used_bytes),
+            unit: volume_usage::Unit::Bytes.into(),
+        },
+        VolumeUsage {
+            available: to_i64(usage.available_inodes),
+            total: to_i64(usage.total_inodes),
+            used: to_i64(usage.used_inodes),
+            unit: volume_usage::Unit::Inodes.into(),
+        },
+    ])
+}
+
+/// Reports whether the volume published at `volume_path` is still kept off the node's disk.
+///
+/// Volumes are only written to disk in unprivileged mode, or if the tmpfs has gone missing from underneath them
+/// (such as if they were published before switching from unprivileged to privileged mode).
+async fn get_volume_condition(
+    volume_path: &Path,
+    privileged: bool,
+) -> Result<VolumeCondition, VolumeStatsError> {
+    if privileged && !fs::is_mountpoint(volume_path).await? {
+        return Ok(VolumeCondition {
+            abnormal: true,
+            message:
+                "volume is not mounted as a ramdisk, so its secrets are stored on the node's disk"
+                    .to_string(),
+        });
+    }
+    Ok(VolumeCondition {
+        abnormal: false,
+        message: String::new(),
+    })
+}
+
+/// Parses the deadline that the client specified for the request, if any.
+///
+/// See <https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests> for the format.
+fn grpc_timeout(metadata: &MetadataMap) -> Option<Duration> {
+    let timeout = metadata.get("grpc-timeout")?.to_str().ok()?;
+    let (value, unit) = timeout.split_at(timeout.len().checked_sub(1)?);
+    if value.is_empty() || value.len() > 8 {
+        return None;
+    }
+    let value = value.parse::<u64>().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(value * 60 * 60),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+fn log_if_endpoint_error<T, E: std::error::Error + 'static>(
+    error_msg: &str,
+    res: Result<T, E>,
+) -> Result<T, E> {
+    if let Err(err) = &res {
+        tracing::warn!(error = err as &dyn std::error::Error, "{error_msg}");
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        io::ErrorKind,
+        os::unix::fs::{MetadataExt, PermissionsExt},
+        path::{Path, PathBuf},
+        pin::pin,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use futures::{StreamExt as _, TryStreamExt as _};
+    use openssl::{
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        stack::Stack,
+        x509::{X509ReqBuilder, extension::SubjectAlternativeName},
+    };
+    use serde::{
+        Deserialize,
+        de::{
+            IntoDeserializer,
+            value::{self, MapDeserializer},
+        },
+    };
+    use snafu::{ResultExt, Snafu};
+    use stackable_operator::{kube, utils::cluster_info::KubernetesClusterInfo};
+    use tokio::time::Instant;
+    use tonic::{Code, Request, Status, metadata::MetadataMap};
+
+    use super::{
+        CSR_FILE_NAME, CSR_READY_FILE_NAME, CSR_REQUEST_FILE_NAME, CsrHandshakes,
+        NODE_CAPABILITIES, PENDING_CSR_HANDSHAKE_SUFFIX, PendingCsrHandshake, PublishError,
+        RefreshableVolume, SecretProvisionerNode, UnpublishError, VOLUME_LAYOUT_GENERATION,
+        VolumeLayoutError, VolumeLocks, VolumeManifest, clean_secret_dir, dir_mode,
+        ensure_selector_unchanged, ensure_volume_layout_supported, ensure_within_volume_root,
+        get_volume_condition, get_volume_usage, grpc_timeout, node_capabilities,
+        pending_csr_handshake_path, refreshable_volume_path, run_csr_handshake, save_secret_data,
+        selector_fingerprint_path, set_volume_group, volume_layout_path, volume_manifest_path,
+        volume_mount_group, write_csr_request, write_pending_csr_handshake, write_secret_files,
+        write_selector_fingerprint, write_volume_layout,
+    };
+    use crate::{
+        backend::{
+            self, SecretBackend, SecretBackendError, SecretContents, SecretIdentity,
+            SecretVolumeSelector,
+            coordination::{self, LeasePool, testing::FakeLeaseApi},
+            csr::{CsrKeyRequirements, CsrRequest, SignedCsr, ValidatedCsr},
+            pod_info::{DependencyWait, NodeInfo, PodInfo, SchedulingPodInfo},
+        },
+        format::SecretData,
+        grpc::csi::v1::{
+            NodeGetCapabilitiesRequest, NodeGetVolumeStatsRequest, NodePublishVolumeRequest,
+            VolumeCapability, node_server::Node, node_service_capability, volume_capability,
+        },
+        identity_api::{self, IssuedIdentities, identity_path},
+        utils::fs,
+    };
+
+    fn timeout_header(value: &'static str) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("grpc-timeout", value.parse().unwrap());
+        metadata
+    }
+
+    #[test]
+    fn grpc_timeout_should_parse_units() {
+        assert_eq!(
+            grpc_timeout(&timeout_header("2M")),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            grpc_timeout(&timeout_header("15S")),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            grpc_timeout(&timeout_header("1500m")),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            grpc_timeout(&timeout_header("99999999n")),
+            Some(Duration::from_nanos(99999999))
+        );
+    }
+
+    #[test]
+    fn grpc_timeout_should_reject_invalid() {
+        assert_eq!(grpc_timeout(&MetadataMap::new()), None);
+        assert_eq!(grpc_timeout(&timeout_header("S")), None);
+        assert_eq!(grpc_timeout(&timeout_header("15")), None);
+        assert_eq!(grpc_timeout(&timeout_header("15x")), None);
+        assert_eq!(grpc_timeout(&timeout_header("123456789S")), None);
+    }
+
+    /// Simulates a volume that was previously published for a selector with the given `fingerprint`.
+    async fn published_volume(target_path: &Path, fingerprint: &str) {
+        fs::create_dir(target_path).await.unwrap();
+        fs::write_file(&target_path.join("tls.crt"), 0o640, None, b"old cert")
+            .await
+            .unwrap();
+        write_selector_fingerprint(target_path, fingerprint)
+            .await
+            .unwrap();
+        fs::write_file(&identity_path(target_path), 0o600, None, b"{}")
+            .await
+            .unwrap();
+        RefreshableVolume {
+            volume_id: "my-volume".to_string(),
+            volume_context: HashMap::new(),
+            volume_mount_group: None,
+            rotated_for: None,
+        }
+        .record(target_path)
+        .await
+        .unwrap();
+        write_volume_layout(target_path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn republish_with_same_selector_should_keep_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        ensure_selector_unchanged(&target_path, "new", false, false)
+            .await
+            .unwrap();
+
+        published_volume(&target_path, "old").await;
+        ensure_selector_unchanged(&target_path, "old", false, false)
+            .await
+            .unwrap();
+        assert!(target_path.join("tls.crt").exists());
+    }
+
+    #[tokio::test]
+    async fn republish_with_changed_selector_should_be_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        published_volume(&target_path, "old").await;
+
+        let err = ensure_selector_unchanged(&target_path, "new", false, false)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, PublishError::SelectorChanged { .. }),
+            "{err:?}"
+        );
+        assert_eq!(Status::from(err).code(), Code::FailedPrecondition);
+        assert!(target_path.join("tls.crt").exists());
+        assert_eq!(
+            fs::read_to_string(&selector_fingerprint_path(&target_path))
+                .await
+                .unwrap(),
+            "old"
+        );
+    }
+
+    #[tokio::test]
+    async fn republish_with_allowed_selector_change_should_clean_up_before_reprovisioning() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        published_volume(&target_path, "old").await;
+
+        ensure_selector_unchanged(&target_path, "new", true, false)
+            .await
+            .unwrap();
+        // The old volume must be gone before the new secret is provisioned,
+        // so that none of the old selector's files can leak into the new volume
+        assert!(!target_path.exists());
+        assert!(!selector_fingerprint_path(&target_path).exists());
+    }
+
+    #[tokio::test]
+    async fn unpublish_should_remove_selector_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        published_volume(&target_path, "old").await;
+
+        clean_secret_dir(&target_path, false).await.unwrap();
+        assert!(!target_path.exists());
+        assert!(!selector_fingerprint_path(&target_path).exists());
+        assert!(dir.path().read_dir().unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn unpublish_should_be_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        published_volume(&target_path, "old").await;
+
+        // Kubelet retries unpublishing if it didn't see the response to the first attempt
+        clean_secret_dir(&target_path, false).await.unwrap();
+        clean_secret_dir(&target_path, false).await.unwrap();
+        assert!(!target_path.exists());
+    }
+
+    #[tokio::test]
+    async fn unpublish_should_accept_older_layout_generations() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        published_volume(&target_path, "old").await;
+        assert_eq!(
+            ensure_volume_layout_supported(&target_path).await.unwrap(),
+            VOLUME_LAYOUT_GENERATION
+        );
+        fs::write_file(&volume_layout_path(&target_path), 0o600, None, b"1")
+            .await
+            .unwrap();
+        assert_eq!(
+            ensure_volume_layout_supported(&target_path).await.unwrap(),
+            1
+        );
+        // Volumes published before the layout generation was recorded
+        fs::remove_file(&volume_layout_path(&target_path))
+            .await
+            .unwrap();
+        assert_eq!(
+            ensure_volume_layout_supported(&target_path).await.unwrap(),
+            1
+        );
+
+        clean_secret_dir(&target_path, false).await.unwrap();
+        assert!(dir.path().read_dir().unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn unpublish_should_refuse_newer_layout_generations() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        published_volume(&target_path, "old").await;
+        // Such as after secret-operator was downgraded
+        let newer = (VOLUME_LAYOUT_GENERATION + 1).to_string();
+        fs::write_file(
+            &volume_layout_path(&target_path),
+            0o600,
+            None,
+            newer.as_bytes(),
+        )
+        .await
+        .unwrap();
+
+        let err = clean_secret_dir(&target_path, false).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                UnpublishError::VolumeLayout {
+                    source: VolumeLayoutError::UnsupportedGeneration { generation }
+                } if generation == VOLUME_LAYOUT_GENERATION + 1
+            ),
+            "{err:?}"
+        );
+        assert_eq!(Status::from(err).code(), Code::FailedPrecondition);
+        // Nothing was removed, since we can't know which files the newer version expects
+        assert!(target_path.join("tls.crt").exists());
+        assert!(selector_fingerprint_path(&target_path).exists());
+    }
+
+    #[tokio::test]
+    async fn unpublish_should_succeed_if_volume_is_already_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        // Such as after the node was restarted, since the volume is not persisted
+        clean_secret_dir(&dir.path().join("mount"), false)
+            .await
+            .unwrap();
+        clean_secret_dir(&dir.path().join("missing/mount"), true)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn unpublish_should_not_escape_volume_root() {
+        let volume_root = Path::new("/var/lib/kubelet/pods");
+        ensure_within_volume_root(
+            Path::new("/var/lib/kubelet/pods/my-pod/volumes/kubernetes.io~csi/tls/mount"),
+            volume_root,
+        )
+        .unwrap();
+        for path in [
+            "/",
+            "/var/lib/kubelet",
+            "/var/lib/kubelet/pods",
+            "/var/lib/kubelet/pods/",
+            "/var/lib/kubelet/pods-other/my-pod",
+            "/var/lib/kubelet/pods/my-pod/../../../../etc",
+            "var/lib/kubelet/pods/my-pod",
+        ] {
+            let err = ensure_within_volume_root(Path::new(path), volume_root).unwrap_err();
+            assert!(
+                matches!(err, UnpublishError::OutsideVolumeRoot { .. }),
+                "{path}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn volume_usage_should_report_bytes_and_inodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = get_volume_usage(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(usage.len(), 2);
+        // Some filesystems (such as btrfs) don't report inode counts
+        assert!(usage[0].total > 0, "{usage:?}");
+
+        let err = get_volume_usage(dir.path().join("missing").to_str().unwrap()).unwrap_err();
+        assert_eq!(Status::from(err).code(), Code::NotFound);
+        assert_eq!(
+            Status::from(get_volume_usage("").unwrap_err()).code(),
+            Code::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn volume_condition_should_flag_volumes_that_are_not_ramdisks() {
+        let dir = tempfile::tempdir().unwrap();
+        // Unprivileged mode never mounts a tmpfs
+        let condition = get_volume_condition(dir.path(), false).await.unwrap();
+        assert!(!condition.abnormal, "{condition:?}");
+        let condition = get_volume_condition(dir.path(), true).await.unwrap();
+        assert!(condition.abnormal, "{condition:?}");
+
+        let err = get_volume_condition(&dir.path().join("missing"), true)
+            .await
+            .unwrap_err();
+        assert_eq!(Status::from(err).code(), Code::NotFound);
+    }
+
+    /// A client for handlers that must not depend on the API server, since all of its requests fail.
+    fn unreachable_client() -> stackable_operator::client::Client {
+        let kube_client = kube::Client::new(
+            tower::service_fn(|_| async {
+                Err::<http::Response<kube::client::Body>, _>(std::io::Error::other(
+                    "API server is unreachable",
+                ))
+            }),
+            "default",
+        );
+        stackable_operator::client::Client::new(
+            kube_client,
+            None,
+            "default".to_string(),
+            KubernetesClusterInfo {
+                cluster_domain: "cluster.local".parse().unwrap(),
+            },
+        )
+    }
+
+    /// A node for the volumes in `volume_root`, which can't reach the API server.
+    fn offline_node(volume_root: &Path) -> SecretProvisionerNode {
+        SecretProvisionerNode {
+            client: unreachable_client(),
+            node_name: "my-node".to_string(),
+            privileged: false,
+            volume_root: volume_root.to_path_buf(),
+            dependency_wait_fraction: 0.5,
+            leases: LeasePool::disabled(),
+            issued_identities: IssuedIdentities::default(),
+            csr_handshakes: CsrHandshakes::default(),
+            volume_locks: VolumeLocks::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn advertised_capabilities_should_be_implemented() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        fs::create_dir(&target_path).await.unwrap();
+        let node = SecretProvisionerNode {
+            // Advertises every capability, see node_capabilities
+            privileged: true,
+            ..offline_node(dir.path())
+        };
+        let volume_stats = || {
+            Request::new(NodeGetVolumeStatsRequest {
+                volume_id: "my-volume".to_string(),
+                volume_path: target_path.to_str().unwrap().to_string(),
+                ..Default::default()
+            })
+        };
+        // An invalid group is rejected before anything is requested from the API server
+        let publish_with_invalid_group = || {
+            Request::new(NodePublishVolumeRequest {
+                volume_id: "my-volume".to_string(),
+                target_path: target_path.to_str().unwrap().to_string(),
+                volume_capability: Some(VolumeCapability {
+                    access_type: Some(volume_capability::AccessType::Mount(
+                        volume_capability::MountVolume {
+                            volume_mount_group: "wheel".to_string(),
+                            ..Default::default()
+                        },
+                    )),
+                    ..Default::default()
+                }),
+                volume_context: [
+                    ("secrets.stackable.tech/class", "my-class"),
+                    ("csi.storage.k8s.io/pod.name", "my-pod"),
+                    ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+                ]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+                ..Default::default()
+            })
+        };
+        let capabilities = node
+            .node_get_capabilities(Request::new(NodeGetCapabilitiesRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .capabilities
+            .into_iter()
+            .map(|capability| match capability.r#type {
+                Some(node_service_capability::Type::Rpc(rpc)) => rpc.r#type(),
+                other => panic!("unexpected capability {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(capabilities, NODE_CAPABILITIES);
+
+        for capability in capabilities {
+            let result = match capability {
+                node_service_capability::rpc::Type::GetVolumeStats
+                | node_service_capability::rpc::Type::VolumeCondition => node
+                    .node_get_volume_stats(volume_stats())
+                    .await
+                    .map(|stats| {
+                        let stats = stats.into_inner();
+                        assert!(!stats.usage.is_empty());
+                        assert!(stats.volume_condition.is_some());
+                    }),
+                node_service_capability::rpc::Type::VolumeMountGroup => {
+                    let result = node
+                        .node_publish_volume(publish_with_invalid_group())
+                        .await
+                        .map(drop);
+                    assert_eq!(
+                        result.as_ref().map_err(Status::code),
+                        Err(Code::InvalidArgument),
+                        "{result:?}"
+                    );
+                    result
+                }
+                _ => panic!("{capability:?} is advertised, but has no known handler"),
+            };
+            if let Err(status) = result {
+                assert_ne!(
+                    status.code(),
+                    Code::Unimplemented,
+                    "{capability:?} is advertised, but not implemented"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn volume_locks_should_only_block_the_same_volume() {
+        let locks = VolumeLocks::default();
+        let volume_a = locks.lock("volume-a").await;
+        let volume_b = locks.lock("volume-b").await;
+        let mut next_volume_a = pin!(locks.lock("volume-a"));
+        assert!(futures::poll!(&mut next_volume_a).is_pending());
+        drop(volume_a);
+        drop(next_volume_a.await);
+        drop(volume_b);
+
+        // Locks that are no longer used are forgotten
+        let _volume_c = locks.lock("volume-c").await;
+        assert_eq!(locks.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refresh_should_not_recreate_unpublished_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        published_volume(&target_path, "fingerprint").await;
+        let node = offline_node(dir.path());
+        let volume = RefreshableVolume::read_published(&target_path)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The volume is unpublished while the refresh is starting
+        let unpublishing = node.volume_locks.lock(&volume.volume_id).await;
+        let mut refresh = pin!(node.refresh_volume(&target_path, &volume));
+        assert!(futures::poll!(&mut refresh).is_pending());
+        clean_secret_dir(&target_path, false).await.unwrap();
+        drop(unpublishing);
+
+        assert!(!refresh.await.unwrap());
+        // Otherwise kubelet couldn't remove the volume's directory
+        for path in [
+            target_path.clone(),
+            refreshable_volume_path(&target_path),
+            identity_path(&target_path),
+            selector_fingerprint_path(&target_path),
+            volume_layout_path(&target_path),
+        ] {
+            assert!(!fs::try_exists(&path).await.unwrap(), "{path:?} exists");
+        }
+    }
+
+    #[tokio::test]
+    async fn refresh_should_refuse_newer_layout_generations() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        published_volume(&target_path, "fingerprint").await;
+        let newer = (VOLUME_LAYOUT_GENERATION + 1).to_string();
+        fs::write_file(
+            &volume_layout_path(&target_path),
+            0o600,
+            None,
+            newer.as_bytes(),
+        )
+        .await
+        .unwrap();
+        let node = offline_node(dir.path());
+        let volume = RefreshableVolume::read_published(&target_path)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Fails before contacting the API server, which the offline node couldn't reach anyway
+        let err = node
+            .refresh_volume(&target_path, &volume)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                PublishError::VolumeLayout {
+                    source: VolumeLayoutError::UnsupportedGeneration { .. }
+                }
+            ),
+            "{err:?}"
+        );
+        assert_eq!(
+            fs::read(&target_path.join("tls.crt")).await.unwrap(),
+            b"old cert"
+        );
+    }
+
+    #[test]
+    fn volume_mount_group_should_only_be_advertised_if_file_groups_can_be_changed() {
+        let volume_mount_group = node_service_capability::rpc::Type::VolumeMountGroup;
+        assert!(node_capabilities(true).any(|rpc| rpc == volume_mount_group));
+        assert!(!node_capabilities(false).any(|rpc| rpc == volume_mount_group));
+        assert_eq!(
+            node_capabilities(false).count(),
+            NODE_CAPABILITIES.len() - 1
+        );
+    }
+
+    /// Drops `CAP_CHOWN` from the current thread, so that it can only give files to its own groups (even as root).
+    fn drop_chown_capability() {
+        const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+        const CAP_CHOWN: u32 = 0;
+        #[repr(C)]
+        struct Header {
+            version: u32,
+            pid: i32,
+        }
+        #[repr(C)]
+        #[derive(Default, Clone, Copy)]
+        struct Data {
+            effective: u32,
+            permitted: u32,
+            inheritable: u32,
+        }
+        let mut header = Header {
+            version: LINUX_CAPABILITY_VERSION_3,
+            // The calling thread
+            pid: 0,
+        };
+        let mut data = [Data::default(); 2];
+        // SAFETY: header and data match the layout expected by the kernel for version 3
+        unsafe {
+            assert_eq!(
+                libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()),
+                0
+            );
+            data[0].effective &= !(1 << CAP_CHOWN);
+            assert_eq!(
+                libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn denied_group_changes_should_be_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        // nogroup, which is also mapped in most build sandboxes
+        let foreign_group = 65534;
+        // SAFETY: getegid always succeeds
+        assert_ne!(unsafe { libc::getegid() }, foreign_group);
+        let target_path = dir.path().to_owned();
+        // Capabilities are per-thread, so don't affect other tests
+        let err = std::thread::spawn(move || {
+            drop_chown_capability();
+            set_volume_group(&target_path, foreign_group).unwrap_err()
+        })
+        .join()
+        .unwrap();
+        assert!(
+            matches!(err, PublishError::ChangeGroupDenied { group, .. } if group == foreign_group),
+            "{err:?}"
+        );
+        assert_eq!(Status::from(err).code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn save_secret_data_should_apply_requested_file_mode_and_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        fs::create_dir(&target_path).await.unwrap();
+        // Changing the group to one of our own groups is allowed even without privileges
+        let gid = unsafe { libc::getegid() };
+        let selector = SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, value::Error>>(
+            HashMap::from([
+                ("secrets.stackable.tech/class", "my-class".to_string()),
+                ("csi.storage.k8s.io/pod.name", "my-pod".to_string()),
+                (
+                    "csi.storage.k8s.io/pod.namespace",
+                    "my-namespace".to_string(),
+                ),
+                ("secrets.stackable.tech/file.mode", "0400".to_string()),
+                ("secrets.stackable.tech/file.group", gid.to_string()),
+            ])
+            .into_deserializer(),
+        )
+        .unwrap();
+        let data = SecretContents {
+            data: SecretData::Unknown(HashMap::from([(
+                "nested/secret".to_string(),
+                b"hunter2".to_vec(),
+            )])),
+            expires_after: None,
+            identity: SecretIdentity::default(),
+        };
+
+        save_secret_data(&target_path, data, selector)
+            .await
+            .unwrap();
+        let metadata = target_path.join("nested/secret").metadata().unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o400);
+        assert!(metadata.permissions().readonly());
+        assert_eq!(metadata.gid(), gid);
+        // Only the owner can read the file, so nobody else may list the directory either
+        let metadata = target_path.join("nested").metadata().unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o700);
+        assert_eq!(metadata.gid(), gid);
+    }
+
+    #[test]
+    fn dirs_should_be_accessible_to_file_readers() {
+        assert_eq!(dir_mode(0o640), 0o750);
+        assert_eq!(dir_mode(0o600), 0o700);
+        assert_eq!(dir_mode(0o644), 0o755);
+        assert_eq!(dir_mode(0o400), 0o700);
+    }
+
+    #[test]
+    fn volume_mount_group_should_be_parsed() {
+        let mount = |group: &str| VolumeCapability {
+            access_type: Some(volume_capability::AccessType::Mount(
+                volume_capability::MountVolume {
+                    volume_mount_group: group.to_string(),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+        assert_eq!(
+            volume_mount_group(Some(&mount("1000"))).unwrap(),
+            Some(1000)
+        );
+        // Kubelet only sets the group if the Pod has an fsGroup
+        assert_eq!(volume_mount_group(Some(&mount(""))).unwrap(), None);
+        assert_eq!(volume_mount_group(None).unwrap(), None);
+        assert_eq!(
+            Status::from(volume_mount_group(Some(&mount("wheel"))).unwrap_err()).code(),
+            Code::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_save_should_not_leave_partial_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        fs::create_dir(&target_path).await.unwrap();
+        // Published previously
+        fs::write_file(&target_path.join("tls.crt"), 0o640, None, b"old cert")
+            .await
+            .unwrap();
+        // Using a regular file as a directory fails regardless of our privileges
+        fs::write_file(&target_path.join("not-a-dir"), 0o640, None, b"")
+            .await
+            .unwrap();
+        let selector = || {
+            SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, value::Error>>(
+                HashMap::from([
+                    ("secrets.stackable.tech/class", "my-class"),
+                    ("csi.storage.k8s.io/pod.name", "my-pod"),
+                    ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+                ])
+                .into_deserializer(),
+            )
+            .unwrap()
+        };
+        let data = |files: &[(&str, &[u8])]| SecretContents {
+            data: SecretData::Unknown(
+                files
+                    .iter()
+                    .map(|(name, contents)| (name.to_string(), contents.to_vec()))
+                    .collect(),
+            ),
+            expires_after: None,
+            identity: SecretIdentity::default(),
+        };
+
+        save_secret_data(
+            &target_path,
+            data(&[
+                ("tls.crt", b"new cert"),
+                ("tls.key", b"new key"),
+                ("not-a-dir/ca.crt", b"new ca"),
+            ]),
+            selector(),
+        )
+        .await
+        .unwrap_err();
+        // No new or staged files are visible, and the previous files are kept
+        for file in ["tls.key", fs::StagingDir::NAME] {
+            assert!(
+                !fs::try_exists(&target_path.join(file)).await.unwrap(),
+                "{file}"
+            );
+        }
+        assert_eq!(
+            fs::read(&target_path.join("tls.crt")).await.unwrap(),
+            b"old cert"
+        );
+
+        // Re-publishing replaces the previous files
+        save_secret_data(
+            &target_path,
+            data(&[("tls.crt", b"new cert"), ("tls.key", b"new key")]),
+            selector(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fs::read(&target_path.join("tls.crt")).await.unwrap(),
+            b"new cert"
+        );
+        assert_eq!(
+            fs::read(&target_path.join("tls.key")).await.unwrap(),
+            b"new key"
+        );
+    }
+
+    #[tokio::test]
+    async fn secret_files_should_not_use_staging_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_name = format!("{}/new/secret", fs::StagingDir::NAME);
+        let err = write_secret_files(
+            dir.path(),
+            [(file_name.as_str(), b"hunter2".as_slice())],
+            0o640,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(err, PublishError::ReservedFileName { .. }),
+            "{err:?}"
+        );
+        assert!(
+            !fs::try_exists(&dir.path().join(fs::StagingDir::NAME))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn unpublish_should_remove_created_dirs_deepest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        fs::create_dir(&target_path).await.unwrap();
+        // Created by the Pod, so it must never be removed by the manifest
+        fs::create_dir(&target_path.join("existing")).await.unwrap();
+        write_secret_files(
+            &target_path,
+            [
+                ("a/b/secret", b"hunter2".as_slice()),
+                ("a/c/secret", b"hunter2".as_slice()),
+                ("existing/x/secret", b"hunter2".as_slice()),
+            ],
+            0o640,
+            None,
+        )
+        .await
+        .unwrap();
+        let manifest = VolumeManifest::read(&target_path).await.unwrap();
+        assert_eq!(
+            manifest.dirs,
+            ["a", "a/b", "a/c", "existing/x"]
+                .into_iter()
+                .map(PathBuf::from)
+                .collect()
+        );
+        assert_eq!(manifest.files.len(), 3);
+        fs::write_file(&target_path.join("a/c/pod-file"), 0o640, None, b"")
+            .await
+            .unwrap();
+
+        manifest.remove_from(&target_path);
+        for removed in ["a/b", "a/c/secret", "existing/x"] {
+            assert!(
+                !fs::try_exists(&target_path.join(removed)).await.unwrap(),
+                "{removed}"
+            );
+        }
+        // Directories that are still in use are kept
+        for kept in ["a/c/pod-file", "existing"] {
+            assert!(
+                fs::try_exists(&target_path.join(kept)).await.unwrap(),
+                "{kept}"
+            );
+        }
+
+        clean_secret_dir(&target_path, false).await.unwrap();
+        assert!(dir.path().read_dir().unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_write_should_remove_created_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        // Using a regular file as a directory fails regardless of our privileges
+        fs::write_file(&dir.path().join("not-a-dir"), 0o640, None, b"")
+            .await
+            .unwrap();
+        write_secret_files(
+            dir.path(),
+            [
+                ("nested/deeper/ca.crt", b"new ca".as_slice()),
+                ("not-a-dir/ca.crt", b"new ca".as_slice()),
+            ],
+            0o640,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(!fs::try_exists(&dir.path().join("nested")).await.unwrap());
+        assert!(
+            !fs::try_exists(&volume_manifest_path(dir.path()))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[derive(Debug, Snafu)]
+    #[snafu(display("failed to connect to secret store"))]
+    struct ConnectError {
+        source: std::io::Error,
+    }
+
+    impl SecretBackendError for ConnectError {
+        fn grpc_code(&self) -> Code {
+            Code::Unavailable
+        }
+    }
+
+    /// Can never reach wherever it would get its secrets from.
+    #[derive(Debug)]
+    struct UnreachableBackend;
+
+    #[async_trait]
+    impl SecretBackend for UnreachableBackend {
+        type Error = ConnectError;
+
+        async fn get_secret_data(
+            &self,
+            _selector: &SecretVolumeSelector,
+            _pod_info: PodInfo,
+        ) -> Result<SecretContents, Self::Error> {
+            Err(std::io::Error::from(ErrorKind::ConnectionRefused)).context(ConnectSnafu)
+        }
+    }
+
+    /// A `Pod` without any addresses or listeners.
+    fn pod_info() -> PodInfo {
+        PodInfo {
+            pod_ips: Vec::new(),
+            service_name: None,
+            node_name: "my-node".to_string(),
+            node_ips: Vec::new(),
+            node: NodeInfo {
+                labels: Default::default(),
+                internal_ips: Vec::new(),
+            },
+            listener_addresses: HashMap::new(),
+            kubernetes_cluster_domain: "cluster.local".parse().unwrap(),
+            scheduling: SchedulingPodInfo {
+                namespace: "my-namespace".to_string(),
+                volume_listener_names: HashMap::new(),
+                has_node_scope: false,
+            },
+            dependency_wait: DependencyWait::none(),
+        }
+    }
+
+    #[tokio::test]
+    async fn publish_error_status_should_include_every_cause() {
+        let selector = SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, value::Error>>(
+            HashMap::from([
+                ("secrets.stackable.tech/class", "my-class"),
+                ("csi.storage.k8s.io/pod.name", "my-pod"),
+                ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+            ])
+            .into_deserializer(),
+        )
+        .unwrap();
+        let source = backend::dynamic::from(UnreachableBackend)
+            .get_secret_data(&selector, pod_info())
+            .await
+            .unwrap_err();
+
+        let status = Status::from(PublishError::BackendGetSecretData { source });
+        let status_message = status.message();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(
+            status_message.contains("backend failed")
+                && status_message.contains("connection refused"),
+            "{status_message}"
+        );
+        assert_eq!(
+            status_message,
+            "backend failed to get secret data: failed to connect to secret store: connection refused"
+        );
+    }
+
+    /// Takes a coordination slot for every secret that it issues, like the Kerberos backend does while provisioning
+    /// keytabs.
+    #[derive(Debug)]
+    struct CoordinatedBackend {
+        leases: LeasePool,
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl SecretBackend for CoordinatedBackend {
+        type Error = Infallible;
+
+        async fn get_secret_data(
+            &self,
+            _selector: &SecretVolumeSelector,
+            _pod_info: PodInfo,
+        ) -> Result<SecretContents, Self::Error> {
+            self.leases
+                .run("provision-keytab", async {
+                    let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+                    self.max_running.fetch_max(running, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(30)).await;
+                    self.running.fetch_sub(1, Ordering::SeqCst);
+                })
+                .await;
+            Ok(SecretContents {
+                data: SecretData::Unknown(HashMap::from([(
+                    "keytab".to_string(),
+                    b"new keytab".to_vec(),
+                )])),
+                expires_after: None,
+                identity: SecretIdentity::default(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn refreshes_should_leave_coordination_to_backend() {
+        tokio::time::pause();
+        let dir = tempfile::tempdir().unwrap();
+        let api = Arc::new(FakeLeaseApi::default());
+        // Fewer slots than refreshes that run at the same time
+        let leases = coordination::testing::pool(&api, 1, "my-node");
+        let node = SecretProvisionerNode {
+            leases: leases.clone(),
+            ..offline_node(dir.path())
+        };
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let backend = backend::dynamic::from(CoordinatedBackend {
+            leases,
+            running: Arc::default(),
+            max_running: max_running.clone(),
+        });
+        let mut volumes = Vec::new();
+        for volume_id in ["volume-a", "volume-b"] {
+            let target_path = dir.path().join(volume_id);
+            fs::create_dir(&target_path).await.unwrap();
+            let volume = RefreshableVolume {
+                volume_id: volume_id.to_string(),
+                volume_context: [
+                    ("secrets.stackable.tech/class", "kerberos"),
+                    ("csi.storage.k8s.io/pod.name", volume_id),
+                    ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+                ]
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+                volume_mount_group: None,
+                rotated_for: None,
+            };
+            volume.record(&target_path).await.unwrap();
+            volumes.push((target_path, volume));
+        }
+
+        let started = Instant::now();
+        futures::stream::iter(&volumes)
+            .map(|(target_path, volume)| {
+                node.reissue_volume(
+                    target_path,
+                    volume.clone(),
+                    volume.selector().unwrap(),
+                    &backend,
+                    pod_info(),
+                    Some("my-rotation".to_string()),
+                )
+            })
+            .buffer_unordered(2)
+            .try_collect::<Vec<()>>()
+            .await
+            .unwrap();
+
+        // Each refresh only waits for the other one, rather than for a slot that it holds itself
+        assert!(
+            started.elapsed() < coordination::testing::ACQUIRE_TIMEOUT,
+            "refreshes took {:?}",
+            started.elapsed()
+        );
+        assert_eq!(max_running.load(Ordering::SeqCst), 1);
+        assert_eq!(api.holder("secret-operator-expensive-operation-0"), None);
+        for (target_path, _) in &volumes {
+            assert_eq!(
+                fs::read_to_string(&target_path.join("keytab"))
+                    .await
+                    .unwrap(),
+                "new keytab"
+            );
+            let refreshed = RefreshableVolume::read_published(target_path)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(refreshed.rotated_for.as_deref(), Some("my-rotation"));
+        }
+    }
+
+    #[tokio::test]
+    async fn unpublish_error_status_should_include_every_cause() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        let err = UnpublishError::from(fs::read(&missing).await.unwrap_err());
+
+        let status = Status::from(err);
+        let status_message = status.message();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            status_message,
+            format!("failed to read file {missing:?}: No such file or directory (os error 2)")
+        );
+    }
+
+    /// Signs every CSR, without a real CA.
+    #[derive(Debug)]
+    struct FakeCsrSigner;
+
+    #[async_trait]
+    impl SecretBackend for FakeCsrSigner {
+        type Error = Infallible;
+
+        async fn get_secret_data(
+            &self,
+            _selector: &SecretVolumeSelector,
+            _pod_info: PodInfo,
+        ) -> Result<SecretContents, Self::Error> {
+            unimplemented!("only used for the CSR handshake")
+        }
+
+        async fn sign_csr(
+            &self,
+            _selector: &SecretVolumeSelector,
+            csr: &ValidatedCsr,
+        ) -> Result<Option<SignedCsr>, Self::Error> {
+            Ok(Some(SignedCsr {
+                certificate_pem: format!("certificate for {} names", csr.names.len()).into_bytes(),
+                ca_pem: b"fake ca".to_vec(),
+                expires_after: None,
+                identity: SecretIdentity {
+                    subject_alt_names: csr.names.iter().map(ToString::to_string).collect(),
+                    ..SecretIdentity::default()
+                },
+            }))
+        }
+    }
+
+    fn csr_handshake_selector(timeout: &str) -> SecretVolumeSelector {
+        SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, value::Error>>(
+            HashMap::from([
+                ("secrets.stackable.tech/class", "tls"),
+                ("csi.storage.k8s.io/pod.name", "my-pod"),
+                ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+                (
+                    "secrets.stackable.tech/backend.autotls.csr-handshake",
+                    "true",
+                ),
+                (
+                    "secrets.stackable.tech/backend.autotls.csr-handshake.timeout",
+                    timeout,
+                ),
+            ])
+            .into_deserializer(),
+        )
+        .unwrap()
+    }
+
+    fn csr_request() -> CsrRequest {
+        CsrRequest {
+            dns_names: vec!["my-pod.my-svc.my-namespace.svc.cluster.local".to_string()],
+            ip_addresses: vec![],
+            key: CsrKeyRequirements::Rsa { min_length: 2048 },
+        }
+    }
+
+    /// Generates a key and a CSR for `dns_names`, like the Pod's init container would.
+    fn generate_csr(dns_names: &[&str]) -> Vec<u8> {
+        let key = PKey::try_from(Rsa::generate(2048).unwrap()).unwrap();
+        let mut req = X509ReqBuilder::new().unwrap();
+        req.set_pubkey(&key).unwrap();
+        let mut san = SubjectAlternativeName::new();
+        for dns in dns_names {
+            san.dns(dns);
+        }
+        let mut extensions = Stack::new().unwrap();
+        extensions
+            .push(san.build(&req.x509v3_context(None)).unwrap())
+            .unwrap();
+        req.add_extensions(&extensions).unwrap();
+        req.sign(&key, MessageDigest::sha256()).unwrap();
+        req.build().to_pem().unwrap()
+    }
+
+    #[tokio::test]
+    async fn csr_handshake_should_sign_csr_written_by_pod() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        fs::create_dir(&target_path).await.unwrap();
+        let selector = csr_handshake_selector("1m");
+        write_csr_request(&target_path, &csr_request(), &selector)
+            .await
+            .unwrap();
+        let published_request: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(&target_path.join(CSR_REQUEST_FILE_NAME))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            published_request["dnsNames"],
+            serde_json::json!(["my-pod.my-svc.my-namespace.svc.cluster.local"])
+        );
+
+        // The init container only starts once the volume has been published
+        let init_container = tokio::spawn({
+            let csr_path = target_path.join(CSR_FILE_NAME);
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let csr = generate_csr(&["my-pod.my-svc.my-namespace.svc.cluster.local"]);
+                // Simulate a slow writer, the handshake must not pick up the partial CSR
+                let (start, end) = csr.split_at(csr.len() / 2);
+                fs::write_file(&csr_path, 0o644, None, start).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                fs::write_file(&csr_path, 0o644, None, &[start, end].concat())
+                    .await
+                    .unwrap();
+            }
+        });
+        let backend = backend::dynamic::from(FakeCsrSigner);
+        let mut signed_identity = None;
+        let completed = run_csr_handshake(
+            &target_path,
+            &*backend,
+            &selector,
+            &csr_request(),
+            Duration::from_millis(10),
+            async |signed| {
+                signed_identity = Some(signed.identity.clone());
+                Ok(())
+            },
+        )
+        .await
+        .unwrap();
+        init_container.await.unwrap();
+
+        assert!(completed);
+        assert_eq!(
+            signed_identity.unwrap().subject_alt_names,
+            ["my-pod.my-svc.my-namespace.svc.cluster.local"]
+        );
+        assert_eq!(
+            fs::read_to_string(&target_path.join("tls.crt"))
+                .await
+                .unwrap(),
+            "certificate for 1 names"
+        );
+        assert_eq!(
+            fs::read_to_string(&target_path.join("ca.crt"))
+                .await
+                .unwrap(),
+            "fake ca"
+        );
+        assert!(target_path.join(CSR_READY_FILE_NAME).exists());
+    }
+
+    #[tokio::test]
+    async fn csr_handshake_should_reject_name_escalation() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        fs::create_dir(&target_path).await.unwrap();
+        fs::write_file(
+            &target_path.join(CSR_FILE_NAME),
+            0o644,
+            None,
+            &generate_csr(&[
+                "my-pod.my-svc.my-namespace.svc.cluster.local",
+                "kubernetes.default.svc.cluster.local",
+            ]),
+        )
+        .await
+        .unwrap();
+
+        let backend = backend::dynamic::from(FakeCsrSigner);
+        let err = run_csr_handshake(
+            &target_path,
+            &*backend,
+            &csr_handshake_selector("1m"),
+            &csr_request(),
+            Duration::from_millis(10),
+            async |_| panic!("pod must not be tagged for a rejected CSR"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.event_reason(), "CsrNameEscalation", "{err:?}");
+        assert!(!target_path.join("tls.crt").exists());
+        assert!(!target_path.join(CSR_READY_FILE_NAME).exists());
+    }
+
+    #[tokio::test]
+    async fn csr_handshake_should_time_out_without_csr() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_path = dir.path().join("mount");
+        let backend = backend::dynamic::from(FakeCsrSigner);
+        let selector = csr_handshake_selector("1s");
+
+        // The volume was unpublished before the Pod wrote its CSR
+        let completed = run_csr_handshake(
+            &target_path,
+            &*backend,
+            &selector,
+            &csr_request(),
+            Duration::from_millis(10),
+            async |_| Ok(()),
+        )
+        .await
+        .unwrap();
+        assert!(!completed);
+
+        fs::create_dir(&target_path).await.unwrap();
+        let err = run_csr_handshake(
+            &target_path,
+            &*backend,
+            &selector,
+            &csr_request(),
+            Duration::from_millis(10),
+            async |_| Ok(()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.event_reason(), "CsrTimeout", "{err:?}");
+        assert!(!target_path.join(CSR_READY_FILE_NAME).exists());
+    }
+
+    #[tokio::test]
+    async fn pending_csr_handshakes_should_be_found_after_restart() {
+        let volume_root = tempfile::tempdir().unwrap();
+        let volume_dir = volume_root
+            .path()
+            .join("my-pod-uid/volumes/kubernetes.io~csi/my-volume");
+        let target_path = volume_dir.join("mount");
+        fs::create_dir_all(&target_path, 0o750, None).await.unwrap();
+        let volume_context = HashMap::from([
+            ("secrets.stackable.tech/class", "tls"),
+            ("csi.storage.k8s.io/pod.name", "my-pod"),
+            ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+            (
+                "secrets.stackable.tech/backend.autotls.csr-handshake",
+                "true",
+            ),
+        ])
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        write_pending_csr_handshake(
+            &target_path,
+            &PendingCsrHandshake {
+                volume_id: "my-volume-id".to_string(),
+                volume_context,
+                volume_mount_group: Some(1000),
+                csr_request: csr_request(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let found =
+            identity_api::find_next_to_volumes(volume_root.path(), PENDING_CSR_HANDSHAKE_SUFFIX)
+                .await;
+        assert_eq!(
+            found,
+            [(
+                pending_csr_handshake_path(&target_path),
+                target_path.clone()
+            )]
+        );
+        let pending =
+            serde_json::from_slice::<PendingCsrHandshake>(&fs::read(&found[0].0).await.unwrap())
+                .unwrap();
+        assert_eq!(pending.volume_id, "my-volume-id");
+        assert_eq!(pending.volume_mount_group, Some(1000));
+        assert_eq!(pending.csr_request.dns_names, csr_request().dns_names);
+        let selector = SecretVolumeSelector::deserialize::<MapDeserializer<'_, _, value::Error>>(
+            pending.volume_context.into_deserializer(),
+        )
+        .unwrap();
+        assert!(selector.autotls_csr_handshake);
+
+        // Kubelet must be able to remove the volume's directory once it has been unpublished
+        clean_secret_dir(&target_path, false).await.unwrap();
+        assert!(volume_dir.read_dir().unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn csr_handshakes_should_be_tracked_by_volume() {
+        let handshakes = CsrHandshakes::default();
+        let (finish, finished) = tokio::sync::oneshot::channel::<()>();
+        handshakes.spawn("finishes".to_string(), async move {
+            let _ = finished.await;
+        });
+        handshakes.spawn("cancelled".to_string(), std::future::pending());
+        assert!(handshakes.is_running("finishes"));
+        assert!(handshakes.is_running("cancelled"));
+
+        finish.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while handshakes.is_running("finishes") {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        handshakes.cancel("cancelled");
+        assert!(!handshakes.is_running("cancelled"));
+    }
+
+    #[tokio::test]
+    async fn republished_csr_handshake_should_replace_previous_one() {
+        let handshakes = CsrHandshakes::default();
+        let (previous_dropped, previous_cancelled) = tokio::sync::oneshot::channel::<()>();
+        handshakes.spawn("my-volume".to_string(), async move {
+            // Dropped along with the task once it is cancelled
+            let _previous_dropped = previous_dropped;
+            std::future::pending::<()>().await;
+        });
+        handshakes.spawn("my-volume".to_string(), async {});
+
+        // Resolves with an error once the sender has been dropped
+        tokio::time::timeout(Duration::from_secs(10), previous_cancelled)
+            .await
+            .unwrap()
+            .unwrap_err();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while handshakes.is_running("my-volume") {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[cfg(feature = "failpoints")]
+    mod failpoints {
+        use std::time::Duration;
+
+        use super::{
+            CSR_FILE_NAME, CSR_READY_FILE_NAME, FakeCsrSigner, clean_secret_dir,
+            csr_handshake_selector, csr_request, ensure_selector_unchanged, generate_csr,
+            published_volume, run_csr_handshake, selector_fingerprint_path,
+            write_selector_fingerprint,
+        };
+        use crate::{
+            backend,
+            utils::{failpoint, fs},
+        };
+
+        #[tokio::test]
+        async fn missing_fingerprint_should_not_block_republish() {
+            let dir = tempfile::tempdir().unwrap();
+            let target_path = dir.path().join("mount");
+            fs::create_dir(&target_path).await.unwrap();
+            failpoint::configure_for_current_thread("node.publish.before_fingerprint=1*return")
+                .unwrap();
+
+            write_selector_fingerprint(&target_path, "my-selector")
+                .await
+                .unwrap_err();
+            assert!(!selector_fingerprint_path(&target_path).exists());
+            // Kubelet retries the failed publish, which must not be mistaken for a selector change
+            ensure_selector_unchanged(&target_path, "my-selector", false, false)
+                .await
+                .unwrap();
+            write_selector_fingerprint(&target_path, "my-selector")
+                .await
+                .unwrap();
+            assert!(selector_fingerprint_path(&target_path).exists());
+        }
+
+        #[tokio::test]
+        async fn interrupted_unpublish_should_be_retryable() {
+            let dir = tempfile::tempdir().unwrap();
+            let target_path = dir.path().join("mount");
+            published_volume(&target_path, "old").await;
+            failpoint::configure_for_current_thread(
+                "node.unpublish.before_remove_fingerprint=1*return",
+            )
+            .unwrap();
+
+            clean_secret_dir(&target_path, false).await.unwrap_err();
+            assert!(!target_path.exists());
+            assert!(selector_fingerprint_path(&target_path).exists());
+
+            // The volume is already gone, so the retry only needs to finish the cleanup
+            clean_secret_dir(&target_path, false).await.unwrap();
+            assert!(dir.path().read_dir().unwrap().next().is_none());
+        }
+
+        #[tokio::test]
+        async fn failed_csr_handshake_should_not_mark_certificate_ready() {
+            let dir = tempfile::tempdir().unwrap();
+            let target_path = dir.path().join("mount");
+            fs::create_dir(&target_path).await.unwrap();
+            fs::write_file(
+                &target_path.join(CSR_FILE_NAME),
+                0o644,
+                None,
+                &generate_csr(&["my-pod.my-svc.my-namespace.svc.cluster.local"]),
+            )
+            .await
+            .unwrap();
+            failpoint::configure_for_current_thread("node.csr_handshake.before_ready=return")
+                .unwrap();
+
+            let backend = backend::dynamic::from(FakeCsrSigner);
+            run_csr_handshake(
+                &target_path,
+                &*backend,
+                &csr_handshake_selector("1m"),
+                &csr_request(),
+                Duration::from_millis(10),
+                async |_| Ok(()),
+            )
+            .await
+            .unwrap_err();
+            // The init container keeps waiting (and eventually fails), rather than starting with a broken volume
+            assert!(!target_path.join(CSR_READY_FILE_NAME).exists());
+        }
     }
-    res
 }
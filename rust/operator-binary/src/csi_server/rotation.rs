@@ -0,0 +1,510 @@
+//! Refreshes the volumes on this node when their SecretClass requests a forced rotation (see
+//! [`FORCE_ROTATION_ANNOTATION`](crate::backend::tls::FORCE_ROTATION_ANNOTATION)).
+//!
+//! Every volume records the rotation that its secret was last issued for (see [`RefreshableVolume`]), which doubles as
+//! the progress marker of a rotation. A rotation that was interrupted (such as by a restart) resumes with the volumes
+//! that haven't been refreshed yet, and requesting the same rotation again doesn't refresh anything.
+//!
+//! Volumes that use the CSR handshake, or that were published by older versions of secret-operator, are not recorded,
+//! and are only rotated once their `Pod` is restarted.
+
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::StreamExt as _;
+use serde_json::json;
+use snafu::{ResultExt, Snafu};
+use stackable_operator::kube::{
+    self,
+    api::{Patch, PatchParams},
+    runtime::reflector::ObjectRef,
+};
+use stackable_secret_operator_crd_utils::ownership;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use super::node::{PublishError, RefreshableVolume, SecretProvisionerNode};
+use crate::{
+    backend,
+    crd::{NodeRotationProgress, SecretClass, SecretClassStatus},
+    metrics::{self, RotationRefreshOutcome},
+};
+
+/// How often to check whether the SecretClasses of this node's volumes have requested a new rotation.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// How often to update the SecretClass status while its volumes are being refreshed.
+const PROGRESS_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+const FIELD_MANAGER_SCOPE: &str = "rotation";
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum RotationError {
+    #[snafu(display("failed to get {class}"))]
+    GetSecretClass {
+        source: stackable_operator::client::Error,
+        class: ObjectRef<SecretClass>,
+    },
+
+    #[snafu(display("failed to reissue secret"))]
+    Reissue { source: PublishError },
+}
+
+/// Everything that a rotation interacts with, so that it can be replaced in tests.
+#[async_trait]
+trait RotationTarget: Send + Sync {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// The forced rotation that the SecretClass `class` requests, if any.
+    async fn requested_rotation(&self, class: &str) -> Result<Option<String>, Self::Error>;
+
+    /// Reissues the secret of the `volume` published at `target_path`, and records the rotation that it was issued for.
+    ///
+    /// Returns `false` if the volume has been unpublished since it was found.
+    async fn refresh(
+        &self,
+        target_path: &Path,
+        volume: &RefreshableVolume,
+    ) -> Result<bool, Self::Error>;
+
+    /// Reports how far this node has come with rotating the volumes of the SecretClass `class`.
+    async fn report(&self, class: &str, progress: &NodeRotationProgress);
+}
+
+#[async_trait]
+impl RotationTarget for SecretProvisionerNode {
+    type Error = RotationError;
+
+    async fn requested_rotation(&self, class: &str) -> Result<Option<String>, RotationError> {
+        let secret_class = self.client.get::<SecretClass>(class, &()).await.context(
+            rotation_error::GetSecretClassSnafu {
+                class: ObjectRef::new(class),
+            },
+        )?;
+        Ok(backend::dynamic::force_rotation(&secret_class))
+    }
+
+    async fn refresh(
+        &self,
+        target_path: &Path,
+        volume: &RefreshableVolume,
+    ) -> Result<bool, RotationError> {
+        self.refresh_volume(target_path, volume)
+            .await
+            .context(rotation_error::ReissueSnafu)
+    }
+
+    async fn report(&self, class: &str, progress: &NodeRotationProgress) {
+        let status = SecretClassStatus {
+            rotation_progress: [(self.node_name.clone(), progress.clone())].into(),
+        };
+        let params = PatchParams {
+            field_manager: Some(ownership::field_manager(FIELD_MANAGER_SCOPE)),
+            ..Default::default()
+        };
+        // Merge patches only replace this node's entry, so every node can report its own progress
+        let result = kube::Api::<SecretClass>::all(self.client.as_kube_client())
+            .patch_status(class, &params, &Patch::Merge(json!({ "status": status })))
+            .await;
+        if let Err(err) = result {
+            tracing::warn!(
+                class = %ObjectRef::<SecretClass>::new(class),
+                error = &err as &dyn std::error::Error,
+                "failed to report rotation progress"
+            );
+        }
+    }
+}
+
+/// Refreshes the volumes on this node whenever their SecretClass requests a new forced rotation, starting with any
+/// rotations that were interrupted before the provisioner was restarted.
+///
+/// At most `parallelism` volumes are refreshed at the same time. Backends still take a coordination slot (see
+/// [`LeasePool`](backend::coordination::LeasePool)) for their expensive operations, such as provisioning keytabs.
+pub async fn run(node: Arc<SecretProvisionerNode>, parallelism: NonZeroUsize) {
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    // Refreshing many volumes can take longer than the poll interval, which shouldn't be followed by a burst of polls
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        poll.tick().await;
+        rotate(&*node, &node.volume_root, parallelism).await;
+    }
+}
+
+/// Refreshes the volumes in `volume_root` that haven't been refreshed for the rotation requested by their SecretClass.
+async fn rotate(target: &impl RotationTarget, volume_root: &Path, parallelism: NonZeroUsize) {
+    let mut volumes_by_class = BTreeMap::<String, Vec<_>>::new();
+    for (target_path, volume) in RefreshableVolume::find(volume_root).await {
+        match volume.selector() {
+            Ok(selector) => volumes_by_class
+                .entry(selector.class)
+                .or_default()
+                .push((target_path, volume)),
+            Err(err) => tracing::warn!(
+                volume.path = %target_path.display(),
+                error = &err as &dyn std::error::Error,
+                "failed to parse selector of refreshable volume, skipping..."
+            ),
+        }
+    }
+    for (class, volumes) in volumes_by_class {
+        match target.requested_rotation(&class).await {
+            Ok(Some(rotation)) => {
+                rotate_class(target, &class, &rotation, volumes, parallelism).await
+            }
+            Ok(None) => {}
+            Err(err) => tracing::warn!(
+                class = %ObjectRef::<SecretClass>::new(&class),
+                error = &err as &dyn std::error::Error,
+                "failed to check for forced rotation, retrying later"
+            ),
+        }
+    }
+}
+
+/// Refreshes the `volumes` of `class` that haven't been refreshed for `rotation` yet.
+///
+/// Volumes that fail to be refreshed keep their previous progress marker, so they are retried by the next [`rotate`].
+async fn rotate_class<T: RotationTarget>(
+    target: &T,
+    class: &str,
+    rotation: &str,
+    volumes: Vec<(PathBuf, RefreshableVolume)>,
+    parallelism: NonZeroUsize,
+) {
+    let total = volumes.len();
+    let pending = volumes
+        .into_iter()
+        .filter(|(_, volume)| volume.rotated_for.as_deref() != Some(rotation))
+        .collect::<Vec<_>>();
+    if pending.is_empty() {
+        return;
+    }
+    let class_ref = ObjectRef::<SecretClass>::new(class);
+    tracing::info!(
+        class = %class_ref,
+        rotation,
+        volumes.total = total,
+        volumes.pending = pending.len(),
+        "refreshing volumes for forced rotation"
+    );
+    let mut progress = NodeRotationProgress {
+        rotation: rotation.to_string(),
+        total: total as u32,
+        refreshed: (total - pending.len()) as u32,
+        failed: 0,
+    };
+    target.report(class, &progress).await;
+    let mut last_report = Instant::now();
+    let mut refreshes = futures::stream::iter(pending)
+        .map(|(target_path, volume)| async move {
+            let result = target.refresh(&target_path, &volume).await;
+            (target_path, result)
+        })
+        .buffer_unordered(parallelism.get());
+    while let Some((target_path, result)) = refreshes.next().await {
+        match result {
+            Ok(true) => {
+                progress.refreshed += 1;
+                metrics::record_rotation_refresh(RotationRefreshOutcome::Refreshed);
+            }
+            // The volume is gone, so it no longer needs to be rotated
+            Ok(false) => progress.total -= 1,
+            Err(err) => {
+                progress.failed += 1;
+                metrics::record_rotation_refresh(RotationRefreshOutcome::Failed);
+                tracing::warn!(
+                    class = %class_ref,
+                    volume.path = %target_path.display(),
+                    error = &err as &dyn std::error::Error,
+                    "failed to refresh volume for forced rotation, retrying later"
+                );
+            }
+        }
+        if last_report.elapsed() >= PROGRESS_REPORT_INTERVAL {
+            target.report(class, &progress).await;
+            last_report = Instant::now();
+        }
+    }
+    tracing::info!(
+        class = %class_ref,
+        rotation,
+        volumes.refreshed = progress.refreshed,
+        volumes.failed = progress.failed,
+        "finished refreshing volumes for forced rotation"
+    );
+    target.report(class, &progress).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeSet, HashMap},
+        num::NonZeroUsize,
+        path::{Path, PathBuf},
+        sync::{
+            Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use async_trait::async_trait;
+    use snafu::Snafu;
+    use tokio::sync::Notify;
+
+    use super::{RotationTarget, rotate};
+    use crate::{
+        crd::NodeRotationProgress, csi_server::node::RefreshableVolume,
+        metrics::ROTATION_REFRESHES, utils::fs,
+    };
+
+    const VOLUMES: usize = 50;
+    const PARALLELISM: NonZeroUsize = NonZeroUsize::new(4).unwrap();
+
+    #[derive(Debug, Snafu)]
+    #[snafu(display("injected failure"))]
+    struct InjectedFailure;
+
+    /// Simulates the volumes' backends and SecretClasses.
+    #[derive(Default)]
+    struct FakeTarget {
+        /// The rotation requested by the SecretClass `tls`, other SecretClasses never request any.
+        rotation: Mutex<Option<String>>,
+        /// The volumes that fail the next time that they are refreshed.
+        failing: Mutex<BTreeSet<String>>,
+        /// Simulates the provisioner being stopped, by never finishing refreshes after this many have been started.
+        hang_after: Mutex<Option<usize>>,
+        hanging: Notify,
+        /// The volumes that refreshes were started for, in order.
+        attempts: Mutex<Vec<String>>,
+        failures: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        reports: Mutex<Vec<NodeRotationProgress>>,
+    }
+
+    impl FakeTarget {
+        /// Forgets everything about the previous run, as if the provisioner had been restarted.
+        fn restart(&self) {
+            *self.hang_after.lock().unwrap() = None;
+            self.attempts.lock().unwrap().clear();
+            self.failures.store(0, Ordering::SeqCst);
+            self.in_flight.store(0, Ordering::SeqCst);
+            self.reports.lock().unwrap().clear();
+        }
+
+        fn attempts(&self) -> Vec<String> {
+            self.attempts.lock().unwrap().clone()
+        }
+
+        fn last_report(&self) -> Option<NodeRotationProgress> {
+            self.reports.lock().unwrap().last().cloned()
+        }
+    }
+
+    #[async_trait]
+    impl RotationTarget for FakeTarget {
+        type Error = InjectedFailure;
+
+        async fn requested_rotation(&self, class: &str) -> Result<Option<String>, InjectedFailure> {
+            Ok(match class {
+                "tls" => self.rotation.lock().unwrap().clone(),
+                _ => None,
+            })
+        }
+
+        async fn refresh(
+            &self,
+            target_path: &Path,
+            volume: &RefreshableVolume,
+        ) -> Result<bool, InjectedFailure> {
+            let attempt = {
+                let mut attempts = self.attempts.lock().unwrap();
+                attempts.push(volume.volume_id.clone());
+                attempts.len()
+            };
+            if self
+                .hang_after
+                .lock()
+                .unwrap()
+                .is_some_and(|hang_after| attempt > hang_after)
+            {
+                self.hanging.notify_one();
+                std::future::pending::<()>().await;
+            }
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            // Lets the other refreshes start, so that they overlap
+            tokio::task::yield_now().await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            if self.failing.lock().unwrap().remove(&volume.volume_id) {
+                self.failures.fetch_add(1, Ordering::SeqCst);
+                return Err(InjectedFailure);
+            }
+            let rotated_for = self.rotation.lock().unwrap().clone();
+            RefreshableVolume {
+                rotated_for,
+                ..volume.clone()
+            }
+            .record(target_path)
+            .await
+            .unwrap();
+            Ok(true)
+        }
+
+        async fn report(&self, class: &str, progress: &NodeRotationProgress) {
+            assert_eq!(class, "tls");
+            self.reports.lock().unwrap().push(progress.clone());
+        }
+    }
+
+    /// Publishes a volume of `class`, which has never been rotated.
+    async fn published_volume(volume_root: &Path, name: &str, class: &str) -> PathBuf {
+        let target_path = volume_root
+            .join(format!("{name}-uid/volumes/kubernetes.io~csi/{name}"))
+            .join("mount");
+        fs::create_dir_all(&target_path, 0o750, None).await.unwrap();
+        RefreshableVolume {
+            volume_id: name.to_string(),
+            volume_context: HashMap::from_iter(
+                [
+                    ("secrets.stackable.tech/class", class),
+                    ("csi.storage.k8s.io/pod.name", name),
+                    ("csi.storage.k8s.io/pod.namespace", "my-namespace"),
+                ]
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+            ),
+            volume_mount_group: None,
+            rotated_for: None,
+        }
+        .record(&target_path)
+        .await
+        .unwrap();
+        target_path
+    }
+
+    /// The IDs of the volumes in `volume_root` that have been refreshed for `rotation`.
+    async fn rotated_volumes(volume_root: &Path, rotation: &str) -> BTreeSet<String> {
+        RefreshableVolume::find(volume_root)
+            .await
+            .into_iter()
+            .filter(|(_, volume)| volume.rotated_for.as_deref() == Some(rotation))
+            .map(|(_, volume)| volume.volume_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rotation_should_resume_after_restart_and_retry_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let volume_root = dir.path();
+        for i in 0..VOLUMES {
+            published_volume(volume_root, &format!("pod-{i}"), "tls").await;
+        }
+        published_volume(volume_root, "other-pod", "other").await;
+        let failed_refreshes_before = ROTATION_REFRESHES.get(&["failed"]);
+
+        let target = FakeTarget::default();
+        *target.rotation.lock().unwrap() = Some("2025-01-01T00:00:00Z".to_string());
+        let failing = (0..VOLUMES)
+            .step_by(10)
+            .map(|i| format!("pod-{i}"))
+            .collect::<BTreeSet<_>>();
+        *target.failing.lock().unwrap() = failing.clone();
+        *target.hang_after.lock().unwrap() = Some(20);
+
+        // The provisioner is stopped while the rotation is in progress, interrupting the refreshes that were running
+        tokio::select! {
+            () = rotate(&target, volume_root, PARALLELISM) => panic!("rotation should have been interrupted"),
+            () = target.hanging.notified() => {}
+        }
+        let rotated_before_restart = rotated_volumes(volume_root, "2025-01-01T00:00:00Z").await;
+        assert!(!rotated_before_restart.is_empty());
+        assert!(rotated_before_restart.len() <= 20);
+        let max_in_flight = target.max_in_flight.load(Ordering::SeqCst);
+        assert!(
+            (2..=PARALLELISM.get()).contains(&max_in_flight),
+            "{max_in_flight} refreshes ran at the same time"
+        );
+
+        // Only the volumes that weren't refreshed before the restart are refreshed after it
+        target.restart();
+        rotate(&target, volume_root, PARALLELISM).await;
+        let attempts = target.attempts();
+        assert_eq!(attempts.len(), VOLUMES - rotated_before_restart.len());
+        assert!(
+            attempts
+                .iter()
+                .all(|id| !rotated_before_restart.contains(id))
+        );
+        let failures = target.failures.load(Ordering::SeqCst) as u32;
+        assert_eq!(
+            target.last_report(),
+            Some(NodeRotationProgress {
+                rotation: "2025-01-01T00:00:00Z".to_string(),
+                total: VOLUMES as u32,
+                refreshed: VOLUMES as u32 - failures,
+                failed: failures,
+            })
+        );
+
+        // Failed volumes are retried until they succeed
+        target.restart();
+        rotate(&target, volume_root, PARALLELISM).await;
+        assert_eq!(target.attempts().len(), failures as usize);
+        assert_eq!(
+            target.last_report(),
+            Some(NodeRotationProgress {
+                rotation: "2025-01-01T00:00:00Z".to_string(),
+                total: VOLUMES as u32,
+                refreshed: VOLUMES as u32,
+                failed: 0,
+            })
+        );
+        // Every injected failure was hit exactly once, either before or after the restart
+        assert!(target.failing.lock().unwrap().is_empty());
+        assert_eq!(
+            ROTATION_REFRESHES.get(&["failed"]) - failed_refreshes_before,
+            failing.len() as f64
+        );
+        assert_eq!(
+            rotated_volumes(volume_root, "2025-01-01T00:00:00Z")
+                .await
+                .len(),
+            VOLUMES
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_rotation_should_be_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let volume_root = dir.path();
+        for i in 0..VOLUMES {
+            published_volume(volume_root, &format!("pod-{i}"), "tls").await;
+        }
+        let target = FakeTarget::default();
+
+        // Nothing is refreshed until a rotation is requested
+        rotate(&target, volume_root, PARALLELISM).await;
+        assert_eq!(target.attempts().len(), 0);
+
+        *target.rotation.lock().unwrap() = Some("first".to_string());
+        rotate(&target, volume_root, PARALLELISM).await;
+        assert_eq!(target.attempts().len(), VOLUMES);
+
+        // Requesting the same rotation again doesn't refresh or report anything
+        target.restart();
+        rotate(&target, volume_root, PARALLELISM).await;
+        assert_eq!(target.attempts().len(), 0);
+        assert_eq!(target.last_report(), None);
+
+        *target.rotation.lock().unwrap() = Some("second".to_string());
+        rotate(&target, volume_root, PARALLELISM).await;
+        assert_eq!(target.attempts().len(), VOLUMES);
+        assert_eq!(rotated_volumes(volume_root, "second").await.len(), VOLUMES);
+    }
+}
@@ -8,8 +8,8 @@ use openssl::{
 use snafu::{OptionExt, ResultExt, Snafu};
 
 use super::{
-    SecretFormat, WellKnownSecretData,
-    well_known::{CompatibilityOptions, TlsPem, TlsPkcs12},
+    SecretFormat, WellKnownSecretData, jks,
+    well_known::{CompatibilityOptions, TlsJks, TlsPem, TlsPkcs12},
 };
 use crate::format::utils::split_pem_certificates;
 
@@ -29,7 +29,18 @@ pub fn convert(
             )?))
         }
 
-        (from, to) => NoValidConversionSnafu { from, to }.fail(),
+        (WellKnownSecretData::TlsPem(pem), SecretFormat::TlsJks) => {
+            Ok(WellKnownSecretData::TlsJks(convert_tls_to_jks(
+                pem,
+                compat.tls_jks_password.as_deref().unwrap_or_default(),
+            )?))
+        }
+
+        (from, to) => NoValidConversionSnafu {
+            from: SecretFormat::from(&from),
+            to,
+        }
+        .fail(),
     }
 }
 
@@ -46,6 +57,12 @@ pub enum ConvertError {
         context(false)
     )]
     TlsToPkcs12 { source: TlsToPkcs12Error },
+
+    #[snafu(
+        display("failed to convert from PEM certificate to JKS"),
+        context(false)
+    )]
+    TlsToJks { source: TlsToJksError },
 }
 
 pub fn convert_tls_to_pkcs12(
@@ -149,3 +166,65 @@ pub enum TlsToPkcs12Error {
     #[snafu(display("failed to encrypt data for truststore"))]
     EncryptDataForTruststore,
 }
+
+pub fn convert_tls_to_jks(pem: TlsPem, password: &str) -> Result<TlsJks, TlsToJksError> {
+    use tls_to_jks_error::*;
+    let cert = X509::from_pem(&pem.certificate_pem)
+        .and_then(|cert| cert.to_der())
+        .context(LoadCertSnafu)?;
+    let cas = split_pem_certificates(&pem.ca_pem)
+        .map(|ca| X509::from_pem(ca).and_then(|ca| ca.to_der()))
+        .collect::<Result<Vec<_>, _>>()
+        .context(LoadCaSnafu)?;
+
+    let keystore = if pem.key_pem.is_empty() {
+        None
+    } else {
+        let key = PKey::private_key_from_pem(&pem.key_pem)
+            .and_then(|key| key.private_key_to_pkcs8())
+            .context(LoadKeySnafu)?;
+        let chain = [&cert].into_iter().chain(&cas).map(Vec::as_slice).collect();
+        let entry = jks::Entry::PrivateKey {
+            alias: "tls",
+            pkcs8_der: &key,
+            chain,
+        };
+        Some(jks::write_store(&[entry], password).context(BuildKeystoreSnafu)?)
+    };
+
+    let ca_aliases = (0..cas.len())
+        .map(|i| format!("ca-{i}"))
+        .collect::<Vec<_>>();
+    let trusted_cas = ca_aliases
+        .iter()
+        .zip(&cas)
+        .map(|(alias, ca)| jks::Entry::TrustedCert {
+            alias,
+            cert_der: ca,
+        })
+        .collect::<Vec<_>>();
+    Ok(TlsJks {
+        keystore,
+        truststore: jks::write_store(&trusted_cas, password).context(BuildTruststoreSnafu)?,
+        password: password.as_bytes().to_vec(),
+    })
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum TlsToJksError {
+    #[snafu(display("failed to load certificate"))]
+    LoadCert { source: OpensslError },
+
+    #[snafu(display("failed to load private key"))]
+    LoadKey { source: OpensslError },
+
+    #[snafu(display("failed to load CA certificate"))]
+    LoadCa { source: OpensslError },
+
+    #[snafu(display("failed to build keystore"))]
+    BuildKeystore { source: OpensslError },
+
+    #[snafu(display("failed to build truststore"))]
+    BuildTruststore { source: OpensslError },
+}
@@ -0,0 +1,199 @@
+//! Renders secret files as shell variable assignments, for applications that can only consume secrets through
+//! environment variables (such as by running `source secrets.env` before starting).
+
+use std::collections::BTreeMap;
+
+use snafu::{OptionExt, Snafu};
+
+use super::{
+    SecretFiles,
+    well_known::{EnvFile, EnvFileOptions},
+};
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum RenderError {
+    #[snafu(display(
+        "{key:?} contains binary data, which cannot be stored in an environment variable"
+    ))]
+    BinaryValue { key: String },
+
+    #[snafu(display("{key:?} cannot be turned into a valid environment variable name"))]
+    InvalidVariableName { key: String },
+
+    #[snafu(display(
+        "{first:?} and {second:?} would both be stored in the environment variable {name}"
+    ))]
+    DuplicateVariable {
+        name: String,
+        first: String,
+        second: String,
+    },
+}
+
+/// Renders each file in `files` as a variable assignment, with the file's name as the variable name.
+///
+/// Characters that are not allowed in variable names are replaced by `_`.
+pub fn render(files: SecretFiles, options: &EnvFileOptions) -> Result<EnvFile, RenderError> {
+    use render_error::*;
+    let mut variables = BTreeMap::<String, (String, String)>::new();
+    for (key, value) in files {
+        let name = variable_name(&key, options).context(InvalidVariableNameSnafu { key: &key })?;
+        let value = match String::from_utf8(value) {
+            Ok(value) if !value.chars().any(is_binary_char) => value,
+            _ => return BinaryValueSnafu { key }.fail(),
+        };
+        if let Some((first, _)) = variables.get(&name) {
+            let (first, second) = if *first < key {
+                (first.clone(), key)
+            } else {
+                (key, first.clone())
+            };
+            return DuplicateVariableSnafu {
+                name,
+                first,
+                second,
+            }
+            .fail();
+        }
+        variables.insert(name, (key, value));
+    }
+
+    let mut env = String::new();
+    for (name, (_, value)) in variables {
+        env.push_str(&name);
+        env.push('=');
+        env.push_str(&shell_quote(&value));
+        env.push('\n');
+    }
+    Ok(EnvFile {
+        env: env.into_bytes(),
+    })
+}
+
+fn variable_name(key: &str, options: &EnvFileOptions) -> Option<String> {
+    let name = match &options.prefix {
+        Some(prefix) => format!("{prefix}{key}").to_uppercase(),
+        None => key.to_string(),
+    };
+    let name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|first| !first.is_ascii_digit());
+    valid.then_some(name)
+}
+
+/// Control characters are allowed in files but make for a confusing environment, so only common whitespace is
+/// accepted. This also rejects NUL, which cannot be stored in an environment variable at all.
+fn is_binary_char(c: char) -> bool {
+    c.is_control() && !matches!(c, '\n' | '\r' | '\t')
+}
+
+/// Quotes `value` so that the shell reads it back verbatim.
+///
+/// Nothing is expanded inside single quotes, so only single quotes themselves need to be escaped, by closing the
+/// quoted string, adding an escaped quote, and reopening it.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::{RenderError, render};
+    use crate::format::{SecretFiles, well_known::EnvFileOptions};
+
+    fn files(files: &[(&str, &[u8])]) -> SecretFiles {
+        files
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_vec()))
+            .collect()
+    }
+
+    fn render_str(files: SecretFiles, options: &EnvFileOptions) -> String {
+        String::from_utf8(render(files, options).unwrap().env).unwrap()
+    }
+
+    /// Evaluates `env` in a shell, and returns the value of the variable `name`.
+    fn source_and_read(env: &str, name: &str) -> String {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{env}\nprintf '%s' \"${name}\""))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn render_should_quote_values() {
+        let tricky = "it's $HOME and `uname` \\n\nsecond line \"quoted\" ünïcödé 🔑";
+        let env = render_str(
+            files(&[("password", tricky.as_bytes()), ("user", b"admin")]),
+            &EnvFileOptions::default(),
+        );
+        assert_eq!(
+            env,
+            "password='it'\\''s $HOME and `uname` \\n\nsecond line \"quoted\" ünïcödé 🔑'\nuser='admin'\n"
+        );
+        assert_eq!(source_and_read(&env, "password"), tricky);
+        assert_eq!(source_and_read(&env, "user"), "admin");
+    }
+
+    #[test]
+    fn render_should_prefix_names() {
+        let data = files(&[("tls.crt", b"cert"), ("db-password", b"hunter2")]);
+        assert_eq!(
+            render_str(data.clone(), &EnvFileOptions::default()),
+            "db_password='hunter2'\ntls_crt='cert'\n"
+        );
+        assert_eq!(
+            render_str(
+                data,
+                &EnvFileOptions {
+                    prefix: Some("app_".to_string()),
+                }
+            ),
+            "APP_DB_PASSWORD='hunter2'\nAPP_TLS_CRT='cert'\n"
+        );
+    }
+
+    #[test]
+    fn render_should_reject_binary_values() {
+        let keytab = b"\x05\x02\x00\x00\x00\x3fEXAMPLE.COM";
+        let err = render(
+            files(&[("user", b"admin"), ("keytab", keytab)]),
+            &EnvFileOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(&err, RenderError::BinaryValue { key } if key == "keytab"));
+
+        let err = render(
+            files(&[("der", &[0x30, 0x82, 0xff])]),
+            &EnvFileOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(&err, RenderError::BinaryValue { key } if key == "der"));
+    }
+
+    #[test]
+    fn render_should_reject_invalid_names() {
+        let err = render(files(&[("1password", b"x")]), &EnvFileOptions::default()).unwrap_err();
+        assert!(matches!(&err, RenderError::InvalidVariableName { key } if key == "1password"));
+
+        let err = render(
+            files(&[("db.password", b"x"), ("db-password", b"y")]),
+            &EnvFileOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "\"db-password\" and \"db.password\" would both be stored in the environment variable db_password"
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! Writes Java KeyStores (JKS), for legacy JVM products that can't read PKCS#12 stores.
+//!
+//! Oracle never documented JKS, the format here follows OpenJDK's `sun.security.provider.JavaKeyStore` (for the store
+//! itself) and `sun.security.provider.KeyProtector` (for protecting private keys).
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::{error::ErrorStack as OpensslError, sha::Sha1};
+
+const MAGIC: u32 = 0xfeed_feed;
+const VERSION: u32 = 2;
+
+const TAG_PRIVATE_KEY: u32 = 1;
+const TAG_TRUSTED_CERT: u32 = 2;
+
+const CERT_TYPE_X509: &str = "X.509";
+
+/// Mixed into the integrity digest, together with the store password.
+const INTEGRITY_SALT: &[u8] = b"Mighty Aphrodite";
+
+/// The OID of Sun's proprietary key protection algorithm (1.3.6.1.4.1.42.2.17.1.1).
+const KEY_PROTECTOR_OID: &[u64] = &[1, 3, 6, 1, 4, 1, 42, 2, 17, 1, 1];
+
+const SHA1_LEN: usize = 20;
+
+/// An entry of a JKS store.
+pub enum Entry<'a> {
+    /// A private key, along with its certificate chain (starting with the key's own certificate).
+    PrivateKey {
+        alias: &'a str,
+        /// The private key, encoded as a DER PKCS#8 `PrivateKeyInfo`.
+        pkcs8_der: &'a [u8],
+        chain: Vec<&'a [u8]>,
+    },
+
+    /// A DER-encoded certificate that is trusted by the store's user.
+    TrustedCert { alias: &'a str, cert_der: &'a [u8] },
+}
+
+/// Encodes `entries` as a JKS store, protected by `password`.
+///
+/// Aliases must be lowercase ASCII, since Java lowercases them when loading the store.
+pub fn write_store(entries: &[Entry], password: &str) -> Result<Vec<u8>, OpensslError> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis() as u64);
+    let mut store = Vec::new();
+    store.extend(MAGIC.to_be_bytes());
+    store.extend(VERSION.to_be_bytes());
+    store.extend(
+        u32::try_from(entries.len())
+            .expect("too many JKS entries")
+            .to_be_bytes(),
+    );
+    for entry in entries {
+        match entry {
+            Entry::PrivateKey {
+                alias,
+                pkcs8_der,
+                chain,
+            } => {
+                store.extend(TAG_PRIVATE_KEY.to_be_bytes());
+                write_utf(&mut store, alias);
+                store.extend(timestamp.to_be_bytes());
+                write_bytes(&mut store, &protect_key(pkcs8_der, password)?);
+                store.extend(
+                    u32::try_from(chain.len())
+                        .expect("JKS certificate chain too long")
+                        .to_be_bytes(),
+                );
+                for cert_der in chain {
+                    write_cert(&mut store, cert_der);
+                }
+            }
+            Entry::TrustedCert { alias, cert_der } => {
+                store.extend(TAG_TRUSTED_CERT.to_be_bytes());
+                write_utf(&mut store, alias);
+                store.extend(timestamp.to_be_bytes());
+                write_cert(&mut store, cert_der);
+            }
+        }
+    }
+    let digest = integrity_digest(&store, password);
+    store.extend(digest);
+    Ok(store)
+}
+
+/// Writes `s` in the format of Java's `DataOutput.writeUTF`, which matches UTF-8 for ASCII.
+fn write_utf(out: &mut Vec<u8>, s: &str) {
+    debug_assert!(
+        s.bytes().all(|b| b.is_ascii() && b != 0),
+        "JKS aliases must be ASCII"
+    );
+    out.extend(
+        u16::try_from(s.len())
+            .expect("JKS string too long")
+            .to_be_bytes(),
+    );
+    out.extend(s.as_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend(
+        u32::try_from(bytes.len())
+            .expect("JKS value too long")
+            .to_be_bytes(),
+    );
+    out.extend(bytes);
+}
+
+fn write_cert(out: &mut Vec<u8>, cert_der: &[u8]) {
+    write_utf(out, CERT_TYPE_X509);
+    write_bytes(out, cert_der);
+}
+
+/// Java hashes passwords as UTF-16BE, without a byte order mark.
+fn password_bytes(password: &str) -> Vec<u8> {
+    password.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+fn integrity_digest(store: &[u8], password: &str) -> [u8; SHA1_LEN] {
+    let mut digest = Sha1::new();
+    digest.update(&password_bytes(password));
+    digest.update(INTEGRITY_SALT);
+    digest.update(store);
+    digest.finish()
+}
+
+/// Protects `pkcs8_der` with `password`, returning a DER `EncryptedPrivateKeyInfo`.
+///
+/// The key is XORed with a keystream of chained SHA-1 digests of the password, starting from a random salt. The salt
+/// is prepended, and a SHA-1 digest of the password and plaintext key is appended to detect wrong passwords.
+fn protect_key(pkcs8_der: &[u8], password: &str) -> Result<Vec<u8>, OpensslError> {
+    let password = password_bytes(password);
+    let mut salt = [0; SHA1_LEN];
+    openssl::rand::rand_bytes(&mut salt)?;
+    let mut protected = salt.to_vec();
+    let mut keystream_block = salt;
+    for chunk in pkcs8_der.chunks(SHA1_LEN) {
+        let mut digest = Sha1::new();
+        digest.update(&password);
+        digest.update(&keystream_block);
+        keystream_block = digest.finish();
+        protected.extend(chunk.iter().zip(keystream_block).map(|(k, s)| k ^ s));
+    }
+    let mut check = Sha1::new();
+    check.update(&password);
+    check.update(pkcs8_der);
+    protected.extend(check.finish());
+    Ok(encrypted_private_key_info(&protected))
+}
+
+fn encrypted_private_key_info(protected_key: &[u8]) -> Vec<u8> {
+    yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            w.next().write_sequence(|w| {
+                w.next()
+                    .write_oid(&yasna::models::ObjectIdentifier::from_slice(
+                        KEY_PROTECTOR_OID,
+                    ));
+                w.next().write_null();
+            });
+            w.next().write_bytes(protected_key);
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::{pkey::PKey, sha::Sha1, x509::X509};
+
+    use crate::format::{convert::convert_tls_to_jks, well_known::TlsPem};
+
+    const PASSWORD: &str = "changeit";
+
+    // Generated by keytool, see fixtures/jks/generate.sh
+    const KEYTOOL_KEYSTORE: &[u8] = include_bytes!("../../fixtures/jks/keystore.jks");
+    const KEYTOOL_TRUSTSTORE: &[u8] = include_bytes!("../../fixtures/jks/truststore.jks");
+    const CA_CRT: &[u8] = include_bytes!("../../fixtures/jks/ca.crt");
+    const TLS_CRT: &[u8] = include_bytes!("../../fixtures/jks/tls.crt");
+    const TLS_KEY: &[u8] = include_bytes!("../../fixtures/jks/tls.key");
+
+    #[derive(Debug, PartialEq)]
+    enum ParsedEntry {
+        PrivateKey {
+            alias: String,
+            /// The recovered key, normalized to the traditional DER encoding.
+            key_der: Vec<u8>,
+            chain: Vec<Vec<u8>>,
+        },
+        TrustedCert {
+            alias: String,
+            cert_der: Vec<u8>,
+        },
+    }
+
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        fn take(&mut self, len: usize) -> &[u8] {
+            let (taken, rest) = self.0.split_at(len);
+            self.0 = rest;
+            taken
+        }
+
+        fn u16(&mut self) -> u16 {
+            u16::from_be_bytes(self.take(2).try_into().unwrap())
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_be_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn utf(&mut self) -> String {
+            let len = self.u16().into();
+            String::from_utf8(self.take(len).to_vec()).unwrap()
+        }
+
+        fn bytes(&mut self) -> Vec<u8> {
+            let len = self.u32() as usize;
+            self.take(len).to_vec()
+        }
+
+        fn cert(&mut self) -> Vec<u8> {
+            assert_eq!(self.utf(), "X.509");
+            self.bytes()
+        }
+    }
+
+    fn sha1(parts: &[&[u8]]) -> [u8; 20] {
+        let mut digest = Sha1::new();
+        for part in parts {
+            digest.update(part);
+        }
+        digest.finish()
+    }
+
+    fn utf16be(password: &str) -> Vec<u8> {
+        password.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    /// Reads a JKS store, following OpenJDK's `JavaKeyStore.engineLoad` rather than reusing the writer.
+    fn read_store(store: &[u8], password: &str) -> Vec<ParsedEntry> {
+        let password = utf16be(password);
+        let (data, digest) = store.split_at(store.len() - 20);
+        assert_eq!(
+            sha1(&[&password, b"Mighty Aphrodite", data]),
+            digest,
+            "store integrity check failed"
+        );
+        let mut reader = Reader(data);
+        assert_eq!(reader.u32(), 0xfeedfeed);
+        assert_eq!(reader.u32(), 2);
+        let count = reader.u32();
+        let entries = (0..count)
+            .map(|_| {
+                let tag = reader.u32();
+                let alias = reader.utf();
+                let _timestamp = reader.take(8);
+                match tag {
+                    1 => {
+                        let protected_key = reader.bytes();
+                        let chain_len = reader.u32();
+                        ParsedEntry::PrivateKey {
+                            alias,
+                            key_der: recover_key(&protected_key, &password),
+                            chain: (0..chain_len).map(|_| reader.cert()).collect(),
+                        }
+                    }
+                    2 => ParsedEntry::TrustedCert {
+                        alias,
+                        cert_der: reader.cert(),
+                    },
+                    _ => panic!("unknown entry tag {tag}"),
+                }
+            })
+            .collect();
+        assert!(reader.0.is_empty(), "trailing data in store");
+        entries
+    }
+
+    /// Recovers a private key, following OpenJDK's `KeyProtector.recover`.
+    fn recover_key(encrypted_private_key_info: &[u8], password: &[u8]) -> Vec<u8> {
+        let (oid, protected) = yasna::parse_der(encrypted_private_key_info, |r| {
+            r.read_sequence(|r| {
+                let oid = r.next().read_sequence(|r| {
+                    let oid = r.next().read_oid()?;
+                    r.next().read_null()?;
+                    Ok(oid)
+                })?;
+                Ok((oid, r.next().read_bytes()?))
+            })
+        })
+        .unwrap();
+        assert_eq!(oid.to_string(), "1.3.6.1.4.1.42.2.17.1.1");
+        let (salt, rest) = protected.split_at(20);
+        let (encrypted, check) = rest.split_at(rest.len() - 20);
+        let mut keystream_block = salt.to_vec();
+        let mut key = Vec::new();
+        for chunk in encrypted.chunks(20) {
+            keystream_block = sha1(&[password, &keystream_block]).to_vec();
+            key.extend(chunk.iter().zip(&keystream_block).map(|(k, s)| k ^ s));
+        }
+        assert_eq!(sha1(&[password, &key]), check, "wrong key password");
+        PKey::private_key_from_pkcs8(&key)
+            .unwrap()
+            .private_key_to_der()
+            .unwrap()
+    }
+
+    fn der(pem: &[u8]) -> Vec<u8> {
+        X509::from_pem(pem).unwrap().to_der().unwrap()
+    }
+
+    fn pem() -> TlsPem {
+        TlsPem {
+            certificate_pem: TLS_CRT.to_vec(),
+            key_pem: TLS_KEY.to_vec(),
+            ca_pem: CA_CRT.to_vec(),
+        }
+    }
+
+    #[test]
+    fn keytool_stores_should_be_readable() {
+        assert_eq!(
+            read_store(KEYTOOL_KEYSTORE, PASSWORD),
+            [ParsedEntry::PrivateKey {
+                alias: "tls".to_string(),
+                key_der: PKey::private_key_from_pem(TLS_KEY)
+                    .unwrap()
+                    .private_key_to_der()
+                    .unwrap(),
+                chain: vec![der(TLS_CRT), der(CA_CRT)],
+            }]
+        );
+        assert_eq!(
+            read_store(KEYTOOL_TRUSTSTORE, PASSWORD),
+            [ParsedEntry::TrustedCert {
+                alias: "ca-0".to_string(),
+                cert_der: der(CA_CRT),
+            }]
+        );
+    }
+
+    #[test]
+    fn converted_stores_should_match_keytool() {
+        let jks = convert_tls_to_jks(pem(), PASSWORD).unwrap();
+        assert_eq!(
+            read_store(&jks.keystore.unwrap(), PASSWORD),
+            read_store(KEYTOOL_KEYSTORE, PASSWORD)
+        );
+        assert_eq!(
+            read_store(&jks.truststore, PASSWORD),
+            read_store(KEYTOOL_TRUSTSTORE, PASSWORD)
+        );
+        assert_eq!(jks.password, PASSWORD.as_bytes());
+    }
+
+    #[test]
+    fn keystore_should_only_be_provided_with_a_key() {
+        let jks = convert_tls_to_jks(
+            TlsPem {
+                key_pem: Vec::new(),
+                ..pem()
+            },
+            "",
+        )
+        .unwrap();
+        assert!(jks.keystore.is_none());
+        assert_eq!(read_store(&jks.truststore, "").len(), 1);
+    }
+}
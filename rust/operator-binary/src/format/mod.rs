@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use snafu::Snafu;
 
-use self::well_known::CompatibilityOptions;
+use self::well_known::{CompatibilityOptions, EnvFileOptions};
 pub use self::{
     convert::ConvertError,
     well_known::{FromFilesError as ParseError, SecretFormat, WellKnownSecretData},
@@ -10,6 +10,8 @@ pub use self::{
 use crate::format::well_known::NamingOptions;
 
 mod convert;
+mod env_file;
+mod jks;
 mod utils;
 pub mod well_known;
 
@@ -33,14 +35,22 @@ impl SecretData {
         format: Option<SecretFormat>,
         names: NamingOptions,
         compat: CompatibilityOptions,
+        env_file: EnvFileOptions,
     ) -> Result<SecretFiles, IntoFilesError> {
-        if let Some(format) = format {
-            Ok(self.parse()?.convert_to(format, compat)?.into_files(names))
-        } else {
-            Ok(match self {
-                SecretData::WellKnown(data) => data.into_files(names),
-                SecretData::Unknown(files) => files,
-            })
+        match format {
+            // Any secret can be rendered as an env file, even if it is not in a well-known format
+            Some(SecretFormat::EnvFile) => {
+                Ok(env_file::render(self.into_native_files(names), &env_file)?.into_files())
+            }
+            Some(format) => Ok(self.parse()?.convert_to(format, compat)?.into_files(names)),
+            None => Ok(self.into_native_files(names)),
+        }
+    }
+
+    fn into_native_files(self, names: NamingOptions) -> SecretFiles {
+        match self {
+            SecretData::WellKnown(data) => data.into_files(names),
+            SecretData::Unknown(files) => files,
         }
     }
 }
@@ -55,4 +65,17 @@ pub enum IntoFilesError {
         context(false)
     )]
     Convert { source: ConvertError },
+
+    #[snafu(display("failed to render secret data as env file"), context(false))]
+    RenderEnvFile { source: env_file::RenderError },
+}
+
+impl IntoFilesError {
+    pub fn grpc_code(&self) -> tonic::Code {
+        match self {
+            // The secret's contents are not going to change by retrying, but the user may request another format
+            IntoFilesError::RenderEnvFile { .. } => tonic::Code::InvalidArgument,
+            _ => tonic::Code::Unavailable,
+        }
+    }
 }
@@ -1,6 +1,11 @@
-use serde::Deserialize;
+use std::collections::BTreeMap;
+
 use snafu::{OptionExt, Snafu};
-use strum::EnumDiscriminants;
+pub use stackable_secret_operator_crd_utils::volume::SecretFormat;
+use stackable_secret_operator_crd_utils::volume::{
+    FILE_KERBEROS_KEYTAB_KEYTAB, FILE_KERBEROS_KEYTAB_KRB5_CONF,
+    FILE_KERBEROS_KEYTAB_SHORTENED_NAMES,
+};
 
 use super::{ConvertError, SecretFiles, convert};
 
@@ -11,8 +16,11 @@ const FILE_PEM_CERT_CA: &str = "ca.crt";
 const FILE_PKCS12_CERT_KEYSTORE: &str = "keystore.p12";
 const FILE_PKCS12_CERT_TRUSTSTORE: &str = "truststore.p12";
 
-const FILE_KERBEROS_KEYTAB_KEYTAB: &str = "keytab";
-const FILE_KERBEROS_KEYTAB_KRB5_CONF: &str = "krb5.conf";
+const FILE_JKS_CERT_KEYSTORE: &str = "keystore.jks";
+const FILE_JKS_CERT_TRUSTSTORE: &str = "truststore.jks";
+const FILE_JKS_CERT_PASSWORD: &str = "keystore.password";
+
+const FILE_ENV_FILE_ENV: &str = "secrets.env";
 
 #[derive(Debug)]
 pub struct TlsPem {
@@ -27,22 +35,55 @@ pub struct TlsPkcs12 {
     pub truststore: Vec<u8>,
 }
 
+#[derive(Debug)]
+pub struct TlsJks {
+    /// Only provided if there is a private key.
+    pub keystore: Option<Vec<u8>>,
+    pub truststore: Vec<u8>,
+    /// The password that protects both stores (and the private key).
+    pub password: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub struct Kerberos {
-    pub keytab: Vec<u8>,
+    /// The keytab containing every principal, provided as `keytab`.
+    pub keytab: Option<Vec<u8>>,
+    /// Keytabs that only contain some of the principals, by file name.
+    pub keytab_groups: BTreeMap<String, Vec<u8>>,
     pub krb5_conf: Vec<u8>,
+    /// The full names of the principal components that were shortened, only provided if any were.
+    pub shortened_names: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct EnvFile {
+    pub env: Vec<u8>,
+}
+impl EnvFile {
+    pub fn into_files(self) -> SecretFiles {
+        [(FILE_ENV_FILE_ENV.to_string(), self.env)].into()
+    }
 }
 
-#[derive(Debug, EnumDiscriminants)]
-#[strum_discriminants(
-    name(SecretFormat),
-    derive(Deserialize),
-    serde(rename_all = "kebab-case")
-)]
+#[derive(Debug)]
 pub enum WellKnownSecretData {
     TlsPem(TlsPem),
     TlsPkcs12(TlsPkcs12),
+    TlsJks(TlsJks),
     Kerberos(Kerberos),
+    EnvFile(EnvFile),
+}
+
+impl From<&WellKnownSecretData> for SecretFormat {
+    fn from(data: &WellKnownSecretData) -> Self {
+        match data {
+            WellKnownSecretData::TlsPem(_) => SecretFormat::TlsPem,
+            WellKnownSecretData::TlsPkcs12(_) => SecretFormat::TlsPkcs12,
+            WellKnownSecretData::TlsJks(_) => SecretFormat::TlsJks,
+            WellKnownSecretData::Kerberos(_) => SecretFormat::Kerberos,
+            WellKnownSecretData::EnvFile(_) => SecretFormat::EnvFile,
+        }
+    }
 }
 
 impl WellKnownSecretData {
@@ -66,11 +107,34 @@ impl WellKnownSecretData {
                 (names.tls_pkcs12_truststore_name, truststore),
             ]
             .into(),
-            WellKnownSecretData::Kerberos(Kerberos { keytab, krb5_conf }) => [
-                (FILE_KERBEROS_KEYTAB_KEYTAB.to_string(), keytab),
-                (FILE_KERBEROS_KEYTAB_KRB5_CONF.to_string(), krb5_conf),
-            ]
-            .into(),
+            WellKnownSecretData::TlsJks(TlsJks {
+                keystore,
+                truststore,
+                password,
+            }) => keystore
+                .map(|keystore| (FILE_JKS_CERT_KEYSTORE.to_string(), keystore))
+                .into_iter()
+                .chain([
+                    (FILE_JKS_CERT_TRUSTSTORE.to_string(), truststore),
+                    (FILE_JKS_CERT_PASSWORD.to_string(), password),
+                ])
+                .collect(),
+            WellKnownSecretData::Kerberos(Kerberos {
+                keytab,
+                keytab_groups,
+                krb5_conf,
+                shortened_names,
+            }) => keytab
+                .map(|keytab| (FILE_KERBEROS_KEYTAB_KEYTAB.to_string(), keytab))
+                .into_iter()
+                .chain(keytab_groups)
+                .chain([(FILE_KERBEROS_KEYTAB_KRB5_CONF.to_string(), krb5_conf)])
+                .chain(
+                    shortened_names
+                        .map(|names| (FILE_KERBEROS_KEYTAB_SHORTENED_NAMES.to_string(), names)),
+                )
+                .collect(),
+            WellKnownSecretData::EnvFile(env_file) => env_file.into_files(),
         }
     }
 
@@ -93,10 +157,18 @@ impl WellKnownSecretData {
                 keystore,
                 truststore: take_file(SecretFormat::TlsPkcs12, FILE_PKCS12_CERT_TRUSTSTORE)?,
             }))
+        } else if let Ok(truststore) = take_file(SecretFormat::TlsJks, FILE_JKS_CERT_TRUSTSTORE) {
+            Ok(WellKnownSecretData::TlsJks(TlsJks {
+                keystore: take_file(SecretFormat::TlsJks, FILE_JKS_CERT_KEYSTORE).ok(),
+                truststore,
+                password: take_file(SecretFormat::TlsJks, FILE_JKS_CERT_PASSWORD)?,
+            }))
         } else if let Ok(keytab) = take_file(SecretFormat::Kerberos, FILE_KERBEROS_KEYTAB_KEYTAB) {
             Ok(WellKnownSecretData::Kerberos(Kerberos {
-                keytab,
+                keytab: Some(keytab),
+                keytab_groups: BTreeMap::new(),
                 krb5_conf: take_file(SecretFormat::Kerberos, FILE_KERBEROS_KEYTAB_KRB5_CONF)?,
+                shortened_names: files.remove(FILE_KERBEROS_KEYTAB_SHORTENED_NAMES),
             }))
         } else {
             from_files_error::UnknownFormatSnafu {
@@ -118,89 +190,71 @@ impl WellKnownSecretData {
 /// Options that some (legacy) applications require to ensure compatibility.
 ///
 /// The expectation is that this will be unset the vast majority of the time.
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default)]
 pub struct CompatibilityOptions {
     /// The password used to encrypt the TLS PKCS#12 keystore
     ///
     /// Required for some applications that misbehave with blank keystore passwords (such as Hadoop).
     /// Has no effect if `format` is not `tls-pkcs12`.
-    #[serde(
-        rename = "secrets.stackable.tech/format.compatibility.tls-pkcs12.password",
-        default
-    )]
     pub tls_pkcs12_password: Option<String>,
+
+    /// The password used to protect the TLS JKS keystore and truststore, which is also written to `keystore.password`
+    ///
+    /// Has no effect if `format` is not `tls-jks`.
+    pub tls_jks_password: Option<String>,
+}
+
+/// Options for the `env-file` format.
+#[derive(Debug, Default)]
+pub struct EnvFileOptions {
+    /// A prefix for all variable names. If set, variable names are also upper-cased.
+    ///
+    /// Has no effect if `format` is not `env-file`.
+    pub prefix: Option<String>,
 }
 
 /// Options to customize the well-known format file names.
 ///
 /// The fields will either contain the default value or the custom user-provided one. This is also
 /// the reason why the fields are not wrapped in [`Option`].
-#[derive(Debug, Deserialize)]
+#[derive(Debug)]
 pub struct NamingOptions {
     /// An alternative name used for the TLS PKCS#12 keystore file.
     ///
     /// Has no effect if the `format` is not `tls-pkcs12`.
-    #[serde(
-        rename = "secrets.stackable.tech/format.tls-pkcs12.keystore-name",
-        default = "default_pkcs12_keystore_name"
-    )]
     pub tls_pkcs12_keystore_name: String,
 
     /// An alternative name used for the TLS PKCS#12 keystore file.
     ///
     /// Has no effect if the `format` is not `tls-pkcs12`.
-    #[serde(
-        rename = "secrets.stackable.tech/format.tls-pkcs12.truststore-name",
-        default = "default_pkcs12_truststore_name"
-    )]
     pub tls_pkcs12_truststore_name: String,
 
     /// An alternative name used for the TLS PEM certificate.
     ///
     /// Has no effect if the `format` is not `tls-pem`.
-    #[serde(
-        rename = "secrets.stackable.tech/format.tls-pem.cert-name",
-        default = "default_tls_pem_cert_name"
-    )]
     pub tls_pem_cert_name: String,
 
     /// An alternative name used for the TLS PEM certificate key.
     ///
     /// Has no effect if the `format` is not `tls-pem`.
-    #[serde(
-        rename = "secrets.stackable.tech/format.tls-pem.key-name",
-        default = "default_tls_pem_key_name"
-    )]
     pub tls_pem_key_name: String,
 
     /// An alternative name used for the TLS PEM certificate authority.
     ///
     /// Has no effect if the `format` is not `tls-pem`.
-    #[serde(
-        rename = "secrets.stackable.tech/format.tls-pem.ca-name",
-        default = "default_tls_pem_ca_name"
-    )]
     pub tls_pem_ca_name: String,
 }
 
-fn default_pkcs12_keystore_name() -> String {
-    FILE_PKCS12_CERT_KEYSTORE.to_owned()
-}
-
-fn default_pkcs12_truststore_name() -> String {
-    FILE_PKCS12_CERT_TRUSTSTORE.to_owned()
-}
-
-fn default_tls_pem_cert_name() -> String {
-    FILE_PEM_CERT_CERT.to_owned()
-}
-
-fn default_tls_pem_key_name() -> String {
-    FILE_PEM_CERT_KEY.to_owned()
-}
-
-fn default_tls_pem_ca_name() -> String {
-    FILE_PEM_CERT_CA.to_owned()
+impl Default for NamingOptions {
+    fn default() -> Self {
+        Self {
+            tls_pkcs12_keystore_name: FILE_PKCS12_CERT_KEYSTORE.to_owned(),
+            tls_pkcs12_truststore_name: FILE_PKCS12_CERT_TRUSTSTORE.to_owned(),
+            tls_pem_cert_name: FILE_PEM_CERT_CERT.to_owned(),
+            tls_pem_key_name: FILE_PEM_CERT_KEY.to_owned(),
+            tls_pem_ca_name: FILE_PEM_CERT_CA.to_owned(),
+        }
+    }
 }
 
 #[derive(Snafu, Debug)]
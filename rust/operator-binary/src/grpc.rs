@@ -11,3 +11,12 @@ pub mod csi {
         tonic::include_proto!("csi.v1");
     }
 }
+
+/// secret-operator's own APIs, see `proto/`
+pub mod stackable {
+    pub mod secret {
+        pub mod v1alpha1 {
+            tonic::include_proto!("stackable.secret.v1alpha1");
+        }
+    }
+}
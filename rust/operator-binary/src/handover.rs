@@ -0,0 +1,317 @@
+//! Hands the CSI listener over from the provisioner that is currently serving it, so that kubelet can keep connecting
+//! while the provisioner is being replaced (such as during a DaemonSet upgrade).
+//!
+//! Every provisioner also listens on a control socket next to the CSI socket (see [`control_socket_path`]). A new
+//! provisioner that finds the CSI socket live asks the current one for its listener over the control socket. The
+//! current provisioner passes the listener's file descriptor back (using `SCM_RIGHTS`), stops accepting connections,
+//! and finishes the requests that are already in flight. Both processes share the same listening socket, so
+//! connections that are queued in the meantime are accepted by the new provisioner instead of being refused.
+//!
+//! If the handover fails (for example, because the current provisioner predates it), the new provisioner falls back
+//! to replacing the socket.
+//!
+//! This only avoids downtime if the new provisioner starts before the old one is stopped.
+
+use std::{
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixListener as StdUnixListener,
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, Interest},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::utils::{remove_stale_socket, uds_bind_private};
+
+/// Sent by the new provisioner to ask for the listener.
+const HANDOVER_REQUEST: &[u8] = b"stackable-secret-operator/handover/v1\n";
+
+/// How long the handshake may take before the new provisioner gives up and replaces the socket instead.
+const HANDOVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+const FD_LEN: u32 = size_of::<RawFd>() as u32;
+const CONTROL_LEN: usize = unsafe { libc::CMSG_SPACE(FD_LEN) } as usize;
+
+/// The control socket used to hand over the listener of `csi_endpoint`.
+pub fn control_socket_path(csi_endpoint: &Path) -> PathBuf {
+    let mut path = csi_endpoint.as_os_str().to_owned();
+    path.push(".handover");
+    path.into()
+}
+
+/// Takes over the listener of the provisioner that is currently serving `csi_endpoint`.
+///
+/// Returns `None` if nothing is serving `csi_endpoint` or the handover failed, in which case a new listener should be
+/// bound instead.
+pub async fn take_over(csi_endpoint: &Path) -> Option<UnixListener> {
+    // A leftover control socket doesn't mean that anyone is still around to answer it
+    if UnixStream::connect(csi_endpoint).await.is_err() {
+        return None;
+    }
+    let control_socket = control_socket_path(csi_endpoint);
+    match tokio::time::timeout(HANDOVER_TIMEOUT, request_listener(&control_socket)).await {
+        Ok(Ok(listener)) => {
+            tracing::info!(
+                csi.endpoint = %csi_endpoint.display(),
+                "took over CSI listener from the previous provisioner"
+            );
+            Some(listener)
+        }
+        Ok(Err(err)) => {
+            tracing::warn!(
+                csi.endpoint = %csi_endpoint.display(),
+                error = &err as &dyn std::error::Error,
+                "failed to take over CSI listener from the previous provisioner, replacing the socket instead"
+            );
+            None
+        }
+        Err(_) => {
+            tracing::warn!(
+                csi.endpoint = %csi_endpoint.display(),
+                timeout = ?HANDOVER_TIMEOUT,
+                "timed out taking over CSI listener from the previous provisioner, replacing the socket instead"
+            );
+            None
+        }
+    }
+}
+
+async fn request_listener(control_socket: &Path) -> io::Result<UnixListener> {
+    let mut control = UnixStream::connect(control_socket).await?;
+    control.write_all(HANDOVER_REQUEST).await?;
+    let fd = loop {
+        control.readable().await?;
+        match control.try_io(Interest::READABLE, || recv_fd(control.as_fd())) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            res => break res?,
+        }
+    };
+    let listener = StdUnixListener::from(fd);
+    listener.set_nonblocking(true)?;
+    UnixListener::from_std(listener)
+}
+
+/// Hands the CSI listener over to the next provisioner, once it asks for it.
+pub struct HandoverSource {
+    control: UnixListener,
+    listener: OwnedFd,
+}
+
+impl HandoverSource {
+    /// Listens for handover requests for `listener`, which must be bound to `csi_endpoint`.
+    pub fn bind(csi_endpoint: &Path, listener: &UnixListener) -> io::Result<Self> {
+        let control_socket = control_socket_path(csi_endpoint);
+        remove_stale_socket(&control_socket);
+        Ok(Self {
+            control: uds_bind_private(control_socket)?,
+            listener: listener.as_fd().try_clone_to_owned()?,
+        })
+    }
+
+    /// Waits until the listener has been handed over to the next provisioner.
+    ///
+    /// The caller should stop accepting connections once this returns. Connections that it still accepts until then
+    /// must be served as usual.
+    pub async fn handed_over(self) {
+        loop {
+            let mut conn = match self.control.accept().await {
+                Ok((conn, _)) => conn,
+                Err(err) => {
+                    tracing::warn!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to accept handover request"
+                    );
+                    continue;
+                }
+            };
+            match tokio::time::timeout(HANDOVER_TIMEOUT, self.send_listener(&mut conn)).await {
+                Ok(Ok(())) => {
+                    tracing::info!("handed CSI listener over to the next provisioner");
+                    return;
+                }
+                Ok(Err(err)) => tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to hand CSI listener over to the next provisioner"
+                ),
+                Err(_) => tracing::warn!(
+                    timeout = ?HANDOVER_TIMEOUT,
+                    "timed out handing CSI listener over to the next provisioner"
+                ),
+            }
+        }
+    }
+
+    async fn send_listener(&self, conn: &mut UnixStream) -> io::Result<()> {
+        let mut request = [0; HANDOVER_REQUEST.len()];
+        conn.read_exact(&mut request).await?;
+        if request != HANDOVER_REQUEST {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "received an invalid handover request",
+            ));
+        }
+        loop {
+            conn.writable().await?;
+            match conn.try_io(Interest::WRITABLE, || {
+                send_fd(conn.as_fd(), self.listener.as_fd())
+            }) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Buffer for the ancillary data of a message that carries a single file descriptor.
+#[repr(C, align(8))]
+struct ControlBuffer([u8; CONTROL_LEN]);
+
+/// Sends `fd` over `socket`.
+///
+/// Ancillary data can't be sent on its own, so a single dummy byte is sent along with it.
+fn send_fd(socket: BorrowedFd, fd: BorrowedFd) -> io::Result<()> {
+    let mut payload = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr().cast(),
+        iov_len: payload.len(),
+    };
+    let mut control = ControlBuffer([0; CONTROL_LEN]);
+    // SAFETY: all pointers in msg point to live buffers of the given lengths, and control is large enough (and
+    // aligned) for a single cmsghdr carrying one fd
+    unsafe {
+        let mut msg = std::mem::zeroed::<libc::msghdr>();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.0.as_mut_ptr().cast();
+        msg.msg_controllen = CONTROL_LEN as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(FD_LEN) as _;
+        libc::CMSG_DATA(cmsg)
+            .cast::<RawFd>()
+            .write_unaligned(fd.as_raw_fd());
+        if libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Receives a file descriptor sent by [`send_fd`].
+fn recv_fd(socket: BorrowedFd) -> io::Result<OwnedFd> {
+    let mut payload = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr().cast(),
+        iov_len: payload.len(),
+    };
+    let mut control = ControlBuffer([0; CONTROL_LEN]);
+    // SAFETY: all pointers in msg point to live buffers of the given lengths, and the kernel only fills in complete
+    // cmsghdrs (MSG_CTRUNC is checked before trusting any fds)
+    unsafe {
+        let mut msg = std::mem::zeroed::<libc::msghdr>();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.0.as_mut_ptr().cast();
+        msg.msg_controllen = CONTROL_LEN as _;
+        match libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => {}
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if msg.msg_flags & libc::MSG_CTRUNC != 0
+            || cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+            || (*cmsg).cmsg_len != libc::CMSG_LEN(FD_LEN) as _
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "handover response did not contain a listener",
+            ));
+        }
+        let fd = libc::CMSG_DATA(cmsg).cast::<RawFd>().read_unaligned();
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{UnixListener, UnixStream},
+        sync::mpsc,
+    };
+
+    use super::{HandoverSource, take_over};
+    use crate::utils::uds_bind_private;
+
+    /// Answers every connection with `name`, until `stop` completes.
+    async fn serve(listener: UnixListener, name: u8, stop: impl Future<Output = ()>) {
+        tokio::pin!(stop);
+        loop {
+            tokio::select! {
+                () = &mut stop => return,
+                conn = listener.accept() => {
+                    // take_over's liveness probe hangs up without waiting for an answer
+                    let _ = conn.unwrap().0.write_all(&[name]).await;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn handover_should_not_refuse_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let csi_endpoint = dir.path().join("csi.sock");
+
+        // Nothing is serving yet
+        assert!(take_over(&csi_endpoint).await.is_none());
+        let listener = uds_bind_private(&csi_endpoint).unwrap();
+        let handover = HandoverSource::bind(&csi_endpoint, &listener).unwrap();
+        let old = tokio::spawn(serve(listener, b'A', handover.handed_over()));
+
+        let (served_by_tx, mut served_by) = mpsc::channel(1);
+        let client = tokio::spawn({
+            let csi_endpoint = csi_endpoint.clone();
+            async move {
+                loop {
+                    // Every connection must be answered by one of the provisioners
+                    let mut conn = UnixStream::connect(&csi_endpoint).await.unwrap();
+                    let mut name = [0];
+                    conn.read_exact(&mut name).await.unwrap();
+                    if served_by_tx.send(name[0]).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        for _ in 0..5 {
+            assert_eq!(served_by.recv().await, Some(b'A'));
+        }
+
+        let listener = take_over(&csi_endpoint).await.unwrap();
+        let handover = HandoverSource::bind(&csi_endpoint, &listener).unwrap();
+        let new = tokio::spawn(serve(listener, b'B', handover.handed_over()));
+        old.await.unwrap();
+
+        let mut served_by_new = 0;
+        while served_by_new < 5 {
+            if served_by.recv().await.unwrap() == b'B' {
+                served_by_new += 1;
+            }
+        }
+        drop(served_by);
+        client.await.unwrap();
+        new.abort();
+    }
+}
@@ -0,0 +1,621 @@
+//! Read-only gRPC API that lets other controllers query which identities were issued for the volumes on this node,
+//! rather than having to parse the mounted secrets themselves.
+//!
+//! Only non-sensitive metadata is recorded (see [`SecretIdentity`]), so key material can never be returned.
+//! The API is served on a separate Unix socket (see `--identity-api-listen`), which is only accessible to the
+//! operator's user and the group set by `--identity-api-group`. Every request is also authorized against the
+//! credentials of the connecting process (see [`IdentityApiAccess`]).
+//!
+//! Each identity is also stored next to its volume, so that it can be restored after a restart (see
+//! [`IssuedIdentities::restore`]).
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use stackable_operator::k8s_openapi::chrono::{DateTime, FixedOffset};
+use tokio::net::unix::UCred;
+use tonic::{Request, Response, Status, service::Interceptor};
+
+use crate::{
+    backend::{SecretIdentity, SecretVolumeSelector},
+    grpc::stackable::secret::v1alpha1::{
+        self as proto, GetVolumeIdentityRequest, GetVolumeIdentityResponse,
+        ListPodIdentitiesRequest, ListPodIdentitiesResponse, identity_query_server::IdentityQuery,
+    },
+    utils::{
+        UnixConnectInfo,
+        fs::{self, FsError},
+    },
+};
+
+/// Appended to the name of a volume's target path to get the file that its identity is stored in.
+const IDENTITY_FILE_SUFFIX: &str = ".identity.json";
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+pub enum RecordError {
+    #[snafu(display("failed to serialize identity"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(transparent)]
+    Fs { source: FsError },
+}
+
+#[derive(Snafu, Debug)]
+#[snafu(module)]
+enum ReadError {
+    #[snafu(transparent)]
+    Fs { source: FsError },
+
+    #[snafu(display("failed to parse identity file {path:?}"))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+}
+
+/// Where the identity of the volume published at `target_path` is stored.
+///
+/// This is kept next to the volume (rather than inside of it), so that it is not visible to the `Pod`.
+pub fn identity_path(target_path: &Path) -> PathBuf {
+    let mut file_name = target_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(IDENTITY_FILE_SUFFIX);
+    target_path.with_file_name(file_name)
+}
+
+/// The identity issued for a single volume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeIdentity {
+    pub volume_id: String,
+    pub pod_uid: Option<String>,
+    pub pod_name: String,
+    pub pod_namespace: String,
+    pub secret_class: String,
+    pub identity: SecretIdentity,
+    pub expires_after: Option<DateTime<FixedOffset>>,
+}
+
+impl VolumeIdentity {
+    /// Describes the `identity` that was issued for the volume `volume_id`.
+    pub fn new(
+        volume_id: &str,
+        selector: &SecretVolumeSelector,
+        identity: SecretIdentity,
+        expires_after: Option<DateTime<FixedOffset>>,
+    ) -> Self {
+        Self {
+            volume_id: volume_id.to_string(),
+            pod_uid: selector.pod_uid.clone(),
+            pod_name: selector.pod.clone(),
+            pod_namespace: selector.namespace.clone(),
+            secret_class: selector.class.clone(),
+            identity,
+            expires_after,
+        }
+    }
+}
+
+impl From<VolumeIdentity> for proto::VolumeIdentity {
+    fn from(volume: VolumeIdentity) -> Self {
+        let VolumeIdentity {
+            volume_id,
+            pod_uid,
+            pod_name,
+            pod_namespace,
+            secret_class,
+            identity:
+                SecretIdentity {
+                    subject_alt_names,
+                    certificate_serials,
+                    principals,
+                },
+            expires_after,
+        } = volume;
+        Self {
+            volume_id,
+            pod_uid: pod_uid.unwrap_or_default(),
+            pod_name,
+            pod_namespace,
+            secret_class,
+            subject_alt_names,
+            certificate_serials,
+            principals,
+            expires_after: expires_after.map(|expires_after| prost_types::Timestamp {
+                seconds: expires_after.timestamp(),
+                // Always less than one second
+                nanos: expires_after.timestamp_subsec_nanos() as i32,
+            }),
+        }
+    }
+}
+
+/// The identities issued for the volumes that are currently published on this node.
+///
+/// Cloning an `IssuedIdentities` shares the same underlying store.
+#[derive(Debug, Clone, Default)]
+pub struct IssuedIdentities(Arc<RwLock<HashMap<String, VolumeIdentity>>>);
+
+impl IssuedIdentities {
+    /// Restores the identities of the volumes that are still published in `volume_root`, such as after a restart.
+    ///
+    /// Identities that can't be read are skipped, since they are only informational.
+    pub async fn restore(volume_root: &Path) -> Self {
+        let issued = Self::default();
+        for (path, _) in find_next_to_volumes(volume_root, IDENTITY_FILE_SUFFIX).await {
+            match read_identity(&path).await {
+                Ok(volume) => issued.insert(volume),
+                Err(err) => tracing::warn!(
+                    identity.path = %path.display(),
+                    error = &err as &dyn std::error::Error,
+                    "failed to restore volume identity, skipping..."
+                ),
+            }
+        }
+        issued
+    }
+
+    /// Records the identity issued for the volume published at `target_path`, replacing any previous identity of the
+    /// same volume.
+    pub async fn record(
+        &self,
+        target_path: &Path,
+        volume: VolumeIdentity,
+    ) -> Result<(), RecordError> {
+        let contents = serde_json::to_vec_pretty(&volume).context(record_error::SerializeSnafu)?;
+        fs::write_file(&identity_path(target_path), 0o600, None, &contents).await?;
+        self.insert(volume);
+        Ok(())
+    }
+
+    fn insert(&self, volume: VolumeIdentity) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(volume.volume_id.clone(), volume);
+    }
+
+    /// Forgets the identity of a volume, once it has been unpublished.
+    ///
+    /// The volume's identity file is removed along with the volume itself.
+    pub fn forget(&self, volume_id: &str) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(volume_id);
+    }
+
+    fn get(&self, volume_id: &str) -> Option<VolumeIdentity> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(volume_id)
+            .cloned()
+    }
+
+    /// Returns the identities of all volumes of the Pod, ordered by volume ID.
+    fn for_pod(&self, pod_uid: &str) -> Vec<VolumeIdentity> {
+        let mut volumes = self
+            .0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|volume| volume.pod_uid.as_deref() == Some(pod_uid))
+            .cloned()
+            .collect::<Vec<_>>();
+        volumes.sort_by(|a, b| a.volume_id.cmp(&b.volume_id));
+        volumes
+    }
+}
+
+async fn read_identity(path: &Path) -> Result<VolumeIdentity, ReadError> {
+    let contents = fs::read(path).await?;
+    serde_json::from_slice(&contents).context(read_error::ParseSnafu { path })
+}
+
+/// Finds the files whose names end with `suffix` that are stored next to the volumes that are still published in
+/// `volume_root`, along with the target path of their volume.
+pub async fn find_next_to_volumes(volume_root: &Path, suffix: &str) -> Vec<(PathBuf, PathBuf)> {
+    let mut found = Vec::new();
+    // Kubelet publishes volumes to <volume_root>/<pod uid>/volumes/kubernetes.io~csi/<volume name>/mount
+    for pod_dir in list_dir(volume_root).await {
+        for volume_dir in list_dir(&pod_dir.join("volumes/kubernetes.io~csi")).await {
+            for path in list_dir(&volume_dir).await {
+                let Some(target_name) = path
+                    .file_name()
+                    .and_then(OsStr::to_str)
+                    .and_then(|file_name| file_name.strip_suffix(suffix))
+                else {
+                    continue;
+                };
+                let target_path = volume_dir.join(target_name);
+                // Left behind by an interrupted unpublish, which kubelet will retry
+                if !target_path.exists() {
+                    continue;
+                }
+                found.push((path, target_path));
+            }
+        }
+    }
+    found
+}
+
+/// Lists the entries of the directory `path`, which is treated as empty if it can't be read.
+async fn list_dir(path: &Path) -> Vec<PathBuf> {
+    let mut entries = match tokio::fs::read_dir(path).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            if err.kind() != ErrorKind::NotFound {
+                tracing::warn!(
+                    dir.path = %path.display(),
+                    error = &err as &dyn std::error::Error,
+                    "failed to list directory while looking for published volumes"
+                );
+            }
+            return Vec::new();
+        }
+    };
+    let mut paths = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        paths.push(entry.path());
+    }
+    paths
+}
+
+/// Decides which processes may query the identity API, based on the credentials that they connected with.
+///
+/// The socket's permissions already keep everyone else out, but they are easy to loosen by accident (such as by
+/// mounting the socket's directory into another container).
+#[derive(Debug, Clone)]
+pub struct IdentityApiAccess {
+    /// Users that may always query the API.
+    uids: Vec<u32>,
+    /// A group whose members may query the API.
+    group: Option<u32>,
+}
+
+impl IdentityApiAccess {
+    /// Allows root, the operator's own user, and the members of `group` (if any).
+    pub fn new(group: Option<u32>) -> Self {
+        // SAFETY: geteuid always succeeds
+        let own_uid = unsafe { libc::geteuid() };
+        Self {
+            uids: vec![0, own_uid],
+            group,
+        }
+    }
+
+    fn is_allowed(&self, peer: &UCred) -> bool {
+        self.uids.contains(&peer.uid())
+            || self.group.is_some_and(|group| {
+                peer.gid() == group
+                    || peer
+                        .pid()
+                        .is_some_and(|pid| supplementary_groups(pid).contains(&group))
+            })
+    }
+}
+
+impl Interceptor for IdentityApiAccess {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let peer = request
+            .extensions()
+            .get::<UnixConnectInfo>()
+            .and_then(|info| info.peer_cred)
+            .ok_or_else(|| Status::unauthenticated("unable to identify the connecting process"))?;
+        if self.is_allowed(&peer) {
+            Ok(request)
+        } else {
+            Err(Status::permission_denied(format!(
+                "user {} (group {}) may not query the identity API",
+                peer.uid(),
+                peer.gid()
+            )))
+        }
+    }
+}
+
+/// The supplementary groups of the process `pid`, which are not included in its socket credentials.
+fn supplementary_groups(pid: i32) -> Vec<u32> {
+    std::fs::read_to_string(format!("/proc/{pid}/status"))
+        .ok()
+        .and_then(|status| {
+            let groups = status
+                .lines()
+                .find_map(|line| line.strip_prefix("Groups:"))?;
+            Some(
+                groups
+                    .split_whitespace()
+                    .filter_map(|group| group.parse().ok())
+                    .collect(),
+            )
+        })
+        .unwrap_or_default()
+}
+
+pub struct IdentityApi {
+    pub issued: IssuedIdentities,
+}
+
+#[tonic::async_trait]
+impl IdentityQuery for IdentityApi {
+    async fn get_volume_identity(
+        &self,
+        request: Request<GetVolumeIdentityRequest>,
+    ) -> Result<Response<GetVolumeIdentityResponse>, Status> {
+        let volume_id = request.into_inner().volume_id;
+        if volume_id.is_empty() {
+            return Err(Status::invalid_argument("volume_id must not be empty"));
+        }
+        match self.issued.get(&volume_id) {
+            Some(volume) => Ok(Response::new(GetVolumeIdentityResponse {
+                identity: Some(volume.into()),
+            })),
+            None => Err(Status::not_found(format!(
+                "no identity is known for volume {volume_id:?}"
+            ))),
+        }
+    }
+
+    async fn list_pod_identities(
+        &self,
+        request: Request<ListPodIdentitiesRequest>,
+    ) -> Result<Response<ListPodIdentitiesResponse>, Status> {
+        let pod_uid = request.into_inner().pod_uid;
+        if pod_uid.is_empty() {
+            return Err(Status::invalid_argument("pod_uid must not be empty"));
+        }
+        Ok(Response::new(ListPodIdentitiesResponse {
+            identities: self
+                .issued
+                .for_pod(&pod_uid)
+                .into_iter()
+                .map(proto::VolumeIdentity::from)
+                .collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use futures::TryStreamExt;
+    use hyper_util::rt::TokioIo;
+    use stackable_operator::k8s_openapi::chrono::DateTime;
+    use tokio::net::UnixStream;
+    use tokio_stream::wrappers::UnixListenerStream;
+    use tonic::{
+        Code, Request,
+        transport::{Channel, Endpoint, Server, Uri},
+    };
+
+    use super::{IdentityApi, IdentityApiAccess, IssuedIdentities, VolumeIdentity, identity_path};
+    use crate::{
+        backend::SecretIdentity,
+        grpc::stackable::secret::v1alpha1::{
+            GetVolumeIdentityRequest, ListPodIdentitiesRequest,
+            identity_query_client::IdentityQueryClient,
+            identity_query_server::{IdentityQuery, IdentityQueryServer},
+        },
+        utils::{TonicUnixStream, fs, uds_bind_private},
+    };
+
+    fn volume(volume_id: &str, pod_uid: &str) -> VolumeIdentity {
+        VolumeIdentity {
+            volume_id: volume_id.to_string(),
+            pod_uid: Some(pod_uid.to_string()),
+            pod_name: "my-pod".to_string(),
+            pod_namespace: "default".to_string(),
+            secret_class: "tls".to_string(),
+            identity: SecretIdentity {
+                subject_alt_names: vec!["my-pod.default.svc.cluster.local".to_string()],
+                certificate_serials: vec!["1A2B".to_string()],
+                principals: Vec::new(),
+            },
+            expires_after: Some(DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z").unwrap()),
+        }
+    }
+
+    fn api() -> IdentityApi {
+        let issued = IssuedIdentities::default();
+        issued.insert(volume("pvc-b", "pod-1"));
+        issued.insert(volume("pvc-a", "pod-1"));
+        issued.insert(volume("pvc-c", "pod-2"));
+        IdentityApi { issued }
+    }
+
+    /// Serves [`api`] on a Unix socket in `dir`, like `--identity-api-listen`.
+    async fn connect(dir: &Path, access: IdentityApiAccess) -> IdentityQueryClient<Channel> {
+        let socket_path = dir.join("identity-api.sock");
+        let listener = uds_bind_private(&socket_path).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(IdentityQueryServer::with_interceptor(api(), access))
+                .serve_with_incoming(UnixListenerStream::new(listener).map_ok(TonicUnixStream)),
+        );
+        // The URI is ignored, since the connector always connects to the socket
+        let channel = Endpoint::from_static("http://identity-api.invalid")
+            .connect_with_connector(tower::service_fn(move |_: Uri| {
+                let socket_path = socket_path.clone();
+                async move {
+                    Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(socket_path).await?))
+                }
+            }))
+            .await
+            .unwrap();
+        IdentityQueryClient::new(channel)
+    }
+
+    #[tokio::test]
+    async fn identity_api_should_only_answer_authorized_peers() {
+        let request = || GetVolumeIdentityRequest {
+            volume_id: "pvc-a".to_string(),
+        };
+        // SAFETY: getegid always succeeds
+        let own_gid = unsafe { libc::getegid() };
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = connect(
+            dir.path(),
+            IdentityApiAccess {
+                uids: Vec::new(),
+                group: Some(own_gid.wrapping_add(1)),
+            },
+        )
+        .await;
+        let err = client.get_volume_identity(request()).await.unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied, "{err:?}");
+        let err = client
+            .list_pod_identities(ListPodIdentitiesRequest {
+                pod_uid: "pod-1".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied, "{err:?}");
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = connect(
+            dir.path(),
+            IdentityApiAccess {
+                uids: Vec::new(),
+                group: Some(own_gid),
+            },
+        )
+        .await;
+        let identity = client.get_volume_identity(request()).await.unwrap();
+        assert_eq!(identity.into_inner().identity.unwrap().volume_id, "pvc-a");
+
+        // The operator's own user is always allowed
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = connect(dir.path(), IdentityApiAccess::new(None)).await;
+        client.get_volume_identity(request()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn identities_should_be_restored_from_published_volumes() {
+        let volume_root = tempfile::tempdir().unwrap();
+        let issued = IssuedIdentities::default();
+        for (pod_uid, volume_id) in [("pod-1", "pvc-a"), ("pod-1", "pvc-b"), ("pod-2", "pvc-c")] {
+            let volume_dir = volume_root
+                .path()
+                .join(pod_uid)
+                .join("volumes/kubernetes.io~csi")
+                .join(volume_id);
+            std::fs::create_dir_all(volume_dir.join("mount")).unwrap();
+            issued
+                .record(&volume_dir.join("mount"), volume(volume_id, pod_uid))
+                .await
+                .unwrap();
+        }
+        // The volume is gone, but its identity was not removed yet
+        let unpublished = volume_root
+            .path()
+            .join("pod-2/volumes/kubernetes.io~csi/pvc-c/mount");
+        std::fs::remove_dir(&unpublished).unwrap();
+        assert!(identity_path(&unpublished).exists());
+        // Corrupt identities are skipped
+        let corrupt = volume_root
+            .path()
+            .join("pod-1/volumes/kubernetes.io~csi/pvc-b");
+        fs::write_file(
+            &identity_path(&corrupt.join("mount")),
+            0o600,
+            None,
+            b"not json",
+        )
+        .await
+        .unwrap();
+
+        let restored = IssuedIdentities::restore(volume_root.path()).await;
+        let volume_ids = |pod_uid| {
+            restored
+                .for_pod(pod_uid)
+                .into_iter()
+                .map(|volume| volume.volume_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(volume_ids("pod-1"), ["pvc-a"]);
+        assert!(volume_ids("pod-2").is_empty());
+        assert_eq!(
+            restored.get("pvc-a").unwrap().identity,
+            volume("pvc-a", "pod-1").identity
+        );
+    }
+
+    #[tokio::test]
+    async fn get_volume_identity() {
+        let api = api();
+        let identity = api
+            .get_volume_identity(Request::new(GetVolumeIdentityRequest {
+                volume_id: "pvc-a".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .identity
+            .unwrap();
+        assert_eq!(identity.volume_id, "pvc-a");
+        assert_eq!(identity.pod_uid, "pod-1");
+        assert_eq!(identity.secret_class, "tls");
+        assert_eq!(
+            identity.subject_alt_names,
+            ["my-pod.default.svc.cluster.local"]
+        );
+        assert_eq!(identity.certificate_serials, ["1A2B"]);
+        assert_eq!(identity.expires_after.unwrap().seconds, 1893456000);
+
+        // Unpublished volumes are forgotten
+        api.issued.forget("pvc-a");
+        let err = api
+            .get_volume_identity(Request::new(GetVolumeIdentityRequest {
+                volume_id: "pvc-a".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn list_pod_identities() {
+        let api = api();
+        let identities = api
+            .list_pod_identities(Request::new(ListPodIdentitiesRequest {
+                pod_uid: "pod-1".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .identities;
+        let volume_ids = identities
+            .iter()
+            .map(|identity| identity.volume_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(volume_ids, ["pvc-a", "pvc-b"]);
+
+        let identities = api
+            .list_pod_identities(Request::new(ListPodIdentitiesRequest {
+                pod_uid: "unknown-pod".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .identities;
+        assert!(identities.is_empty());
+
+        let err = api
+            .list_pod_identities(Request::new(ListPodIdentitiesRequest {
+                pod_uid: String::new(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+}
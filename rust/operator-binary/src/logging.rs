@@ -0,0 +1,284 @@
+//! Log filtering that can be adjusted at runtime, without restarting secret-operator.
+//!
+//! The filter is read from a file (typically projected from a ConfigMap) that uses the same
+//! [`EnvFilter`] directive syntax as the `SECRET_PROVISIONER_LOG` environment variable.
+
+use std::{io::ErrorKind, path::PathBuf, time::Duration};
+
+use snafu::{ResultExt, Snafu};
+use tokio::signal::unix::{SignalKind, signal};
+use tracing_subscriber::{
+    EnvFilter, Registry,
+    filter::{LevelFilter, ParseError},
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+};
+
+use crate::utils::fs::{self, FsError};
+
+/// How often the directives file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The filter used if neither the environment variable nor the directives file specify one.
+const DEFAULT_DIRECTIVES: &str = "info";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed to read log directives"))]
+    ReadDirectives { source: FsError },
+
+    #[snafu(display("failed to parse log directives {directives:?}"))]
+    ParseDirectives {
+        source: ParseError,
+        directives: String,
+    },
+
+    #[snafu(display("failed to apply log filter"))]
+    ApplyFilter { source: reload::Error },
+}
+
+/// Initializes logging to stdout, using the directives in `directives_path` as the filter.
+///
+/// The directives in `env` are used while `directives_path` does not exist or is empty.
+/// The returned [`DirectivesWatcher`] must be [run](DirectivesWatcher::run) for changes to the file to be applied.
+pub async fn initialize_reloadable_logging(
+    env: &str,
+    directives_path: PathBuf,
+) -> DirectivesWatcher {
+    let fallback = std::env::var(env).unwrap_or_else(|_| DEFAULT_DIRECTIVES.to_string());
+    let (filter, handle) = reload::Layer::new(
+        EnvFilter::builder()
+            .parse(&fallback)
+            .unwrap_or_else(|_| EnvFilter::new(DEFAULT_DIRECTIVES)),
+    );
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    let mut watcher = DirectivesWatcher::new(handle, directives_path, fallback);
+    watcher.reload_and_log().await;
+    watcher
+}
+
+/// Applies changes to a log directives file to a reloadable [`EnvFilter`].
+pub struct DirectivesWatcher {
+    handle: reload::Handle<EnvFilter, Registry>,
+    path: PathBuf,
+    fallback: String,
+    current: Option<String>,
+}
+
+impl DirectivesWatcher {
+    fn new(handle: reload::Handle<EnvFilter, Registry>, path: PathBuf, fallback: String) -> Self {
+        Self {
+            handle,
+            path,
+            fallback,
+            current: None,
+        }
+    }
+
+    /// Polls the directives file for changes, and logs the current filter whenever SIGUSR1 is received.
+    pub async fn run(mut self) {
+        let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+            Ok(sigusr1) => Some(sigusr1),
+            Err(err) => {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to listen for SIGUSR1, the current log filter cannot be dumped on demand"
+                );
+                None
+            }
+        };
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.reload_and_log().await,
+                Some(()) = async { sigusr1.as_mut()?.recv().await } => self.log_current_filter(),
+            }
+        }
+    }
+
+    /// Applies the directives file, if it has changed since it was last applied.
+    ///
+    /// Returns whether a new filter was applied. If the new directives are invalid, the previous filter is kept.
+    async fn reload(&mut self) -> Result<bool, Error> {
+        let contents = match fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).context(ReadDirectivesSnafu),
+        };
+        let directives = normalize_directives(&contents).unwrap_or_else(|| self.fallback.clone());
+        if self.current.as_ref() == Some(&directives) {
+            return Ok(false);
+        }
+        let filter = EnvFilter::builder()
+            .parse(&directives)
+            .context(ParseDirectivesSnafu {
+                directives: &directives,
+            })?;
+        self.handle.reload(filter).context(ApplyFilterSnafu)?;
+        self.current = Some(directives);
+        Ok(true)
+    }
+
+    async fn reload_and_log(&mut self) {
+        match self.reload().await {
+            Ok(true) => tracing::info!(
+                directives.path = %self.path.display(),
+                directives = self.current.as_deref(),
+                "applied new log filter"
+            ),
+            Ok(false) => {}
+            Err(err) => tracing::error!(
+                directives.path = %self.path.display(),
+                error = &err as &dyn std::error::Error,
+                "failed to reload log filter, keeping the previous filter"
+            ),
+        }
+    }
+
+    fn log_current_filter(&self) {
+        let current = self.handle.with_current(|filter| {
+            filter
+                .to_string()
+                .split(',')
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        });
+        match current {
+            Ok(target_levels) => tracing::info!(
+                directives.path = %self.path.display(),
+                ?target_levels,
+                max_level = %LevelFilter::current(),
+                "current log filter"
+            ),
+            Err(err) => tracing::error!(
+                error = &err as &dyn std::error::Error,
+                "failed to read current log filter"
+            ),
+        }
+    }
+}
+
+/// Joins the directives in a file into a single filter string.
+///
+/// Directives may be separated by either commas or newlines, and lines starting with `#` are ignored.
+/// Returns [`None`] if the file contains no directives.
+fn normalize_directives(contents: &str) -> Option<String> {
+    let directives = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .collect::<Vec<_>>();
+    (!directives.is_empty()).then(|| directives.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::{
+        EnvFilter, Layer, Registry,
+        layer::{Context, SubscriberExt},
+        reload,
+    };
+
+    use super::{DirectivesWatcher, normalize_directives};
+    use crate::utils::fs;
+
+    const TARGET: &str = "stackable_secret_operator::toggled";
+
+    /// Records the level of every event emitted for [`TARGET`].
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<Level>>>);
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            if event.metadata().target() == TARGET {
+                self.0.lock().unwrap().push(*event.metadata().level());
+            }
+        }
+    }
+    impl Capture {
+        fn take(&self) -> Vec<Level> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    fn emit_all_levels() {
+        tracing::debug!(target: TARGET, "debug");
+        tracing::info!(target: TARGET, "info");
+        tracing::warn!(target: TARGET, "warn");
+    }
+
+    #[tokio::test]
+    async fn reload_should_toggle_module_levels() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("directives");
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let capture = Capture::default();
+        let _guard = tracing::subscriber::set_default(
+            Registry::default().with(filter).with(capture.clone()),
+        );
+        let mut watcher = DirectivesWatcher::new(handle, path.clone(), "info".to_string());
+
+        // Missing file falls back to the environment's directives
+        assert!(watcher.reload().await.unwrap());
+        emit_all_levels();
+        assert_eq!(capture.take(), [Level::INFO, Level::WARN]);
+
+        fs::write_file(&path, 0o644, format!("info\n{TARGET}=debug\n").as_bytes())
+            .await
+            .unwrap();
+        assert!(watcher.reload().await.unwrap());
+        emit_all_levels();
+        assert_eq!(capture.take(), [Level::DEBUG, Level::INFO, Level::WARN]);
+        // Unchanged files are not reapplied
+        assert!(!watcher.reload().await.unwrap());
+
+        fs::write_file(&path, 0o644, format!("info,{TARGET}=warn").as_bytes())
+            .await
+            .unwrap();
+        assert!(watcher.reload().await.unwrap());
+        emit_all_levels();
+        assert_eq!(capture.take(), [Level::WARN]);
+    }
+
+    #[tokio::test]
+    async fn reload_should_keep_previous_filter_if_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("directives");
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let capture = Capture::default();
+        let _guard = tracing::subscriber::set_default(
+            Registry::default().with(filter).with(capture.clone()),
+        );
+        let mut watcher = DirectivesWatcher::new(handle, path.clone(), "info".to_string());
+
+        fs::write_file(&path, 0o644, format!("{TARGET}=debug").as_bytes())
+            .await
+            .unwrap();
+        assert!(watcher.reload().await.unwrap());
+        fs::write_file(&path, 0o644, format!("{TARGET}=verbose").as_bytes())
+            .await
+            .unwrap();
+        assert!(watcher.reload().await.is_err());
+        emit_all_levels();
+        assert_eq!(capture.take(), [Level::DEBUG, Level::INFO, Level::WARN]);
+    }
+
+    #[test]
+    fn normalize_directives_should_join_lines() {
+        assert_eq!(normalize_directives(""), None);
+        assert_eq!(normalize_directives("# just a comment\n\n"), None);
+        assert_eq!(
+            normalize_directives("# comment\ninfo\n foo=debug, bar=warn \n"),
+            Some("info,foo=debug,bar=warn".to_string())
+        );
+    }
+}
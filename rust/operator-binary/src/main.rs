@@ -1,33 +1,52 @@
-use std::{os::unix::prelude::FileTypeExt, path::PathBuf};
+use std::{
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    sync::Arc,
+};
 
 use anyhow::Context;
-use clap::{Parser, crate_description, crate_version};
+use backend::coordination::{KubeLeaseApi, LeasePool};
+use clap::{CommandFactory, FromArgMatches, crate_description, crate_version};
 use csi_server::{
-    controller::SecretProvisionerController, identity::SecretProvisionerIdentity,
-    node::SecretProvisionerNode,
+    controller::SecretProvisionerController,
+    health::PluginHealth,
+    identity::SecretProvisionerIdentity,
+    node::{CsrHandshakes, SecretProvisionerNode, VolumeLocks},
+    rotation,
 };
-use futures::{FutureExt, TryStreamExt};
-use grpc::csi::v1::{
-    controller_server::ControllerServer, identity_server::IdentityServer, node_server::NodeServer,
+use futures::TryStreamExt;
+use grpc::{
+    csi::v1::{
+        controller_server::ControllerServer, identity_server::IdentityServer,
+        node_server::NodeServer,
+    },
+    stackable::secret::v1alpha1::identity_query_server::IdentityQueryServer,
 };
+use identity_api::{IdentityApi, IdentityApiAccess, IssuedIdentities};
 use stackable_operator::{
     CustomResourceExt, logging::TracingTarget, utils::cluster_info::KubernetesClusterInfoOpts,
 };
+use stackable_secret_operator_crd_utils::ownership;
 use tokio::signal::unix::{SignalKind, signal};
 use tokio_stream::wrappers::UnixListenerStream;
 use tonic::transport::Server;
-use utils::{TonicUnixStream, uds_bind_private};
+use utils::{TonicUnixStream, remove_stale_socket, uds_bind_group, uds_bind_private};
 
 mod backend;
+mod config;
 mod crd;
 mod csi_server;
 mod external_crd;
 mod format;
 mod grpc;
+mod handover;
+mod identity_api;
+mod logging;
+mod metrics;
 mod utils;
 
 pub const APP_NAME: &str = "secret";
-pub const OPERATOR_NAME: &str = "secrets.stackable.tech";
 
 #[derive(clap::Parser)]
 #[clap(author, version)]
@@ -53,12 +72,82 @@ struct SecretOperatorRun {
     #[clap(long, env)]
     privileged: bool,
 
+    /// The directory that kubelet publishes volumes into, which is the `pods` directory of kubelet's root directory.
+    ///
+    /// Volumes are never deleted if kubelet asks to unpublish a path outside of it.
+    #[clap(long, env, default_value = "/var/lib/kubelet/pods")]
+    volume_root: PathBuf,
+
+    /// The fraction (between 0 and 1) of each NodePublishVolume request's deadline that may be spent waiting for
+    /// objects that the volume depends on (such as Services or Listeners) to be created, or for a free coordination
+    /// slot (see `--coordination-slots`).
+    #[clap(long, env, default_value_t = 0.5, value_parser = parse_fraction)]
+    publish_dependency_wait_fraction: f64,
+
+    /// How many expensive operations (such as provisioning Kerberos keytabs or saving TLS CAs) may run at the same time
+    /// across all nodes, coordinated using Lease objects in the operator's namespace.
+    ///
+    /// Operations are not coordinated if this is not set. Operations that can't get a slot within a minute run anyway.
+    #[clap(long, env)]
+    coordination_slots: Option<NonZeroU32>,
+
+    /// How many volumes each node may refresh at the same time when a SecretClass requests a forced rotation (using the
+    /// `secrets.stackable.tech/force-rotation` annotation).
+    #[clap(long, env, default_value = "4")]
+    rotation_parallelism: NonZeroUsize,
+
+    /// A Unix socket to serve a read-only API on, which other controllers can use to query the identities (such as
+    /// certificate SANs and Kerberos principals) that were issued for the volumes on this node.
+    ///
+    /// The API never returns key material, and is disabled if this is not set.
+    #[clap(long, env)]
+    identity_api_listen: Option<PathBuf>,
+
+    /// The ID of a group whose members may query the identity API (see `--identity-api-listen`), in addition to the
+    /// operator's own user.
+    #[clap(long, env)]
+    identity_api_group: Option<u32>,
+
+    /// An address (such as `0.0.0.0:9090`) to serve Prometheus metrics on over HTTP.
+    ///
+    /// Metrics are not served if this is not set.
+    #[clap(long, env)]
+    metrics_listen: Option<SocketAddr>,
+
     /// Tracing log collector system
     #[arg(long, env, default_value_t, value_enum)]
     pub tracing_target: TracingTarget,
 
+    /// A file containing log filter directives (in the same format as SECRET_PROVISIONER_LOG), such as a projected ConfigMap.
+    ///
+    /// Changes to the file are applied without restarting, and sending SIGUSR1 logs the current filter.
+    /// SECRET_PROVISIONER_LOG is still used while the file is missing or empty.
+    /// Only supported with the default tracing target.
+    #[clap(long, env)]
+    log_directives_file: Option<PathBuf>,
+
     #[command(flatten)]
     pub cluster_info_opts: KubernetesClusterInfoOpts,
+
+    /// A YAML file that sets defaults for any of these options, keyed by flag name (such as `node-name`).
+    ///
+    /// Flags and environment variables take precedence over the file.
+    #[clap(long, env = config::CONFIG_ENV)]
+    config: Option<PathBuf>,
+
+    /// Print the resolved configuration (with sensitive values redacted) and where each value came from, then exit.
+    #[clap(long)]
+    print_effective_config: bool,
+}
+
+/// Parses a fraction between 0 and 1 (inclusive).
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction = value.parse::<f64>().map_err(|err| err.to_string())?;
+    if (0.0..=1.0).contains(&fraction) {
+        Ok(fraction)
+    } else {
+        Err(format!("{fraction} is not between 0 and 1"))
+    }
 }
 
 mod built_info {
@@ -67,7 +156,17 @@ mod built_info {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let opts = Opts::parse();
+    let args = std::env::args_os().collect::<Vec<_>>();
+    let config_file = match config::find_config_path(&args, std::env::var_os(config::CONFIG_ENV)) {
+        Some(path) => Some(config::ConfigFile::load(path).await?),
+        None => None,
+    };
+    let mut command = Opts::command();
+    if let Some(config_file) = &config_file {
+        command = config_file.apply(command, |name| std::env::var_os(name))?;
+    }
+    let matches = command.clone().get_matches_from(&args);
+    let opts = Opts::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
     match opts.cmd {
         stackable_operator::cli::Command::Crd => {
             crd::SecretClass::print_yaml_schema(built_info::PKG_VERSION)?;
@@ -76,14 +175,58 @@ async fn main() -> anyhow::Result<()> {
             csi_endpoint,
             node_name,
             tracing_target,
+            log_directives_file,
             privileged,
+            volume_root,
+            publish_dependency_wait_fraction,
+            coordination_slots,
+            rotation_parallelism,
+            identity_api_listen,
+            identity_api_group,
+            metrics_listen,
             cluster_info_opts,
+            config: _,
+            print_effective_config,
         }) => {
-            stackable_operator::logging::initialize_logging(
-                "SECRET_PROVISIONER_LOG",
-                APP_NAME,
-                tracing_target,
-            );
+            if print_effective_config {
+                let run_command = command
+                    .find_subcommand(config::RUN_SUBCOMMAND)
+                    .context("run subcommand must exist")?;
+                let run_matches = matches
+                    .subcommand_matches(config::RUN_SUBCOMMAND)
+                    .context("run subcommand must have been parsed")?;
+                print!(
+                    "{}",
+                    config::effective_config(
+                        run_command,
+                        run_matches,
+                        config_file.as_ref(),
+                        |name| std::env::var_os(name)
+                    )
+                );
+                return Ok(());
+            }
+            match log_directives_file {
+                Some(log_directives_file) => {
+                    anyhow::ensure!(
+                        matches!(tracing_target, TracingTarget::None),
+                        "--log-directives-file is not supported with --tracing-target={tracing_target:?}"
+                    );
+                    tokio::spawn(
+                        logging::initialize_reloadable_logging(
+                            "SECRET_PROVISIONER_LOG",
+                            log_directives_file,
+                        )
+                        .await
+                        .run(),
+                    );
+                }
+                None => stackable_operator::logging::initialize_logging(
+                    "SECRET_PROVISIONER_LOG",
+                    APP_NAME,
+                    tracing_target,
+                ),
+            }
             stackable_operator::utils::print_startup_string(
                 crate_description!(),
                 crate_version!(),
@@ -92,19 +235,86 @@ async fn main() -> anyhow::Result<()> {
                 built_info::BUILT_TIME_UTC,
                 built_info::RUSTC_VERSION,
             );
+            #[cfg(feature = "failpoints")]
+            utils::failpoint::configure_from_env().context("failed to configure failpoints")?;
 
             let client = stackable_operator::client::initialize_operator(
-                Some(OPERATOR_NAME.to_string()),
+                Some(ownership::OPERATOR_NAME.to_string()),
                 &cluster_info_opts,
             )
             .await?;
-            if csi_endpoint
-                .symlink_metadata()
-                .is_ok_and(|meta| meta.file_type().is_socket())
-            {
-                let _ = std::fs::remove_file(&csi_endpoint);
+            let csi_listener = match handover::take_over(&csi_endpoint).await {
+                Some(listener) => listener,
+                None => {
+                    remove_stale_socket(&csi_endpoint);
+                    uds_bind_private(&csi_endpoint).context("failed to bind CSI listener")?
+                }
+            };
+            let handover = handover::HandoverSource::bind(&csi_endpoint, &csi_listener)
+                .context("failed to bind CSI handover listener")?;
+            let leases = match coordination_slots {
+                Some(slots) => LeasePool::new(
+                    KubeLeaseApi(client.get_api(client.as_kube_client().default_namespace())),
+                    slots,
+                    node_name.clone(),
+                ),
+                None => LeasePool::disabled(),
+            };
+            let issued_identities = IssuedIdentities::restore(&volume_root).await;
+            if let Some(identity_api_listen) = identity_api_listen {
+                remove_stale_socket(&identity_api_listen);
+                let listener = match identity_api_group {
+                    Some(group) => uds_bind_group(identity_api_listen, group),
+                    None => uds_bind_private(identity_api_listen),
+                }
+                .context("failed to bind identity API listener")?;
+                let identity_api = Server::builder()
+                    .add_service(IdentityQueryServer::with_interceptor(
+                        IdentityApi {
+                            issued: issued_identities.clone(),
+                        },
+                        IdentityApiAccess::new(identity_api_group),
+                    ))
+                    .serve_with_incoming(UnixListenerStream::new(listener).map_ok(TonicUnixStream));
+                tokio::spawn(async move {
+                    if let Err(err) = identity_api.await {
+                        tracing::error!(
+                            error = &err as &dyn std::error::Error,
+                            "identity API server failed"
+                        );
+                    }
+                });
+            }
+            if let Some(metrics_listen) = metrics_listen {
+                let listener = tokio::net::TcpListener::bind(metrics_listen)
+                    .await
+                    .context("failed to bind metrics listener")?;
+                tokio::spawn(async move {
+                    if let Err(err) = metrics::serve(listener).await {
+                        tracing::error!(
+                            error = &err as &dyn std::error::Error,
+                            "metrics server failed"
+                        );
+                    }
+                });
             }
+            let csr_handshakes = CsrHandshakes::default();
+            let node = Arc::new(SecretProvisionerNode {
+                client: client.clone(),
+                node_name,
+                privileged,
+                volume_root,
+                dependency_wait_fraction: publish_dependency_wait_fraction,
+                leases: leases.clone(),
+                issued_identities,
+                csr_handshakes: csr_handshakes.clone(),
+                volume_locks: VolumeLocks::default(),
+            });
+            node.resume_csr_handshakes().await;
+            let rotations = tokio::spawn(rotation::run(node.clone(), rotation_parallelism));
+            let (health, health_server) = PluginHealth::new();
             let mut sigterm = signal(SignalKind::terminate())?;
+            let mut handed_over = false;
             Server::builder()
                 .add_service(
                     tonic_reflection::server::Builder::configure()
@@ -112,24 +322,56 @@ async fn main() -> anyhow::Result<()> {
                         .register_encoded_file_descriptor_set(grpc::FILE_DESCRIPTOR_SET_BYTES)
                         .build_v1()?,
                 )
-                .add_service(IdentityServer::new(SecretProvisionerIdentity))
-                .add_service(ControllerServer::new(SecretProvisionerController {
-                    client: client.clone(),
+                .add_service(health_server)
+                .add_service(IdentityServer::new(SecretProvisionerIdentity {
+                    health: health.clone(),
                 }))
-                .add_service(NodeServer::new(SecretProvisionerNode {
+                .add_service(ControllerServer::new(SecretProvisionerController {
                     client,
-                    node_name,
-                    privileged,
+                    leases,
                 }))
+                .add_service(NodeServer::from_arc(node))
                 .serve_with_incoming_shutdown(
-                    UnixListenerStream::new(
-                        uds_bind_private(csi_endpoint).context("failed to bind CSI listener")?,
-                    )
-                    .map_ok(TonicUnixStream),
-                    sigterm.recv().map(|_| ()),
+                    UnixListenerStream::new(csi_listener).map_ok(TonicUnixStream),
+                    async {
+                        tokio::select! {
+                            _ = sigterm.recv() => {}
+                            () = handover.handed_over() => handed_over = true,
+                        }
+                        // Let health watchers know that we're going away before the server stops
+                        health.set_serving(false).await;
+                    },
                 )
                 .await?;
+            if handed_over {
+                // The new provisioner resumes them from the pending CSR handshakes on disk
+                csr_handshakes.cancel_all();
+                // The new provisioner also resumes interrupted rotations from the volumes' progress markers
+                rotations.abort();
+                // Exiting early would just make kubelet restart the container, which would then take the
+                // listener back from the new provisioner
+                tracing::info!("waiting to be terminated, since the CSI listener was handed over");
+                sigterm.recv().await;
+            }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_fraction;
+
+    #[test]
+    fn fractions_should_be_between_zero_and_one() {
+        assert_eq!(parse_fraction("0"), Ok(0.0));
+        assert_eq!(parse_fraction("0.5"), Ok(0.5));
+        assert_eq!(parse_fraction("1"), Ok(1.0));
+        for invalid in ["-0.1", "1.5", "NaN", "inf", "lots"] {
+            assert!(
+                parse_fraction(invalid).is_err(),
+                "{invalid} should be rejected"
+            );
+        }
+    }
+}
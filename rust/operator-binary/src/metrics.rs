@@ -0,0 +1,410 @@
+//! Counters and histograms that are exported in the Prometheus text format, see [`serve`].
+
+use std::{collections::BTreeMap, convert::Infallible, fmt::Write, sync::Mutex, time::Duration};
+
+use http_body_util::Full;
+use hyper::{Response, body::Bytes, header::CONTENT_TYPE, server::conn::http1};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+
+/// How often publishing a volume waited for one of its dependencies, by `kind` (such as `service`) and `outcome`.
+///
+/// See [`DependencyWaitOutcome`] for the possible outcomes.
+pub static DEPENDENCY_WAITS: Counter = Counter::new(
+    "secret_operator_publish_dependency_waits_total",
+    "How often publishing a volume waited for an object that it depends on",
+    &["kind", "outcome"],
+);
+
+/// The time that publishing volumes spent waiting for their dependencies, by `kind`.
+pub static DEPENDENCY_WAIT_SECONDS: Counter = Counter::new(
+    "secret_operator_publish_dependency_wait_seconds_total",
+    "The time that publishing volumes spent waiting for objects that they depend on",
+    &["kind"],
+);
+
+/// How often a volume was refreshed because its SecretClass requested a forced rotation, by `outcome`.
+///
+/// See [`RotationRefreshOutcome`] for the possible outcomes.
+pub static ROTATION_REFRESHES: Counter = Counter::new(
+    "secret_operator_rotation_volume_refreshes_total",
+    "How often a volume was refreshed because its SecretClass requested a forced rotation",
+    &["outcome"],
+);
+
+/// How often an expensive operation tried to acquire a coordination lease, by `operation` and `outcome`.
+///
+/// See [`LeaseAcquireOutcome`] for the possible outcomes.
+pub static LEASE_ACQUISITIONS: Counter = Counter::new(
+    "secret_operator_lease_acquisitions_total",
+    "How often an expensive operation tried to acquire a coordination lease",
+    &["operation", "outcome"],
+);
+
+/// How often a coordination lease was released after its operation finished, by `operation` and `outcome`.
+///
+/// See [`LeaseReleaseOutcome`] for the possible outcomes.
+pub static LEASE_RELEASES: Counter = Counter::new(
+    "secret_operator_lease_releases_total",
+    "How often a coordination lease was released after its operation finished",
+    &["operation", "outcome"],
+);
+
+/// How long expensive operations waited for a coordination lease, by `operation`.
+pub static LEASE_WAIT_SECONDS: Histogram = Histogram::new(
+    "secret_operator_lease_wait_seconds",
+    "How long expensive operations waited for a coordination lease",
+    &["operation"],
+    &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0],
+);
+
+const METRICS: &[&dyn Metric] = &[
+    &DEPENDENCY_WAITS,
+    &DEPENDENCY_WAIT_SECONDS,
+    &ROTATION_REFRESHES,
+    &LEASE_ACQUISITIONS,
+    &LEASE_RELEASES,
+    &LEASE_WAIT_SECONDS,
+];
+
+/// How waiting for a dependency ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum DependencyWaitOutcome {
+    /// The dependency already existed, so there was no need to wait.
+    Present,
+
+    /// The dependency was created while waiting for it.
+    Appeared,
+
+    /// The dependency was still missing once the wait had to be given up.
+    TimedOut,
+}
+
+/// Records that publishing waited `waited` for a dependency of `kind`.
+pub fn record_dependency_wait(
+    kind: &'static str,
+    outcome: DependencyWaitOutcome,
+    waited: Duration,
+) {
+    DEPENDENCY_WAITS.inc_by(&[kind, outcome.into()], 1.0);
+    DEPENDENCY_WAIT_SECONDS.inc_by(&[kind], waited.as_secs_f64());
+}
+
+/// How refreshing a volume for a forced rotation ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum RotationRefreshOutcome {
+    /// The volume now contains a secret that was issued after the rotation.
+    Refreshed,
+
+    /// The volume could not be refreshed, and will be retried.
+    Failed,
+}
+
+/// Records that a volume was refreshed for a forced rotation.
+pub fn record_rotation_refresh(outcome: RotationRefreshOutcome) {
+    ROTATION_REFRESHES.inc_by(&[outcome.into()], 1.0);
+}
+
+/// How trying to acquire a coordination lease ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum LeaseAcquireOutcome {
+    /// A lease was acquired, and held while the operation was running.
+    Acquired,
+
+    /// No lease became free in time, so the operation ran without one.
+    TimedOut,
+
+    /// The lease API failed, so the operation ran without a lease.
+    Unavailable,
+}
+
+/// Records that an `operation` waited `waited` to acquire a coordination lease.
+pub fn record_lease_acquire(
+    operation: &'static str,
+    outcome: LeaseAcquireOutcome,
+    waited: Duration,
+) {
+    LEASE_ACQUISITIONS.inc_by(&[operation, outcome.into()], 1.0);
+    LEASE_WAIT_SECONDS.observe(&[operation], waited.as_secs_f64());
+}
+
+/// How releasing a coordination lease ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum LeaseReleaseOutcome {
+    /// The lease was released, and can be acquired by the next operation.
+    Released,
+
+    /// The lease could not be released, and stays held until it expires.
+    Failed,
+}
+
+/// Records that an `operation` released its coordination lease.
+pub fn record_lease_release(operation: &'static str, outcome: LeaseReleaseOutcome) {
+    LEASE_RELEASES.inc_by(&[operation, outcome.into()], 1.0);
+}
+
+/// A metric that can be rendered by [`render`].
+trait Metric: Sync {
+    fn render(&self, out: &mut String);
+}
+
+/// Formats `label_values` (matching `label_names`) as the inside of a Prometheus label set.
+fn format_labels(label_names: &[&str], label_values: &[&str]) -> String {
+    label_names
+        .iter()
+        .zip(label_values)
+        // Label values are only ever static identifiers, so they never need to be escaped
+        .map(|(name, value)| format!("{name}=\"{value}\""))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A counter with a fixed set of labels, which is tracked separately for each combination of label values.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<&'static str>, f64>>,
+}
+
+impl Counter {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            label_names,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Adds `value` to the counter for `label_values`, which must be in the same order as the counter's label names.
+    pub fn inc_by(&self, label_values: &[&'static str], value: f64) {
+        debug_assert_eq!(label_values.len(), self.label_names.len());
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .entry(label_values.to_vec())
+            .or_default() += value;
+    }
+
+    /// The current value of the counter for `label_values`.
+    #[cfg(test)]
+    pub fn get(&self, label_values: &[&'static str]) -> f64 {
+        self.values
+            .lock()
+            .unwrap()
+            .get(label_values)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Metric for Counter {
+    fn render(&self, out: &mut String) {
+        let Self {
+            name,
+            help,
+            label_names,
+            values,
+        } = self;
+        // Writing to a String never fails
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (label_values, value) in values.lock().unwrap().iter() {
+            let labels = format_labels(label_names, label_values);
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    }
+}
+
+/// A histogram with a fixed set of labels and buckets, which is tracked separately for each combination of label
+/// values.
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    /// The upper bounds of the buckets, in ascending order. The `+Inf` bucket is implied.
+    buckets: &'static [f64],
+    values: Mutex<BTreeMap<Vec<&'static str>, HistogramValues>>,
+}
+
+#[derive(Debug, Default)]
+struct HistogramValues {
+    /// The number of observations that fit into each bucket (including all smaller buckets)
+    bucket_counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+        buckets: &'static [f64],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            label_names,
+            buckets,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records `value` for `label_values`, which must be in the same order as the histogram's label names.
+    pub fn observe(&self, label_values: &[&'static str], value: f64) {
+        debug_assert_eq!(label_values.len(), self.label_names.len());
+        let mut values = self.values.lock().unwrap();
+        let values = values
+            .entry(label_values.to_vec())
+            .or_insert_with(|| HistogramValues {
+                bucket_counts: vec![0; self.buckets.len()],
+                ..HistogramValues::default()
+            });
+        for (bucket, count) in self.buckets.iter().zip(&mut values.bucket_counts) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+        values.sum += value;
+        values.count += 1;
+    }
+
+    /// The number of values recorded for `label_values`.
+    #[cfg(test)]
+    pub fn count(&self, label_values: &[&'static str]) -> u64 {
+        self.values
+            .lock()
+            .unwrap()
+            .get(label_values)
+            .map_or(0, |values| values.count)
+    }
+}
+
+impl Metric for Histogram {
+    fn render(&self, out: &mut String) {
+        let Self {
+            name,
+            help,
+            label_names,
+            buckets,
+            values,
+        } = self;
+        // Writing to a String never fails
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (label_values, values) in values.lock().unwrap().iter() {
+            let labels = format_labels(label_names, label_values);
+            // Buckets are labelled with le, which must come after the other labels
+            let bucket_labels = if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{labels},")
+            };
+            for (bucket, count) in buckets.iter().zip(&values.bucket_counts) {
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{{bucket_labels}le=\"{bucket}\"}} {count}"
+                );
+            }
+            let count = values.count;
+            let _ = writeln!(out, "{name}_bucket{{{bucket_labels}le=\"+Inf\"}} {count}");
+            let _ = writeln!(out, "{name}_sum{{{labels}}} {}", values.sum);
+            let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+        }
+    }
+}
+
+/// Renders all metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    for metric in METRICS {
+        metric.render(&mut out);
+    }
+    out
+}
+
+/// Serves [`render`] to every HTTP request received on `listener`.
+pub async fn serve(listener: TcpListener) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(|_| async {
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                        .body(Full::<Bytes>::from(render()))
+                        .expect("metrics response must be valid"),
+                )
+            });
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to serve metrics request"
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counter, Histogram, Metric};
+
+    #[test]
+    fn counters_should_be_rendered_per_label_value() {
+        static COUNTER: Counter = Counter::new("test_total", "A test counter", &["kind"]);
+        COUNTER.inc_by(&["b"], 1.0);
+        COUNTER.inc_by(&["a"], 0.5);
+        COUNTER.inc_by(&["b"], 2.0);
+        assert_eq!(COUNTER.get(&["b"]), 3.0);
+        assert_eq!(COUNTER.get(&["c"]), 0.0);
+
+        let mut out = String::new();
+        COUNTER.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_total A test counter\n\
+             # TYPE test_total counter\n\
+             test_total{kind=\"a\"} 0.5\n\
+             test_total{kind=\"b\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn histograms_should_be_rendered_per_label_value() {
+        static HISTOGRAM: Histogram =
+            Histogram::new("test_seconds", "A test histogram", &["kind"], &[0.5, 1.0]);
+        HISTOGRAM.observe(&["a"], 0.25);
+        HISTOGRAM.observe(&["a"], 0.75);
+        HISTOGRAM.observe(&["a"], 2.0);
+        assert_eq!(HISTOGRAM.count(&["a"]), 3);
+        assert_eq!(HISTOGRAM.count(&["b"]), 0);
+
+        let mut out = String::new();
+        HISTOGRAM.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_seconds A test histogram\n\
+             # TYPE test_seconds histogram\n\
+             test_seconds_bucket{kind=\"a\",le=\"0.5\"} 1\n\
+             test_seconds_bucket{kind=\"a\",le=\"1\"} 2\n\
+             test_seconds_bucket{kind=\"a\",le=\"+Inf\"} 3\n\
+             test_seconds_sum{kind=\"a\"} 3\n\
+             test_seconds_count{kind=\"a\"} 3\n"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Named points at which faults can be injected, to test how the operator recovers from them.
+//!
+//! Failpoints only exist if the operator is built with the `failpoints` feature, and are compiled out entirely
+//! otherwise. They are configured by setting
+//! [`FAILPOINTS_ENV`](stackable_secret_operator_crd_utils::failpoint::FAILPOINTS_ENV), see
+//! [`stackable_secret_operator_crd_utils::failpoint`] for the format.
+//!
+//! `krb5-provision-keytab` reads the same variable, and provides `credential_cache.save.before_patch` (before saving
+//! newly generated credentials to the credential cache).
+
+#[cfg(test)]
+pub use stackable_secret_operator_crd_utils::failpoint::configure_for_current_thread;
+pub use stackable_secret_operator_crd_utils::failpoint::{InjectedFault, configure_from_env, eval};
+
+use crate::backend::SecretBackendError;
+
+/// Before a backend is asked for a volume's secret data.
+pub const BACKEND_GET_SECRET_DATA_BEFORE: &str = "backend.get_secret_data.before";
+/// After the backend has returned the secret data successfully.
+pub const BACKEND_GET_SECRET_DATA_AFTER: &str = "backend.get_secret_data.after";
+/// After writing a file, before it replaces the previous file.
+pub const FS_WRITE_FILE_BEFORE_RENAME: &str = "fs.write_file.before_rename";
+/// Before a staged secret file replaces its previous version.
+pub const FS_STAGING_DIR_BEFORE_REPLACE: &str = "fs.staging_dir.before_replace";
+/// Before recording the selector fingerprint of a published volume.
+pub const NODE_PUBLISH_BEFORE_FINGERPRINT: &str = "node.publish.before_fingerprint";
+/// After removing a volume, before removing its fingerprint.
+pub const NODE_UNPUBLISH_BEFORE_REMOVE_FINGERPRINT: &str =
+    "node.unpublish.before_remove_fingerprint";
+/// Before marking the certificate of a CSR handshake as ready.
+pub const NODE_CSR_HANDSHAKE_BEFORE_READY: &str = "node.csr_handshake.before_ready";
+/// Before trying to acquire a slot for an expensive operation.
+pub const COORDINATION_LEASE_BEFORE_ACQUIRE: &str = "coordination.lease.before_acquire";
+
+impl SecretBackendError for InjectedFault {
+    fn grpc_code(&self) -> tonic::Code {
+        tonic::Code::Unavailable
+    }
+}
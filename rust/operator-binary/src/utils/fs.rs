@@ -0,0 +1,914 @@
+//! Filesystem operations that keep track of which file they failed on.
+//!
+//! [`std::io::Error`] does not carry the path it refers to, so every operation here wraps errors in an
+//! [`FsError`] that describes what was being done, and to which path.
+//!
+//! Operations that create or modify files also check whether permission errors were likely caused by SELinux.
+
+use std::{
+    ffi::{CString, OsStr, OsString},
+    fmt::Display,
+    fs::Permissions,
+    io::ErrorKind,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{
+            ffi::OsStrExt,
+            fs::{MetadataExt, PermissionsExt},
+        },
+    },
+    path::{Component, Path, PathBuf},
+};
+
+use snafu::{GenerateImplicitData, ResultExt, Snafu};
+use sys_mount::{Mount, MountFlags, UnmountFlags};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+#[cfg(feature = "failpoints")]
+use super::failpoint;
+use super::selinux::{self, SelinuxDenial};
+
+/// The kind of filesystem operation that failed.
+#[derive(Debug, Clone, Copy)]
+pub enum FsOperation {
+    CreateDir,
+    CreateFile,
+    ReadFile,
+    Stat,
+    WriteFile,
+    SyncFile,
+    ReplaceFile,
+    Chmod,
+    Chown,
+    Mount,
+    Unmount,
+    Delete,
+}
+
+impl Display for FsOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FsOperation::CreateDir => "create directory",
+            FsOperation::CreateFile => "create file",
+            FsOperation::ReadFile => "read file",
+            FsOperation::Stat => "inspect",
+            FsOperation::WriteFile => "write file",
+            FsOperation::SyncFile => "sync file",
+            FsOperation::ReplaceFile => "replace file",
+            FsOperation::Chmod => "set permissions of",
+            FsOperation::Chown => "change group of",
+            FsOperation::Mount => "mount",
+            FsOperation::Unmount => "unmount",
+            FsOperation::Delete => "delete",
+        })
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("failed to {operation} {path:?}{selinux}"))]
+pub struct FsError {
+    source: std::io::Error,
+    operation: FsOperation,
+    path: PathBuf,
+    #[snafu(implicit)]
+    selinux: SelinuxHint,
+}
+
+impl FsError {
+    /// The kind of the underlying OS error.
+    pub fn kind(&self) -> ErrorKind {
+        self.source.kind()
+    }
+
+    /// Permission errors are also reported for SELinux denials, so check whether that is the more likely cause.
+    fn diagnose_selinux(mut self) -> Self {
+        if self.kind() == ErrorKind::PermissionDenied {
+            self.selinux = SelinuxHint(selinux::diagnose(&self.path));
+            if let Some(denial) = &self.selinux.0 {
+                tracing::warn!(
+                    path = %self.path.display(),
+                    operation = %self.operation,
+                    %denial,
+                    "file operation was likely denied by SELinux"
+                );
+            }
+        }
+        self
+    }
+}
+
+/// Appended to the [`FsError`] message if SELinux is the likely cause.
+#[derive(Debug, Default)]
+struct SelinuxHint(Option<SelinuxDenial>);
+
+impl GenerateImplicitData for SelinuxHint {
+    // Only filled in by FsError::diagnose_selinux, since reads and deletes are not checked
+    fn generate() -> Self {
+        Self::default()
+    }
+}
+
+impl Display for SelinuxHint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(denial) => write!(f, " ({denial})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Creates a directory, failing if any parent does not exist.
+pub async fn create_dir(path: &Path) -> Result<(), FsError> {
+    tokio::fs::create_dir(path)
+        .await
+        .context(FsSnafu {
+            operation: FsOperation::CreateDir,
+            path,
+        })
+        .map_err(FsError::diagnose_selinux)
+}
+
+/// Creates a directory and all of its missing parents, setting `mode` (and `group`, if any) on each directory that is
+/// created.
+///
+/// Directories that already exist (including ones that are created concurrently) are left as they are.
+/// Returns the directories that were created, outermost first.
+pub async fn create_dir_all(
+    path: &Path,
+    mode: u32,
+    group: Option<u32>,
+) -> Result<Vec<PathBuf>, FsError> {
+    let mut missing = Vec::new();
+    for dir in path.ancestors() {
+        // Any other errors will be reported by create_dir below
+        if tokio::fs::metadata(dir).await.is_ok() {
+            break;
+        }
+        missing.push(dir);
+    }
+    let mut created = Vec::new();
+    for dir in missing.into_iter().rev() {
+        match create_dir(dir).await {
+            Ok(()) => {
+                set_mode(dir, mode).await?;
+                if let Some(group) = group {
+                    set_group(dir, group)?;
+                }
+                created.push(dir.to_path_buf());
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(created)
+}
+
+/// Sets the Unix permission bits of `path`.
+pub async fn set_mode(path: &Path, mode: u32) -> Result<(), FsError> {
+    tokio::fs::set_permissions(path, Permissions::from_mode(mode))
+        .await
+        .context(FsSnafu {
+            operation: FsOperation::Chmod,
+            path,
+        })
+        .map_err(FsError::diagnose_selinux)
+}
+
+/// Changes the group that owns `path`, leaving its owner as it is.
+pub fn set_group(path: &Path, group: u32) -> Result<(), FsError> {
+    std::os::unix::fs::chown(path, None, Some(group))
+        .context(FsSnafu {
+            operation: FsOperation::Chown,
+            path,
+        })
+        .map_err(FsError::diagnose_selinux)
+}
+
+/// Replaces the file at `path` with a new file with `mode`, containing `contents`.
+///
+/// The new file is owned by `group` if set, and otherwise by the group of the current process.
+///
+/// The new file is written next to `path` and then renamed over it, so readers see either the old or the new
+/// contents, never a partially written file. Errors always refer to `path`, rather than the temporary file.
+pub async fn write_file(
+    path: &Path,
+    mode: u32,
+    group: Option<u32>,
+    contents: &[u8],
+) -> Result<(), FsError> {
+    stage_file(path, mode, group, contents)
+        .await?
+        .commit()
+        .await
+}
+
+/// A file that has been written next to its final path, but only replaces it once it is [committed](Self::commit).
+#[must_use = "a staged file only replaces its path once it is committed"]
+#[derive(Debug)]
+struct StagedFile {
+    tmp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl StagedFile {
+    /// Replaces the file's final path with the staged file.
+    async fn commit(self) -> Result<(), FsError> {
+        let result = self.rename().await;
+        if result.is_err() {
+            self.discard().await;
+        }
+        result.map_err(FsError::diagnose_selinux)
+    }
+
+    async fn rename(&self) -> Result<(), FsError> {
+        #[cfg(feature = "failpoints")]
+        fail_point(
+            failpoint::FS_WRITE_FILE_BEFORE_RENAME,
+            FsOperation::ReplaceFile,
+            &self.path,
+        )
+        .await?;
+        tokio::fs::rename(&self.tmp_path, &self.path)
+            .await
+            .context(FsSnafu {
+                operation: FsOperation::ReplaceFile,
+                path: &self.path,
+            })
+    }
+
+    /// Removes the staged file, leaving its final path untouched.
+    async fn discard(&self) {
+        // Best effort, the staged file may already be gone
+        let _ = tokio::fs::remove_file(&self.tmp_path).await;
+    }
+}
+
+/// Writes a file with `mode` and `contents` that will replace `path` once it is committed, see [`write_file`].
+async fn stage_file(
+    path: &Path,
+    mode: u32,
+    group: Option<u32>,
+    contents: &[u8],
+) -> Result<StagedFile, FsError> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let staged = StagedFile {
+        // Must be in the same directory, since rename cannot move files between filesystems
+        tmp_path: path.with_file_name(tmp_name),
+        path: path.to_path_buf(),
+    };
+    match write_synced(&staged.tmp_path, path, mode, group, contents).await {
+        Ok(()) => Ok(staged),
+        Err(err) => {
+            // The temporary file may not have been created in the first place
+            staged.discard().await;
+            Err(err.diagnose_selinux())
+        }
+    }
+}
+
+/// A hidden directory inside of a target directory, which holds new versions of files in the target directory until
+/// all of them have been written.
+///
+/// Unlike [`write_file`], this never leaves temporary files next to the files that they replace.
+#[must_use = "staged files only replace their previous versions once they are committed"]
+#[derive(Debug)]
+pub struct StagingDir {
+    target: PathBuf,
+    path: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+impl StagingDir {
+    /// The name of the staging directory inside of the target directory, which no other file may use.
+    pub const NAME: &'static str = "..staging";
+
+    /// Holds the new versions of the staged files.
+    const NEW_DIR: &'static str = "new";
+
+    /// Holds the previous versions of the files that have been replaced, until the commit has succeeded.
+    const PREVIOUS_DIR: &'static str = "previous";
+
+    /// Creates the staging directory inside of `target`, replacing any leftovers from an interrupted commit.
+    pub async fn create(target: &Path) -> Result<Self, FsError> {
+        let path = target.join(Self::NAME);
+        match remove_dir_all(&path).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        create_dir(&path).await?;
+        // Staged files must not be read before they are committed
+        set_mode(&path, 0o700).await?;
+        Ok(Self {
+            target: target.to_path_buf(),
+            path,
+            files: Vec::new(),
+        })
+    }
+
+    /// Stages a file with `mode` and `contents` that will replace `relative_path` in the target directory once the
+    /// staging directory is committed.
+    ///
+    /// The file's parent directories must exist in the target directory by the time that it is committed.
+    /// Errors refer to the file's final path, rather than the staged file.
+    pub async fn write_file(
+        &mut self,
+        relative_path: &Path,
+        mode: u32,
+        group: Option<u32>,
+        contents: &[u8],
+    ) -> Result<(), FsError> {
+        let staged_path = self.path.join(Self::NEW_DIR).join(relative_path);
+        if let Some(parent) = staged_path.parent() {
+            create_dir_all(parent, 0o700, None).await?;
+        }
+        write_synced(
+            &staged_path,
+            &self.target.join(relative_path),
+            mode,
+            group,
+            contents,
+        )
+        .await
+        .map_err(FsError::diagnose_selinux)?;
+        self.files.push(relative_path.to_path_buf());
+        Ok(())
+    }
+
+    /// Replaces the files in the target directory with their staged versions, in the order that they were staged.
+    ///
+    /// Each file is replaced atomically. If any file can't be replaced, the files that have already been replaced are
+    /// restored to their previous versions. The staging directory is removed either way.
+    pub async fn commit(self) -> Result<(), FsError> {
+        let mut replaced = Vec::new();
+        let mut result = Ok(());
+        for relative_path in &self.files {
+            match self.replace(relative_path).await {
+                Ok(had_previous) => replaced.push((relative_path, had_previous)),
+                Err(err) => {
+                    result = Err(err.diagnose_selinux());
+                    break;
+                }
+            }
+        }
+        if result.is_err() {
+            for (relative_path, had_previous) in replaced.into_iter().rev() {
+                self.restore(relative_path, had_previous).await;
+            }
+        }
+        self.discard().await;
+        result
+    }
+
+    /// Moves the staged version of `relative_path` into the target directory, returning whether it replaced a
+    /// previous version.
+    async fn replace(&self, relative_path: &Path) -> Result<bool, FsError> {
+        let path = self.target.join(relative_path);
+        let previous_path = self.path.join(Self::PREVIOUS_DIR).join(relative_path);
+        if let Some(parent) = previous_path.parent() {
+            create_dir_all(parent, 0o700, None).await?;
+        }
+        // Keep the previous version around, in case one of the following files can't be replaced
+        let had_previous = match tokio::fs::hard_link(&path, &previous_path).await {
+            Ok(()) => true,
+            Err(err) if err.kind() == ErrorKind::NotFound => false,
+            Err(err) => {
+                return Err(err).context(FsSnafu {
+                    operation: FsOperation::ReplaceFile,
+                    path,
+                });
+            }
+        };
+        #[cfg(feature = "failpoints")]
+        fail_point(
+            failpoint::FS_STAGING_DIR_BEFORE_REPLACE,
+            FsOperation::ReplaceFile,
+            &path,
+        )
+        .await?;
+        tokio::fs::rename(self.path.join(Self::NEW_DIR).join(relative_path), &path)
+            .await
+            .context(FsSnafu {
+                operation: FsOperation::ReplaceFile,
+                path: &path,
+            })?;
+        Ok(had_previous)
+    }
+
+    /// Restores the previous version of `relative_path` in the target directory, or removes it if there was none.
+    async fn restore(&self, relative_path: &Path, had_previous: bool) {
+        let path = self.target.join(relative_path);
+        let result = if had_previous {
+            tokio::fs::rename(
+                self.path.join(Self::PREVIOUS_DIR).join(relative_path),
+                &path,
+            )
+            .await
+        } else {
+            tokio::fs::remove_file(&path).await
+        };
+        if let Err(err) = result {
+            tracing::warn!(
+                path = %path.display(),
+                error = &err as &dyn std::error::Error,
+                "failed to restore the previous version of a file after a failed commit"
+            );
+        }
+    }
+
+    /// Removes the staging directory, leaving the target directory untouched.
+    pub async fn discard(&self) {
+        // Best effort, anything that is left behind is removed by the next attempt
+        let _ = tokio::fs::remove_dir_all(&self.path).await;
+    }
+}
+
+/// Writes `contents` to `tmp_path`, reporting errors for `path`.
+async fn write_synced(
+    tmp_path: &Path,
+    path: &Path,
+    mode: u32,
+    group: Option<u32>,
+    contents: &[u8],
+) -> Result<(), FsError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(mode)
+        .open(tmp_path)
+        .await
+        .context(FsSnafu {
+            operation: FsOperation::CreateFile,
+            path,
+        })?;
+    file.write_all(contents).await.context(FsSnafu {
+        operation: FsOperation::WriteFile,
+        path,
+    })?;
+    // The mode passed to open is restricted by the umask
+    file.set_permissions(Permissions::from_mode(mode))
+        .await
+        .context(FsSnafu {
+            operation: FsOperation::Chmod,
+            path,
+        })?;
+    if let Some(group) = group {
+        std::os::unix::fs::chown(tmp_path, None, Some(group)).context(FsSnafu {
+            operation: FsOperation::Chown,
+            path,
+        })?;
+    }
+    // Otherwise a crash could leave behind an empty file after the rename
+    file.sync_all().await.context(FsSnafu {
+        operation: FsOperation::SyncFile,
+        path,
+    })
+}
+
+/// Fails as if `operation` had failed on `path`, if the failpoint `name` is configured to fail.
+#[cfg(feature = "failpoints")]
+pub async fn fail_point(name: &str, operation: FsOperation, path: &Path) -> Result<(), FsError> {
+    failpoint::eval(name)
+        .await
+        .map_err(std::io::Error::other)
+        .context(FsSnafu { operation, path })
+}
+
+/// Reads the contents of the file at `path` as UTF-8.
+pub async fn read_to_string(path: &Path) -> Result<String, FsError> {
+    tokio::fs::read_to_string(path).await.context(FsSnafu {
+        operation: FsOperation::ReadFile,
+        path,
+    })
+}
+
+/// Reads the contents of the file at `path`.
+pub async fn read(path: &Path) -> Result<Vec<u8>, FsError> {
+    tokio::fs::read(path).await.context(FsSnafu {
+        operation: FsOperation::ReadFile,
+        path,
+    })
+}
+
+/// Checks whether `path` exists, failing if that cannot be determined.
+pub async fn try_exists(path: &Path) -> Result<bool, FsError> {
+    tokio::fs::try_exists(path).await.context(FsSnafu {
+        operation: FsOperation::Stat,
+        path,
+    })
+}
+
+/// Space and inode usage of a filesystem, see [`statvfs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilesystemUsage {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub available_bytes: u64,
+    pub total_inodes: u64,
+    pub used_inodes: u64,
+    pub available_inodes: u64,
+}
+
+/// Reports the usage of the filesystem that `path` is on.
+///
+/// For secret volumes mounted as a tmpfs, this is the usage of the volume itself.
+pub fn statvfs(path: &Path) -> Result<FilesystemUsage, FsError> {
+    let context = FsSnafu {
+        operation: FsOperation::Stat,
+        path,
+    };
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(std::io::Error::from)
+        .context(context)?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: c_path is a valid C string, and stat is only read if statvfs succeeded
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) == -1 {
+            return Err(std::io::Error::last_os_error()).context(context);
+        }
+        stat.assume_init()
+    };
+    // The field types vary between platforms
+    let block_size = stat.f_frsize as u64;
+    Ok(FilesystemUsage {
+        total_bytes: stat.f_blocks as u64 * block_size,
+        used_bytes: (stat.f_blocks - stat.f_bfree) as u64 * block_size,
+        available_bytes: stat.f_bavail as u64 * block_size,
+        total_inodes: stat.f_files as u64,
+        used_inodes: (stat.f_files - stat.f_ffree) as u64,
+        available_inodes: stat.f_favail as u64,
+    })
+}
+
+/// Whether a filesystem is mounted at `path`, rather than `path` being a plain directory of its parent's filesystem.
+///
+/// Bind mounts of a directory on the same filesystem are not detected.
+pub async fn is_mountpoint(path: &Path) -> Result<bool, FsError> {
+    let metadata = tokio::fs::symlink_metadata(path).await.context(FsSnafu {
+        operation: FsOperation::Stat,
+        path,
+    })?;
+    let parent_path = path.join("..");
+    let parent = tokio::fs::metadata(&parent_path).await.context(FsSnafu {
+        operation: FsOperation::Stat,
+        path: &parent_path,
+    })?;
+    // The root directory is its own parent
+    Ok(metadata.dev() != parent.dev() || metadata.ino() == parent.ino())
+}
+
+/// Mounts a new tmpfs at `path`, which may not contain devices or executables.
+pub fn mount_tmpfs(path: &Path) -> Result<(), FsError> {
+    Mount::builder()
+        .fstype("tmpfs")
+        .flags(MountFlags::NODEV | MountFlags::NOEXEC | MountFlags::NOSUID)
+        .mount("", path)
+        .context(FsSnafu {
+            operation: FsOperation::Mount,
+            path,
+        })
+        .map_err(FsError::diagnose_selinux)?;
+    Ok(())
+}
+
+/// Unmounts whatever filesystem is mounted at `path`.
+pub fn unmount(path: &Path) -> Result<(), FsError> {
+    sys_mount::unmount(path, UnmountFlags::empty()).context(FsSnafu {
+        operation: FsOperation::Unmount,
+        path,
+    })
+}
+
+/// Deletes the file at `path`.
+pub async fn remove_file(path: &Path) -> Result<(), FsError> {
+    tokio::fs::remove_file(path).await.context(FsSnafu {
+        operation: FsOperation::Delete,
+        path,
+    })
+}
+
+/// Deletes the directory at `path`, along with all of its contents.
+pub async fn remove_dir_all(path: &Path) -> Result<(), FsError> {
+    tokio::fs::remove_dir_all(path).await.context(FsSnafu {
+        operation: FsOperation::Delete,
+        path,
+    })
+}
+
+/// Deletes the file (or the empty directory, if `is_dir`) at `relative_path` inside of `root`.
+///
+/// Unlike [`remove_file`], no symlink below `root` is followed on the way there, so a symlink planted inside of `root`
+/// fails the removal with [`ErrorKind::NotADirectory`] or `ELOOP` instead of redirecting it to somewhere else.
+pub fn remove_beneath(root: &Path, relative_path: &Path, is_dir: bool) -> Result<(), FsError> {
+    let path = root.join(relative_path);
+    let context = FsSnafu {
+        operation: FsOperation::Delete,
+        path: &path,
+    };
+    let (Some(parent), Some(name)) = (relative_path.parent(), relative_path.file_name()) else {
+        return Err(std::io::Error::from(ErrorKind::InvalidInput)).context(context);
+    };
+    let open_dir = |dir_fd: RawFd, name: &OsStr, follow: bool| {
+        let c_name = CString::new(name.as_bytes())?;
+        let mut flags = libc::O_DIRECTORY | libc::O_RDONLY | libc::O_CLOEXEC;
+        if !follow {
+            flags |= libc::O_NOFOLLOW;
+        }
+        // SAFETY: c_name is a valid C string, and the returned fd is owned by nobody else
+        match unsafe { libc::openat(dir_fd, c_name.as_ptr(), flags) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            fd => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        }
+    };
+    // root itself is trusted, only the path below it is not
+    let mut dir = open_dir(libc::AT_FDCWD, root.as_os_str(), true).context(context)?;
+    for component in parent.components() {
+        let Component::Normal(component) = component else {
+            return Err(std::io::Error::from(ErrorKind::InvalidInput)).context(context);
+        };
+        dir = open_dir(dir.as_raw_fd(), component, false).context(context)?;
+    }
+    let c_name = CString::new(name.as_bytes())
+        .map_err(std::io::Error::from)
+        .context(context)?;
+    let flags = if is_dir { libc::AT_REMOVEDIR } else { 0 };
+    // SAFETY: dir is an open directory fd, and c_name is a valid C string
+    if unsafe { libc::unlinkat(dir.as_raw_fd(), c_name.as_ptr(), flags) } == -1 {
+        return Err(std::io::Error::last_os_error()).context(context);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::Permissions,
+        os::unix::fs::PermissionsExt,
+        path::Path,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+    };
+
+    use super::{StagingDir, create_dir_all, is_mountpoint, remove_beneath, statvfs, write_file};
+    use crate::utils::error_full_message;
+
+    #[tokio::test]
+    async fn write_file_error_should_name_item_path() {
+        let dir = tempfile::tempdir().unwrap();
+        // Using a regular file as a directory fails regardless of our privileges
+        tokio::fs::write(dir.path().join("not-a-dir"), b"")
+            .await
+            .unwrap();
+        let item_path = dir.path().join("not-a-dir/nested/secret");
+        let err = write_file(&item_path, 0o640, None, b"hello")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_full_message(&err),
+            format!(
+                "failed to create file {:?}: Not a directory (os error 20)",
+                item_path
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn write_file_should_never_expose_partial_contents() {
+        const LEN: usize = 4 << 20;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        write_file(&path, 0o640, None, &vec![b'a'; LEN])
+            .await
+            .unwrap();
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = std::thread::spawn({
+            let path = path.clone();
+            let done = done.clone();
+            move || {
+                while !done.load(Ordering::Relaxed) {
+                    let contents = std::fs::read(&path).unwrap();
+                    assert_eq!(contents.len(), LEN);
+                    assert!(contents.iter().all(|&b| b == contents[0]));
+                }
+            }
+        });
+        for i in 0..20 {
+            let byte = if i % 2 == 0 { b'b' } else { b'a' };
+            write_file(&path, 0o640, None, &vec![byte; LEN])
+                .await
+                .unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        reader.join().unwrap();
+
+        // The temporary file must not be left behind
+        let mut entries = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, ["secret"]);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+    }
+
+    #[tokio::test]
+    async fn create_dir_all_should_only_report_created_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("existing");
+        std::fs::create_dir(&existing).unwrap();
+        std::fs::set_permissions(&existing, Permissions::from_mode(0o755)).unwrap();
+
+        let created = create_dir_all(&existing.join("a/b"), 0o750, None)
+            .await
+            .unwrap();
+        assert_eq!(created, [existing.join("a"), existing.join("a/b")]);
+        for created in &created {
+            let mode = std::fs::metadata(created).unwrap().permissions().mode();
+            assert_eq!(mode & 0o7777, 0o750, "{created:?}");
+        }
+        // Pre-existing parents must be left as they are
+        let mode = std::fs::metadata(&existing).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o755);
+
+        // Siblings share the parent that has already been created
+        let created = create_dir_all(&existing.join("a/c"), 0o750, None)
+            .await
+            .unwrap();
+        assert_eq!(created, [existing.join("a/c")]);
+        let created = create_dir_all(&existing.join("a/b"), 0o750, None)
+            .await
+            .unwrap();
+        assert_eq!(created, Vec::<std::path::PathBuf>::new());
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[tokio::test]
+    async fn failed_replace_should_keep_previous_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        write_file(&path, 0o640, None, b"old").await.unwrap();
+        crate::utils::failpoint::configure_for_current_thread(
+            "fs.write_file.before_rename=1*return",
+        )
+        .unwrap();
+
+        write_file(&path, 0o640, None, b"new").await.unwrap_err();
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        let entries = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(entries, 1, "the temporary file must be removed");
+
+        write_file(&path, 0o640, None, b"new").await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+    }
+
+    #[tokio::test]
+    async fn failed_commit_should_restore_previous_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("existing"), 0o640, None, b"old")
+            .await
+            .unwrap();
+
+        let mut staging = StagingDir::create(dir.path()).await.unwrap();
+        for file in ["existing", "new", "missing-dir/file"] {
+            staging
+                .write_file(Path::new(file), 0o640, None, b"new")
+                .await
+                .unwrap();
+        }
+        let err = staging.commit().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+        assert_eq!(std::fs::read(dir.path().join("existing")).unwrap(), b"old");
+        // Neither the files that were replaced before the failure, nor the staging directory, may be left behind
+        let entries = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(entries, ["existing"]);
+    }
+
+    #[tokio::test]
+    async fn commit_should_replace_every_staged_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("existing"), 0o640, None, b"old")
+            .await
+            .unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        // Left behind by an interrupted commit
+        std::fs::create_dir_all(dir.path().join(StagingDir::NAME).join("new")).unwrap();
+
+        let mut staging = StagingDir::create(dir.path()).await.unwrap();
+        for file in ["existing", "nested/new"] {
+            staging
+                .write_file(Path::new(file), 0o600, None, b"new")
+                .await
+                .unwrap();
+        }
+        // Staged files are not visible before they are committed
+        assert_eq!(std::fs::read(dir.path().join("existing")).unwrap(), b"old");
+        assert!(!dir.path().join("nested/new").exists());
+        staging.commit().await.unwrap();
+
+        for file in ["existing", "nested/new"] {
+            let path = dir.path().join(file);
+            assert_eq!(std::fs::read(&path).unwrap(), b"new", "{file}");
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o7777, 0o600, "{file}");
+        }
+        assert!(!dir.path().join(StagingDir::NAME).exists());
+    }
+
+    #[cfg(feature = "failpoints")]
+    #[tokio::test]
+    async fn interrupted_commit_should_keep_previous_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        write_file(&path, 0o640, None, b"old").await.unwrap();
+        crate::utils::failpoint::configure_for_current_thread(
+            "fs.staging_dir.before_replace=1*return",
+        )
+        .unwrap();
+
+        let mut staging = StagingDir::create(dir.path()).await.unwrap();
+        staging
+            .write_file(Path::new("secret"), 0o640, None, b"new")
+            .await
+            .unwrap();
+        staging.commit().await.unwrap_err();
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        let entries = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(entries, 1, "the staging directory must be removed");
+    }
+
+    #[test]
+    fn statvfs_should_report_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = statvfs(dir.path()).unwrap();
+        assert!(usage.total_bytes > 0);
+        assert!(usage.used_bytes <= usage.total_bytes);
+        assert!(usage.available_bytes <= usage.total_bytes);
+
+        let missing = dir.path().join("missing");
+        let err = statvfs(&missing).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    /// The publish and cleanup paths must go through this module, so that errors always carry the path
+    /// that they refer to.
+    #[test]
+    fn csi_node_should_only_use_fs_wrappers() {
+        for (module, source) in [("csi_server/node.rs", include_str!("../csi_server/node.rs"))] {
+            for forbidden in ["std::fs", "tokio::fs", "sys_mount"] {
+                assert!(
+                    !source.contains(forbidden),
+                    "{module} must use crate::utils::fs instead of {forbidden}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn plain_directories_should_not_be_mountpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        tokio::fs::create_dir(dir.path().join("volume"))
+            .await
+            .unwrap();
+        assert!(!is_mountpoint(&dir.path().join("volume")).await.unwrap());
+        assert!(is_mountpoint(Path::new("/")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn remove_beneath_should_not_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("volume");
+        let outside = dir.path().join("outside");
+        create_dir_all(&root.join("nested/empty"), 0o750, None)
+            .await
+            .unwrap();
+        tokio::fs::create_dir(&outside).await.unwrap();
+        tokio::fs::write(outside.join("secret"), b"").await.unwrap();
+        tokio::fs::write(root.join("nested/secret"), b"")
+            .await
+            .unwrap();
+        tokio::fs::symlink(&outside, root.join("link"))
+            .await
+            .unwrap();
+
+        remove_beneath(&root, Path::new("link/secret"), false).unwrap_err();
+        assert!(outside.join("secret").exists());
+        // Non-empty directories are kept
+        remove_beneath(&root, Path::new("nested"), true).unwrap_err();
+
+        remove_beneath(&root, Path::new("nested/secret"), false).unwrap();
+        remove_beneath(&root, Path::new("nested/empty"), true).unwrap();
+        remove_beneath(&root, Path::new("nested"), true).unwrap();
+        assert!(!root.join("nested").exists());
+    }
+}
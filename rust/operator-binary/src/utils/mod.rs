@@ -1,9 +1,16 @@
+#[cfg(feature = "failpoints")]
+pub mod failpoint;
+pub mod fs;
+pub mod selinux;
+
 use std::fmt::Write as _; // import without risk of name clashing
 use std::{
     fmt::{Debug, LowerHex},
+    future::Future,
     ops::{Deref, DerefMut},
-    os::unix::prelude::AsRawFd,
+    os::unix::prelude::{AsRawFd, FileTypeExt, PermissionsExt},
     path::Path,
+    time::Duration,
 };
 
 use futures::{Stream, StreamExt, pin_mut};
@@ -14,7 +21,7 @@ use socket2::Socket;
 use time::OffsetDateTime;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    net::{UnixListener, UnixStream},
+    net::{UnixListener, UnixStream, unix::UCred},
 };
 use tonic::transport::server::Connected;
 
@@ -72,21 +79,47 @@ impl AsyncWrite for TonicUnixStream {
     }
 }
 
+/// Describes the process on the other end of a [`TonicUnixStream`], available as a request extension.
+#[derive(Debug, Clone)]
+pub struct UnixConnectInfo {
+    /// The credentials of the peer process, if they could be determined.
+    pub peer_cred: Option<UCred>,
+}
+
 impl Connected for TonicUnixStream {
-    type ConnectInfo = ();
+    type ConnectInfo = UnixConnectInfo;
 
-    fn connect_info(&self) -> Self::ConnectInfo {}
+    fn connect_info(&self) -> Self::ConnectInfo {
+        UnixConnectInfo {
+            peer_cred: self.0.peer_cred().ok(),
+        }
+    }
 }
 
 /// Bind a Unix Domain Socket listener that is only accessible to the current user
 pub fn uds_bind_private(path: impl AsRef<Path>) -> Result<UnixListener, std::io::Error> {
+    uds_bind(path.as_ref(), 0o600)
+}
+
+/// Bind a Unix Domain Socket listener that is only accessible to the current user and the members of `group`
+pub fn uds_bind_group(path: impl AsRef<Path>, group: u32) -> Result<UnixListener, std::io::Error> {
+    let path = path.as_ref();
+    // Until the chown, the socket is only accessible to the current user
+    let listener = uds_bind(path, 0o600)?;
+    std::os::unix::fs::chown(path, None, Some(group))?;
+    // Set explicitly, since the mode set by uds_bind is still subject to the umask
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+fn uds_bind(path: &Path, mode: libc::mode_t) -> Result<UnixListener, std::io::Error> {
     // Workaround for https://github.com/tokio-rs/tokio/issues/4422
     let socket = Socket::new(socket2::Domain::UNIX, socket2::Type::STREAM, None)?;
     unsafe {
         // Socket-level chmod is propagated to the file created by Socket::bind.
         // We need to chmod /before/ creating the file, because otherwise there is a brief window where
         // the file is world-accessible (unless restricted by the global umask).
-        if libc::fchmod(socket.as_raw_fd(), 0o600) == -1 {
+        if libc::fchmod(socket.as_raw_fd(), mode) == -1 {
             return Err(std::io::Error::last_os_error());
         }
     }
@@ -96,6 +129,16 @@ pub fn uds_bind_private(path: impl AsRef<Path>) -> Result<UnixListener, std::io:
     UnixListener::from_std(socket.into())
 }
 
+/// Removes a socket left behind by a previous run, so that it can be bound again.
+pub fn remove_stale_socket(path: &Path) {
+    if path
+        .symlink_metadata()
+        .is_ok_and(|meta| meta.file_type().is_socket())
+    {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
 /// Helper for formatting byte arrays
 pub struct FmtByteSlice<'a>(pub &'a [u8]);
 impl LowerHex for FmtByteSlice<'_> {
@@ -131,6 +174,32 @@ pub async fn trystream_any<S: Stream<Item = Result<bool, E>>, E>(stream: S) -> R
     Ok(false)
 }
 
+/// Calls `lookup` every `interval` until it returns `Some`, giving up once `timeout` has elapsed.
+///
+/// `lookup` is always called at least once, even if `timeout` is zero. Returns `Ok(None)` if the value
+/// did not appear in time. The wait is cancelled if the returned future is dropped.
+pub async fn poll_until_some<T, E, F, Fut>(
+    timeout: Duration,
+    interval: Duration,
+    mut lookup: F,
+) -> Result<Option<T>, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<T>, E>>,
+{
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(value) = lookup().await? {
+            return Ok(Some(value));
+        }
+        let now = tokio::time::Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        tokio::time::sleep_until(deadline.min(now + interval)).await;
+    }
+}
+
 /// Concatenate chunks of bytes, short-circuiting on [`Err`].
 ///
 /// This is a byte-oriented equivalent to [`Iterator::collect::<Result<String, _>>`](`Iterator::collect`).
@@ -203,11 +272,16 @@ impl<T> DerefMut for Unloggable<T> {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
     use futures::StreamExt;
     use openssl::asn1::Asn1Time;
     use time::OffsetDateTime;
 
-    use super::{asn1time_to_offsetdatetime, iterator_try_concat_bytes};
+    use super::{asn1time_to_offsetdatetime, iterator_try_concat_bytes, poll_until_some};
     use crate::utils::{FmtByteSlice, error_full_message, trystream_any};
 
     #[test]
@@ -265,6 +339,63 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn poll_until_some_should_wait_for_value() {
+        let attempts = &AtomicUsize::new(0);
+        let value = poll_until_some(
+            Duration::from_secs(10),
+            Duration::from_millis(1),
+            || async move {
+                // Only appears on the third attempt
+                Result::<_, ()>::Ok(
+                    (attempts.fetch_add(1, Ordering::SeqCst) >= 2).then_some("appeared"),
+                )
+            },
+        )
+        .await;
+        assert_eq!(value, Ok(Some("appeared")));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn poll_until_some_should_give_up_after_timeout() {
+        let attempts = &AtomicUsize::new(0);
+        let value = poll_until_some(
+            Duration::from_millis(20),
+            Duration::from_millis(5),
+            || async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Result::<Option<()>, ()>::Ok(None)
+            },
+        )
+        .await;
+        assert_eq!(value, Ok(None));
+        assert!(attempts.load(Ordering::SeqCst) > 1);
+    }
+
+    #[tokio::test]
+    async fn poll_until_some_should_try_once_without_timeout() {
+        let attempts = &AtomicUsize::new(0);
+        let value = poll_until_some(Duration::ZERO, Duration::from_secs(10), || async move {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Result::<Option<()>, ()>::Ok(None)
+        })
+        .await;
+        assert_eq!(value, Ok(None));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn poll_until_some_should_propagate_errors() {
+        let value = poll_until_some(
+            Duration::from_secs(10),
+            Duration::from_millis(1),
+            || async { Result::<Option<()>, _>::Err("lookup failed") },
+        )
+        .await;
+        assert_eq!(value, Err("lookup failed"));
+    }
+
     #[test]
     fn iterator_try_concat_bytes_should_work() {
         assert_eq!(
@@ -0,0 +1,131 @@
+//! Diagnoses file operations that were likely denied by SELinux, rather than by regular Unix permissions.
+//!
+//! SELinux denials are reported as `EACCES` or `EPERM`, just like any other permission error, which tends to send
+//! users looking for problems with file ownership and modes instead.
+
+use std::{
+    ffi::{CStr, CString},
+    fmt::Display,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+const ENFORCE_PATH: &str = "/sys/fs/selinux/enforce";
+const CONTEXT_XATTR: &CStr = c"security.selinux";
+/// The SELinux type that container runtimes label volumes with, so that containers can access them.
+pub const EXPECTED_TYPE: &str = "container_file_t";
+
+/// A permission error that was likely caused by SELinux.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelinuxDenial {
+    /// The SELinux type of the file, if it could be read.
+    pub found_type: Option<String>,
+}
+
+impl Display for SelinuxDenial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SELinux denial likely; expected context {EXPECTED_TYPE}, found {}",
+            self.found_type.as_deref().unwrap_or("no readable context")
+        )
+    }
+}
+
+/// Checks whether a permission error for `path` was likely caused by SELinux.
+///
+/// This is the case if SELinux is enforcing, and `path` (or its parent, if `path` does not exist yet) is not labelled
+/// as [`EXPECTED_TYPE`].
+pub fn diagnose(path: &Path) -> Option<SelinuxDenial> {
+    diagnose_with(Path::new(ENFORCE_PATH), path)
+}
+
+fn diagnose_with(enforce_path: &Path, path: &Path) -> Option<SelinuxDenial> {
+    if !is_enforcing(enforce_path) {
+        return None;
+    }
+    let context = read_context(path).or_else(|| read_context(path.parent()?));
+    classify(context.as_deref())
+}
+
+/// `enforce_path` contains `1` if SELinux is enforcing, and `0` if it is permissive.
+/// It does not exist at all if SELinux is disabled.
+fn is_enforcing(enforce_path: &Path) -> bool {
+    std::fs::read_to_string(enforce_path).is_ok_and(|enforce| enforce.trim() == "1")
+}
+
+/// Reads the SELinux context of `path`, such as `system_u:object_r:container_file_t:s0:c1,c2`.
+fn read_context(path: &Path) -> Option<String> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut buf = [0u8; 256];
+    // SAFETY: both strings are null-terminated, and buf is valid for buf.len() bytes
+    let len = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            CONTEXT_XATTR.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        )
+    };
+    let context = buf.get(..usize::try_from(len).ok()?)?;
+    // The context is usually (but not always) null-terminated
+    let context = context.strip_suffix(b"\0").unwrap_or(context);
+    Some(String::from_utf8_lossy(context).into_owned())
+}
+
+/// Decides whether a file with `context` is likely to be denied by an enforcing SELinux policy.
+fn classify(context: Option<&str>) -> Option<SelinuxDenial> {
+    // Contexts have the format user:role:type:level, where level may itself contain colons
+    match context.and_then(|context| context.split(':').nth(2)) {
+        Some(EXPECTED_TYPE) => None,
+        found_type => Some(SelinuxDenial {
+            found_type: found_type.map(str::to_string),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SelinuxDenial, classify, diagnose_with, is_enforcing};
+
+    #[test]
+    fn is_enforcing_should_parse_enforce_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let enforce_path = dir.path().join("enforce");
+        assert!(!is_enforcing(&enforce_path));
+        std::fs::write(&enforce_path, "0").unwrap();
+        assert!(!is_enforcing(&enforce_path));
+        std::fs::write(&enforce_path, "1\n").unwrap();
+        assert!(is_enforcing(&enforce_path));
+    }
+
+    #[test]
+    fn classify_should_only_flag_unexpected_types() {
+        assert_eq!(
+            classify(Some("system_u:object_r:container_file_t:s0:c1,c2")),
+            None
+        );
+        let denial = classify(Some("system_u:object_r:var_lib_t:s0")).unwrap();
+        assert_eq!(
+            denial,
+            SelinuxDenial {
+                found_type: Some("var_lib_t".to_string())
+            }
+        );
+        assert_eq!(
+            denial.to_string(),
+            "SELinux denial likely; expected context container_file_t, found var_lib_t"
+        );
+        assert_eq!(classify(None), Some(SelinuxDenial { found_type: None }));
+    }
+
+    #[test]
+    fn diagnose_should_ignore_permissive_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let enforce_path = dir.path().join("enforce");
+        let target = dir.path().join("secret");
+        assert_eq!(diagnose_with(&enforce_path, &target), None);
+        std::fs::write(&enforce_path, "0").unwrap();
+        assert_eq!(diagnose_with(&enforce_path, &target), None);
+    }
+}